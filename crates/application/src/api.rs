@@ -0,0 +1,904 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+
+use crate::configuration::{
+    to_form_time, Configuration, FieldError, FORM_BRIGHTNESS_KEY, FORM_DIALECT_KEY, FORM_DISPLAY_COLOR_KEY,
+    FORM_HOSTNAME_KEY, FORM_MQTT_HOST_KEY, FORM_MQTT_PORT_KEY, FORM_MQTT_USERNAME_KEY,
+    FORM_NIGHT_END_KEY, FORM_NIGHT_START_KEY,
+};
+use crate::boot_validation::ImageState;
+use crate::color::Color;
+use crate::form_urlencoded::FormFields;
+use crate::notification::{Notification, Pattern, DEFAULT_NOTIFICATION_DURATION};
+use crate::time_source::TimeSourceHealth;
+use crate::{pin_lockout_ticks, MAX_PIN_ATTEMPTS};
+
+/// Maximum size of a request body
+pub const MAX_API_BODY_LENGTH: usize = 1024;
+
+/// Maximum time to wait for the application to apply submitted settings.
+/// The application handles them on its next tick.
+pub const SETTINGS_TIMEOUT: Duration = Duration::from_secs(30);
+
+const ACTIONS_PATH: &str = "/api/actions/";
+
+/// Content type required for the requests changing the clock. Browsers send it
+/// cross-origin only after a preflight request, which is never allowed.
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// States of the behaviour showing the notifications
+const NOTIFICATION_STATES: [&str; 2] = ["DisplayTime", "Notification"];
+
+/// Status of the clock, served on `GET /api/status`
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ApiStatus {
+    /// State of the behaviour state-machine
+    pub state: String,
+    /// Current time as `hh:mm:ss`, `None` if not synchronized
+    pub time: Option<String>,
+    pub time_source: TimeSourceHealth,
+    /// Firmware version
+    pub version: String,
+    /// Validation of the running image after an update
+    pub image: ImageState,
+    /// Seconds since the clock started
+    pub uptime: u64,
+    /// Signal strength of the WiFi network in dBm, `None` if not connected
+    pub rssi: Option<i8>,
+}
+
+/// Action requested with `POST /api/actions/{action}`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ApiAction {
+    /// Synchronize the time sources
+    Sync,
+    /// Install the latest firmware
+    Ota,
+    Reboot,
+    /// Show the time again after `Off`
+    On,
+    /// Turn the display off, until `On` or a button push
+    Off,
+}
+
+impl fmt::Display for ApiAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sync => write!(f, "sync"),
+            Self::Ota => write!(f, "ota"),
+            Self::Reboot => write!(f, "reboot"),
+            Self::On => write!(f, "on"),
+            Self::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl FromStr for ApiAction {
+    type Err = anyhow::Error;
+
+    fn from_str(action: &str) -> Result<Self> {
+        match action {
+            "sync" => Ok(Self::Sync),
+            "ota" => Ok(Self::Ota),
+            "reboot" => Ok(Self::Reboot),
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            _ => Err(anyhow!("Unknown action {:?}", action)),
+        }
+    }
+}
+
+/// State shared between the HTTP server threads and the application
+pub struct ApiState {
+    /// Last status published by the application
+    pub status: Option<ApiStatus>,
+    /// Current settings, `None` until the clock is configured
+    pub settings: Option<Configuration>,
+    /// Submitted settings, to be applied by the application
+    pub settings_fields: Option<FormFields>,
+    /// Validation result of the submitted settings, set by the application
+    pub settings_errors: Option<Vec<FieldError>>,
+    /// Requested actions, handled by the application in order
+    pub actions: VecDeque<ApiAction>,
+    /// Notification to show, replaced by a newer one until the application takes it
+    pub notification: Option<Notification>,
+    /// PIN shown on the display, asked to change the clock from the network.
+    /// `None` when the access point isn't protected by a PIN.
+    pub pin: Option<String>,
+    /// The PIN must be shown on the display, set until the application takes it
+    pub pin_requested: bool,
+    /// Wrong PINs since the last right one, and the end of the lockout
+    pin_failures: u32,
+    pin_locked_until: Option<Instant>,
+}
+
+impl ApiState {
+    pub const fn new() -> Self {
+        Self {
+            status: None,
+            settings: None,
+            settings_fields: None,
+            settings_errors: None,
+            actions: VecDeque::new(),
+            notification: None,
+            pin: None,
+            pin_requested: false,
+            pin_failures: 0,
+            pin_locked_until: None,
+        }
+    }
+}
+
+impl ApiState {
+    /// Queue an action for the application. Requesting the same action twice
+    /// before it is handled runs it once.
+    pub fn request_action(&mut self, action: ApiAction) {
+        if !self.actions.contains(&action) {
+            self.actions.push_back(action);
+        }
+    }
+
+    /// Check the PIN of a request changing the clock from the network.
+    ///
+    /// A wrong PIN asks for the PIN on the display. Like the configuration
+    /// form, the check is locked after `MAX_PIN_ATTEMPTS` wrong PINs, counting
+    /// a tick as a second.
+    pub fn check_pin(&mut self, pin: Option<&str>) -> Result<(), FieldError> {
+        let Some(expected) = &self.pin else {
+            return Ok(());
+        };
+        if self.pin_locked_until.is_some_and(|until| Instant::now() < until) {
+            return Err(FieldError::PinLocked);
+        }
+        if pin.is_some_and(|pin| pin.trim() == expected) {
+            self.pin_failures = 0;
+            return Ok(());
+        }
+
+        self.pin_requested = true;
+        self.pin_failures += 1;
+        if self.pin_failures >= MAX_PIN_ATTEMPTS {
+            let lockout = Duration::from_secs(pin_lockout_ticks(self.pin_failures).into());
+            self.pin_locked_until = Some(Instant::now() + lockout);
+            Err(FieldError::PinLocked)
+        } else {
+            Err(FieldError::InvalidPin)
+        }
+    }
+
+    /// Submit settings fields, to be validated and applied by the application.
+    pub fn submit_settings(&mut self, fields: FormFields) {
+        self.settings_fields = Some(fields);
+        self.settings_errors = None;
+    }
+
+    /// Queue a notification for the application.
+    ///
+    /// # Errors
+    /// The clock is not showing the time, e.g. in night mode or turned off.
+    pub fn notify(&mut self, notification: Notification) -> Result<()> {
+        let state = self.status.as_ref().map(|status| status.state.as_str());
+        if !state.is_some_and(|state| NOTIFICATION_STATES.contains(&state)) {
+            return Err(anyhow!("The clock is not showing the time"));
+        }
+        self.notification = Some(notification);
+        Ok(())
+    }
+}
+
+impl Default for ApiState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// JSON answer of the REST API
+#[derive(Debug, PartialEq)]
+pub struct ApiResponse {
+    /// HTTP status code
+    pub status: u16,
+    pub body: String,
+}
+
+impl ApiResponse {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self { status, body },
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self { status, body: json!({ "error": message }).to_string() }
+    }
+}
+
+/// Settings served on `GET /api/config`, see `doc/rest_api.md`.
+///
+/// The members follow the configuration backup, without the WiFi networks.
+#[derive(Debug, PartialEq, Serialize)]
+struct ApiConfig {
+    display_color: String,
+    brightness: Option<u8>,
+    dialect: Option<String>,
+    night_mode: Option<NightMode>,
+    hostname: Option<String>,
+    /// The password is never served.
+    mqtt: Option<MqttConfig>,
+    update: Option<UpdateConfig>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct MqttConfig {
+    host: String,
+    port: u16,
+    username: String,
+}
+
+/// Server and channel of the firmware updates
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpdateConfig {
+    server: Option<String>,
+    channel: Option<String>,
+}
+
+/// Settings accepted on `PUT /api/config`, missing members keep their value.
+///
+/// The update settings are read-only, see `UPDATE_KEYS`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigUpdate {
+    display_color: Option<String>,
+    brightness: Option<u8>,
+    dialect: Option<String>,
+    /// `null` disables the night mode, hence the nested option.
+    #[serde(default, deserialize_with = "present")]
+    night_mode: Option<Option<NightMode>>,
+    hostname: Option<String>,
+    /// `null` disables MQTT, missing members keep their value.
+    #[serde(default, deserialize_with = "present")]
+    mqtt: Option<Option<MqttUpdate>>,
+    update: Option<UpdateConfig>,
+}
+
+/// The password is only set on the settings page. It is kept while the host
+/// and user name don't change.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MqttUpdate {
+    host: Option<String>,
+    port: Option<u16>,
+    username: Option<String>,
+}
+
+/// Body of `POST /api/notify`, also used by the MQTT bridge
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NotificationRequest {
+    color: String,
+    pattern: Option<String>,
+    /// Seconds
+    duration: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NightMode {
+    #[serde(default)]
+    start: Option<String>,
+    #[serde(default)]
+    end: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiFieldError {
+    field: &'static str,
+    message: String,
+}
+
+/// Deserialize a member that is present, possibly `null`.
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> std::result::Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Whether the path is served by `handle_api_request()`.
+pub fn is_api_path(path: &str) -> bool {
+    path == "/api" || path.starts_with("/api/")
+}
+
+/// Handle a request of the REST API, see `doc/rest_api.md`.
+///
+/// The query of `path`, if any, is ignored. A `PUT /api/config` request blocks
+/// until the application applied the settings, or `SETTINGS_TIMEOUT`.
+///
+/// Requests other than `GET` must have the `application/json` content type,
+/// even without body, so that a web page can't send them from a browser. They
+/// must also give the PIN shown on the clock, if any, in the `X-Pin` header.
+pub fn handle_api_request(
+    state: &Mutex<ApiState>,
+    method: &str,
+    path: &str,
+    content_type: Option<&str>,
+    pin: Option<&str>,
+    body: &[u8],
+) -> ApiResponse {
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let is_json = is_json_content_type(content_type);
+
+    if let Some(action) = path.strip_prefix(ACTIONS_PATH) {
+        return match method {
+            "POST" if !is_json => unsupported_media_type(),
+            "POST" => with_pin(state, pin, || post_action(state, action)),
+            _ => method_not_allowed(),
+        };
+    }
+    match (path, method) {
+        ("/api/status", "GET") => get_status(state),
+        ("/api/config", "GET") => get_config(state),
+        ("/api/config", "PUT") | ("/api/notify", "POST") if !is_json => unsupported_media_type(),
+        ("/api/config", "PUT") => with_pin(state, pin, || put_config(state, body)),
+        ("/api/notify", "POST") => with_pin(state, pin, || post_notify(state, body)),
+        ("/api/status" | "/api/config" | "/api/notify", _) => method_not_allowed(),
+        _ => ApiResponse::error(404, "Not found"),
+    }
+}
+
+/// Wait for the application to report the validation result of the submitted
+/// settings, at most `timeout`.
+pub fn wait_settings_errors(state: &Mutex<ApiState>, timeout: Duration) -> Option<Vec<FieldError>> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let Some(errors) = state.lock().unwrap().settings_errors.take() {
+            return Some(errors);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    None
+}
+
+/// Whether the content type is JSON, with any parameter such as the charset.
+fn is_json_content_type(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(JSON_CONTENT_TYPE))
+}
+
+fn method_not_allowed() -> ApiResponse {
+    ApiResponse::error(405, "Method not allowed")
+}
+
+fn unsupported_media_type() -> ApiResponse {
+    ApiResponse::error(415, "Content-Type must be application/json")
+}
+
+/// Handle a request changing the clock once its PIN is checked, see `ApiState::check_pin()`.
+fn with_pin(state: &Mutex<ApiState>, pin: Option<&str>, handle: impl FnOnce() -> ApiResponse) -> ApiResponse {
+    let pin_check = state.lock().unwrap().check_pin(pin);
+    match pin_check {
+        Ok(()) => handle(),
+        Err(error @ FieldError::PinLocked) => ApiResponse::error(429, &error.to_string()),
+        Err(error) => ApiResponse::error(401, &error.to_string()),
+    }
+}
+
+fn not_configured() -> ApiResponse {
+    ApiResponse::error(503, "The clock is not configured")
+}
+
+fn get_status(state: &Mutex<ApiState>) -> ApiResponse {
+    match &state.lock().unwrap().status {
+        Some(status) => ApiResponse::json(200, status),
+        None => ApiResponse::error(503, "Status not available yet"),
+    }
+}
+
+fn get_config(state: &Mutex<ApiState>) -> ApiResponse {
+    let Some(configuration) = state.lock().unwrap().settings.clone() else {
+        return not_configured();
+    };
+    match config_to_api(&configuration) {
+        Ok(config) => ApiResponse::json(200, &config),
+        Err(e) => ApiResponse::error(500, &e.to_string()),
+    }
+}
+
+fn put_config(state: &Mutex<ApiState>, body: &[u8]) -> ApiResponse {
+    let Some(current) = state.lock().unwrap().settings.clone() else {
+        return not_configured();
+    };
+    let fields = match std::str::from_utf8(body).map_err(anyhow::Error::from).and_then(|json| config_update_to_fields(json, &current)) {
+        Ok(fields) => fields,
+        Err(e) => return ApiResponse::error(400, &e.to_string()),
+    };
+
+    state.lock().unwrap().submit_settings(fields);
+
+    match wait_settings_errors(state, SETTINGS_TIMEOUT) {
+        Some(errors) if errors.is_empty() => get_config(state),
+        Some(errors) => {
+            let errors: Vec<ApiFieldError> = errors
+                .iter()
+                .map(|error| ApiFieldError { field: json_member(error.field()), message: error.to_string() })
+                .collect();
+            ApiResponse::json(422, &json!({ "errors": errors }))
+        }
+        None => {
+            state.lock().unwrap().settings_fields = None;
+            ApiResponse::error(504, "The settings were not applied in time")
+        }
+    }
+}
+
+fn post_action(state: &Mutex<ApiState>, name: &str) -> ApiResponse {
+    let Ok(action) = name.parse::<ApiAction>() else {
+        return ApiResponse::error(404, "Unknown action");
+    };
+
+    let mut state = state.lock().unwrap();
+    if state.settings.is_none() {
+        return not_configured();
+    }
+    state.request_action(action);
+    ApiResponse::json(202, &json!({ "action": action.to_string() }))
+}
+
+fn post_notify(state: &Mutex<ApiState>, body: &[u8]) -> ApiResponse {
+    let notification = match notification_from_json(body) {
+        Ok(notification) => notification,
+        Err(e) => return ApiResponse::error(400, &e.to_string()),
+    };
+
+    let mut state = state.lock().unwrap();
+    if state.settings.is_none() {
+        return not_configured();
+    }
+    if let Err(e) = state.notify(notification) {
+        return ApiResponse::error(409, &e.to_string());
+    }
+    let notification = json!({
+        "color": notification.color.to_string(),
+        "pattern": notification.pattern.to_string(),
+        "duration": notification.duration.as_secs(),
+    });
+    ApiResponse::json(202, &json!({ "notification": notification }))
+}
+
+/// Parse a notification request, see `doc/rest_api.md`. The pattern defaults
+/// to `flash`, the duration to `DEFAULT_NOTIFICATION_DURATION`.
+pub fn notification_from_json(json: &[u8]) -> Result<Notification> {
+    let request: NotificationRequest = serde_json::from_slice(json)?;
+    let color = Color::from_rgb_hex_string(&request.color)?;
+    let pattern = match request.pattern {
+        Some(pattern) => pattern.parse()?,
+        None => Pattern::default(),
+    };
+    let duration = request.duration.map_or(DEFAULT_NOTIFICATION_DURATION, Duration::from_secs);
+    Notification::new(color, pattern, duration)
+}
+
+fn config_to_api(configuration: &Configuration) -> Result<ApiConfig> {
+    let Some(display_color) = configuration.get_display_color() else {
+        return Err(anyhow!("Invalid configuration"));
+    };
+    let night_start = configuration.get_night_start();
+    let night_end = configuration.get_night_end();
+    let night_mode = match (night_start, night_end) {
+        (None, None) => None,
+        _ => Some(NightMode { start: night_start.map(to_form_time), end: night_end.map(to_form_time) }),
+    };
+
+    Ok(ApiConfig {
+        display_color: display_color.to_string(),
+        brightness: configuration.get_brightness(),
+        dialect: configuration.get_dialect().map(|dialect| dialect.to_string()),
+        night_mode,
+        hostname: configuration.get_hostname(),
+        mqtt: configuration
+            .get_mqtt_broker()
+            .map(|broker| MqttConfig { host: broker.host, port: broker.port, username: broker.username }),
+        update: Some(UpdateConfig {
+            server: configuration.get_update_server(),
+            channel: configuration.get_update_channel().map(|channel| channel.to_string()),
+        }),
+    })
+}
+
+/// Convert a `PUT /api/config` body to the fields of the settings page, to be
+/// validated like a submitted settings page. Missing members keep the value of
+/// `current`.
+fn config_update_to_fields(json: &str, current: &Configuration) -> Result<FormFields> {
+    let update: ConfigUpdate = serde_json::from_str(json)?;
+    let mut fields = current.settings_fields();
+
+    if let Some(color) = update.display_color {
+        set_field(&mut fields, FORM_DISPLAY_COLOR_KEY, color);
+    }
+    if let Some(brightness) = update.brightness {
+        set_field(&mut fields, FORM_BRIGHTNESS_KEY, brightness.to_string());
+    }
+    if let Some(dialect) = update.dialect {
+        set_field(&mut fields, FORM_DIALECT_KEY, dialect);
+    }
+    if let Some(night_mode) = update.night_mode {
+        let night_mode = night_mode.unwrap_or(NightMode { start: None, end: None });
+        set_field(&mut fields, FORM_NIGHT_START_KEY, night_mode.start.unwrap_or_default());
+        set_field(&mut fields, FORM_NIGHT_END_KEY, night_mode.end.unwrap_or_default());
+    }
+    if let Some(hostname) = update.hostname {
+        set_field(&mut fields, FORM_HOSTNAME_KEY, hostname);
+    }
+    match update.mqtt {
+        Some(Some(mqtt)) => {
+            let members = [
+                (FORM_MQTT_HOST_KEY, mqtt.host),
+                (FORM_MQTT_PORT_KEY, mqtt.port.map(|port| port.to_string())),
+                (FORM_MQTT_USERNAME_KEY, mqtt.username),
+            ];
+            for (key, value) in members {
+                if let Some(value) = value {
+                    set_field(&mut fields, key, value);
+                }
+            }
+        }
+        Some(None) => set_field(&mut fields, FORM_MQTT_HOST_KEY, String::new()),
+        None => (),
+    }
+    if update.update.is_some() {
+        return Err(anyhow!("The update settings can only be changed in the configuration form"));
+    }
+    Ok(fields)
+}
+
+fn set_field(fields: &mut FormFields, key: &str, value: String) {
+    match fields.iter_mut().find(|(field, _)| field == key) {
+        Some((_, current)) => *current = value,
+        None => fields.push((String::from(key), value)),
+    }
+}
+
+/// JSON member matching a field of the settings page
+fn json_member(field: &'static str) -> &'static str {
+    match field {
+        FORM_DISPLAY_COLOR_KEY => "display_color",
+        FORM_BRIGHTNESS_KEY => "brightness",
+        FORM_DIALECT_KEY => "dialect",
+        FORM_NIGHT_START_KEY => "night_mode.start",
+        FORM_NIGHT_END_KEY => "night_mode.end",
+        FORM_HOSTNAME_KEY => "hostname",
+        FORM_MQTT_HOST_KEY => "mqtt.host",
+        FORM_MQTT_PORT_KEY => "mqtt.port",
+        _ => field,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::configuration::MqttBroker;
+    use crate::display::Dialect;
+    use crate::firmware_update::{UpdateChannel, DEFAULT_UPDATE_SERVER};
+    use crate::time::Time;
+
+    const JSON: Option<&str> = Some(JSON_CONTENT_TYPE);
+
+    fn configuration() -> Configuration {
+        Configuration::new(
+            String::from("home"),
+            String::from("secret"),
+            Some(Time::new(22, 0, 0).unwrap()),
+            None,
+            Color::new(0, 255, 0),
+        )
+        .with_brightness(60)
+    }
+
+    fn configured_state() -> Mutex<ApiState> {
+        let mut state = ApiState::new();
+        state.settings = Some(configuration());
+        Mutex::new(state)
+    }
+
+    fn json_body(response: &ApiResponse) -> serde_json::Value {
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    #[test]
+    fn get_config_without_secrets() {
+        let state = configured_state();
+        let response = handle_api_request(&state, "GET", "/api/config", None, None, &[]);
+
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            json_body(&response),
+            json!({
+                "display_color": "00FF00",
+                "brightness": 60,
+                "dialect": "bern",
+                "night_mode": {"start": "22:00", "end": null},
+                "hostname": "wordclock",
+                "mqtt": null,
+                "update": {"server": DEFAULT_UPDATE_SERVER, "channel": "stable"},
+            })
+        );
+        assert!(!response.body.contains("secret"));
+    }
+
+    #[test]
+    fn pin_check() {
+        let mut state = ApiState::new();
+        assert_eq!(state.check_pin(None), Ok(()));
+
+        state.pin = Some(String::from("0427"));
+        assert_eq!(state.check_pin(Some(" 0427 ")), Ok(()));
+        assert!(!state.pin_requested);
+        assert_eq!(state.check_pin(None), Err(FieldError::InvalidPin));
+        assert!(state.pin_requested);
+
+        // A right PIN resets the count of wrong ones
+        for _ in 2..MAX_PIN_ATTEMPTS {
+            assert_eq!(state.check_pin(Some("1234")), Err(FieldError::InvalidPin));
+        }
+        assert_eq!(state.check_pin(Some("0427")), Ok(()));
+        for _ in 1..MAX_PIN_ATTEMPTS {
+            assert_eq!(state.check_pin(Some("1234")), Err(FieldError::InvalidPin));
+        }
+        assert_eq!(state.check_pin(Some("1234")), Err(FieldError::PinLocked));
+        assert_eq!(state.check_pin(Some("0427")), Err(FieldError::PinLocked));
+    }
+
+    #[test]
+    fn not_configured_yet() {
+        let state = Mutex::new(ApiState::new());
+        assert_eq!(handle_api_request(&state, "GET", "/api/config", None, None, &[]).status, 503);
+        assert_eq!(handle_api_request(&state, "PUT", "/api/config", JSON, None, b"{}").status, 503);
+        assert_eq!(handle_api_request(&state, "POST", "/api/actions/reboot", JSON, None, &[]).status, 503);
+        assert_eq!(handle_api_request(&state, "GET", "/api/status", None, None, &[]).status, 503);
+        assert!(state.lock().unwrap().actions.is_empty());
+    }
+
+    #[test]
+    fn routing() {
+        let state = configured_state();
+        assert_eq!(handle_api_request(&state, "GET", "/api/unknown", None, None, &[]).status, 404);
+        assert_eq!(handle_api_request(&state, "DELETE", "/api/config", JSON, None, &[]).status, 405);
+        assert_eq!(handle_api_request(&state, "POST", "/api/status", JSON, None, &[]).status, 405);
+        assert_eq!(handle_api_request(&state, "GET", "/api/actions/sync", None, None, &[]).status, 405);
+        assert_eq!(handle_api_request(&state, "POST", "/api/actions/explode", JSON, None, &[]).status, 404);
+        assert_eq!(handle_api_request(&state, "GET", "/api/config?pretty", None, None, &[]).status, 200);
+        assert!(is_api_path("/api/status"));
+        assert!(!is_api_path("/apis"));
+    }
+
+    #[test]
+    fn queue_actions() {
+        let state = configured_state();
+        let response = handle_api_request(&state, "POST", "/api/actions/sync", JSON, None, &[]);
+        assert_eq!(response.status, 202);
+        assert_eq!(json_body(&response), json!({"action": "sync"}));
+
+        handle_api_request(&state, "POST", "/api/actions/reboot", JSON, None, &[]);
+        handle_api_request(&state, "POST", "/api/actions/sync", JSON, None, &[]);
+        assert_eq!(state.lock().unwrap().actions, [ApiAction::Sync, ApiAction::Reboot]);
+    }
+
+    #[test]
+    fn changes_require_json_content_type() {
+        let state = configured_state();
+        let form = Some("application/x-www-form-urlencoded");
+        for content_type in [None, form, Some("text/plain"), Some("application/jsonp")] {
+            assert_eq!(handle_api_request(&state, "POST", "/api/actions/reboot", content_type, None, &[]).status, 415);
+            assert_eq!(handle_api_request(&state, "PUT", "/api/config", content_type, None, b"{}").status, 415);
+            let body = br#"{"color": "FF0000"}"#;
+            assert_eq!(handle_api_request(&state, "POST", "/api/notify", content_type, None, body).status, 415);
+        }
+        assert!(state.lock().unwrap().actions.is_empty());
+        assert_eq!(state.lock().unwrap().settings_fields, None);
+        assert_eq!(state.lock().unwrap().notification, None);
+
+        let json = Some("Application/JSON; charset=utf-8");
+        assert_eq!(handle_api_request(&state, "POST", "/api/actions/reboot", json, None, &[]).status, 202);
+        assert_eq!(handle_api_request(&state, "GET", "/api/config", form, None, &[]).status, 200);
+    }
+
+    #[test]
+    fn changes_require_pin() {
+        let state = configured_state();
+        state.lock().unwrap().pin = Some(String::from("0427"));
+        let body = br#"{"color": "FF0000"}"#;
+        assert_eq!(handle_api_request(&state, "PUT", "/api/config", JSON, None, b"{}").status, 401);
+        assert_eq!(handle_api_request(&state, "POST", "/api/actions/reboot", JSON, Some("1234"), &[]).status, 401);
+        assert_eq!(handle_api_request(&state, "POST", "/api/notify", JSON, None, body).status, 401);
+        assert_eq!(state.lock().unwrap().settings_fields, None);
+        assert!(state.lock().unwrap().actions.is_empty());
+        assert!(state.lock().unwrap().pin_requested);
+
+        assert_eq!(handle_api_request(&state, "GET", "/api/config", None, None, &[]).status, 200);
+        assert_eq!(handle_api_request(&state, "POST", "/api/actions/reboot", JSON, Some("0427"), &[]).status, 202);
+
+        for _ in 0..MAX_PIN_ATTEMPTS {
+            handle_api_request(&state, "POST", "/api/actions/off", JSON, None, &[]);
+        }
+        let response = handle_api_request(&state, "POST", "/api/actions/off", JSON, Some("0427"), &[]);
+        assert_eq!(response.status, 429);
+        assert_eq!(json_body(&response), json!({"error": FieldError::PinLocked.to_string()}));
+        assert_eq!(state.lock().unwrap().actions, [ApiAction::Reboot]);
+    }
+
+    #[test]
+    fn queue_notification() {
+        let state = configured_state();
+        let set_state = |name: &str| {
+            state.lock().unwrap().status = Some(ApiStatus {
+                state: String::from(name),
+                time: None,
+                time_source: TimeSourceHealth { synchronized: true, last_network_sync: None, board_synchronized: None },
+                version: String::from("2.0.1"),
+                image: ImageState::Valid,
+                uptime: 0,
+                rssi: None,
+            });
+        };
+
+        set_state("NightMode");
+        let response = handle_api_request(&state, "POST", "/api/notify", JSON, None, br#"{"color": "FF0000"}"#);
+        assert_eq!(response.status, 409);
+        assert_eq!(state.lock().unwrap().notification, None);
+
+        set_state("DisplayTime");
+        let response = handle_api_request(&state, "POST", "/api/notify", JSON, None, br#"{"color": "FF0000"}"#);
+        assert_eq!(response.status, 202);
+        assert_eq!(json_body(&response), json!({"notification": {"color": "FF0000", "pattern": "flash", "duration": 5}}));
+
+        // Replaced by a newer one until shown
+        let body = br#"{"color": "00ff00", "pattern": "pulse", "duration": 10}"#;
+        assert_eq!(handle_api_request(&state, "POST", "/api/notify", JSON, None, body).status, 202);
+        let expected = Notification::new(Color::new(0, 255, 0), Pattern::Pulse, Duration::from_secs(10)).unwrap();
+        assert_eq!(state.lock().unwrap().notification, Some(expected));
+        assert_eq!(handle_api_request(&state, "GET", "/api/notify", None, None, &[]).status, 405);
+    }
+
+    #[test]
+    fn invalid_notification() {
+        for body in [
+            r#"{}"#,
+            r#"{"color": "red"}"#,
+            r#"{"color": "FF0000", "pattern": "blink"}"#,
+            r#"{"color": "FF0000", "duration": 0}"#,
+            r#"{"color": "FF0000", "duration": 3600}"#,
+            r#"{"color": "FF0000", "sound": true}"#,
+        ] {
+            assert!(notification_from_json(body.as_bytes()).is_err(), "{}", body);
+        }
+        let state = configured_state();
+        assert_eq!(handle_api_request(&state, "POST", "/api/notify", JSON, None, b"not json").status, 400);
+    }
+
+    #[test]
+    fn status() {
+        let state = configured_state();
+        state.lock().unwrap().status = Some(ApiStatus {
+            state: String::from("DisplayTime"),
+            time: Some(String::from("12:34:56")),
+            time_source: TimeSourceHealth { synchronized: true, last_network_sync: Some(10), board_synchronized: None },
+            version: String::from("2.0.1"),
+            image: ImageState::Pending,
+            uptime: 3600,
+            rssi: Some(-60),
+        });
+
+        let response = handle_api_request(&state, "GET", "/api/status", None, None, &[]);
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            json_body(&response),
+            json!({
+                "state": "DisplayTime",
+                "time": "12:34:56",
+                "time_source": {"synchronized": true, "last_network_sync": 10, "board_synchronized": null},
+                "version": "2.0.1",
+                "image": {"state": "pending"},
+                "uptime": 3600,
+                "rssi": -60,
+            })
+        );
+    }
+
+    #[test]
+    fn update_settings_are_read_only() {
+        let staged = configuration().with_update_server("http://mirror.lan/ota").with_update_channel(UpdateChannel::Nightly);
+        for json in [r#"{"update": {"server": "http://attacker.lan/ota"}}"#, r#"{"update": {"channel": "stable"}}"#] {
+            let error = config_update_to_fields(json, &staged).unwrap_err();
+            assert_eq!(error.to_string(), "The update settings can only be changed in the configuration form");
+        }
+
+        let fields = config_update_to_fields(r#"{"brightness": 20}"#, &staged).unwrap();
+        let mut updated = staged.clone();
+        updated.update_settings(&fields).unwrap();
+        assert_eq!(updated.get_update_server(), Some(String::from("http://mirror.lan/ota")));
+        assert_eq!(updated.get_update_channel(), Some(UpdateChannel::Nightly));
+    }
+
+    #[test]
+    fn update_keeps_missing_members() {
+        let fields = config_update_to_fields(r#"{"brightness": 20, "dialect": "half_hour"}"#, &configuration()).unwrap();
+        let mut updated = configuration();
+        updated.update_settings(&fields).unwrap();
+        assert_eq!(updated, configuration().with_brightness(20).with_dialect(Dialect::HalfHour));
+
+        let fields = config_update_to_fields(r#"{"night_mode": null}"#, &configuration()).unwrap();
+        updated.update_settings(&fields).unwrap();
+        assert_eq!(updated.get_night_start(), None);
+        assert_eq!(updated.get_brightness(), Some(60));
+
+        let fields = config_update_to_fields(r#"{"hostname": "kitchen"}"#, &configuration()).unwrap();
+        updated.update_settings(&fields).unwrap();
+        assert_eq!(updated.get_hostname(), Some(String::from("kitchen")));
+        assert_eq!(updated.get_brightness(), Some(60));
+    }
+
+    #[test]
+    fn mqtt_update() {
+        let mut updated = configuration();
+        let fields = config_update_to_fields(r#"{"mqtt": {"host": "broker.lan", "username": "clock"}}"#, &updated).unwrap();
+        updated.update_settings(&fields).unwrap();
+        assert_eq!(updated.get_mqtt_broker(), Some(MqttBroker { username: String::from("clock"), ..MqttBroker::new("broker.lan") }));
+
+        // The password set on the settings page is kept, and never served
+        let broker = MqttBroker { username: String::from("clock"), password: String::from("pass"), ..MqttBroker::new("broker.lan") };
+        updated = updated.with_mqtt_broker(Some(broker.clone()));
+        let fields = config_update_to_fields(r#"{"mqtt": {"port": 8883}}"#, &updated).unwrap();
+        updated.update_settings(&fields).unwrap();
+        assert_eq!(updated.get_mqtt_broker(), Some(MqttBroker { port: 8883, ..broker }));
+        assert_eq!(
+            serde_json::to_value(config_to_api(&updated).unwrap()).unwrap()["mqtt"],
+            json!({"host": "broker.lan", "port": 8883, "username": "clock"})
+        );
+
+        let fields = config_update_to_fields(r#"{"mqtt": null}"#, &updated).unwrap();
+        updated.update_settings(&fields).unwrap();
+        assert_eq!(updated.get_mqtt_broker(), None);
+    }
+
+    #[test]
+    fn invalid_update() {
+        for body in [r#"{"password": "x"}"#, r#"{"mqtt": {"password": "x"}}"#, r#"{"brightness": 300}"#, "not json"] {
+            assert!(config_update_to_fields(body, &configuration()).is_err(), "{}", body);
+        }
+        let state = configured_state();
+        assert_eq!(handle_api_request(&state, "PUT", "/api/config", JSON, None, b"[1, 2]").status, 400);
+        assert_eq!(state.lock().unwrap().settings_fields, None);
+    }
+
+    #[test]
+    fn update_is_validated_by_the_application() {
+        let state = configured_state();
+        thread::scope(|scope| {
+            scope.spawn(|| loop {
+                let mut state = state.lock().unwrap();
+                if state.settings_fields.take().is_some() {
+                    state.settings_errors = Some(vec![FieldError::InvalidBrightness]);
+                    break;
+                }
+                drop(state);
+                thread::sleep(Duration::from_millis(10));
+            });
+
+            let response = handle_api_request(&state, "PUT", "/api/config", JSON, None, br#"{"brightness": 0}"#);
+            assert_eq!(response.status, 422);
+            assert_eq!(json_body(&response)["errors"][0]["field"], "brightness");
+        });
+    }
+}
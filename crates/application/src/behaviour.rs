@@ -2,7 +2,13 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
-use log::info;
+use std::fmt::Write;
+use std::mem::discriminant;
+
+use log::{info, warn};
+
+use crate::error_recovery::ErrorKind;
+use crate::menu::{menu_actions, MenuNavigator, MenuOutcome, DEVICE_MENU};
 
 /// Possible state of the device
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -11,17 +17,19 @@ pub enum State {
     Startup,
     DisplayTime,
     Configuration,
-    MenuFota,
-    MenuCleanConfig,
-    MenuExit,
+    Menu,
     Fota,
     CleanConfig,
     NightMode,
+    /// Display turned off on request, the clock keeps running.
+    DisplayOff,
+    /// Notification shown over the time, for a while.
+    Notification,
     Error,
 }
 
 /// Possible event that may trigger a state transition of the device.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Event {
     Init,
     Start,
@@ -30,59 +38,434 @@ pub enum Event {
     Tick,
     EnterShortPush,
     EnterLongPush,
+    EnterDoublePush,
+    UpShortPush,
+    UpLongPush,
+    DownShortPush,
+    DownLongPush,
     Night,
     Day,
-    Error,
+    Error(ErrorKind),
+    /// Retry the startup sequence after an error.
+    Retry,
+    /// Resume the time display with the board RTC time after an error.
+    FallbackToRtc,
+    /// Go back to configuration mode after an error.
+    Reconfigure,
+    /// Firmware update requested through the REST API.
+    FirmwareUpdateRequest,
+    /// No newer firmware to install.
+    FirmwareUpToDate,
+    /// Display turned on or off through the REST API or MQTT.
+    TurnOn,
+    TurnOff,
+    /// Notification requested through the REST API or MQTT.
+    Notify,
+    /// Notification shown for its whole duration.
+    NotificationDone,
+    /// No configuration received in time, retry the stored one.
+    ConfigurationTimeout,
+}
+
+/// Action carried out by the application when entering, leaving or staying in a state.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StateAction {
+    Startup,
+    Configuration,
+    StartConfiguration,
+    CancelConfigurationTimeout,
+    DisplayTime,
+    NightMode,
+    DrawMenu,
+    FirmwareUpdate,
+    CleanConfig,
+    DrawError,
+    StartRecovery,
+    RecoveryTick,
+    ClearDisplay,
+    /// Apply the settings submitted from the settings page.
+    ApplySettings,
+    /// Carry out the actions requested through the REST API or MQTT.
+    HandleActions,
+    StartNotification,
+    /// Draw the current frame of the notification pattern.
+    DrawNotification,
+}
+
+/// Condition that must hold for a transition to be taken.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Guard {
+    /// Going back in the menu leaves the root menu.
+    MenuExits,
+    /// The current state was entered from the given state.
+    EnteredFrom(State),
+}
+
+/// Effect of a transition on the menu navigation.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MenuEffect {
+    Reset,
+    Next,
+    Previous,
+    Select,
+    Back,
+}
+
+/// Row of the transition table
+pub struct Transition {
+    /// Source state, `None` matches any state.
+    pub from: Option<State>,
+    /// Triggering event. Only the variant is compared, not the payload.
+    pub event: Event,
+    pub guard: Option<Guard>,
+    /// Target state, `None` for an internal transition.
+    pub to: Option<State>,
+    /// Effect on the menu. `MenuEffect::Select` goes to the target state of
+    /// the menu action it triggers, if any.
+    pub effect: Option<MenuEffect>,
+    /// Actions of the transition, run before the entry actions of the target.
+    /// Internal transitions without actions run the do-actions of the state.
+    pub actions: &'static [StateAction],
+}
+
+/// Actions attached to a state
+pub struct StateActions {
+    pub state: State,
+    pub entry: &'static [StateAction],
+    pub exit: &'static [StateAction],
+    /// Executed on internal transitions.
+    pub do_actions: &'static [StateAction],
 }
 
+const fn transition(from: State, event: Event, to: State) -> Transition {
+    Transition { from: Some(from), event, guard: None, to: Some(to), effect: None, actions: &[] }
+}
+
+const fn internal(from: State, event: Event, effect: Option<MenuEffect>) -> Transition {
+    Transition { from: Some(from), event, guard: None, to: None, effect, actions: &[] }
+}
+
+const fn internal_action(from: State, event: Event, actions: &'static [StateAction]) -> Transition {
+    Transition { from: Some(from), event, guard: None, to: None, effect: None, actions }
+}
+
+const fn guarded(from: State, event: Event, guard: Guard, to: State) -> Transition {
+    Transition { from: Some(from), event, guard: Some(guard), to: Some(to), effect: None, actions: &[] }
+}
+
+/// Transition table of the device, evaluated in order. The first matching row is taken.
+/// Events without matching row are ignored.
+pub const TRANSITIONS: &[Transition] = &[
+    transition(State::Initial, Event::Init, State::Startup),
+    transition(State::Startup, Event::InvalidConfiguration, State::Configuration),
+    transition(State::Startup, Event::Start, State::DisplayTime),
+    transition(State::Configuration, Event::ValidConfiguration, State::Startup),
+    internal(State::Configuration, Event::Tick, None),
+    transition(State::Configuration, Event::ConfigurationTimeout, State::Startup),
+    internal_action(State::Configuration, Event::EnterShortPush, &[StateAction::CancelConfigurationTimeout]),
+    internal(State::DisplayTime, Event::Tick, None),
+    Transition {
+        from: Some(State::DisplayTime),
+        event: Event::EnterShortPush,
+        guard: None,
+        to: Some(State::Menu),
+        effect: Some(MenuEffect::Reset),
+        actions: &[],
+    },
+    Transition {
+        from: Some(State::DisplayTime),
+        event: Event::EnterLongPush,
+        guard: None,
+        to: Some(State::Menu),
+        effect: Some(MenuEffect::Reset),
+        actions: &[],
+    },
+    transition(State::DisplayTime, Event::Night, State::NightMode),
+    transition(State::DisplayTime, Event::FirmwareUpdateRequest, State::Fota),
+    transition(State::DisplayTime, Event::TurnOff, State::DisplayOff),
+    transition(State::DisplayTime, Event::Notify, State::Notification),
+    internal(State::Menu, Event::EnterShortPush, Some(MenuEffect::Next)),
+    internal(State::Menu, Event::DownShortPush, Some(MenuEffect::Next)),
+    internal(State::Menu, Event::UpShortPush, Some(MenuEffect::Previous)),
+    internal(State::Menu, Event::EnterLongPush, Some(MenuEffect::Select)),
+    guarded(State::Menu, Event::EnterDoublePush, Guard::MenuExits, State::DisplayTime),
+    internal(State::Menu, Event::EnterDoublePush, Some(MenuEffect::Back)),
+    guarded(State::Fota, Event::FirmwareUpToDate, Guard::EnteredFrom(State::NightMode), State::NightMode),
+    guarded(State::Fota, Event::FirmwareUpToDate, Guard::EnteredFrom(State::DisplayOff), State::DisplayOff),
+    transition(State::Fota, Event::FirmwareUpToDate, State::DisplayTime),
+    transition(State::CleanConfig, Event::InvalidConfiguration, State::Startup),
+    internal(State::NightMode, Event::Tick, None),
+    transition(State::NightMode, Event::Day, State::DisplayTime),
+    transition(State::NightMode, Event::FirmwareUpdateRequest, State::Fota),
+    transition(State::NightMode, Event::TurnOff, State::DisplayOff),
+    internal(State::DisplayOff, Event::Tick, None),
+    transition(State::DisplayOff, Event::TurnOn, State::DisplayTime),
+    transition(State::DisplayOff, Event::EnterShortPush, State::DisplayTime),
+    transition(State::DisplayOff, Event::FirmwareUpdateRequest, State::Fota),
+    internal(State::Notification, Event::Tick, None),
+    transition(State::Notification, Event::NotificationDone, State::DisplayTime),
+    transition(State::Notification, Event::EnterShortPush, State::DisplayTime),
+    transition(State::Notification, Event::FirmwareUpdateRequest, State::Fota),
+    transition(State::Notification, Event::TurnOff, State::DisplayOff),
+    internal(State::Error, Event::Tick, None),
+    transition(State::Error, Event::Retry, State::Startup),
+    transition(State::Error, Event::FallbackToRtc, State::DisplayTime),
+    transition(State::Error, Event::Reconfigure, State::Configuration),
+    Transition {
+        from: None,
+        event: Event::Error(ErrorKind::Hardware),
+        guard: None,
+        to: Some(State::Error),
+        effect: None,
+        actions: &[],
+    },
+];
+
+/// Entry, exit and do-actions of every state.
+pub const STATE_ACTIONS: &[StateActions] = &[
+    StateActions { state: State::Initial, entry: &[], exit: &[], do_actions: &[] },
+    StateActions { state: State::Startup, entry: &[StateAction::Startup], exit: &[], do_actions: &[] },
+    StateActions {
+        state: State::Configuration,
+        entry: &[StateAction::StartConfiguration],
+        exit: &[],
+        do_actions: &[StateAction::Configuration],
+    },
+    StateActions {
+        state: State::DisplayTime,
+        entry: &[StateAction::DisplayTime],
+        exit: &[],
+        do_actions: &[StateAction::ApplySettings, StateAction::HandleActions, StateAction::DisplayTime],
+    },
+    StateActions {
+        state: State::Menu,
+        entry: &[StateAction::DrawMenu],
+        exit: &[StateAction::ClearDisplay],
+        do_actions: &[StateAction::DrawMenu],
+    },
+    StateActions { state: State::Fota, entry: &[StateAction::FirmwareUpdate], exit: &[], do_actions: &[] },
+    StateActions { state: State::CleanConfig, entry: &[StateAction::CleanConfig], exit: &[], do_actions: &[] },
+    StateActions {
+        state: State::NightMode,
+        entry: &[StateAction::ClearDisplay, StateAction::NightMode],
+        exit: &[],
+        do_actions: &[StateAction::ApplySettings, StateAction::HandleActions, StateAction::NightMode],
+    },
+    StateActions {
+        state: State::DisplayOff,
+        entry: &[StateAction::ClearDisplay],
+        exit: &[],
+        do_actions: &[StateAction::ApplySettings, StateAction::HandleActions],
+    },
+    StateActions {
+        state: State::Notification,
+        entry: &[StateAction::StartNotification],
+        exit: &[],
+        do_actions: &[StateAction::ApplySettings, StateAction::HandleActions, StateAction::DrawNotification],
+    },
+    StateActions {
+        state: State::Error,
+        entry: &[StateAction::DrawError, StateAction::StartRecovery],
+        exit: &[],
+        do_actions: &[StateAction::RecoveryTick],
+    },
+];
+
 /// Device state-machine implementation
 pub struct Behaviour {
     state: State,
+    /// State left by the last transition to another state
+    previous_state: State,
+    menu: MenuNavigator,
+    last_error: Option<ErrorKind>,
 }
 
 impl Behaviour {
     pub fn new() -> Self {
         Self {
             state: State::Initial,
+            previous_state: State::Initial,
+            menu: MenuNavigator::new(DEVICE_MENU),
+            last_error: None,
         }
     }
 
     /// React to a given event
-    pub fn handle_event(&mut self, event: Event) {
-        let old_state = self.state;
-        match (&self.state, event) {
-            (State::Initial, Event::Init) => self.state = State::Startup,
-            (State::Startup, Event::InvalidConfiguration) => self.state = State::Configuration,
-            (State::Startup, Event::Start) => self.state = State::DisplayTime,
-            (State::Configuration, Event::ValidConfiguration) => self.state = State::Startup,
-            (State::Configuration, Event::Tick) => (),
-            (State::DisplayTime, Event::Tick) => (),
-            (State::DisplayTime, Event::EnterShortPush) => self.state = State::MenuFota,
-            (State::DisplayTime, Event::EnterLongPush) => self.state = State::MenuFota,
-            (State::DisplayTime, Event::Night) => self.state = State::NightMode,
-            (State::MenuFota, Event::EnterShortPush) => self.state = State::MenuCleanConfig,
-            (State::MenuFota, Event::EnterLongPush) => self.state = State::Fota,
-            (State::MenuFota, _) => (),
-            (State::MenuCleanConfig, Event::EnterShortPush) => self.state = State::MenuExit,
-            (State::MenuCleanConfig, Event::EnterLongPush) => self.state = State::CleanConfig,
-            (State::MenuCleanConfig, _) => (),
-            (State::MenuExit, Event::EnterShortPush) => self.state = State::MenuFota,
-            (State::MenuExit, Event::EnterLongPush) => self.state = State::DisplayTime,
-            (State::Fota, _) => (),
-            (State::CleanConfig, Event::InvalidConfiguration) => self.state = State::Startup,
-            (State::NightMode, Event::Day) => self.state = State::DisplayTime,
-            (_, Event::Error) => self.state = State::Error,
-            (_, _) => self.state = State::Error,
-        }
-
-        if old_state != self.state {
-            info!("{:?} -> {:?}", old_state, self.state);
+    ///
+    /// Return the actions to be carried out by the application, in order.
+    pub fn handle_event(&mut self, event: Event) -> Vec<StateAction> {
+        if let Event::Error(kind) = event {
+            self.last_error = Some(kind);
         }
+
+        let Some(transition) = TRANSITIONS.iter().find(|transition| self.matches(transition, &event)) else {
+            warn!("Ignore {:?} in {:?}", event, self.state);
+            return Vec::new();
+        };
+
+        let mut to = transition.to;
+        match transition.effect {
+            Some(MenuEffect::Reset) => self.menu.reset(),
+            Some(MenuEffect::Next) => _ = self.menu.select_next(),
+            Some(MenuEffect::Previous) => _ = self.menu.select_previous(),
+            Some(MenuEffect::Select) => {
+                if let MenuOutcome::Action(target) = self.menu.select() {
+                    to = Some(target);
+                }
+            }
+            Some(MenuEffect::Back) => _ = self.menu.back(),
+            None => (),
+        }
+
+        let mut actions = Vec::new();
+        match to {
+            Some(target) => {
+                actions.extend_from_slice(state_actions(self.state).exit);
+                actions.extend_from_slice(transition.actions);
+                info!("{:?} -> {:?}", self.state, target);
+                self.previous_state = self.state;
+                self.state = target;
+                actions.extend_from_slice(state_actions(self.state).entry);
+            }
+            None if !transition.actions.is_empty() => actions.extend_from_slice(transition.actions),
+            None => actions.extend_from_slice(state_actions(self.state).do_actions),
+        }
+
+        actions
     }
 
     pub fn current_state(&self) -> State {
         self.state
     }
+
+    /// Reason of the last error event
+    pub fn last_error(&self) -> Option<ErrorKind> {
+        self.last_error
+    }
+
+    /// Navigation state of the menu, only relevant in `State::Menu`.
+    pub fn menu(&self) -> &MenuNavigator {
+        &self.menu
+    }
+
+    fn matches(&self, transition: &Transition, event: &Event) -> bool {
+        if transition.from.is_some_and(|from| from != self.state) {
+            return false;
+        }
+        if discriminant(&transition.event) != discriminant(event) {
+            return false;
+        }
+
+        match transition.guard {
+            Some(Guard::MenuExits) => self.menu.would_exit(),
+            Some(Guard::EnteredFrom(state)) => self.previous_state == state,
+            None => true,
+        }
+    }
+}
+
+impl Default for Behaviour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn state_actions(state: State) -> &'static StateActions {
+    STATE_ACTIONS
+        .iter()
+        .find(|actions| actions.state == state)
+        .expect("Every state has an entry in STATE_ACTIONS")
+}
+
+/// Name of the event variant, without payload.
+fn event_name(event: &Event) -> String {
+    let name = format!("{:?}", event);
+    match name.find('(') {
+        Some(index) => name[..index].to_string(),
+        None => name,
+    }
+}
+
+fn guard_label(guard: Guard) -> String {
+    match guard {
+        Guard::MenuExits => String::from("menu exits"),
+        Guard::EnteredFrom(state) => format!("entered from {:?}", state),
+    }
+}
+
+fn actions_label(actions: &[StateAction]) -> String {
+    actions.iter().map(|action| format!("{:?}", action)).collect::<Vec<_>>().join(", ")
+}
+
+/// Render the transition table as a PlantUML state diagram.
+///
+/// The diagram stored in `doc/uml/1_problem_description/use_case/system_state.puml`
+/// is generated with this function, run `cargo xtask state_uml` to update it.
+pub fn state_diagram_plantuml() -> String {
+    let mut uml = String::new();
+    let states: Vec<State> = STATE_ACTIONS
+        .iter()
+        .map(|actions| actions.state)
+        .filter(|state| *state != State::Initial)
+        .collect();
+
+    // Writing to a String can't fail, the results are ignored.
+    let _ = writeln!(uml, "@startuml\n");
+    let _ = writeln!(uml, "' Generated from the `Behaviour` transition table, do not edit manually.");
+    let _ = writeln!(uml, "' Run `cargo xtask state_uml` to update this file.\n");
+    let _ = writeln!(uml, "mainframe System state\n");
+
+    for state in &states {
+        let actions = state_actions(*state);
+        let _ = writeln!(uml, "state {:?}", state);
+        if !actions.entry.is_empty() {
+            let _ = writeln!(uml, "{:?} : entry / {}", state, actions_label(actions.entry));
+        }
+        if !actions.exit.is_empty() {
+            let _ = writeln!(uml, "{:?} : exit / {}", state, actions_label(actions.exit));
+        }
+        if !actions.do_actions.is_empty() {
+            let _ = writeln!(uml, "{:?} : do / {}", state, actions_label(actions.do_actions));
+        }
+    }
+    let _ = writeln!(uml);
+
+    for transition in TRANSITIONS {
+        let mut label = event_name(&transition.event);
+        if let Some(guard) = transition.guard {
+            let _ = write!(label, " [{}]", guard_label(guard));
+        }
+        if let Some(effect) = transition.effect {
+            let _ = write!(label, " / Menu{:?}", effect);
+        }
+        if !transition.actions.is_empty() {
+            let _ = write!(label, " / {}", actions_label(transition.actions));
+        }
+
+        let sources = match transition.from {
+            Some(from) => vec![from],
+            None => states.clone(),
+        };
+        for from in sources {
+            let source = match from {
+                State::Initial => String::from("[*]"),
+                _ => format!("{:?}", from),
+            };
+            match transition.to {
+                Some(to) => _ = writeln!(uml, "{} --> {:?} : {}", source, to, label),
+                None => _ = writeln!(uml, "{} : {}", source, label),
+            }
+            if transition.effect == Some(MenuEffect::Select) {
+                for (action, target) in menu_actions(DEVICE_MENU) {
+                    let event = event_name(&transition.event);
+                    let _ = writeln!(uml, "{} --> {:?} : {} [menu triggers {}] / MenuSelect", source, target, event, action);
+                }
+            }
+        }
+    }
+
+    let _ = writeln!(uml, "\n@enduml");
+    uml
 }
 
 #[cfg(test)]
@@ -106,4 +489,199 @@ mod tests {
         state_machine.handle_event(Event::Start);
         assert_eq!(state_machine.state, State::DisplayTime);
     }
+
+    #[test]
+    fn entry_and_exit_actions() {
+        let mut state_machine = Behaviour::new();
+        assert_eq!(state_machine.handle_event(Event::Init), vec![StateAction::Startup]);
+        assert_eq!(state_machine.handle_event(Event::Start), vec![StateAction::DisplayTime]);
+        assert_eq!(state_machine.handle_event(Event::EnterShortPush), vec![StateAction::DrawMenu]);
+
+        // Third menu entry leaves the menu
+        state_machine.handle_event(Event::EnterShortPush);
+        state_machine.handle_event(Event::EnterShortPush);
+        assert_eq!(
+            state_machine.handle_event(Event::EnterLongPush),
+            vec![StateAction::ClearDisplay, StateAction::DisplayTime]
+        );
+        assert_eq!(state_machine.state, State::DisplayTime);
+    }
+
+    #[test]
+    fn do_actions_on_internal_transition() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        state_machine.handle_event(Event::Start);
+
+        assert_eq!(
+            state_machine.handle_event(Event::Tick),
+            vec![StateAction::ApplySettings, StateAction::HandleActions, StateAction::DisplayTime]
+        );
+        assert_eq!(state_machine.state, State::DisplayTime);
+    }
+
+    #[test]
+    fn requested_firmware_update() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        state_machine.handle_event(Event::Start);
+
+        assert_eq!(state_machine.handle_event(Event::FirmwareUpdateRequest), vec![StateAction::FirmwareUpdate]);
+        assert_eq!(state_machine.state, State::Fota);
+
+        assert_eq!(state_machine.handle_event(Event::FirmwareUpToDate), vec![StateAction::DisplayTime]);
+        assert_eq!(state_machine.state, State::DisplayTime);
+
+        // Back to the state the update was requested from
+        state_machine.handle_event(Event::TurnOff);
+        state_machine.handle_event(Event::FirmwareUpdateRequest);
+        assert_eq!(state_machine.handle_event(Event::FirmwareUpToDate), vec![StateAction::ClearDisplay]);
+        assert_eq!(state_machine.state, State::DisplayOff);
+    }
+
+    #[test]
+    fn display_turned_off_and_on() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        state_machine.handle_event(Event::Start);
+
+        assert_eq!(state_machine.handle_event(Event::TurnOff), vec![StateAction::ClearDisplay]);
+        assert_eq!(state_machine.state, State::DisplayOff);
+        assert_eq!(
+            state_machine.handle_event(Event::Tick),
+            vec![StateAction::ApplySettings, StateAction::HandleActions]
+        );
+        assert_eq!(state_machine.handle_event(Event::TurnOn), vec![StateAction::DisplayTime]);
+        assert_eq!(state_machine.state, State::DisplayTime);
+
+        // A push on the button turns the display on too
+        state_machine.handle_event(Event::TurnOff);
+        state_machine.handle_event(Event::EnterShortPush);
+        assert_eq!(state_machine.state, State::DisplayTime);
+    }
+
+    #[test]
+    fn notification_preempts_time() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        state_machine.handle_event(Event::Start);
+
+        assert_eq!(state_machine.handle_event(Event::Notify), vec![StateAction::StartNotification]);
+        assert_eq!(state_machine.state, State::Notification);
+        assert_eq!(
+            state_machine.handle_event(Event::Tick),
+            vec![StateAction::ApplySettings, StateAction::HandleActions, StateAction::DrawNotification]
+        );
+        assert_eq!(state_machine.handle_event(Event::NotificationDone), vec![StateAction::DisplayTime]);
+        assert_eq!(state_machine.state, State::DisplayTime);
+
+        // Dismissed with a push on the button
+        state_machine.handle_event(Event::Notify);
+        state_machine.handle_event(Event::EnterShortPush);
+        assert_eq!(state_machine.state, State::DisplayTime);
+
+        // Not shown in night mode
+        state_machine.handle_event(Event::Night);
+        assert!(state_machine.handle_event(Event::Notify).is_empty());
+        assert_eq!(state_machine.state, State::NightMode);
+    }
+
+    #[test]
+    fn unexpected_event_is_ignored() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+
+        assert!(state_machine.handle_event(Event::Tick).is_empty());
+        assert!(state_machine.handle_event(Event::EnterLongPush).is_empty());
+        assert_eq!(state_machine.state, State::Startup);
+    }
+
+    #[test]
+    fn error_from_any_state() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        state_machine.handle_event(Event::Start);
+
+        assert_eq!(
+            state_machine.handle_event(Event::Error(ErrorKind::WifiNotFound)),
+            vec![StateAction::DrawError, StateAction::StartRecovery]
+        );
+        assert_eq!(state_machine.state, State::Error);
+        assert_eq!(state_machine.last_error(), Some(ErrorKind::WifiNotFound));
+    }
+
+    #[test]
+    fn configuration_timeout() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        assert_eq!(
+            state_machine.handle_event(Event::InvalidConfiguration),
+            vec![StateAction::StartConfiguration]
+        );
+        assert_eq!(
+            state_machine.handle_event(Event::EnterShortPush),
+            vec![StateAction::CancelConfigurationTimeout]
+        );
+        assert_eq!(state_machine.handle_event(Event::Tick), vec![StateAction::Configuration]);
+
+        state_machine.handle_event(Event::ConfigurationTimeout);
+        assert_eq!(state_machine.state, State::Startup);
+    }
+
+    #[test]
+    fn recover_from_error() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        state_machine.handle_event(Event::Error(ErrorKind::TimeSync));
+
+        assert_eq!(state_machine.handle_event(Event::Tick), vec![StateAction::RecoveryTick]);
+        state_machine.handle_event(Event::Retry);
+        assert_eq!(state_machine.state, State::Startup);
+
+        state_machine.handle_event(Event::Error(ErrorKind::TimeSync));
+        state_machine.handle_event(Event::FallbackToRtc);
+        assert_eq!(state_machine.state, State::DisplayTime);
+
+        state_machine.handle_event(Event::Error(ErrorKind::WifiAuth));
+        state_machine.handle_event(Event::Reconfigure);
+        assert_eq!(state_machine.state, State::Configuration);
+    }
+
+    #[test]
+    fn guarded_menu_transitions() {
+        let mut state_machine = Behaviour::new();
+        state_machine.handle_event(Event::Init);
+        state_machine.handle_event(Event::Start);
+        state_machine.handle_event(Event::EnterShortPush);
+        state_machine.handle_event(Event::EnterShortPush);
+
+        // Clean config asks for a confirmation first
+        assert_eq!(state_machine.handle_event(Event::EnterLongPush), vec![StateAction::DrawMenu]);
+        assert_eq!(state_machine.state, State::Menu);
+        state_machine.handle_event(Event::EnterLongPush);
+        assert_eq!(state_machine.state, State::CleanConfig);
+    }
+
+    #[test]
+    fn every_state_has_actions() {
+        for transition in TRANSITIONS {
+            if let Some(to) = transition.to {
+                state_actions(to);
+            }
+        }
+        for (_, target) in menu_actions(DEVICE_MENU) {
+            state_actions(target);
+        }
+    }
+
+    #[test]
+    fn plantuml_export() {
+        let uml = state_diagram_plantuml();
+        assert!(uml.starts_with("@startuml"));
+        assert!(uml.contains("[*] --> Startup : Init\n"));
+        assert!(uml.contains("Menu --> Fota : EnterLongPush [menu triggers firmware_update] / MenuSelect\n"));
+        assert!(uml.contains("Menu : EnterShortPush / MenuNext\n"));
+        assert!(uml.contains("NightMode --> Error : Error\n"));
+        assert!(uml.contains("DisplayTime : entry / DisplayTime\n"));
+    }
 }
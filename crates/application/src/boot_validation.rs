@@ -0,0 +1,181 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use anyhow::Result;
+use log::*;
+use serde::Serialize;
+
+use crate::configuration::PersistentStorage;
+use crate::firmware_update::FirmwareUpdate;
+use crate::version::Version;
+
+/// Version of the installed image, until it is confirmed
+const PENDING_IMAGE_KEY: &str = "ota_pending";
+/// Version of the last image rolled back
+const ROLLED_BACK_KEY: &str = "ota_rolled_back";
+
+/// Validation of the running firmware image, shown in the status
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ImageState {
+    /// Image confirmed by the health checkpoint, or flashed over USB
+    Valid,
+    /// Updated image, booted once and not confirmed yet
+    Pending,
+    /// The update to `version` failed, the previous image is running
+    RolledBack { version: String },
+}
+
+/// Load a stored string, a missing key is empty.
+fn load(storage: &mut impl PersistentStorage, key: &str) -> String {
+    storage.load_string(key).unwrap_or_default()
+}
+
+/// Record `version` as installed, to name it if the bootloader rolls it back.
+///
+/// # Errors
+/// The storage fails.
+pub fn mark_installed(storage: &mut impl PersistentStorage, version: &Version) -> Result<()> {
+    storage.store_string(ROLLED_BACK_KEY, "")?;
+    storage.store_string(PENDING_IMAGE_KEY, &version.to_string())
+}
+
+/// State of the `running` image, `pending_verify` if the bootloader waits for
+/// its confirmation.
+///
+/// The bootloader boots an updated image once. It boots the previous image on
+/// the next reset, unless the updated one is confirmed. If another image than
+/// the installed one is running, the update was rolled back.
+///
+/// # Errors
+/// The storage fails.
+pub fn check_boot(storage: &mut impl PersistentStorage, running: &Version, pending_verify: bool) -> Result<ImageState> {
+    let pending = load(storage, PENDING_IMAGE_KEY);
+    if pending.is_empty() {
+        let rolled_back = load(storage, ROLLED_BACK_KEY);
+        if rolled_back.is_empty() {
+            return Ok(ImageState::Valid);
+        }
+        return Ok(ImageState::RolledBack { version: rolled_back });
+    }
+
+    if Version::from_string(&pending).ok().as_ref() != Some(running) {
+        warn!("Update to {} failed, running {}", pending, running);
+        record_rollback(storage, &pending)?;
+        return Ok(ImageState::RolledBack { version: pending });
+    }
+
+    if !pending_verify {
+        // Confirmed to the bootloader before the record, or booted by a
+        // bootloader without rollback
+        storage.store_string(PENDING_IMAGE_KEY, "")?;
+        return Ok(ImageState::Valid);
+    }
+    info!("Image {} pending, rolled back on the next reset until confirmed", pending);
+    Ok(ImageState::Pending)
+}
+
+fn record_rollback(storage: &mut impl PersistentStorage, version: &str) -> Result<()> {
+    storage.store_string(ROLLED_BACK_KEY, version)?;
+    storage.store_string(PENDING_IMAGE_KEY, "")
+}
+
+/// Return the state of the `running` image, as validated by the bootloader.
+pub fn validate_boot(storage: &mut impl PersistentStorage, firmware_update: &impl FirmwareUpdate, running: &Version) -> ImageState {
+    match check_boot(storage, running, firmware_update.is_image_pending()) {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Failed to validate the boot: {}", e);
+            ImageState::Valid
+        }
+    }
+}
+
+/// Keep the running image on the next boots, once it passed the health
+/// checkpoint.
+///
+/// # Errors
+/// The bootloader state or the storage can't be written.
+pub fn confirm(storage: &mut impl PersistentStorage, firmware_update: &impl FirmwareUpdate) -> Result<()> {
+    firmware_update.confirm_image()?;
+    storage.store_string(PENDING_IMAGE_KEY, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use anyhow::anyhow;
+
+    #[derive(Default)]
+    struct Storage(HashMap<String, String>);
+
+    impl PersistentStorage for Storage {
+        fn load_string(&mut self, key: &str) -> Result<String> {
+            self.0.get(key).cloned().ok_or_else(|| anyhow!("Missing key {}", key))
+        }
+
+        fn store_string(&mut self, key: &str, value: &str) -> Result<()> {
+            self.0.insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+    }
+
+    fn version(version: &str) -> Version {
+        Version::from_string(version).unwrap()
+    }
+
+    #[test]
+    fn image_without_update_is_valid() {
+        let mut storage = Storage::default();
+        assert_eq!(check_boot(&mut storage, &version("2.0.1"), false).unwrap(), ImageState::Valid);
+        assert_eq!(check_boot(&mut storage, &version("2.0.1"), false).unwrap(), ImageState::Valid);
+    }
+
+    #[test]
+    fn installed_image_is_pending() {
+        let mut storage = Storage::default();
+        mark_installed(&mut storage, &version("2.1.0")).unwrap();
+
+        assert_eq!(check_boot(&mut storage, &version("v2.1.0"), true).unwrap(), ImageState::Pending);
+        storage.store_string(PENDING_IMAGE_KEY, "").unwrap();
+        assert_eq!(check_boot(&mut storage, &version("2.1.0"), false).unwrap(), ImageState::Valid);
+    }
+
+    #[test]
+    fn image_confirmed_without_record_is_valid() {
+        let mut storage = Storage::default();
+        mark_installed(&mut storage, &version("2.1.0")).unwrap();
+
+        assert_eq!(check_boot(&mut storage, &version("2.1.0"), false).unwrap(), ImageState::Valid);
+        assert_eq!(load(&mut storage, PENDING_IMAGE_KEY), "");
+    }
+
+    #[test]
+    fn image_rolled_back_by_the_bootloader() {
+        let mut storage = Storage::default();
+        mark_installed(&mut storage, &version("2.1.0")).unwrap();
+        assert_eq!(check_boot(&mut storage, &version("2.1.0"), true).unwrap(), ImageState::Pending);
+
+        // Reset before the confirmation, the previous image runs
+        let rolled_back = ImageState::RolledBack { version: String::from("v2.1.0") };
+        assert_eq!(check_boot(&mut storage, &version("2.0.1"), false).unwrap(), rolled_back);
+        assert_eq!(check_boot(&mut storage, &version("2.0.1"), false).unwrap(), rolled_back);
+
+        // Until the next update
+        mark_installed(&mut storage, &version("2.1.1")).unwrap();
+        assert_eq!(check_boot(&mut storage, &version("2.1.1"), true).unwrap(), ImageState::Pending);
+    }
+
+    #[test]
+    fn image_state_in_json() {
+        assert_eq!(serde_json::to_string(&ImageState::Valid).unwrap(), r#"{"state":"valid"}"#);
+        assert_eq!(serde_json::to_string(&ImageState::Pending).unwrap(), r#"{"state":"pending"}"#);
+        assert_eq!(
+            serde_json::to_string(&ImageState::RolledBack { version: String::from("v2.1.0") }).unwrap(),
+            r#"{"state":"rolled_back","version":"v2.1.0"}"#
+        );
+    }
+}
@@ -0,0 +1,308 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::behaviour::Event;
+use crate::time_monotonic::TimeMonotonic;
+
+/// Time a raw level must stay stable before being accepted.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(30);
+/// Minimal press duration to be recognized as a long press.
+pub const DEFAULT_LONG_PRESS: Duration = Duration::from_millis(2000);
+/// Maximal delay between a release and the next press to form a double press.
+pub const DEFAULT_DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(300);
+
+/// Interface to read the raw level of a push-button
+pub trait PushButton {
+    /// Return `true` while the button is physically pressed.
+    fn is_pressed(&self) -> bool;
+}
+
+/// Push-buttons available on the device
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Button {
+    Enter,
+    Up,
+    Down,
+}
+
+/// Gestures recognized on a single push-button
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Gesture {
+    ShortPress,
+    LongPress,
+    DoublePress,
+}
+
+/// Timings used to recognize the gestures of a push-button
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ButtonTimings {
+    pub debounce: Duration,
+    pub long_press: Duration,
+    /// A zero window disables the double press detection. Short presses are
+    /// then reported on release, without waiting for a second press.
+    pub double_press_window: Duration,
+}
+
+impl Default for ButtonTimings {
+    fn default() -> Self {
+        Self {
+            debounce: DEFAULT_DEBOUNCE,
+            long_press: DEFAULT_LONG_PRESS,
+            double_press_window: DEFAULT_DOUBLE_PRESS_WINDOW,
+        }
+    }
+}
+
+/// Non-blocking gesture recognizer for a single push-button
+///
+/// The recognizer must be fed periodically with the raw button level and the
+/// current monotonic time. The raw level is debounced before being used.
+/// - A long press is reported as soon as the button is held for `long_press`,
+///   without waiting for the release.
+/// - A short press is reported once the `double_press_window` elapsed after
+///   the release, or directly on release if double press detection is disabled.
+/// - A double press is reported on the second release.
+pub struct GestureRecognizer {
+    timings: ButtonTimings,
+    raw_pressed: bool,
+    raw_changed_at: Option<Instant>,
+    pressed: bool,
+    pressed_at: Option<Instant>,
+    long_press_reported: bool,
+    pending_release_at: Option<Instant>,
+}
+
+impl GestureRecognizer {
+    pub fn new(timings: ButtonTimings) -> Self {
+        Self {
+            timings,
+            raw_pressed: false,
+            raw_changed_at: None,
+            pressed: false,
+            pressed_at: None,
+            long_press_reported: false,
+            pending_release_at: None,
+        }
+    }
+
+    /// Feed the recognizer with the raw button level sampled at `now`.
+    ///
+    /// Return the gesture completed by this sample, if any.
+    pub fn update(&mut self, raw_pressed: bool, now: Instant) -> Option<Gesture> {
+        if raw_pressed != self.raw_pressed {
+            self.raw_pressed = raw_pressed;
+            self.raw_changed_at = Some(now);
+        }
+
+        if let Some(changed_at) = self.raw_changed_at {
+            if self.raw_pressed != self.pressed && now - changed_at >= self.timings.debounce {
+                self.pressed = self.raw_pressed;
+                // Date the edge at the raw change, so the debounce doesn't shift the timings.
+                return if self.pressed {
+                    self.on_press(changed_at)
+                } else {
+                    self.on_release(changed_at)
+                };
+            }
+        }
+
+        if self.pressed {
+            if let Some(pressed_at) = self.pressed_at {
+                if !self.long_press_reported && now - pressed_at >= self.timings.long_press {
+                    self.long_press_reported = true;
+                    self.pending_release_at = None;
+                    return Some(Gesture::LongPress);
+                }
+            }
+        } else if let Some(released_at) = self.pending_release_at {
+            // A second press may still be bouncing, wait for it to settle.
+            if !self.raw_pressed && now - released_at >= self.timings.double_press_window {
+                self.pending_release_at = None;
+                return Some(Gesture::ShortPress);
+            }
+        }
+
+        None
+    }
+
+    fn on_press(&mut self, at: Instant) -> Option<Gesture> {
+        self.pressed_at = Some(at);
+        self.long_press_reported = false;
+        None
+    }
+
+    fn on_release(&mut self, at: Instant) -> Option<Gesture> {
+        self.pressed_at = None;
+        if self.long_press_reported {
+            return None;
+        }
+
+        if self.pending_release_at.take().is_some() {
+            Some(Gesture::DoublePress)
+        } else if self.timings.double_press_window.is_zero() {
+            Some(Gesture::ShortPress)
+        } else {
+            self.pending_release_at = Some(at);
+            None
+        }
+    }
+}
+
+struct RegisteredButton {
+    button: Button,
+    input: Box<dyn PushButton>,
+    recognizer: GestureRecognizer,
+}
+
+/// Translate the push-buttons gestures into application events
+///
+/// `poll()` never blocks and must be called periodically from the main loop,
+/// with a period smaller than the debounce time for best results.
+pub struct ButtonInput<T: TimeMonotonic> {
+    // The monotonic time is only public for testing
+    pub time_monotonic: T,
+    buttons: Vec<RegisteredButton>,
+}
+
+impl<T: TimeMonotonic> ButtonInput<T> {
+    pub fn new(time_monotonic: T) -> Self {
+        Self {
+            time_monotonic,
+            buttons: Vec::new(),
+        }
+    }
+
+    /// Register a push-button to be polled with the given timings.
+    pub fn add_button(&mut self, button: Button, input: Box<dyn PushButton>, timings: ButtonTimings) {
+        self.buttons.push(RegisteredButton {
+            button,
+            input,
+            recognizer: GestureRecognizer::new(timings),
+        });
+    }
+
+    /// Sample all registered buttons and return the generated events.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let now = self.time_monotonic.now();
+        let mut events = Vec::new();
+
+        for registered in self.buttons.iter_mut() {
+            let pressed = registered.input.is_pressed();
+            if let Some(gesture) = registered.recognizer.update(pressed, now) {
+                debug!("{:?} button {:?}", registered.button, gesture);
+                match gesture_to_event(registered.button, gesture) {
+                    Some(event) => events.push(event),
+                    None => warn!("No event for {:?} on {:?} button", gesture, registered.button),
+                }
+            }
+        }
+
+        events
+    }
+}
+
+fn gesture_to_event(button: Button, gesture: Gesture) -> Option<Event> {
+    match (button, gesture) {
+        (Button::Enter, Gesture::ShortPress) => Some(Event::EnterShortPush),
+        (Button::Enter, Gesture::LongPress) => Some(Event::EnterLongPush),
+        (Button::Enter, Gesture::DoublePress) => Some(Event::EnterDoublePush),
+        (Button::Up, Gesture::ShortPress) => Some(Event::UpShortPush),
+        (Button::Up, Gesture::LongPress) => Some(Event::UpLongPush),
+        (Button::Down, Gesture::ShortPress) => Some(Event::DownShortPush),
+        (Button::Down, Gesture::LongPress) => Some(Event::DownLongPush),
+        (_, Gesture::DoublePress) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replay a script of (raw level, time in ms) samples and collect the gestures.
+    fn replay(timings: ButtonTimings, script: &[(bool, u64)]) -> Vec<(Gesture, u64)> {
+        let start = Instant::now();
+        let mut recognizer = GestureRecognizer::new(timings);
+        let mut gestures = Vec::new();
+
+        for (pressed, ms) in script {
+            if let Some(gesture) = recognizer.update(*pressed, start + Duration::from_millis(*ms)) {
+                gestures.push((gesture, *ms));
+            }
+        }
+
+        gestures
+    }
+
+    #[test]
+    fn short_press_after_double_press_window() {
+        let gestures = replay(
+            ButtonTimings::default(),
+            &[(true, 0), (true, 40), (true, 100), (false, 150), (false, 190), (false, 400), (false, 460)],
+        );
+        assert_eq!(gestures, vec![(Gesture::ShortPress, 460)]);
+    }
+
+    #[test]
+    fn short_press_on_release_without_double_press() {
+        let timings = ButtonTimings {
+            double_press_window: Duration::ZERO,
+            ..Default::default()
+        };
+        let gestures = replay(timings, &[(true, 0), (true, 40), (false, 150), (false, 190)]);
+        assert_eq!(gestures, vec![(Gesture::ShortPress, 190)]);
+    }
+
+    #[test]
+    fn long_press_while_held() {
+        let gestures = replay(
+            ButtonTimings::default(),
+            &[(true, 0), (true, 40), (true, 1990), (true, 2010), (true, 3000), (false, 3100), (false, 3140), (false, 4000)],
+        );
+        assert_eq!(gestures, vec![(Gesture::LongPress, 2010)]);
+    }
+
+    #[test]
+    fn double_press() {
+        let gestures = replay(
+            ButtonTimings::default(),
+            &[(true, 0), (true, 40), (false, 100), (false, 140), (true, 250), (true, 290), (false, 350), (false, 390), (false, 1000)],
+        );
+        assert_eq!(gestures, vec![(Gesture::DoublePress, 390)]);
+    }
+
+    #[test]
+    fn bounces_are_filtered() {
+        let gestures = replay(
+            ButtonTimings::default(),
+            &[
+                (true, 0), (false, 5), (true, 10), (false, 15), (true, 20), (true, 60),
+                (false, 150), (true, 155), (false, 160), (false, 200), (false, 600),
+            ],
+        );
+        assert_eq!(gestures, vec![(Gesture::ShortPress, 600)]);
+    }
+
+    #[test]
+    fn glitch_shorter_than_debounce_is_ignored() {
+        let gestures = replay(
+            ButtonTimings::default(),
+            &[(true, 0), (false, 10), (false, 50), (false, 1000)],
+        );
+        assert!(gestures.is_empty());
+    }
+
+    #[test]
+    fn configurable_long_press() {
+        let timings = ButtonTimings {
+            long_press: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let gestures = replay(timings, &[(true, 0), (true, 40), (true, 510), (false, 600), (false, 640)]);
+        assert_eq!(gestures, vec![(Gesture::LongPress, 510)]);
+    }
+}
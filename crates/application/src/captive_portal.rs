@@ -0,0 +1,201 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+/// DNS server port
+pub const DNS_PORT: u16 = 53;
+
+/// Largest DNS message handled, as sent over UDP without EDNS
+pub const MAX_DNS_MESSAGE_LENGTH: usize = 512;
+
+/// Time to live of the answers, in seconds. Short, so clients query again
+/// once connected to their home network.
+const ANSWER_TTL: u32 = 60;
+
+/// URLs requested by the operating systems to check internet connectivity.
+///
+/// Redirecting them to the configuration page makes the system open it
+/// automatically, instead of reporting "no internet".
+pub const CONNECTIVITY_CHECK_PATHS: &[&str] = &[
+    // Android, Chrome OS
+    "/generate_204",
+    "/gen_204",
+    // Apple
+    "/hotspot-detect.html",
+    "/library/test/success.html",
+    // Windows
+    "/connecttest.txt",
+    "/ncsi.txt",
+    "/redirect",
+    // Firefox
+    "/canonical.html",
+    "/success.txt",
+];
+
+const HEADER_LENGTH: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+const RCODE_FORMAT_ERROR: u16 = 1;
+const RCODE_NOT_IMPLEMENTED: u16 = 4;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Compressed name, pointing to the question name right after the header
+const QUESTION_NAME_POINTER: [u8; 2] = [0xC0, HEADER_LENGTH as u8];
+
+/// Answer a DNS query, resolving every name to `address`.
+///
+/// Only A (and ANY) queries of the Internet class get an answer record; other
+/// types get an empty answer, so clients fall back to IPv4. Return `None` for
+/// messages that must be ignored, like responses or truncated queries.
+pub fn dns_response(query: &[u8], address: [u8; 4]) -> Option<Vec<u8>> {
+    if query.len() < HEADER_LENGTH || query.len() > MAX_DNS_MESSAGE_LENGTH {
+        return None;
+    }
+    let flags = read_u16(query, 2)?;
+    if flags & FLAG_RESPONSE != 0 {
+        return None;
+    }
+
+    let id = &query[0..2];
+    let response_flags = FLAG_RESPONSE | FLAG_AUTHORITATIVE | (flags & (OPCODE_MASK | FLAG_RECURSION_DESIRED));
+    if flags & OPCODE_MASK != 0 {
+        return Some(error_response(id, response_flags | RCODE_NOT_IMPLEMENTED));
+    }
+    if read_u16(query, 4)? != 1 {
+        return Some(error_response(id, response_flags | RCODE_FORMAT_ERROR));
+    }
+
+    let Some(question_end) = question_end(query) else {
+        return Some(error_response(id, response_flags | RCODE_FORMAT_ERROR));
+    };
+    let question = &query[HEADER_LENGTH..question_end];
+    let query_type = read_u16(query, question_end - 4)?;
+    let query_class = read_u16(query, question_end - 2)?;
+    let answer = (query_type == TYPE_A || query_type == TYPE_ANY) && query_class == CLASS_IN;
+
+    let mut response = Vec::with_capacity(question_end + 16);
+    response.extend_from_slice(id);
+    response.extend_from_slice(&response_flags.to_be_bytes());
+    // One question, one or no answer, no authority nor additional records
+    for count in [1, u16::from(answer), 0, 0] {
+        response.extend_from_slice(&count.to_be_bytes());
+    }
+    response.extend_from_slice(question);
+    if answer {
+        response.extend_from_slice(&QUESTION_NAME_POINTER);
+        response.extend_from_slice(&TYPE_A.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+        response.extend_from_slice(&(address.len() as u16).to_be_bytes());
+        response.extend_from_slice(&address);
+    }
+    Some(response)
+}
+
+/// Response without question nor records.
+fn error_response(id: &[u8], flags: u16) -> Vec<u8> {
+    let mut response = Vec::with_capacity(HEADER_LENGTH);
+    response.extend_from_slice(id);
+    response.extend_from_slice(&flags.to_be_bytes());
+    response.extend_from_slice(&[0; HEADER_LENGTH - 4]);
+    response
+}
+
+/// Offset after the type and class of the first question.
+///
+/// Compressed names are not expected in queries, and are refused.
+fn question_end(query: &[u8]) -> Option<usize> {
+    let mut offset = HEADER_LENGTH;
+    loop {
+        let length = usize::from(*query.get(offset)?);
+        offset += 1;
+        match length {
+            0 => break,
+            1..=63 => offset += length,
+            _ => return None,
+        }
+    }
+    let end = offset + 4;
+    (end <= query.len()).then_some(end)
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: [u8; 4] = [192, 168, 71, 1];
+
+    // Queries as sent by the clients joining the access point
+    /// Android connectivity check: A connectivitycheck.gstatic.com
+    const ANDROID_QUERY: &[u8] = include_bytes!("../tests/fixtures/dns/android_connectivitycheck_a.bin");
+    /// macOS captive portal check: A captive.apple.com, with EDNS option
+    const APPLE_QUERY: &[u8] = include_bytes!("../tests/fixtures/dns/apple_captive_a_edns.bin");
+    /// IPv6 lookup: AAAA captive.apple.com
+    const AAAA_QUERY: &[u8] = include_bytes!("../tests/fixtures/dns/apple_captive_aaaa.bin");
+
+    #[test]
+    fn answer_a_query() {
+        let response = dns_response(ANDROID_QUERY, ADDRESS).unwrap();
+        let question_end = ANDROID_QUERY.len();
+
+        assert_eq!(response[0..2], ANDROID_QUERY[0..2]);
+        // Response, authoritative, recursion desired copied, no error
+        assert_eq!(response[2..4], [0x85, 0x00]);
+        assert_eq!(response[4..12], [0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response[12..question_end], ANDROID_QUERY[12..]);
+        assert_eq!(
+            response[question_end..],
+            [0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 71, 1]
+        );
+    }
+
+    #[test]
+    fn additional_records_are_dropped() {
+        let response = dns_response(APPLE_QUERY, ADDRESS).unwrap();
+        // "captive.apple.com" question, followed by the answer
+        let question_end = HEADER_LENGTH + 19 + 4;
+
+        assert_eq!(response[0..2], APPLE_QUERY[0..2]);
+        assert_eq!(response[4..12], [0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response[12..question_end], APPLE_QUERY[12..question_end]);
+        assert_eq!(response[response.len() - 4..], ADDRESS);
+        assert_eq!(response.len(), question_end + 16);
+    }
+
+    #[test]
+    fn other_types_get_empty_answer() {
+        let response = dns_response(AAAA_QUERY, ADDRESS).unwrap();
+
+        assert_eq!(response[2..4], [0x85, 0x00]);
+        assert_eq!(response[4..12], [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(response[12..], AAAA_QUERY[12..]);
+    }
+
+    #[test]
+    fn invalid_queries() {
+        // Too short, or a response
+        assert_eq!(dns_response(&ANDROID_QUERY[..10], ADDRESS), None);
+        let mut response = ANDROID_QUERY.to_vec();
+        response[2] |= 0x80;
+        assert_eq!(dns_response(&response, ADDRESS), None);
+
+        // Truncated question
+        let response = dns_response(&ANDROID_QUERY[..ANDROID_QUERY.len() - 2], ADDRESS).unwrap();
+        assert_eq!(response[2..], [0x85, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Not a standard query
+        let mut status = ANDROID_QUERY.to_vec();
+        status[2] = 0x10;
+        let response = dns_response(&status, ADDRESS).unwrap();
+        assert_eq!(response[2..4], [0x94, 0x04]);
+        assert_eq!(response.len(), HEADER_LENGTH);
+    }
+}
@@ -5,9 +5,10 @@
 use std::fmt;
 
 use anyhow::{anyhow, Result};
-use regex::Regex;
 use rgb::RGB8;
 
+use crate::display::MAX_BRIGHTNESS;
+
 /// Length of a color in string representation
 pub const COLOR_AS_STRING_LENGTH: usize = 6;
 
@@ -26,26 +27,24 @@ impl Color {
     /// # Error
     /// Fails if input is not 3 hex words as RRGGBB.
     pub fn from_rgb_hex_string(rgb: &str) -> Result<Self> {
-        let re = Regex::new(r"^([0-9a-fA-F]{2})([0-9a-fA-F]{2})([0-9a-fA-F]{2})$")?;
-
-        if let Some(cap) = re.captures(rgb) {
-            let r:u8 = u8::from_str_radix(&cap[1], 16)?;
-            let g:u8 = u8::from_str_radix(&cap[2], 16)?;
-            let b:u8 = u8::from_str_radix(&cap[3], 16)?;
-
-            Ok(Color { rgb: RGB8{r, g, b} })
-
-        } else {
-            Err(anyhow!("Provided input is not a valid hex rgb string RRGGBB: {}", rgb))
+        // from_str_radix would accept a leading sign, only hex digits are allowed
+        if rgb.len() != COLOR_AS_STRING_LENGTH || !rgb.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("Provided input is not a valid hex rgb string RRGGBB: {}", rgb));
         }
+
+        let channel = |index: usize| u8::from_str_radix(&rgb[index..index + 2], 16);
+        Ok(Color { rgb: RGB8{r: channel(0)?, g: channel(2)?, b: channel(4)?} })
     }
 
     pub fn is_black(&self) -> bool {
-        if self.rgb.r == 0 && self.rgb.g == 0 && self.rgb.b == 0 {
-            true
-        } else {
-            false
-        }
+        self.rgb.r == 0 && self.rgb.g == 0 && self.rgb.b == 0
+    }
+
+    /// Color scaled to the given brightness, in percent
+    pub fn dimmed(&self, brightness: u8) -> Self {
+        let brightness = u16::from(brightness.min(MAX_BRIGHTNESS));
+        let scale = |channel: u8| (u16::from(channel) * brightness / u16::from(MAX_BRIGHTNESS)) as u8;
+        Color { rgb: RGB8{r: scale(self.rgb.r), g: scale(self.rgb.g), b: scale(self.rgb.b)} }
     }
 }
 
@@ -114,6 +113,24 @@ mod tests {
         // to big entry
         let color = Color::from_rgb_hex_string("00112233");
         assert!(color.is_err());
+
+        // signs are not hex digits
+        let color = Color::from_rgb_hex_string("+1+2+3");
+        assert!(color.is_err());
+
+        // multi-byte char, 6 bytes long
+        let color = Color::from_rgb_hex_string("é00AA");
+        assert!(color.is_err());
+
+        // surrounding whitespace
+        let color = Color::from_rgb_hex_string(" AABBC");
+        assert!(color.is_err());
+    }
+
+    #[test]
+    fn lower_case_rgb_hex_string() {
+        let color = Color::from_rgb_hex_string("aabbcc").unwrap();
+        assert_eq!(color, Color::new(170, 187, 204));
     }
 
     #[test]
@@ -127,4 +144,13 @@ mod tests {
         let color = Color::new(0,0,0);
         assert!(color.is_black());
     }
+
+    #[test]
+    fn dimmed_color() {
+        let color = Color::new(255, 100, 1);
+        assert_eq!(color.dimmed(MAX_BRIGHTNESS), color);
+        assert_eq!(color.dimmed(200), color);
+        assert_eq!(color.dimmed(40), Color::new(102, 40, 0));
+        assert!(color.dimmed(0).is_black());
+    }
 }
\ No newline at end of file
@@ -2,36 +2,263 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
+use std::fmt;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
-use regex::Regex;
+use log::*;
 
+use crate::display::{Dialect, MAX_BRIGHTNESS, MIN_BRIGHTNESS};
+use crate::firmware_update::{is_valid_update_server, UpdateChannel, DEFAULT_UPDATE_SERVER};
+use crate::form_urlencoded::{self, FormFields};
+use crate::mqtt::MQTT_PORT;
+use crate::network::{is_valid_hostname, DEFAULT_HOSTNAME};
 use crate::time::{Time, TIME_STRING_LENGTH};
 use crate::color::{Color, COLOR_AS_STRING_LENGTH};
 
 /// Key entries used as index for persistent storage.
 const WIFI_SSID_KEY: &str = "wifi_ssid";
 const WIFI_PASSWORD_KEY: &str = "wifi_password";
+const WIFI_PRIORITY_KEY: &str = "wifi_priority";
+const WIFI_COUNT_KEY: &str = "wifi_count";
 const NIGHT_START_KEY: &str = "night_start";
 const NIGHT_END_KEY: &str = "night_end";
 const VALID_CONFIG_KEY: &str = "valid_config";
 const DISPLAY_COLOR_KEY: &str = "display_color";
+const BRIGHTNESS_KEY: &str = "brightness";
+const DIALECT_KEY: &str = "dialect";
+const HOSTNAME_KEY: &str = "hostname";
+const MQTT_HOST_KEY: &str = "mqtt_host";
+const MQTT_PORT_KEY: &str = "mqtt_port";
+const MQTT_USERNAME_KEY: &str = "mqtt_username";
+const MQTT_PASSWORD_KEY: &str = "mqtt_password";
+const UPDATE_SERVER_KEY: &str = "update_server";
+const UPDATE_CHANNEL_KEY: &str = "update_channel";
+const CONFIG_VERSION_KEY: &str = "config_version";
+
+/// Version of the stored configuration layout. Add a migration step in
+/// `ConfigurationManager::migrate()` when increasing it.
+pub const CURRENT_CONFIG_VERSION: u32 = 7;
+
+/// Maximum number of stored WiFi networks
+pub const MAX_WIFI_NETWORKS: usize = 5;
+
+/// Priority of a WiFi network when not specified. Higher priorities are preferred.
+pub const DEFAULT_WIFI_PRIORITY: u8 = 0;
 
 /// Value used to tag a valid/invalid config in persistent storage
 const INVALID_CONFIG_VALUE: &str = "1";
 const VALID_CONFIG_VALUE: &str = "0";
 
-/// REGEX used to parse the http get query string containing the configuration
-const CONFIGURATION_QUERY_STRING_REGEX: &str = r"^\/get\?input_wifi_ssid=(?P<ssid>.*)&input_wifi_password=(?P<password>.*)&input_night_mode_start=(?P<night_start>[\%3A0-9]*)&input_night_mode_end=(?P<night_end>[\%3A0-9]*)&favcolor=(?P<display_color>[\%230-9a-fA-F]*)";
+/// Field names of the configuration form
+pub const FORM_SSID_KEY: &str = "input_wifi_ssid";
+pub const FORM_PASSWORD_KEY: &str = "input_wifi_password";
+pub const FORM_PRIORITY_KEY: &str = "input_wifi_priority";
+pub const FORM_NIGHT_START_KEY: &str = "input_night_mode_start";
+pub const FORM_NIGHT_END_KEY: &str = "input_night_mode_end";
+pub const FORM_DISPLAY_COLOR_KEY: &str = "favcolor";
+pub const FORM_PIN_KEY: &str = "input_pin";
+pub const FORM_BRIGHTNESS_KEY: &str = "input_brightness";
+pub const FORM_DIALECT_KEY: &str = "input_dialect";
+pub const FORM_HOSTNAME_KEY: &str = "input_hostname";
+pub const FORM_MQTT_HOST_KEY: &str = "input_mqtt_host";
+pub const FORM_MQTT_PORT_KEY: &str = "input_mqtt_port";
+pub const FORM_MQTT_USERNAME_KEY: &str = "input_mqtt_username";
+pub const FORM_MQTT_PASSWORD_KEY: &str = "input_mqtt_password";
+pub const FORM_UPDATE_SERVER_KEY: &str = "input_update_server";
+pub const FORM_UPDATE_CHANNEL_KEY: &str = "input_update_channel";
+
+/// Fields of the settings page, that can be changed without reconfiguring the WiFi
+///
+/// The MQTT password is accepted on the settings page, but never filled in.
+pub const SETTINGS_KEYS: [&str; 9] = [
+    FORM_DISPLAY_COLOR_KEY,
+    FORM_BRIGHTNESS_KEY,
+    FORM_NIGHT_START_KEY,
+    FORM_NIGHT_END_KEY,
+    FORM_DIALECT_KEY,
+    FORM_HOSTNAME_KEY,
+    FORM_MQTT_HOST_KEY,
+    FORM_MQTT_PORT_KEY,
+    FORM_MQTT_USERNAME_KEY,
+];
+
+/// Fields of the update settings, only accepted in the configuration form
+///
+/// Like the WiFi, they are protected by the PIN shown on the clock or the
+/// access point passphrase. The settings page shows them read-only.
+pub const UPDATE_KEYS: [&str; 2] = [FORM_UPDATE_SERVER_KEY, FORM_UPDATE_CHANNEL_KEY];
+
+/// Maximum length of the MQTT broker host name, shorter than the 253 bytes of
+/// DNS to fit in the persistent storage
+pub const MAX_BROKER_HOST_LENGTH: usize = 128;
+
+/// Longest value the persistent storage must hold. The device reads the values
+/// into a buffer of this size, plus the terminating NUL.
+pub const MAX_STORED_STRING_LENGTH: usize = 179;
+
+/// Night mode must start in the afternoon and end in the morning.
+const NIGHT_START_EARLIEST_HOUR: u8 = 12;
+const NIGHT_END_LATEST_HOUR: u8 = 12;
+
+/// Invalid field of the configuration form
+///
+/// WiFi network errors hold the index of the network in the form.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldError {
+    EmptySsid(usize),
+    InvalidPriority(usize),
+    TooManyNetworks,
+    /// The field doesn't contain a `hh:mm` time.
+    InvalidTime(&'static str),
+    NightStartOutOfRange,
+    NightEndOutOfRange,
+    InvalidColor,
+    InvalidBrightness,
+    InvalidDialect,
+    InvalidHostname,
+    InvalidMqttHost,
+    InvalidMqttPort,
+    InvalidUpdateServer,
+    InvalidUpdateChannel,
+    /// None of the networks is visible, or connection to all of them failed.
+    NetworkNotFound,
+    NetworkAuthentication(usize),
+    /// The PIN shown on the display was not entered properly.
+    InvalidPin,
+    /// Too many wrong PINs, the form is refused for a while.
+    PinLocked,
+}
+
+impl FieldError {
+    /// Name of the form field with the error
+    pub fn field(&self) -> &'static str {
+        match self {
+            Self::EmptySsid(_) | Self::TooManyNetworks | Self::NetworkNotFound => FORM_SSID_KEY,
+            Self::InvalidPriority(_) => FORM_PRIORITY_KEY,
+            Self::NetworkAuthentication(_) => FORM_PASSWORD_KEY,
+            Self::InvalidTime(field) => field,
+            Self::NightStartOutOfRange => FORM_NIGHT_START_KEY,
+            Self::NightEndOutOfRange => FORM_NIGHT_END_KEY,
+            Self::InvalidColor => FORM_DISPLAY_COLOR_KEY,
+            Self::InvalidBrightness => FORM_BRIGHTNESS_KEY,
+            Self::InvalidDialect => FORM_DIALECT_KEY,
+            Self::InvalidHostname => FORM_HOSTNAME_KEY,
+            Self::InvalidMqttHost => FORM_MQTT_HOST_KEY,
+            Self::InvalidMqttPort => FORM_MQTT_PORT_KEY,
+            Self::InvalidUpdateServer => FORM_UPDATE_SERVER_KEY,
+            Self::InvalidUpdateChannel => FORM_UPDATE_CHANNEL_KEY,
+            Self::InvalidPin | Self::PinLocked => FORM_PIN_KEY,
+        }
+    }
+
+    /// Index of the WiFi network with the error, `None` if the error is not
+    /// related to a single network.
+    pub fn network(&self) -> Option<usize> {
+        match self {
+            Self::EmptySsid(index) | Self::InvalidPriority(index) | Self::NetworkAuthentication(index) => Some(*index),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptySsid(_) => write!(f, "The WiFi name is required"),
+            Self::InvalidPriority(_) => write!(f, "Priority must be a number between 0 and 255"),
+            Self::TooManyNetworks => write!(f, "At most {} WiFi networks can be stored", MAX_WIFI_NETWORKS),
+            Self::InvalidTime(_) => write!(f, "Invalid time, use the hh:mm format"),
+            Self::NightStartOutOfRange => write!(f, "Night mode must start between 12:00 and 23:59"),
+            Self::NightEndOutOfRange => write!(f, "Night mode must end between 00:00 and 12:00"),
+            Self::InvalidColor => write!(f, "Invalid color"),
+            Self::InvalidBrightness => write!(
+                f,
+                "Brightness must be a number between {} and {}",
+                MIN_BRIGHTNESS, MAX_BRIGHTNESS
+            ),
+            Self::InvalidDialect => write!(f, "Unknown dialect"),
+            Self::InvalidHostname => write!(
+                f,
+                "Name must have up to 63 letters, digits or hyphens, not starting nor ending with a hyphen"
+            ),
+            Self::InvalidMqttHost => write!(f, "Broker must be a host name or an IP address"),
+            Self::InvalidMqttPort => write!(f, "Port must be a number between 1 and 65535"),
+            Self::InvalidUpdateServer => write!(f, "Server must be an http:// or https:// URL, without query"),
+            Self::InvalidUpdateChannel => write!(f, "Unknown update channel"),
+            Self::NetworkNotFound => write!(f, "No WiFi network found"),
+            Self::NetworkAuthentication(_) => write!(f, "WiFi network refused the password"),
+            Self::InvalidPin => write!(f, "Wrong PIN, enter the code shown on the clock"),
+            Self::PinLocked => write!(f, "Too many wrong PINs, try again later"),
+        }
+    }
+}
+
+/// Credentials of a known WiFi network
+#[derive(Debug, Clone, PartialEq)]
+pub struct WifiCredentials {
+    pub ssid: String,
+    pub password: String,
+    /// When several known networks are visible, the one with the highest priority is used.
+    pub priority: u8,
+}
+
+impl WifiCredentials {
+    pub fn new(ssid: &str, password: &str, priority: u8) -> Self {
+        Self {
+            ssid: String::from(ssid),
+            password: String::from(password),
+            priority,
+        }
+    }
+}
+
+/// MQTT broker the clock connects to, see `mqtt_bridge`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttBroker {
+    pub host: String,
+    pub port: u16,
+    /// Empty for an anonymous connection
+    pub username: String,
+    pub password: String,
+}
+
+impl MqttBroker {
+    /// Anonymous connection on the default port
+    pub fn new(host: &str) -> Self {
+        Self { host: String::from(host), port: MQTT_PORT, username: String::new(), password: String::new() }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 struct ConfigurationFields {
-    ssid: String,
-    password: String,
+    networks: Vec<WifiCredentials>,
     night_start: Option<Time>,
     night_end: Option<Time>,
     display_color: Color,
+    /// In percent
+    brightness: u8,
+    dialect: Dialect,
+    /// mDNS host name, without the `.local` domain
+    hostname: String,
+    /// `None` when MQTT is disabled
+    mqtt: Option<MqttBroker>,
+    /// Directory of the release manifests, without trailing `/`
+    update_server: String,
+    update_channel: UpdateChannel,
+}
+
+/// Fields of the settings page
+struct Settings {
+    night_start: Option<Time>,
+    night_end: Option<Time>,
+    display_color: Color,
+    brightness: u8,
+    dialect: Dialect,
+    hostname: String,
+    mqtt: Option<MqttBroker>,
+    update_server: String,
+    update_channel: UpdateChannel,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -47,103 +274,215 @@ pub struct Configuration {
 }
 
 impl Configuration {
-    /// Create a new valid configuration
+    /// Create a new valid configuration, with a single WiFi network
     pub fn new(
         ssid: String,
         password: String,
         night_start: Option<Time>,
         night_end: Option<Time>,
         display_color: Color,
+    ) -> Self {
+        Self::with_networks(
+            vec![WifiCredentials { ssid, password, priority: DEFAULT_WIFI_PRIORITY }],
+            night_start,
+            night_end,
+            display_color,
+        )
+    }
+
+    /// Create a new valid configuration, with several WiFi networks.
+    ///
+    /// The display is at full brightness, with the default dialect and host name.
+    /// Updates follow the stable channel of the GitHub releases.
+    pub fn with_networks(
+        networks: Vec<WifiCredentials>,
+        night_start: Option<Time>,
+        night_end: Option<Time>,
+        display_color: Color,
     ) -> Self {
         Self {
             state: ConfigurationState::Valid(ConfigurationFields {
-                ssid,
-                password,
+                networks,
                 night_start,
                 night_end,
                 display_color,
+                brightness: MAX_BRIGHTNESS,
+                dialect: Dialect::default(),
+                hostname: String::from(DEFAULT_HOSTNAME),
+                mqtt: None,
+                update_server: String::from(DEFAULT_UPDATE_SERVER),
+                update_channel: UpdateChannel::default(),
             }),
         }
     }
 
-    pub fn from_uri_query_string(uri: &str) -> Result<Self> {
-        let re = Regex::new(CONFIGURATION_QUERY_STRING_REGEX)?;
-
-        let mut night_start: Option<Time> = None;
-        let mut night_end: Option<Time> = None;
-        let mut display_color: Color = Color::default();
-        if let Some(cap) = re.captures(uri) {
-            let utf8_encoded_value: String = cap[1].parse()?;
-            let ssid: String = url_escape::decode(&utf8_encoded_value).to_string();
-            if ssid.is_empty() {
-                return Err(anyhow!("Empty SSID: {}", uri));
-            }
-            let utf8_encoded_value: String = cap[2].parse()?;
-            let password: String = url_escape::decode(&utf8_encoded_value).to_string();
-            if let Some(value) = cap.name("night_start") {
-                if value.len() >= 5 {
-                    let mut time = String::from(url_escape::decode(value.as_str()));
-                    time.push_str(":00");
-                    night_start = Some(Time::from_str(&time)?);
-                }
-            }
-            if let Some(value) = cap.name("night_end") {
-                if value.len() >= 5 {
-                    let mut time = String::from(url_escape::decode(value.as_str()));
-                    time.push_str(":00");
-                    night_end = Some(Time::from_str(&time)?);
-                }
-            }
+    /// Set the display brightness, in percent. No effect on an invalid configuration.
+    pub fn with_brightness(mut self, brightness: u8) -> Self {
+        if let ConfigurationState::Valid(fields) = &mut self.state {
+            fields.brightness = brightness;
+        }
+        self
+    }
 
-            if let Some(value) = cap.name("display_color") {
-                if value.len() >= 6 {
-                    let color = String::from(url_escape::decode(value.as_str()));
-                    display_color = Color::from_rgb_hex_string(&color[1..color.len()])?;
-                    if display_color.is_black() {
-                        display_color = Color::default();
-                    }
-                }
-            }
+    /// Set the dialect. No effect on an invalid configuration.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        if let ConfigurationState::Valid(fields) = &mut self.state {
+            fields.dialect = dialect;
+        }
+        self
+    }
 
-            Ok(Configuration {
-                state: ConfigurationState::Valid(ConfigurationFields {
-                    ssid,
-                    password,
-                    night_start,
-                    night_end,
-                    display_color,
-                }),
-            })
-        } else {
-            Err(anyhow!("Failed to parse query string: {}", uri))
+    /// Set the mDNS host name. No effect on an invalid configuration.
+    pub fn with_hostname(mut self, hostname: &str) -> Self {
+        if let ConfigurationState::Valid(fields) = &mut self.state {
+            fields.hostname = String::from(hostname);
         }
+        self
     }
 
-    pub fn is_valid(&self) -> bool {
-        match self.state {
-            ConfigurationState::Valid(_) => true,
-            _ => false,
+    /// Set the MQTT broker, `None` disables MQTT. No effect on an invalid configuration.
+    pub fn with_mqtt_broker(mut self, broker: Option<MqttBroker>) -> Self {
+        if let ConfigurationState::Valid(fields) = &mut self.state {
+            fields.mqtt = broker;
         }
+        self
     }
 
-    pub fn is_invalid(&self) -> bool {
-        match self.state {
-            ConfigurationState::Invalid => true,
-            _ => false,
+    /// Set the server of the firmware updates. No effect on an invalid configuration.
+    pub fn with_update_server(mut self, server: &str) -> Self {
+        if let ConfigurationState::Valid(fields) = &mut self.state {
+            fields.update_server = String::from(server.trim_end_matches('/'));
         }
+        self
     }
 
-    pub fn get_ssid(&self) -> Option<String> {
-        match &self.state {
-            ConfigurationState::Valid(fields) => Some(fields.ssid.clone()),
-            _ => None,
+    /// Set the release channel of the firmware updates. No effect on an invalid configuration.
+    pub fn with_update_channel(mut self, channel: UpdateChannel) -> Self {
+        if let ConfigurationState::Valid(fields) = &mut self.state {
+            fields.update_channel = channel;
         }
+        self
+    }
+
+    /// Create a configuration from an URI query string, like `/get?input_wifi_ssid=...`.
+    pub fn from_uri_query_string(uri: &str) -> Result<Self> {
+        let query = uri.split_once('?').map_or(uri, |(_, query)| query);
+        Self::from_form_fields(&form_urlencoded::parse(query)).map_err(|errors| {
+            let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            anyhow!("Invalid configuration: {}", messages.join(", "))
+        })
+    }
+
+    /// Create a configuration from the decoded fields of the configuration form.
+    ///
+    /// Fields can be in any order, unknown fields are ignored. WiFi networks
+    /// are given by repeated SSID, password and priority fields, matched by
+    /// their position. Networks with an empty SSID and password are ignored.
+    ///
+    /// # Errors
+    /// Return the errors of all invalid fields.
+    pub fn from_form_fields(fields: &[(String, String)]) -> std::result::Result<Self, Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let networks = form_networks(fields, &mut errors);
+
+        let settings = form_settings(fields, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut configuration = Self::with_networks(networks, None, None, Color::default());
+        configuration.set_settings(settings);
+        Ok(configuration)
+    }
+
+    /// Update the settings from the decoded fields of the settings page, see
+    /// `SETTINGS_KEYS`. The WiFi networks and the update settings are kept,
+    /// submitted `UPDATE_KEYS` are ignored.
+    ///
+    /// Like in the configuration form, a missing field resets the setting to
+    /// its default. An empty MQTT password keeps the stored one, as long as
+    /// the broker and user are unchanged. No effect on an invalid configuration.
+    ///
+    /// # Errors
+    /// Return the errors of all invalid fields, the configuration is unchanged.
+    pub fn update_settings(&mut self, fields: &[(String, String)]) -> std::result::Result<(), Vec<FieldError>> {
+        let fields: FormFields = fields.iter().filter(|(key, _)| !UPDATE_KEYS.contains(&key.as_str())).cloned().collect();
+        let mut errors = Vec::new();
+        let mut settings = form_settings(&fields, &mut errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        if let ConfigurationState::Valid(current) = &self.state {
+            settings.update_server = current.update_server.clone();
+            settings.update_channel = current.update_channel;
+        }
+        if let (Some(broker), Some(current)) = (&mut settings.mqtt, self.get_mqtt_broker()) {
+            if broker.password.is_empty() && broker.host == current.host && broker.username == current.username {
+                broker.password = current.password;
+            }
+        }
+
+        self.set_settings(settings);
+        Ok(())
     }
 
-    pub fn get_password(&self) -> Option<String> {
+    /// Fields of the settings page, filled with the current settings and
+    /// update settings.
+    pub fn settings_fields(&self) -> FormFields {
+        let ConfigurationState::Valid(fields) = &self.state else {
+            return Vec::new();
+        };
+        let color = format!("#{}", fields.display_color).to_lowercase();
+        let time = |time: Option<Time>| time.map(to_form_time).unwrap_or_default();
+        let mqtt = fields.mqtt.clone();
+        [
+            (FORM_DISPLAY_COLOR_KEY, color),
+            (FORM_BRIGHTNESS_KEY, fields.brightness.to_string()),
+            (FORM_NIGHT_START_KEY, time(fields.night_start)),
+            (FORM_NIGHT_END_KEY, time(fields.night_end)),
+            (FORM_DIALECT_KEY, fields.dialect.to_string()),
+            (FORM_HOSTNAME_KEY, fields.hostname.clone()),
+            (FORM_MQTT_HOST_KEY, mqtt.as_ref().map(|broker| broker.host.clone()).unwrap_or_default()),
+            (FORM_MQTT_PORT_KEY, mqtt.as_ref().map(|broker| broker.port.to_string()).unwrap_or_default()),
+            (FORM_MQTT_USERNAME_KEY, mqtt.map(|broker| broker.username).unwrap_or_default()),
+            (FORM_UPDATE_SERVER_KEY, fields.update_server.clone()),
+            (FORM_UPDATE_CHANNEL_KEY, fields.update_channel.to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (String::from(key), value))
+        .collect()
+    }
+
+    fn set_settings(&mut self, settings: Settings) {
+        if let ConfigurationState::Valid(fields) = &mut self.state {
+            fields.night_start = settings.night_start;
+            fields.night_end = settings.night_end;
+            fields.display_color = settings.display_color;
+            fields.brightness = settings.brightness;
+            fields.dialect = settings.dialect;
+            fields.hostname = settings.hostname;
+            fields.mqtt = settings.mqtt;
+            fields.update_server = settings.update_server;
+            fields.update_channel = settings.update_channel;
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        matches!(self.state, ConfigurationState::Valid(_))
+    }
+
+    pub fn is_invalid(&self) -> bool {
+        matches!(self.state, ConfigurationState::Invalid)
+    }
+
+    /// Known WiFi networks, in the order they were configured.
+    pub fn get_networks(&self) -> Vec<WifiCredentials> {
         match &self.state {
-            ConfigurationState::Valid(fields) => Some(fields.password.clone()),
-            _ => None,
+            ConfigurationState::Valid(fields) => fields.networks.clone(),
+            _ => Vec::new(),
         }
     }
 
@@ -161,12 +500,75 @@ impl Configuration {
         }
     }
 
+    /// Whether `time` is in the night window, from its start to its end excluded.
+    ///
+    /// The window may cross midnight. Without an end, the night lasts until
+    /// midnight. Without a start, there is no night.
+    pub fn is_night(&self, time: Time) -> bool {
+        let Some(start) = self.get_night_start() else {
+            return false;
+        };
+        let now = time.minutes_since_midnight();
+        let start = start.minutes_since_midnight();
+        match self.get_night_end().map(|end| end.minutes_since_midnight()) {
+            Some(end) if start <= end => start <= now && now < end,
+            Some(end) => start <= now || now < end,
+            None => start <= now,
+        }
+    }
+
     pub fn get_display_color(&self) -> Option<Color> {
         match &self.state {
             ConfigurationState::Valid(fields) => Some(fields.display_color),
             _ => None,
         }
     }
+
+    /// Display brightness, in percent
+    pub fn get_brightness(&self) -> Option<u8> {
+        match &self.state {
+            ConfigurationState::Valid(fields) => Some(fields.brightness),
+            _ => None,
+        }
+    }
+
+    pub fn get_dialect(&self) -> Option<Dialect> {
+        match &self.state {
+            ConfigurationState::Valid(fields) => Some(fields.dialect),
+            _ => None,
+        }
+    }
+
+    /// mDNS host name, the clock is reachable as `<hostname>.local`
+    pub fn get_hostname(&self) -> Option<String> {
+        match &self.state {
+            ConfigurationState::Valid(fields) => Some(fields.hostname.clone()),
+            _ => None,
+        }
+    }
+
+    /// MQTT broker, `None` when MQTT is disabled or the configuration is invalid
+    pub fn get_mqtt_broker(&self) -> Option<MqttBroker> {
+        match &self.state {
+            ConfigurationState::Valid(fields) => fields.mqtt.clone(),
+            _ => None,
+        }
+    }
+
+    /// Directory of the release manifests, see `firmware_update::manifest_url()`
+    pub fn get_update_server(&self) -> Option<String> {
+        match &self.state {
+            ConfigurationState::Valid(fields) => Some(fields.update_server.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_update_channel(&self) -> Option<UpdateChannel> {
+        match &self.state {
+            ConfigurationState::Valid(fields) => Some(fields.update_channel),
+            _ => None,
+        }
+    }
 }
 
 impl Default for Configuration {
@@ -178,6 +580,179 @@ impl Default for Configuration {
     }
 }
 
+/// Parse the WiFi networks of the configuration form.
+fn form_networks(fields: &[(String, String)], errors: &mut Vec<FieldError>) -> Vec<WifiCredentials> {
+    let ssids = form_urlencoded::get_all(fields, FORM_SSID_KEY);
+    let passwords = form_urlencoded::get_all(fields, FORM_PASSWORD_KEY);
+    let priorities = form_urlencoded::get_all(fields, FORM_PRIORITY_KEY);
+
+    let count = ssids.len().max(passwords.len());
+    let mut networks = Vec::new();
+    for index in 0..count {
+        let ssid = ssids.get(index).copied().unwrap_or_default();
+        let password = passwords.get(index).copied().unwrap_or_default();
+        if ssid.is_empty() && password.is_empty() {
+            continue;
+        }
+        if ssid.is_empty() {
+            errors.push(FieldError::EmptySsid(index));
+        }
+        let priority = match priorities.get(index).filter(|value| !value.is_empty()) {
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                errors.push(FieldError::InvalidPriority(index));
+                DEFAULT_WIFI_PRIORITY
+            }),
+            None => DEFAULT_WIFI_PRIORITY,
+        };
+        networks.push(WifiCredentials::new(ssid, password, priority));
+    }
+
+    if networks.is_empty() {
+        errors.push(FieldError::EmptySsid(0));
+    }
+    if networks.len() > MAX_WIFI_NETWORKS {
+        errors.push(FieldError::TooManyNetworks);
+    }
+    networks
+}
+
+/// Parse the settings of the configuration form or the settings page.
+fn form_settings(fields: &[(String, String)], errors: &mut Vec<FieldError>) -> Settings {
+    let night_start = form_time(fields, FORM_NIGHT_START_KEY).unwrap_or_else(|error| {
+        errors.push(error);
+        None
+    });
+    if night_start.is_some_and(|time| time.hour < NIGHT_START_EARLIEST_HOUR) {
+        errors.push(FieldError::NightStartOutOfRange);
+    }
+    let night_end = form_time(fields, FORM_NIGHT_END_KEY).unwrap_or_else(|error| {
+        errors.push(error);
+        None
+    });
+    if night_end.is_some_and(|time| time.hour > NIGHT_END_LATEST_HOUR
+        || (time.hour == NIGHT_END_LATEST_HOUR && time.minute > 0))
+    {
+        errors.push(FieldError::NightEndOutOfRange);
+    }
+
+    let mut display_color = Color::default();
+    if let Some(value) = form_urlencoded::get(fields, FORM_DISPLAY_COLOR_KEY) {
+        let value = value.strip_prefix('#').unwrap_or(value);
+        if !value.is_empty() {
+            match Color::from_rgb_hex_string(value) {
+                Ok(color) if !color.is_black() => display_color = color,
+                Ok(_) => (),
+                Err(_) => errors.push(FieldError::InvalidColor),
+            }
+        }
+    }
+
+    let brightness = match form_urlencoded::get(fields, FORM_BRIGHTNESS_KEY).filter(|value| !value.is_empty()) {
+        Some(value) => match value.parse() {
+            Ok(brightness) if (MIN_BRIGHTNESS..=MAX_BRIGHTNESS).contains(&brightness) => brightness,
+            _ => {
+                errors.push(FieldError::InvalidBrightness);
+                MAX_BRIGHTNESS
+            }
+        },
+        None => MAX_BRIGHTNESS,
+    };
+
+    let dialect = match form_urlencoded::get(fields, FORM_DIALECT_KEY).filter(|value| !value.is_empty()) {
+        Some(value) => Dialect::from_str(value).unwrap_or_else(|_| {
+            errors.push(FieldError::InvalidDialect);
+            Dialect::default()
+        }),
+        None => Dialect::default(),
+    };
+
+    // Host names are case insensitive, keep them in lower case
+    let hostname = match form_urlencoded::get(fields, FORM_HOSTNAME_KEY).map(str::trim).filter(|value| !value.is_empty()) {
+        Some(value) if is_valid_hostname(value) => value.to_ascii_lowercase(),
+        Some(_) => {
+            errors.push(FieldError::InvalidHostname);
+            String::from(DEFAULT_HOSTNAME)
+        }
+        None => String::from(DEFAULT_HOSTNAME),
+    };
+
+    let mqtt = form_mqtt_broker(fields, errors);
+
+    let update_server = match form_urlencoded::get(fields, FORM_UPDATE_SERVER_KEY).map(str::trim).filter(|value| !value.is_empty()) {
+        Some(value) if is_valid_update_server(value) => String::from(value.trim_end_matches('/')),
+        Some(_) => {
+            errors.push(FieldError::InvalidUpdateServer);
+            String::from(DEFAULT_UPDATE_SERVER)
+        }
+        None => String::from(DEFAULT_UPDATE_SERVER),
+    };
+
+    let update_channel = match form_urlencoded::get(fields, FORM_UPDATE_CHANNEL_KEY).filter(|value| !value.is_empty()) {
+        Some(value) => UpdateChannel::from_str(value).unwrap_or_else(|_| {
+            errors.push(FieldError::InvalidUpdateChannel);
+            UpdateChannel::default()
+        }),
+        None => UpdateChannel::default(),
+    };
+
+    Settings { night_start, night_end, display_color, brightness, dialect, hostname, mqtt, update_server, update_channel }
+}
+
+/// Parse the MQTT broker, disabled without host.
+fn form_mqtt_broker(fields: &[(String, String)], errors: &mut Vec<FieldError>) -> Option<MqttBroker> {
+    let host = form_urlencoded::get(fields, FORM_MQTT_HOST_KEY).map(str::trim).unwrap_or_default();
+    if host.is_empty() {
+        return None;
+    }
+    if !is_valid_broker_host(host) {
+        errors.push(FieldError::InvalidMqttHost);
+    }
+
+    let port = match form_urlencoded::get(fields, FORM_MQTT_PORT_KEY).map(str::trim).filter(|value| !value.is_empty()) {
+        Some(value) => match value.parse() {
+            Ok(port) if port != 0 => port,
+            _ => {
+                errors.push(FieldError::InvalidMqttPort);
+                MQTT_PORT
+            }
+        },
+        None => MQTT_PORT,
+    };
+
+    Some(MqttBroker {
+        host: String::from(host),
+        port,
+        username: String::from(form_urlencoded::get(fields, FORM_MQTT_USERNAME_KEY).map(str::trim).unwrap_or_default()),
+        password: String::from(form_urlencoded::get(fields, FORM_MQTT_PASSWORD_KEY).unwrap_or_default()),
+    })
+}
+
+/// Host name or IPv4 address of a broker
+fn is_valid_broker_host(host: &str) -> bool {
+    host.len() <= MAX_BROKER_HOST_LENGTH
+        && host.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Times are shown as `hh:mm` in the forms.
+pub(crate) fn to_form_time(time: Time) -> String {
+    format!("{:0>2}:{:0>2}", time.hour, time.minute)
+}
+
+/// Parse an optional `hh:mm` time field of the configuration form.
+fn form_time(fields: &[(String, String)], key: &'static str) -> std::result::Result<Option<Time>, FieldError> {
+    let Some(value) = form_urlencoded::get(fields, key).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    value
+        .split_once(':')
+        .and_then(|(hour, minute)| Some((hour.parse().ok()?, minute.parse().ok()?)))
+        .and_then(|(hour, minute)| Time::new(hour, minute, 0).ok())
+        .map(Some)
+        .ok_or(FieldError::InvalidTime(key))
+}
+
 /// Interface to store and load data to persistent memory.
 pub trait PersistentStorage {
     /// Load a string identified by the provided key.
@@ -205,100 +780,83 @@ impl<P: PersistentStorage> ConfigurationManager<P> {
 
     /// Load a Configuration from persistent storage.
     ///
+    /// Older stored layouts are migrated to `CURRENT_CONFIG_VERSION` first.
     /// Returned Configuration can be in `Invalid`.
     pub fn load_from_persistent_storage(&mut self) -> Configuration {
         match self.storage_backend.load_string(VALID_CONFIG_KEY) {
-            Ok(value) => {
-                if value != VALID_CONFIG_VALUE {
-                    return Configuration {
-                        state: ConfigurationState::Invalid,
-                    };
-                }
-            }
-            _ => {
-                return Configuration {
-                    state: ConfigurationState::Invalid,
-                }
-            }
+            Ok(value) if value == VALID_CONFIG_VALUE => (),
+            _ => return Configuration::default(),
         }
 
-        let ssid: String;
-        match self.storage_backend.load_string(WIFI_SSID_KEY) {
-            Ok(value) => ssid = value,
-            _ => {
-                return Configuration {
-                    state: ConfigurationState::Invalid,
-                }
-            }
+        let version = self.stored_version();
+        if version > CURRENT_CONFIG_VERSION {
+            warn!("Stored configuration version {} is not supported", version);
+            return Configuration::default();
         }
-
-        let password: String;
-        match self.storage_backend.load_string(WIFI_PASSWORD_KEY) {
-            Ok(value) => password = value,
-            _ => {
-                return Configuration {
-                    state: ConfigurationState::Invalid,
-                }
-            }
+        if let Err(e) = self.migrate(version) {
+            error!("Failed to migrate configuration from version {}: {}", version, e);
+            return Configuration::default();
         }
 
-        let night_start: Option<Time>;
-        match self.storage_backend.load_string(NIGHT_START_KEY) {
-            Ok(value) => {
-                if value.len() == TIME_STRING_LENGTH {
-                    night_start = Some(Time::from_str(&value).unwrap());
-                } else {
-                    night_start = None;
-                }
-            }
-            _ => {
-                return Configuration {
-                    state: ConfigurationState::Invalid,
-                }
-            }
+        let networks = self.load_networks();
+        if networks.is_empty() {
+            return Configuration::default();
         }
 
-        let night_end: Option<Time>;
-        match self.storage_backend.load_string(NIGHT_END_KEY) {
-            Ok(value) => {
-                if value.len() == TIME_STRING_LENGTH {
-                    night_end = Some(Time::from_str(&value).unwrap());
-                } else {
-                    night_end = None;
-                }
-            }
-            _ => {
-                return Configuration {
-                    state: ConfigurationState::Invalid,
-                }
-            }
-        }
+        let night_start = self.load_time(NIGHT_START_KEY);
+        let night_end = self.load_time(NIGHT_END_KEY);
 
-        let display_color: Color;
-        match self.storage_backend.load_string(DISPLAY_COLOR_KEY) {
-            Ok(value) => {
-                if value.len() == COLOR_AS_STRING_LENGTH {
-                    display_color = Color::from_rgb_hex_string(&value).unwrap();
-                } else {
-                    display_color = Color::new(0, 0, 255);
-                }
-            },
-            _ => {
-                return Configuration {
-                    state: ConfigurationState::Invalid,
-                }
+        let display_color = match self.storage_backend.load_string(DISPLAY_COLOR_KEY) {
+            Ok(value) if value.len() == COLOR_AS_STRING_LENGTH => {
+                Color::from_rgb_hex_string(&value).unwrap_or_default()
             }
-        }
+            _ => Color::default(),
+        };
 
-        Configuration {
-            state: ConfigurationState::Valid(ConfigurationFields {
-                ssid,
-                password,
-                night_start,
-                night_end,
-                display_color,
+        let brightness = match self.storage_backend.load_string(BRIGHTNESS_KEY) {
+            Ok(value) => value
+                .parse()
+                .ok()
+                .filter(|brightness| (MIN_BRIGHTNESS..=MAX_BRIGHTNESS).contains(brightness))
+                .unwrap_or(MAX_BRIGHTNESS),
+            Err(_) => MAX_BRIGHTNESS,
+        };
+        let dialect = match self.storage_backend.load_string(DIALECT_KEY) {
+            Ok(value) => Dialect::from_str(&value).unwrap_or_default(),
+            Err(_) => Dialect::default(),
+        };
+
+        let hostname = match self.storage_backend.load_string(HOSTNAME_KEY) {
+            Ok(value) if is_valid_hostname(&value) => value,
+            _ => String::from(DEFAULT_HOSTNAME),
+        };
+
+        let mqtt = match self.storage_backend.load_string(MQTT_HOST_KEY) {
+            Ok(host) if is_valid_broker_host(&host) => Some(MqttBroker {
+                host,
+                port: self.load_string_or_default(MQTT_PORT_KEY).parse().unwrap_or(MQTT_PORT),
+                username: self.load_string_or_default(MQTT_USERNAME_KEY),
+                password: self.load_string_or_default(MQTT_PASSWORD_KEY),
             }),
-        }
+            _ => None,
+        };
+
+        let update_server = match self.storage_backend.load_string(UPDATE_SERVER_KEY) {
+            Ok(value) if is_valid_update_server(&value) => value,
+            _ => String::from(DEFAULT_UPDATE_SERVER),
+        };
+        let update_channel = match self.storage_backend.load_string(UPDATE_CHANNEL_KEY) {
+            Ok(value) => UpdateChannel::from_str(&value).unwrap_or_default(),
+            Err(_) => UpdateChannel::default(),
+        };
+
+        Configuration::with_networks(networks, night_start, night_end, display_color)
+            .with_brightness(brightness)
+            .with_dialect(dialect)
+            .with_hostname(&hostname)
+            .with_mqtt_broker(mqtt)
+            .with_update_server(&update_server)
+            .with_update_channel(update_channel)
     }
 
     /// Store the given Configuration to persistent memory.
@@ -307,20 +865,48 @@ impl<P: PersistentStorage> ConfigurationManager<P> {
     /// The functions will return an error if the hardware fails to carry the operation.
     pub fn store_to_persistent_storage(&mut self, configuration: Configuration) -> Result<()> {
         if configuration.is_valid() {
-            // It is safe to unwrap here, as configuration is guarantee to be valid.
-            self.storage_backend
-                .store_string(WIFI_SSID_KEY, &configuration.get_ssid().unwrap())?;
-            self.storage_backend
-                .store_string(WIFI_PASSWORD_KEY, &configuration.get_password().unwrap())?;
-            if let Some(night_start) = configuration.get_night_start() {
+            let networks = configuration.get_networks();
+            for (index, network) in networks.iter().enumerate() {
                 self.storage_backend
-                    .store_string(NIGHT_START_KEY, night_start.to_string().as_str())?;
-            }
-            if let Some(night_end) = configuration.get_night_end() {
+                    .store_string(&network_key(WIFI_SSID_KEY, index), &network.ssid)?;
+                self.storage_backend
+                    .store_string(&network_key(WIFI_PASSWORD_KEY, index), &network.password)?;
                 self.storage_backend
-                    .store_string(NIGHT_END_KEY, night_end.to_string().as_str())?;
+                    .store_string(&network_key(WIFI_PRIORITY_KEY, index), &network.priority.to_string())?;
             }
+            self.storage_backend
+                .store_string(WIFI_COUNT_KEY, &networks.len().to_string())?;
+            // It is safe to unwrap here, as configuration is guarantee to be valid.
+            // An empty string stands for no night mode
+            let night_start = configuration.get_night_start().map(|time| time.to_string());
+            self.storage_backend
+                .store_string(NIGHT_START_KEY, &night_start.unwrap_or_default())?;
+            let night_end = configuration.get_night_end().map(|time| time.to_string());
+            self.storage_backend
+                .store_string(NIGHT_END_KEY, &night_end.unwrap_or_default())?;
             self.storage_backend.store_string(DISPLAY_COLOR_KEY, &configuration.get_display_color().unwrap().to_string())?;
+            self.storage_backend
+                .store_string(BRIGHTNESS_KEY, &configuration.get_brightness().unwrap().to_string())?;
+            self.storage_backend
+                .store_string(DIALECT_KEY, &configuration.get_dialect().unwrap().to_string())?;
+            self.storage_backend
+                .store_string(HOSTNAME_KEY, &configuration.get_hostname().unwrap())?;
+            // An empty host stands for MQTT disabled
+            let mqtt = configuration.get_mqtt_broker();
+            self.storage_backend
+                .store_string(MQTT_HOST_KEY, mqtt.as_ref().map_or("", |broker| &broker.host))?;
+            self.storage_backend
+                .store_string(MQTT_PORT_KEY, &mqtt.as_ref().map_or(MQTT_PORT, |broker| broker.port).to_string())?;
+            self.storage_backend
+                .store_string(MQTT_USERNAME_KEY, mqtt.as_ref().map_or("", |broker| &broker.username))?;
+            self.storage_backend
+                .store_string(MQTT_PASSWORD_KEY, mqtt.as_ref().map_or("", |broker| &broker.password))?;
+            self.storage_backend
+                .store_string(UPDATE_SERVER_KEY, &configuration.get_update_server().unwrap())?;
+            self.storage_backend
+                .store_string(UPDATE_CHANNEL_KEY, &configuration.get_update_channel().unwrap().to_string())?;
+            self.storage_backend
+                .store_string(CONFIG_VERSION_KEY, &CURRENT_CONFIG_VERSION.to_string())?;
             self.storage_backend
                 .store_string(VALID_CONFIG_KEY, VALID_CONFIG_VALUE)?;
         } else {
@@ -330,6 +916,138 @@ impl<P: PersistentStorage> ConfigurationManager<P> {
         Ok(())
     }
 
+    /// Version of the stored layout.
+    ///
+    /// Layouts stored before the `config_version` key are recognized by their keys.
+    fn stored_version(&mut self) -> u32 {
+        let has_key = |storage: &mut P, key| storage.load_string(key).is_ok_and(|value| !value.is_empty());
+        match self.storage_backend.load_string(CONFIG_VERSION_KEY) {
+            Ok(value) if !value.is_empty() => value.parse().unwrap_or(u32::MAX),
+            _ if has_key(&mut self.storage_backend, DISPLAY_COLOR_KEY) => 1,
+            _ => 0,
+        }
+    }
+
+    /// Upgrade the stored layout from the given version to `CURRENT_CONFIG_VERSION`,
+    /// one version at a time.
+    fn migrate(&mut self, from: u32) -> Result<()> {
+        for version in from..CURRENT_CONFIG_VERSION {
+            info!("Migrate configuration from version {} to {}", version, version + 1);
+            match version {
+                0 => self.migrate_v0_to_v1()?,
+                1 => self.migrate_v1_to_v2()?,
+                2 => self.migrate_v2_to_v3()?,
+                3 => self.migrate_v3_to_v4()?,
+                4 => self.migrate_v4_to_v5()?,
+                5 => self.migrate_v5_to_v6()?,
+                6 => self.migrate_v6_to_v7()?,
+                _ => return Err(anyhow!("No migration from version {}", version)),
+            }
+            self.storage_backend
+                .store_string(CONFIG_VERSION_KEY, &(version + 1).to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Version 1 added the display color. The clocks before showed the time in
+    /// blue, keep it regardless of the current default color.
+    fn migrate_v0_to_v1(&mut self) -> Result<()> {
+        self.storage_backend
+            .store_string(DISPLAY_COLOR_KEY, &Color::new(0, 0, 255).to_string())
+    }
+
+    /// Version 2 added the `config_version` key, and stores a disabled night
+    /// mode as an empty string instead of a missing key.
+    fn migrate_v1_to_v2(&mut self) -> Result<()> {
+        for key in [NIGHT_START_KEY, NIGHT_END_KEY] {
+            if self.storage_backend.load_string(key).is_err() {
+                self.storage_backend.store_string(key, "")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Version 3 stores a list of WiFi networks, with indexed keys.
+    fn migrate_v2_to_v3(&mut self) -> Result<()> {
+        let ssid = self.storage_backend.load_string(WIFI_SSID_KEY)?;
+        let password = self.storage_backend.load_string(WIFI_PASSWORD_KEY)?;
+        self.storage_backend
+            .store_string(&network_key(WIFI_SSID_KEY, 0), &ssid)?;
+        self.storage_backend
+            .store_string(&network_key(WIFI_PASSWORD_KEY, 0), &password)?;
+        self.storage_backend
+            .store_string(&network_key(WIFI_PRIORITY_KEY, 0), &DEFAULT_WIFI_PRIORITY.to_string())?;
+        self.storage_backend.store_string(WIFI_COUNT_KEY, "1")
+    }
+
+    /// Version 4 added the display brightness and the dialect.
+    fn migrate_v3_to_v4(&mut self) -> Result<()> {
+        self.storage_backend
+            .store_string(BRIGHTNESS_KEY, &MAX_BRIGHTNESS.to_string())?;
+        self.storage_backend
+            .store_string(DIALECT_KEY, &Dialect::default().to_string())
+    }
+
+    /// Version 5 added the mDNS host name.
+    fn migrate_v4_to_v5(&mut self) -> Result<()> {
+        self.storage_backend.store_string(HOSTNAME_KEY, DEFAULT_HOSTNAME)
+    }
+
+    /// Version 6 added the MQTT broker, disabled by default.
+    fn migrate_v5_to_v6(&mut self) -> Result<()> {
+        self.storage_backend.store_string(MQTT_HOST_KEY, "")
+    }
+
+    /// Version 7 added the update server and channel, the stable GitHub releases by default.
+    fn migrate_v6_to_v7(&mut self) -> Result<()> {
+        self.storage_backend.store_string(UPDATE_SERVER_KEY, DEFAULT_UPDATE_SERVER)?;
+        self.storage_backend
+            .store_string(UPDATE_CHANNEL_KEY, &UpdateChannel::default().to_string())
+    }
+
+    fn load_string_or_default(&mut self, key: &str) -> String {
+        self.storage_backend.load_string(key).unwrap_or_default()
+    }
+
+    /// Load the stored WiFi networks. Networks with missing credentials are skipped.
+    fn load_networks(&mut self) -> Vec<WifiCredentials> {
+        let count = match self.storage_backend.load_string(WIFI_COUNT_KEY) {
+            Ok(value) => value.parse().unwrap_or(0).min(MAX_WIFI_NETWORKS),
+            Err(_) => 0,
+        };
+
+        let mut networks = Vec::new();
+        for index in 0..count {
+            let (Ok(ssid), Ok(password)) = (
+                self.storage_backend.load_string(&network_key(WIFI_SSID_KEY, index)),
+                self.storage_backend.load_string(&network_key(WIFI_PASSWORD_KEY, index)),
+            ) else {
+                warn!("Missing credentials of WiFi network {}", index);
+                continue;
+            };
+            let priority = self
+                .storage_backend
+                .load_string(&network_key(WIFI_PRIORITY_KEY, index))
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_WIFI_PRIORITY);
+            networks.push(WifiCredentials { ssid, password, priority });
+        }
+        networks
+    }
+
+    fn load_time(&mut self, key: &str) -> Option<Time> {
+        match self.storage_backend.load_string(key) {
+            Ok(value) if value.len() == TIME_STRING_LENGTH => Time::from_str(&value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Storage backend, shared with the state kept next to the configuration
+    pub fn storage(&mut self) -> &mut P {
+        &mut self.storage_backend
+    }
+
     /// Clean the Configuration validity flag in persistent memory.
     ///
     /// # Error
@@ -341,6 +1059,11 @@ impl<P: PersistentStorage> ConfigurationManager<P> {
     }
 }
 
+/// Storage key of a WiFi network field, like `wifi_ssid_0`.
+fn network_key(key: &str, index: usize) -> String {
+    format!("{}_{}", key, index)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -358,11 +1081,16 @@ mod tests {
         assert_eq!(
             Configuration {
                 state: ConfigurationState::Valid(ConfigurationFields {
-                    ssid: String::from("myhomenetwork"),
-                    password: String::from("1234"),
+                    networks: vec![WifiCredentials::new("myhomenetwork", "1234", DEFAULT_WIFI_PRIORITY)],
                     night_start: None,
                     night_end: None,
                     display_color: Color::new(0, 0, 255),
+                    brightness: MAX_BRIGHTNESS,
+                    dialect: Dialect::Bern,
+                    hostname: String::from(DEFAULT_HOSTNAME),
+                    mqtt: None,
+                    update_server: String::from(DEFAULT_UPDATE_SERVER),
+                    update_channel: UpdateChannel::Stable,
                 }),
             },
             config
@@ -375,11 +1103,16 @@ mod tests {
         assert_eq!(
             Configuration {
                 state: ConfigurationState::Valid(ConfigurationFields {
-                    ssid: String::from("myhomenetwork"),
-                    password: String::from("1234"),
+                    networks: vec![WifiCredentials::new("myhomenetwork", "1234", DEFAULT_WIFI_PRIORITY)],
                     night_start: Some(Time::new(23, 30, 0).unwrap()),
                     night_end: Some(Time::new(4, 40, 0).unwrap()),
                     display_color: Color::new(0, 0, 255),
+                    brightness: MAX_BRIGHTNESS,
+                    dialect: Dialect::Bern,
+                    hostname: String::from(DEFAULT_HOSTNAME),
+                    mqtt: None,
+                    update_server: String::from(DEFAULT_UPDATE_SERVER),
+                    update_channel: UpdateChannel::Stable,
                 }),
             },
             config
@@ -392,11 +1125,16 @@ mod tests {
         assert_eq!(
             Configuration {
                 state: ConfigurationState::Valid(ConfigurationFields {
-                    ssid: String::from("Solnet-1234"),
-                    password: String::from("Secret@-7"),
+                    networks: vec![WifiCredentials::new("Solnet-1234", "Secret@-7", DEFAULT_WIFI_PRIORITY)],
                     night_start: None,
                     night_end: None,
                     display_color: Color::new(0, 0, 255),
+                    brightness: MAX_BRIGHTNESS,
+                    dialect: Dialect::Bern,
+                    hostname: String::from(DEFAULT_HOSTNAME),
+                    mqtt: None,
+                    update_server: String::from(DEFAULT_UPDATE_SERVER),
+                    update_channel: UpdateChannel::Stable,
                 }),
             },
             config
@@ -409,31 +1147,252 @@ mod tests {
         assert_eq!(
             Configuration {
                 state: ConfigurationState::Valid(ConfigurationFields {
-                    ssid: String::from("Solnet-1234"),
-                    password: String::from("1234"),
+                    networks: vec![WifiCredentials::new("Solnet-1234", "1234", DEFAULT_WIFI_PRIORITY)],
                     night_start: None,
                     night_end: None,
                     display_color: Color::new(0, 255, 0),
+                    brightness: MAX_BRIGHTNESS,
+                    dialect: Dialect::Bern,
+                    hostname: String::from(DEFAULT_HOSTNAME),
+                    mqtt: None,
+                    update_server: String::from(DEFAULT_UPDATE_SERVER),
+                    update_channel: UpdateChannel::Stable,
                 }),
             },
             config
         );
     }
 
+    #[test]
+    fn from_form_fields_in_any_order() {
+        let fields = form_urlencoded::parse("favcolor=%2300ff00&unknown=1&input_night_mode_end=04%3A40&input_wifi_password=1234&input_wifi_ssid=myhomenetwork");
+        let config = Configuration::from_form_fields(&fields).unwrap();
+        assert_eq!(
+            Configuration {
+                state: ConfigurationState::Valid(ConfigurationFields {
+                    networks: vec![WifiCredentials::new("myhomenetwork", "1234", DEFAULT_WIFI_PRIORITY)],
+                    night_start: None,
+                    night_end: Some(Time::new(4, 40, 0).unwrap()),
+                    display_color: Color::new(0, 255, 0),
+                    brightness: MAX_BRIGHTNESS,
+                    dialect: Dialect::Bern,
+                    hostname: String::from(DEFAULT_HOSTNAME),
+                    mqtt: None,
+                    update_server: String::from(DEFAULT_UPDATE_SERVER),
+                    update_channel: UpdateChannel::Stable,
+                }),
+            },
+            config
+        );
+    }
+
+    #[test]
+    fn from_form_fields_without_ssid() {
+        let fields = form_urlencoded::parse("input_wifi_password=1234");
+        assert_eq!(Configuration::from_form_fields(&fields), Err(vec![FieldError::EmptySsid(0)]));
+    }
+
+    #[test]
+    fn from_form_fields_with_invalid_time() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_night_mode_start=25%3A00&input_night_mode_end=4");
+        assert_eq!(
+            Configuration::from_form_fields(&fields),
+            Err(vec![
+                FieldError::InvalidTime(FORM_NIGHT_START_KEY),
+                FieldError::InvalidTime(FORM_NIGHT_END_KEY)
+            ])
+        );
+    }
+
+    #[test]
+    fn from_form_fields_reports_all_errors() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=&input_night_mode_start=11%3A59&input_night_mode_end=12%3A01&favcolor=%23zz0000");
+        let errors = Configuration::from_form_fields(&fields).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                FieldError::EmptySsid(0),
+                FieldError::NightStartOutOfRange,
+                FieldError::NightEndOutOfRange,
+                FieldError::InvalidColor
+            ]
+        );
+        assert_eq!(errors[1].field(), FORM_NIGHT_START_KEY);
+    }
+
+    #[test]
+    fn night_window_limits() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_night_mode_start=12%3A00&input_night_mode_end=12%3A00");
+        assert!(Configuration::from_form_fields(&fields).is_ok());
+    }
+
     #[test]
     fn from_uri_query_string_with_invalid_color() {
         let config = Configuration::from_uri_query_string("/get?input_wifi_ssid=Solnet-1234&input_wifi_password=1234&input_night_mode_start=&input_night_mode_end=&favcolor=%23000000").unwrap();
         assert_eq!(
             Configuration {
                 state: ConfigurationState::Valid(ConfigurationFields {
-                    ssid: String::from("Solnet-1234"),
-                    password: String::from("1234"),
+                    networks: vec![WifiCredentials::new("Solnet-1234", "1234", DEFAULT_WIFI_PRIORITY)],
                     night_start: None,
                     night_end: None,
                     display_color: Default::default(),
+                    brightness: MAX_BRIGHTNESS,
+                    dialect: Dialect::Bern,
+                    hostname: String::from(DEFAULT_HOSTNAME),
+                    mqtt: None,
+                    update_server: String::from(DEFAULT_UPDATE_SERVER),
+                    update_channel: UpdateChannel::Stable,
                 }),
             },
             config
         );
     }
+
+    #[test]
+    fn from_form_fields_with_networks() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_wifi_password=1234&input_wifi_priority=2\
+            &input_wifi_ssid=&input_wifi_password=&input_wifi_priority=\
+            &input_wifi_ssid=holiday&input_wifi_password=abcd&input_wifi_priority=");
+        let config = Configuration::from_form_fields(&fields).unwrap();
+        assert_eq!(
+            config.get_networks(),
+            vec![
+                WifiCredentials::new("home", "1234", 2),
+                WifiCredentials::new("holiday", "abcd", DEFAULT_WIFI_PRIORITY)
+            ]
+        );
+    }
+
+    #[test]
+    fn from_form_fields_with_invalid_networks() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_wifi_password=1234&input_wifi_priority=high\
+            &input_wifi_ssid=&input_wifi_password=abcd&input_wifi_priority=1");
+        assert_eq!(
+            Configuration::from_form_fields(&fields),
+            Err(vec![FieldError::InvalidPriority(0), FieldError::EmptySsid(1)])
+        );
+        assert_eq!(FieldError::EmptySsid(1).network(), Some(1));
+
+        let too_many = "input_wifi_ssid=a&input_wifi_password=1&".repeat(MAX_WIFI_NETWORKS + 1);
+        assert_eq!(
+            Configuration::from_form_fields(&form_urlencoded::parse(&too_many)),
+            Err(vec![FieldError::TooManyNetworks])
+        );
+    }
+
+    #[test]
+    fn from_form_fields_with_display_settings() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_brightness=40&input_dialect=half_hour");
+        let config = Configuration::from_form_fields(&fields).unwrap();
+        assert_eq!(config.get_brightness(), Some(40));
+        assert_eq!(config.get_dialect(), Some(Dialect::HalfHour));
+
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_brightness=0&input_dialect=zurich");
+        assert_eq!(
+            Configuration::from_form_fields(&fields),
+            Err(vec![FieldError::InvalidBrightness, FieldError::InvalidDialect])
+        );
+    }
+
+    #[test]
+    fn update_settings() {
+        let mut config = Configuration::new(
+            String::from("home"),
+            String::from("1234"),
+            Some(Time::new(22, 0, 0).unwrap()),
+            None,
+            Color::new(0, 255, 0),
+        );
+        let fields = form_urlencoded::parse("favcolor=%23ff0000&input_brightness=25&input_night_mode_start=&input_night_mode_end=06%3A15&input_dialect=half_hour&input_hostname=kitchen&input_mqtt_host=&input_mqtt_port=&input_mqtt_username=");
+        config.update_settings(&fields).unwrap();
+
+        assert_eq!(config.get_networks(), vec![WifiCredentials::new("home", "1234", DEFAULT_WIFI_PRIORITY)]);
+        assert_eq!(config.get_display_color(), Some(Color::new(255, 0, 0)));
+        assert_eq!(config.get_brightness(), Some(25));
+        assert_eq!(config.get_night_start(), None);
+        assert_eq!(config.get_night_end(), Some(Time::new(6, 15, 0).unwrap()));
+        assert_eq!(config.get_dialect(), Some(Dialect::HalfHour));
+        assert_eq!(config.get_hostname(), Some(String::from("kitchen")));
+        assert_eq!(config.get_update_server(), Some(String::from(DEFAULT_UPDATE_SERVER)));
+        assert_eq!(config.get_update_channel(), Some(UpdateChannel::Stable));
+        assert_eq!(config.settings_fields()[..SETTINGS_KEYS.len()], fields[..]);
+
+        // The update settings require the configuration form
+        let fields = form_urlencoded::parse("input_update_server=http%3A%2F%2Fmirror.lan%2Fota&input_update_channel=nightly");
+        config.update_settings(&fields).unwrap();
+        assert_eq!(config.get_update_server(), Some(String::from(DEFAULT_UPDATE_SERVER)));
+        assert_eq!(config.get_update_channel(), Some(UpdateChannel::Stable));
+        let fields = form_urlencoded::parse("input_update_server=ftp%3A%2F%2Fmirror.lan");
+        assert!(config.update_settings(&fields).is_ok());
+
+        // Invalid fields leave the configuration unchanged
+        let before = config.clone();
+        let fields = form_urlencoded::parse("favcolor=%23ff0000&input_brightness=101");
+        assert_eq!(config.update_settings(&fields), Err(vec![FieldError::InvalidBrightness]));
+        assert_eq!(config, before);
+    }
+
+    #[test]
+    fn hostname_setting() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_hostname=Living-Room");
+        let config = Configuration::from_form_fields(&fields).unwrap();
+        assert_eq!(config.get_hostname(), Some(String::from("living-room")));
+
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_hostname=");
+        let config = Configuration::from_form_fields(&fields).unwrap();
+        assert_eq!(config.get_hostname(), Some(String::from(DEFAULT_HOSTNAME)));
+
+        for hostname in ["-clock", "clock-", "my.clock", "grüezi", "my%20clock"] {
+            let fields = form_urlencoded::parse(&format!("input_wifi_ssid=home&input_hostname={}", hostname));
+            assert_eq!(Configuration::from_form_fields(&fields), Err(vec![FieldError::InvalidHostname]), "{}", hostname);
+        }
+    }
+
+    #[test]
+    fn mqtt_setting() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_mqtt_host=192.168.1.2&input_mqtt_username=clock&input_mqtt_password=secret");
+        let mut config = Configuration::from_form_fields(&fields).unwrap();
+        let broker = MqttBroker { username: String::from("clock"), password: String::from("secret"), ..MqttBroker::new("192.168.1.2") };
+        assert_eq!(config.get_mqtt_broker(), Some(broker.clone()));
+
+        // The password is not shown, and kept while the broker and user are unchanged
+        let fields = config.settings_fields();
+        assert!(!fields.iter().any(|(key, _)| key == FORM_MQTT_PASSWORD_KEY));
+        config.update_settings(&fields).unwrap();
+        assert_eq!(config.get_mqtt_broker(), Some(broker));
+        config.update_settings(&form_urlencoded::parse("input_mqtt_host=broker.lan&input_mqtt_username=clock")).unwrap();
+        assert_eq!(config.get_mqtt_broker().unwrap().password, "");
+
+        // Disabled without host
+        config.update_settings(&form_urlencoded::parse("input_mqtt_host=&input_mqtt_port=1884")).unwrap();
+        assert_eq!(config.get_mqtt_broker(), None);
+
+        for (fields, error) in [
+            ("input_mqtt_host=my broker", FieldError::InvalidMqttHost),
+            ("input_mqtt_host=broker..lan", FieldError::InvalidMqttHost),
+            ("input_mqtt_host=broker.lan&input_mqtt_port=0", FieldError::InvalidMqttPort),
+            ("input_mqtt_host=broker.lan&input_mqtt_port=65536", FieldError::InvalidMqttPort),
+        ] {
+            assert_eq!(config.update_settings(&form_urlencoded::parse(fields)), Err(vec![error]), "{}", fields);
+        }
+    }
+
+    #[test]
+    fn night_window() {
+        let time = |hour, minute| Time::new(hour, minute, 0).unwrap();
+        let night = |start: Option<Time>, end: Option<Time>| Configuration::new(String::from("ssid"), String::new(), start, end, Color::default());
+
+        // Across midnight
+        let config = night(Some(time(22, 30)), Some(time(6, 15)));
+        for (hour, minute, is_night) in [(22, 29, false), (22, 30, true), (23, 10, true), (0, 0, true), (6, 14, true), (6, 15, false), (12, 0, false)] {
+            assert_eq!(config.is_night(time(hour, minute)), is_night, "{}:{}", hour, minute);
+        }
+
+        // Within a day, and until midnight without an end
+        assert!(night(Some(time(13, 0)), Some(time(14, 0))).is_night(time(13, 30)));
+        assert!(!night(Some(time(13, 0)), Some(time(14, 0))).is_night(time(23, 0)));
+        assert!(night(Some(time(22, 30)), None).is_night(time(23, 10)));
+        assert!(!night(Some(time(22, 30)), None).is_night(time(1, 0)));
+        assert!(!night(None, Some(time(6, 0))).is_night(time(1, 0)));
+    }
 }
@@ -0,0 +1,427 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::{
+    to_form_time, Configuration, CURRENT_CONFIG_VERSION, FORM_BRIGHTNESS_KEY, FORM_DIALECT_KEY, FORM_DISPLAY_COLOR_KEY,
+    FORM_HOSTNAME_KEY, FORM_MQTT_HOST_KEY, FORM_MQTT_PASSWORD_KEY, FORM_MQTT_PORT_KEY, FORM_MQTT_USERNAME_KEY,
+    FORM_NIGHT_END_KEY, FORM_NIGHT_START_KEY, FORM_PASSWORD_KEY, FORM_PRIORITY_KEY, FORM_SSID_KEY,
+    FORM_UPDATE_CHANNEL_KEY, FORM_UPDATE_SERVER_KEY,
+};
+use crate::form_urlencoded::{self, FormFields};
+use crate::mqtt::MQTT_PORT;
+
+/// Value of the `format` member, identifying a configuration backup
+pub const BACKUP_FORMAT: &str = "wordclock-configuration";
+
+/// Maximum size of an uploaded backup
+pub const MAX_BACKUP_LENGTH: usize = 4096;
+
+/// Whether secrets are written to a backup
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Secrets {
+    Include,
+    /// Secrets are written as `null`.
+    Redact,
+}
+
+/// JSON document of a configuration backup, see `doc/configuration_backup.md`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Backup {
+    format: String,
+    config_version: u32,
+    wifi: WifiBackups,
+    night_mode: Option<NightModeBackup>,
+    display_color: String,
+    /// Missing before version 4, full brightness
+    #[serde(default)]
+    brightness: Option<u8>,
+    /// Missing before version 4, default dialect
+    #[serde(default)]
+    dialect: Option<String>,
+    /// Missing before version 5, default host name
+    #[serde(default)]
+    hostname: Option<String>,
+    /// Missing before version 6, MQTT disabled
+    #[serde(default)]
+    mqtt: Option<MqttBackup>,
+    /// Missing before version 7, stable channel of the GitHub releases
+    #[serde(default)]
+    update: Option<UpdateBackup>,
+}
+
+/// Backups before version 3 hold a single network.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum WifiBackups {
+    List(Vec<WifiBackup>),
+    Single(WifiBackup),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct WifiBackup {
+    ssid: String,
+    password: Option<String>,
+    #[serde(default)]
+    priority: u8,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct MqttBackup {
+    host: String,
+    #[serde(default = "default_mqtt_port")]
+    port: u16,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+fn default_mqtt_port() -> u16 {
+    MQTT_PORT
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct UpdateBackup {
+    server: String,
+    channel: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct NightModeBackup {
+    start: Option<String>,
+    end: Option<String>,
+}
+
+impl Configuration {
+    /// Serialize a valid configuration to a JSON backup.
+    pub fn to_json(&self, secrets: Secrets) -> Result<String> {
+        let Some(display_color) = self.get_display_color() else {
+            return Err(anyhow!("Can't backup invalid configuration"));
+        };
+
+        let night_start = self.get_night_start();
+        let night_end = self.get_night_end();
+        let night_mode = match (night_start, night_end) {
+            (None, None) => None,
+            _ => Some(NightModeBackup {
+                start: night_start.map(to_form_time),
+                end: night_end.map(to_form_time),
+            }),
+        };
+
+        let backup = Backup {
+            format: String::from(BACKUP_FORMAT),
+            config_version: CURRENT_CONFIG_VERSION,
+            wifi: WifiBackups::List(
+                self.get_networks()
+                    .into_iter()
+                    .map(|network| WifiBackup {
+                        ssid: network.ssid,
+                        password: match secrets {
+                            Secrets::Include => Some(network.password),
+                            Secrets::Redact => None,
+                        },
+                        priority: network.priority,
+                    })
+                    .collect(),
+            ),
+            night_mode,
+            display_color: display_color.to_string(),
+            brightness: self.get_brightness(),
+            dialect: self.get_dialect().map(|dialect| dialect.to_string()),
+            hostname: self.get_hostname(),
+            mqtt: self.get_mqtt_broker().map(|broker| MqttBackup {
+                host: broker.host,
+                port: broker.port,
+                username: broker.username,
+                password: match secrets {
+                    Secrets::Include => Some(broker.password),
+                    Secrets::Redact => None,
+                },
+            }),
+            update: match (self.get_update_server(), self.get_update_channel()) {
+                (Some(server), Some(channel)) => Some(UpdateBackup { server, channel: channel.to_string() }),
+                _ => None,
+            },
+        };
+        Ok(serde_json::to_string_pretty(&backup)?)
+    }
+
+    /// Restore a configuration from a JSON backup.
+    ///
+    /// A redacted password is taken from the network of `current` with the same SSID.
+    pub fn from_json(json: &str, current: &Configuration) -> Result<Self> {
+        Self::from_form_fields(&backup_to_form_fields(json, current, &[])?).map_err(|errors| {
+            let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            anyhow!("Invalid backup: {}", messages.join(", "))
+        })
+    }
+}
+
+/// Convert a JSON backup to the fields of the configuration form, to be
+/// validated like a form submitted by the user.
+///
+/// A redacted password is taken from `passwords`, the SSID and password fields
+/// entered next to the uploaded file, or else from the network of `current`
+/// with the same SSID.
+pub fn backup_to_form_fields(json: &str, current: &Configuration, passwords: &[(String, String)]) -> Result<FormFields> {
+    let backup: Backup = serde_json::from_str(json)?;
+    if backup.format != BACKUP_FORMAT {
+        return Err(anyhow!("Not a configuration backup: {}", backup.format));
+    }
+    if backup.config_version > CURRENT_CONFIG_VERSION {
+        return Err(anyhow!("Backup version {} is not supported", backup.config_version));
+    }
+
+    let networks = match backup.wifi {
+        WifiBackups::List(networks) => networks,
+        WifiBackups::Single(network) => vec![network],
+    };
+    let mut known_passwords: Vec<(String, String)> = form_urlencoded::get_all(passwords, FORM_SSID_KEY)
+        .into_iter()
+        .zip(form_urlencoded::get_all(passwords, FORM_PASSWORD_KEY))
+        .map(|(ssid, password)| (String::from(ssid), String::from(password)))
+        .collect();
+    known_passwords.extend(current.get_networks().into_iter().map(|network| (network.ssid, network.password)));
+
+    let mut fields = Vec::new();
+    for network in networks {
+        let password = match network.password {
+            Some(password) => password,
+            None => match known_passwords.iter().find(|(ssid, _)| *ssid == network.ssid) {
+                Some((_, password)) => password.clone(),
+                None => return Err(anyhow!("Backup without password for WiFi network {:?}", network.ssid)),
+            },
+        };
+        fields.push((String::from(FORM_SSID_KEY), network.ssid));
+        fields.push((String::from(FORM_PASSWORD_KEY), password));
+        fields.push((String::from(FORM_PRIORITY_KEY), network.priority.to_string()));
+    }
+
+    if let Some(mqtt) = backup.mqtt {
+        // A redacted password is kept for the same broker and user, otherwise left empty
+        let password = mqtt.password.unwrap_or_else(|| match current.get_mqtt_broker() {
+            Some(broker) if broker.host == mqtt.host && broker.username == mqtt.username => broker.password,
+            _ => String::new(),
+        });
+        fields.push((String::from(FORM_MQTT_HOST_KEY), mqtt.host));
+        fields.push((String::from(FORM_MQTT_PORT_KEY), mqtt.port.to_string()));
+        fields.push((String::from(FORM_MQTT_USERNAME_KEY), mqtt.username));
+        fields.push((String::from(FORM_MQTT_PASSWORD_KEY), password));
+    }
+
+    if let Some(update) = backup.update {
+        fields.push((String::from(FORM_UPDATE_SERVER_KEY), update.server));
+        fields.push((String::from(FORM_UPDATE_CHANNEL_KEY), update.channel));
+    }
+
+    let night_mode = backup.night_mode.unwrap_or(NightModeBackup { start: None, end: None });
+    for (key, value) in [
+        (FORM_NIGHT_START_KEY, night_mode.start.unwrap_or_default()),
+        (FORM_NIGHT_END_KEY, night_mode.end.unwrap_or_default()),
+        (FORM_DISPLAY_COLOR_KEY, backup.display_color),
+        (FORM_BRIGHTNESS_KEY, backup.brightness.map(|brightness| brightness.to_string()).unwrap_or_default()),
+        (FORM_DIALECT_KEY, backup.dialect.unwrap_or_default()),
+        (FORM_HOSTNAME_KEY, backup.hostname.unwrap_or_default()),
+    ] {
+        if !value.is_empty() {
+            fields.push((String::from(key), value));
+        }
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::configuration::{MqttBroker, WifiCredentials, DEFAULT_WIFI_PRIORITY};
+    use crate::display::Dialect;
+    use crate::firmware_update::{UpdateChannel, DEFAULT_UPDATE_SERVER, MAX_UPDATE_SERVER_LENGTH};
+    use crate::configuration::{MAX_BROKER_HOST_LENGTH, MAX_WIFI_NETWORKS};
+    use crate::form_urlencoded::MAX_FORM_BODY_LENGTH;
+    use crate::network::MAX_HOSTNAME_LENGTH;
+    use crate::time::Time;
+
+    fn configuration() -> Configuration {
+        Configuration::new(
+            String::from("home \"wifi\""),
+            String::from("secret"),
+            Some(Time::new(22, 0, 0).unwrap()),
+            Some(Time::new(6, 30, 0).unwrap()),
+            Color::new(0, 255, 0),
+        )
+        .with_brightness(60)
+        .with_dialect(Dialect::HalfHour)
+        .with_hostname("kitchen")
+    }
+
+    #[test]
+    fn roundtrip() {
+        let json = configuration().to_json(Secrets::Include).unwrap();
+        assert_eq!(Configuration::from_json(&json, &Configuration::default()).unwrap(), configuration());
+    }
+
+    #[test]
+    fn documented_format() {
+        let json = configuration().to_json(Secrets::Include).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "format": "wordclock-configuration",
+                "config_version": CURRENT_CONFIG_VERSION,
+                "wifi": [{"ssid": "home \"wifi\"", "password": "secret", "priority": 0}],
+                "night_mode": {"start": "22:00", "end": "06:30"},
+                "display_color": "00FF00",
+                "brightness": 60,
+                "dialect": "half_hour",
+                "hostname": "kitchen",
+                "mqtt": null,
+                "update": {"server": DEFAULT_UPDATE_SERVER, "channel": "stable"},
+            })
+        );
+    }
+
+    #[test]
+    fn redacted_password() {
+        let json = configuration().to_json(Secrets::Redact).unwrap();
+        assert!(!json.contains("secret"));
+
+        // Password is restored from the current configuration with the same SSID
+        assert_eq!(Configuration::from_json(&json, &configuration()).unwrap(), configuration());
+        assert!(Configuration::from_json(&json, &Configuration::default()).is_err());
+    }
+
+    #[test]
+    fn redacted_backup_on_empty_configuration() {
+        let json = configuration().to_json(Secrets::Redact).unwrap();
+        let passwords = vec![
+            (String::from(FORM_SSID_KEY), String::from("home \"wifi\"")),
+            (String::from(FORM_PASSWORD_KEY), String::from("secret")),
+        ];
+
+        let fields = backup_to_form_fields(&json, &Configuration::default(), &passwords).unwrap();
+        assert_eq!(Configuration::from_form_fields(&fields).unwrap(), configuration());
+
+        // The password entered with the file wins over the stored one
+        let stored = Configuration::new(String::from("home \"wifi\""), String::from("old"), None, None, Color::default());
+        let fields = backup_to_form_fields(&json, &stored, &passwords).unwrap();
+        assert_eq!(Configuration::from_form_fields(&fields).unwrap(), configuration());
+
+        let other_network = vec![(String::from(FORM_SSID_KEY), String::from("other")), (String::from(FORM_PASSWORD_KEY), String::from("secret"))];
+        assert!(backup_to_form_fields(&json, &Configuration::default(), &other_network).is_err());
+    }
+
+    #[test]
+    fn several_networks() {
+        let configuration = Configuration::with_networks(
+            vec![WifiCredentials::new("home", "1234", 2), WifiCredentials::new("holiday", "abcd", DEFAULT_WIFI_PRIORITY)],
+            None,
+            None,
+            Color::new(0, 0, 255),
+        );
+        let json = configuration.to_json(Secrets::Include).unwrap();
+        assert_eq!(Configuration::from_json(&json, &Configuration::default()).unwrap(), configuration);
+
+        // Only the known networks can be restored from a redacted backup
+        let json = configuration.to_json(Secrets::Redact).unwrap();
+        let current = Configuration::new(String::from("holiday"), String::from("abcd"), None, None, Color::default());
+        assert!(Configuration::from_json(&json, &current).is_err());
+        assert_eq!(Configuration::from_json(&json, &configuration).unwrap(), configuration);
+    }
+
+    #[test]
+    fn update_server() {
+        let staged = configuration().with_update_server("http://192.168.1.10:8000").with_update_channel(UpdateChannel::Beta);
+        let json = staged.to_json(Secrets::Redact).unwrap();
+        assert!(json.contains("\"channel\": \"beta\""));
+        assert_eq!(Configuration::from_json(&json, &staged).unwrap(), staged);
+
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["update"]["channel"] = serde_json::json!("weekly");
+        assert!(Configuration::from_json(&value.to_string(), &staged).is_err());
+    }
+
+    #[test]
+    fn mqtt_broker() {
+        let broker = MqttBroker { port: 8883, username: String::from("clock"), password: String::from("pass"), ..MqttBroker::new("broker.lan") };
+        let with_mqtt = configuration().with_mqtt_broker(Some(broker));
+        let json = with_mqtt.to_json(Secrets::Include).unwrap();
+        assert_eq!(Configuration::from_json(&json, &Configuration::default()).unwrap(), with_mqtt);
+
+        // A redacted password is restored from the same broker only
+        let json = with_mqtt.to_json(Secrets::Redact).unwrap();
+        assert!(!json.contains("pass\""));
+        assert_eq!(Configuration::from_json(&json, &with_mqtt).unwrap(), with_mqtt);
+        let restored = Configuration::from_json(&json, &configuration()).unwrap();
+        assert_eq!(restored.get_mqtt_broker().unwrap().password, "");
+    }
+
+    /// Every field at its maximum length: SSIDs of 32 bytes, WPA2 passphrases
+    /// of 63 characters and MQTT credentials of 64 characters
+    fn largest_configuration() -> Configuration {
+        let networks = (0..MAX_WIFI_NETWORKS)
+            .map(|index| WifiCredentials::new(&format!("{:\"<32}", index), &"\\".repeat(63), 255))
+            .collect();
+        let host = format!("{}.lan", "b".repeat(MAX_BROKER_HOST_LENGTH - 4));
+        let broker = MqttBroker { port: 65535, username: "u".repeat(64), password: "\"".repeat(64), ..MqttBroker::new(&host) };
+        let server = format!("https://{}", "s".repeat(MAX_UPDATE_SERVER_LENGTH - 8));
+        Configuration::with_networks(networks, Some(Time::new(23, 59, 0).unwrap()), Some(Time::new(11, 59, 0).unwrap()), Color::new(255, 255, 255))
+            .with_dialect(Dialect::HalfHour)
+            .with_hostname(&"h".repeat(MAX_HOSTNAME_LENGTH))
+            .with_mqtt_broker(Some(broker))
+            .with_update_server(&server)
+            .with_update_channel(UpdateChannel::Nightly)
+    }
+
+    #[test]
+    fn largest_backup_is_restored() {
+        let json = largest_configuration().to_json(Secrets::Include).unwrap();
+        assert!(json.len() <= MAX_BACKUP_LENGTH, "Backup of {} bytes", json.len());
+        assert_eq!(Configuration::from_json(&json, &Configuration::default()).unwrap(), largest_configuration());
+
+        // Same fields submitted with the form, each byte percent-encoded
+        let fields = backup_to_form_fields(&json, &Configuration::default(), &[]).unwrap();
+        let form_length: usize = fields.iter().map(|(key, value)| key.len() + 3 * value.len() + 2).sum();
+        assert!(form_length <= MAX_FORM_BODY_LENGTH, "Form of {} bytes", form_length);
+    }
+
+    #[test]
+    fn without_night_mode() {
+        let json = r#"{"format": "wordclock-configuration", "config_version": 2,
+            "wifi": {"ssid": "home", "password": "1234"}, "night_mode": null, "display_color": "0000ff"}"#;
+        let restored = Configuration::from_json(json, &Configuration::default()).unwrap();
+        assert_eq!(
+            restored,
+            Configuration::new(String::from("home"), String::from("1234"), None, None, Color::new(0, 0, 255))
+        );
+        assert_eq!(restored.get_brightness(), Some(100));
+        assert!(restored.to_json(Secrets::Include).unwrap().contains("\"night_mode\": null"));
+    }
+
+    #[test]
+    fn invalid_backups() {
+        let current = Configuration::default();
+        assert!(Configuration::from_json("not json", &current).is_err());
+        assert!(Configuration::from_json(r#"{"format": "other"}"#, &current).is_err());
+        assert!(Configuration::from_json(
+            r#"{"format": "wordclock-configuration", "config_version": 99,
+                "wifi": {"ssid": "home", "password": "1234"}, "night_mode": null, "display_color": "0000ff"}"#,
+            &current
+        )
+        .is_err());
+        assert!(Configuration::from_json(
+            r#"{"format": "wordclock-configuration", "config_version": 2,
+                "wifi": {"ssid": "home", "password": "1234"}, "night_mode": {"start": "08:00", "end": null},
+                "display_color": "0000ff"}"#,
+            &current
+        )
+        .is_err());
+        assert!(Configuration::default().to_json(Secrets::Include).is_err());
+    }
+}
@@ -2,14 +2,23 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
-/// HTML configuration form
-pub const CONFIGURATION_FORM: &str = r##"
-    <!DOCTYPE HTML>
-    <html>
-        <head>
-        <title>Word-Clock</title>
-        <meta name="viewport" content="width=device-width, initial-scale=1">
-        </head>
+use crate::configuration::{
+    FieldError, FORM_BRIGHTNESS_KEY, FORM_DIALECT_KEY, FORM_DISPLAY_COLOR_KEY, FORM_HOSTNAME_KEY, FORM_NIGHT_END_KEY,
+    FORM_NIGHT_START_KEY, FORM_PASSWORD_KEY, FORM_PIN_KEY, FORM_PRIORITY_KEY, FORM_SSID_KEY, FORM_UPDATE_CHANNEL_KEY,
+    FORM_UPDATE_SERVER_KEY, MAX_WIFI_NETWORKS, SETTINGS_KEYS, UPDATE_KEYS,
+};
+use crate::display::Dialect;
+use crate::firmware_update::UpdateChannel;
+use crate::form_urlencoded;
+use crate::network::DEFAULT_HOSTNAME;
+
+/// Default value of the color input
+const DEFAULT_FORM_COLOR: &str = "#ffffff";
+
+/// Style shared by the pages, to be used in `concat!()`
+macro_rules! form_style {
+    () => {
+        r##"
         <style>
             * {
                 box-sizing: border-box;
@@ -39,6 +48,12 @@ pub const CONFIGURATION_FORM: &str = r##"
                 width: auto;
                 margin-top: 4px;
             }
+            input[type=number] {
+                border-width: 1px;
+                border-radius: 16px;
+                width: 5em;
+                margin-top: 4px;
+            }
             input[type=time] {
                 border-width: 1px;
                 border-radius: 16px;
@@ -67,41 +82,623 @@ pub const CONFIGURATION_FORM: &str = r##"
             input[type=submit]:hover {
                 background-color: #0a3494;
             }
+            .config-network {
+                border-bottom: 1px solid #00000030;
+                margin-bottom: 8px;
+            }
+            button {
+                border: 1px solid #1755e6;
+                border-radius: 16px;
+                background: none;
+                color: #1755e6;
+                padding: 4px 16px;
+                margin: 8px;
+            }
+            input[type=range] {
+                width: 12em;
+                vertical-align: middle;
+            }
+            select {
+                border-width: 1px;
+                border-radius: 16px;
+                margin-top: 4px;
+            }
+            .config-saved {
+                color: #1a7f37;
+                margin-bottom: 16px;
+            }
+            .config-error {
+                color: #d01c1c;
+                font-size: small;
+                margin-top: 4px;
+            }
         </style>
+"##
+    };
+}
+
+/// HTML configuration form template
+///
+/// `{{key}}` placeholders are replaced by the escaped value of the field, and
+/// `{{key_error}}` by its error message. `{{networks}}` is replaced by one
+/// `NETWORK_ROW` per WiFi network, `{{update_channel_options}}` by the update
+/// channels, and `{{pin_display}}` hides the PIN card when not required. Use
+/// `render_configuration_form()`.
+pub const CONFIGURATION_FORM: &str = concat!(
+    r##"
+    <!DOCTYPE HTML>
+    <html>
+        <head>
+        <title>Word-Clock</title>
+        <meta charset="utf-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1">
+        </head>
+"##,
+    form_style!(),
+    r##"
         <body>
             <h1>WordClock configuration</h1>
-            <form action="/get" target="hidden-form">
+            <form action="/config" method="post">
+                <div class="config-card" style="display: {{pin_display}}">
+                    <h2 class="config-title">PIN</h2>
+                    <div class="config-element">
+                        <label for="input_pin">Code shown on the clock</label>
+                        <input type="text" id="input_pin" name="input_pin" inputmode="numeric" autocomplete="off" placeholder="0000" value="{{input_pin}}">
+                        {{input_pin_error}}
+                    </div>
+                </div>
                 <div class="config-card">
                     <h2 class="config-title">WiFi</h2>
-                    <div class="config-element">
-                        <label for="input_wifi_ssid">SSID (name)</label>
-                        <input type="text" class="form-control" name="input_wifi_ssid" placeholder="Your WiFi network name">
+                    <div id="networks">
+                        {{networks}}
                     </div>
+                    {{networks_error}}
+                    <datalist id="scanned_networks"></datalist>
+                    <button type="button" onclick="addNetwork()">Add network</button>
+                    <button type="button" onclick="loadNetworks(true)">Refresh networks</button>
                     <div class="config-element">
-                        <label for="input_wifi_password">Password</label>
-                        <input type="text" name="input_wifi_password" placeholder="Your WiFi network password">
+                        Pick a network in the list of visible networks, or type the name of a hidden network.
+                        When several networks are visible, the one with the highest priority is used.
                     </div>
+                    <template id="network_row">{{network_row}}</template>
                 </div>
                 <div class="config-card">
                     <h2 class="config-title">Night-mode</h2>
                     <div class="config-element">
                         <label for="input_night_mode_start">Start at:</label>
-                        <input type="time" name="input_night_mode_start" placeholder="22:00">
-                        Must be between 12:00 and 23:59 
+                        <input type="time" name="input_night_mode_start" placeholder="22:00" value="{{input_night_mode_start}}">
+                        Must be between 12:00 and 23:59
+                        {{input_night_mode_start_error}}
                     </div>
                     <div class="config-element">
                         <label for="input_night_mode_end">End at:</label>
-                        <input type="time" name="input_night_mode_end" placeholder="06:30">
+                        <input type="time" name="input_night_mode_end" placeholder="06:30" value="{{input_night_mode_end}}">
                         Must be between 00:00 and 12:00
+                        {{input_night_mode_end_error}}
                     </div>
                 </div>
                 <div class="config-card">
                     <h2 class="config-title">Display color</h2>
-                    <input type="color" id="favcolor" name="favcolor" value="#ffffff">
+                    <input type="color" id="favcolor" name="favcolor" value="{{favcolor}}">
+                    {{favcolor_error}}
                 </div>
-                <input id="submit" type="submit" value="Submit" onclick="submitMessage()">
+                <div class="config-card">
+                    <h2 class="config-title">Updates</h2>
+                    <div class="config-element">
+                        <label for="input_update_server">Server</label>
+                        <input type="url" id="input_update_server" name="input_update_server" maxlength="128" placeholder="Official releases" value="{{input_update_server}}">
+                        {{input_update_server_error}}
+                    </div>
+                    <div class="config-element">
+                        <label for="input_update_channel">Channel</label>
+                        <select id="input_update_channel" name="input_update_channel">
+                            {{update_channel_options}}
+                        </select>
+                        {{input_update_channel_error}}
+                    </div>
+                    <div class="config-element">
+                        An http:// server on your network can stage the updates. Leave the server empty for the official releases.
+                    </div>
+                </div>
+                <input id="submit" type="submit" value="Submit">
             </form>
-            <iframe style="display:none" name="hidden-form"></iframe>
+            <div class="config-card">
+                <h2 class="config-title">Backup</h2>
+                <div class="config-element">
+                    <a href="/backup">Download configuration</a> (without passwords)
+                </div>
+                <form action="/restore" method="post" enctype="text/plain" onsubmit="return restoreBackup(this)">
+                    <div class="config-element">
+                        <label for="backup_file">Restore from file</label>
+                        <input type="file" id="backup_file" accept="application/json,.json" onchange="showRestorePasswords()">
+                    </div>
+                    <div id="restore_passwords"></div>
+                    <input type="submit" value="Restore">
+                </form>
+            </div>
+            <script>
+                function signalBars(rssi) {
+                    return rssi >= -55 ? "▂▄▆█" : rssi >= -67 ? "▂▄▆" : rssi >= -80 ? "▂▄" : "▂";
+                }
+                function loadNetworks(rescan) {
+                    fetch(rescan ? "/networks?rescan=1" : "/networks").then(response => response.json()).then(networks => {
+                        const options = networks.map(network => {
+                            const option = document.createElement("option");
+                            option.value = network.ssid;
+                            option.label = signalBars(network.rssi) + " " + network.rssi + " dBm"
+                                + (network.security == "open" ? ", open" : "");
+                            return option;
+                        });
+                        document.getElementById("scanned_networks").replaceChildren(...options);
+                    });
+                }
+                window.addEventListener("load", () => loadNetworks(false));
+                function addNetwork() {
+                    const networks = document.getElementById("networks");
+                    if (networks.children.length < {{max_networks}}) {
+                        networks.appendChild(document.getElementById("network_row").content.cloneNode(true));
+                    }
+                }
+                function showRestorePasswords() {
+                    const passwords = document.getElementById("restore_passwords");
+                    passwords.replaceChildren();
+                    const file = document.getElementById("backup_file").files[0];
+                    if (!file) {
+                        return;
+                    }
+                    // The downloaded backups are without WiFi password, ask for them
+                    file.text().then(backup => {
+                        const networks = [].concat(JSON.parse(backup).wifi || []);
+                        for (const network of networks.filter(network => network.password === null)) {
+                            const element = document.createElement("div");
+                            element.className = "config-element";
+                            const label = document.createElement("label");
+                            label.textContent = "Password of " + network.ssid;
+                            const input = document.createElement("input");
+                            input.type = "password";
+                            input.dataset.ssid = network.ssid;
+                            element.append(label, input);
+                            passwords.appendChild(element);
+                        }
+                    }).catch(() => {});
+                }
+                function restoreBackup(form) {
+                    const file = document.getElementById("backup_file").files[0];
+                    if (!file) {
+                        return false;
+                    }
+                    const query = new URLSearchParams({input_pin: document.getElementById("input_pin").value});
+                    for (const input of document.querySelectorAll("#restore_passwords input")) {
+                        query.append("input_wifi_ssid", input.dataset.ssid);
+                        query.append("input_wifi_password", input.value);
+                    }
+                    file.text().then(backup => fetch("/restore?" + query, {method: "POST", body: backup}))
+                        .then(response => response.text())
+                        .then(page => { document.open(); document.write(page); document.close(); });
+                    return false;
+                }
+            </script>
+        </body>
+    </html>
+"##
+);
+
+/// HTML template of a WiFi network in the configuration form, see `CONFIGURATION_FORM`
+pub const NETWORK_ROW: &str = r##"
+                        <div class="config-network">
+                            <div class="config-element">
+                                <label>SSID (name)</label>
+                                <input type="text" class="form-control" list="scanned_networks" name="input_wifi_ssid" placeholder="Your WiFi network name" value="{{input_wifi_ssid}}">
+                                {{input_wifi_ssid_error}}
+                            </div>
+                            <div class="config-element">
+                                <label>Password</label>
+                                <input type="text" name="input_wifi_password" placeholder="Your WiFi network password" value="{{input_wifi_password}}">
+                                {{input_wifi_password_error}}
+                            </div>
+                            <div class="config-element">
+                                <label>Priority</label>
+                                <input type="number" name="input_wifi_priority" min="0" max="255" placeholder="0" value="{{input_wifi_priority}}">
+                                {{input_wifi_priority_error}}
+                            </div>
+                            <button type="button" onclick="this.parentElement.remove()">Remove</button>
+                        </div>
+"##;
+
+/// HTML page shown once the configuration is accepted
+pub const CONFIGURATION_SUBMITTED: &str = r##"
+    <!DOCTYPE HTML>
+    <html>
+        <head>
+        <title>Word-Clock</title>
+        <meta charset="utf-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1">
+        </head>
+        <body style="font-family: Arial, Helvetica, sans-serif; margin: 32px;">
+            <h1>WordClock configuration</h1>
+            <p>Configuration saved. The clock connects to your WiFi network and starts displaying the time.</p>
         </body>
     </html>
-"##;
\ No newline at end of file
+"##;
+
+/// HTML settings page template, served in station mode
+///
+/// Same placeholders as `CONFIGURATION_FORM`. `{{dialect_options}}` is
+/// replaced by the dialects, and `{{settings_status}}` by a confirmation once
+/// saved. The update settings are read-only. The PIN is shown on the clock
+/// while the page is open. Use `render_settings_form()`.
+pub const SETTINGS_FORM: &str = concat!(
+    r##"
+    <!DOCTYPE HTML>
+    <html>
+        <head>
+        <title>Word-Clock</title>
+        <meta charset="utf-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1">
+        </head>
+"##,
+    form_style!(),
+    r##"
+        <body>
+            <h1>WordClock settings</h1>
+            {{settings_status}}
+            <form action="/settings" method="post">
+                <div class="config-card" style="display: {{pin_display}}">
+                    <h2 class="config-title">PIN</h2>
+                    <div class="config-element">
+                        <label for="input_pin">Code shown on the clock</label>
+                        <input type="text" id="input_pin" name="input_pin" inputmode="numeric" autocomplete="off" placeholder="0000" value="{{input_pin}}">
+                        {{input_pin_error}}
+                    </div>
+                </div>
+                <div class="config-card">
+                    <h2 class="config-title">Display</h2>
+                    <div class="config-element">
+                        <label for="favcolor">Color</label>
+                        <input type="color" id="favcolor" name="favcolor" value="{{favcolor}}">
+                        {{favcolor_error}}
+                    </div>
+                    <div class="config-element">
+                        <label for="input_brightness">Brightness</label>
+                        <input type="range" id="input_brightness" name="input_brightness" min="1" max="100" value="{{input_brightness}}" oninput="brightness_value.value = this.value">
+                        <output id="brightness_value">{{input_brightness}}</output> %
+                        {{input_brightness_error}}
+                    </div>
+                    <div class="config-element">
+                        <label for="input_dialect">Dialect</label>
+                        <select id="input_dialect" name="input_dialect">
+                            {{dialect_options}}
+                        </select>
+                        {{input_dialect_error}}
+                    </div>
+                </div>
+                <div class="config-card">
+                    <h2 class="config-title">Night-mode</h2>
+                    <div class="config-element">
+                        <label for="input_night_mode_start">Start at:</label>
+                        <input type="time" name="input_night_mode_start" placeholder="22:00" value="{{input_night_mode_start}}">
+                        Must be between 12:00 and 23:59
+                        {{input_night_mode_start_error}}
+                    </div>
+                    <div class="config-element">
+                        <label for="input_night_mode_end">End at:</label>
+                        <input type="time" name="input_night_mode_end" placeholder="06:30" value="{{input_night_mode_end}}">
+                        Must be between 00:00 and 12:00
+                        {{input_night_mode_end_error}}
+                    </div>
+                </div>
+                <div class="config-card">
+                    <h2 class="config-title">Network</h2>
+                    <div class="config-element">
+                        <label for="input_hostname">Name</label>
+                        <input type="text" id="input_hostname" name="input_hostname" maxlength="63" pattern="[A-Za-z0-9\-]+" value="{{input_hostname}}">.local
+                        {{input_hostname_error}}
+                    </div>
+                </div>
+                <div class="config-card">
+                    <h2 class="config-title">MQTT</h2>
+                    <div class="config-element">
+                        <label for="input_mqtt_host">Broker</label>
+                        <input type="text" id="input_mqtt_host" name="input_mqtt_host" maxlength="128" placeholder="Disabled" value="{{input_mqtt_host}}">
+                        {{input_mqtt_host_error}}
+                    </div>
+                    <div class="config-element">
+                        <label for="input_mqtt_port">Port</label>
+                        <input type="number" id="input_mqtt_port" name="input_mqtt_port" min="1" max="65535" placeholder="1883" value="{{input_mqtt_port}}">
+                        {{input_mqtt_port_error}}
+                    </div>
+                    <div class="config-element">
+                        <label for="input_mqtt_username">User</label>
+                        <input type="text" id="input_mqtt_username" name="input_mqtt_username" autocomplete="off" value="{{input_mqtt_username}}">
+                    </div>
+                    <div class="config-element">
+                        <label for="input_mqtt_password">Password</label>
+                        <input type="password" id="input_mqtt_password" name="input_mqtt_password" autocomplete="off" placeholder="Unchanged">
+                    </div>
+                    <div class="config-element">
+                        The clock is discovered by Home Assistant. Leave the broker empty to disable MQTT.
+                    </div>
+                </div>
+                <div class="config-card">
+                    <h2 class="config-title">Updates</h2>
+                    <div class="config-element">
+                        <label for="input_update_server">Server</label>
+                        <input type="url" id="input_update_server" value="{{input_update_server}}" disabled>
+                    </div>
+                    <div class="config-element">
+                        <label for="input_update_channel">Channel</label>
+                        <select id="input_update_channel" disabled>
+                            {{update_channel_options}}
+                        </select>
+                    </div>
+                    <div class="config-element">
+                        Changed in the configuration form, protected like the WiFi.
+                    </div>
+                </div>
+                <input id="submit" type="submit" value="Save">
+            </form>
+            <div class="config-card">
+                <h2 class="config-title">Backup</h2>
+                <div class="config-element">
+                    <a href="/backup">Download configuration</a> (without passwords)
+                </div>
+            </div>
+        </body>
+    </html>
+"##
+);
+
+/// WiFi network fields, repeated for each network
+const NETWORK_KEYS: [&str; 3] = [FORM_SSID_KEY, FORM_PASSWORD_KEY, FORM_PRIORITY_KEY];
+
+/// Render the configuration form, filled with the given values and errors.
+///
+/// At least one WiFi network is shown. The PIN field is only shown when required.
+pub fn render_configuration_form(fields: &[(String, String)], errors: &[FieldError], pin_required: bool) -> String {
+    let values = NETWORK_KEYS.map(|key| form_urlencoded::get_all(fields, key));
+    let count = values.iter().map(|values| values.len()).max().unwrap_or_default().max(1);
+
+    let mut networks = String::new();
+    for index in 0..count {
+        let mut row = String::from(NETWORK_ROW);
+        for (key, values) in NETWORK_KEYS.iter().zip(&values) {
+            let value = values.get(index).copied().unwrap_or_default();
+            let errors = errors.iter().filter(|error| error.network() == Some(index));
+            row = fill_field(&row, key, value, errors);
+        }
+        networks.push_str(&row);
+    }
+    let mut empty_row = String::from(NETWORK_ROW);
+    for key in NETWORK_KEYS {
+        empty_row = fill_field(&empty_row, key, "", [].iter());
+    }
+    let networks_errors = errors
+        .iter()
+        .filter(|error| error.network().is_none() && NETWORK_KEYS.contains(&error.field()));
+
+    let channel = form_urlencoded::get(fields, FORM_UPDATE_CHANNEL_KEY).unwrap_or_default();
+    let channels = UpdateChannel::ALL.map(|option| (option.to_string(), option.label()));
+
+    let mut page = CONFIGURATION_FORM
+        .replace("{{max_networks}}", &MAX_WIFI_NETWORKS.to_string())
+        .replace("{{update_channel_options}}", &select_options(&channels, channel))
+        .replace("{{pin_display}}", if pin_required { "block" } else { "none" })
+        .replace("{{network_row}}", &empty_row)
+        .replace("{{networks_error}}", &error_messages(networks_errors))
+        .replace("{{networks}}", &networks);
+    let keys = [FORM_PIN_KEY, FORM_NIGHT_START_KEY, FORM_NIGHT_END_KEY, FORM_DISPLAY_COLOR_KEY];
+    for key in keys.into_iter().chain(UPDATE_KEYS) {
+        let value = match form_urlencoded::get(fields, key) {
+            Some(value) => value,
+            None if key == FORM_DISPLAY_COLOR_KEY => DEFAULT_FORM_COLOR,
+            None => "",
+        };
+        page = fill_field(&page, key, value, errors.iter().filter(|error| error.network().is_none()));
+    }
+    page
+}
+
+/// Render the settings page, filled with the given values and errors.
+///
+/// Use the fields of `Configuration::settings_fields()` to show the current
+/// settings, or the submitted fields to show their errors. The PIN field is
+/// only shown when required.
+pub fn render_settings_form(fields: &[(String, String)], errors: &[FieldError], saved: bool, pin_required: bool) -> String {
+    let dialect = form_urlencoded::get(fields, FORM_DIALECT_KEY).unwrap_or_default();
+    let dialects = Dialect::ALL.map(|option| (option.to_string(), option.label()));
+    let channel = form_urlencoded::get(fields, FORM_UPDATE_CHANNEL_KEY).unwrap_or_default();
+    let channels = UpdateChannel::ALL.map(|option| (option.to_string(), option.label()));
+    let status = if saved { "<div class=\"config-saved\">Settings saved.</div>" } else { "" };
+
+    let mut page = SETTINGS_FORM
+        .replace("{{settings_status}}", status)
+        .replace("{{dialect_options}}", &select_options(&dialects, dialect))
+        .replace("{{update_channel_options}}", &select_options(&channels, channel))
+        .replace("{{pin_display}}", if pin_required { "block" } else { "none" });
+    for key in SETTINGS_KEYS.into_iter().chain([FORM_UPDATE_SERVER_KEY, FORM_PIN_KEY]) {
+        let value = match form_urlencoded::get(fields, key) {
+            Some(value) => value,
+            None if key == FORM_DISPLAY_COLOR_KEY => DEFAULT_FORM_COLOR,
+            None if key == FORM_BRIGHTNESS_KEY => "100",
+            None if key == FORM_HOSTNAME_KEY => DEFAULT_HOSTNAME,
+            None => "",
+        };
+        page = fill_field(&page, key, value, errors.iter());
+    }
+    page
+}
+
+/// Options of a `<select>`, from their value and label
+fn select_options(options: &[(String, &str)], selected: &str) -> String {
+    options
+        .iter()
+        .map(|(value, label)| {
+            let selected = if value == selected { " selected" } else { "" };
+            format!("<option value=\"{}\"{}>{}</option>", value, selected, escape_html(label))
+        })
+        .collect()
+}
+
+/// Replace the placeholders of a field by its value and the messages of its errors.
+fn fill_field<'a>(template: &str, key: &str, value: &str, errors: impl Iterator<Item = &'a FieldError>) -> String {
+    let messages = error_messages(errors.filter(|error| error.field() == key));
+    template
+        .replace(&format!("{{{{{}_error}}}}", key), &messages)
+        .replace(&format!("{{{{{}}}}}", key), &escape_html(value))
+}
+
+fn error_messages<'a>(errors: impl Iterator<Item = &'a FieldError>) -> String {
+    errors
+        .map(|error| format!("<div class=\"config-error\">{}</div>", escape_html(&error.to_string())))
+        .collect()
+}
+
+/// Escape text to be inserted in HTML content or attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            // Keep user input from matching a template placeholder
+            '{' => escaped.push_str("&#123;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_empty_form() {
+        let page = render_configuration_form(&[], &[], false);
+        assert!(!page.contains("{{"));
+        assert!(page.contains("name=\"input_wifi_ssid\" placeholder=\"Your WiFi network name\" value=\"\""));
+        assert!(page.contains("list=\"scanned_networks\""));
+        assert!(page.contains("value=\"#ffffff\""));
+        assert!(!page.contains("class=\"config-error\""));
+        assert!(page.contains("style=\"display: none\""));
+    }
+
+    #[test]
+    fn render_pin() {
+        let fields = form_urlencoded::parse("input_pin=12");
+        let page = render_configuration_form(&fields, &[FieldError::InvalidPin], true);
+
+        assert!(page.contains("style=\"display: block\""));
+        assert!(page.contains("name=\"input_pin\" inputmode=\"numeric\" autocomplete=\"off\" placeholder=\"0000\" value=\"12\""));
+        assert!(page.contains(&FieldError::InvalidPin.to_string()));
+    }
+
+    #[test]
+    fn render_values_and_errors() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=%22home%22%3Cnet%3E&input_night_mode_start=10%3A00");
+        let page = render_configuration_form(&fields, &[FieldError::NightStartOutOfRange], false);
+
+        assert!(page.contains("value=\"&quot;home&quot;&lt;net&gt;\""));
+        assert!(page.contains("value=\"10:00\""));
+        assert!(page.contains(&format!("<div class=\"config-error\">{}</div>", FieldError::NightStartOutOfRange)));
+        assert!(!page.contains("{{"));
+    }
+
+    #[test]
+    fn render_networks_and_errors() {
+        let fields = form_urlencoded::parse("input_wifi_ssid=home&input_wifi_password=1234&input_wifi_priority=2\
+            &input_wifi_ssid=&input_wifi_password=abcd&input_wifi_priority=");
+        let errors = [FieldError::EmptySsid(1), FieldError::NetworkAuthentication(0), FieldError::NetworkNotFound];
+        let page = render_configuration_form(&fields, &errors, false);
+
+        let rows: Vec<&str> = page.split("class=\"config-network\"").collect();
+        // Rendered networks, then the empty row template
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].contains("value=\"home\"") && rows[1].contains("value=\"2\""));
+        assert!(rows[1].contains(&FieldError::NetworkAuthentication(0).to_string()));
+        assert!(rows[2].contains("value=\"abcd\"") && rows[2].contains(&FieldError::EmptySsid(1).to_string()));
+        assert!(!rows[2].contains(&FieldError::NetworkAuthentication(0).to_string()));
+        assert!(rows[3].contains("value=\"\"") && !rows[3].contains("config-error"));
+        assert_eq!(page.matches(&FieldError::NetworkNotFound.to_string()).count(), 1);
+        assert!(!page.contains("{{"));
+    }
+
+    #[test]
+    fn render_settings() {
+        let fields = form_urlencoded::parse("favcolor=%2300ff00&input_brightness=40&input_night_mode_start=22%3A00&input_dialect=half_hour");
+        let page = render_settings_form(&fields, &[], true, false);
+
+        assert!(page.contains("value=\"#00ff00\""));
+        assert!(page.contains("max=\"100\" value=\"40\""));
+        assert!(page.contains("<output id=\"brightness_value\">40</output>"));
+        assert!(page.contains("value=\"22:00\""));
+        assert!(page.contains("<option value=\"half_hour\" selected>"));
+        assert!(page.contains("<option value=\"bern\">"));
+        assert!(page.contains("Settings saved."));
+        assert!(page.contains("name=\"input_hostname\" maxlength=\"63\" pattern=\"[A-Za-z0-9\\-]+\" value=\"wordclock\""));
+        assert!(!page.contains("input_wifi_password"));
+        assert!(page.contains("name=\"input_mqtt_host\" maxlength=\"128\" placeholder=\"Disabled\" value=\"\""));
+        assert!(page.contains("<option value=\"stable\">"));
+        assert!(!page.contains("{{"));
+    }
+
+    #[test]
+    fn render_update_settings() {
+        let fields = form_urlencoded::parse("input_update_server=http%3A%2F%2Fmirror.lan%2Fota&input_update_channel=beta");
+        let page = render_configuration_form(&fields, &[FieldError::InvalidUpdateServer], true);
+
+        assert!(page.contains("placeholder=\"Official releases\" value=\"http://mirror.lan/ota\""));
+        assert!(page.contains("<option value=\"beta\" selected>Beta (release candidates)</option>"));
+        assert!(page.contains("<option value=\"stable\">"));
+        assert!(page.contains(&FieldError::InvalidUpdateServer.to_string()));
+        assert!(!page.contains("{{"));
+
+        // Read-only on the settings page
+        let page = render_settings_form(&fields, &[], false, false);
+        assert!(page.contains("<input type=\"url\" id=\"input_update_server\" value=\"http://mirror.lan/ota\" disabled>"));
+        assert!(page.contains("<option value=\"beta\" selected>"));
+        assert!(!page.contains("name=\"input_update_server\""));
+        assert!(!page.contains("{{"));
+    }
+
+    #[test]
+    fn render_mqtt_settings() {
+        let fields = form_urlencoded::parse("input_mqtt_host=broker.lan&input_mqtt_port=1884&input_mqtt_username=clock&input_mqtt_password=secret");
+        let page = render_settings_form(&fields, &[FieldError::InvalidMqttPort], false, false);
+
+        assert!(page.contains("value=\"broker.lan\""));
+        assert!(page.contains("placeholder=\"1883\" value=\"1884\""));
+        assert!(page.contains("value=\"clock\""));
+        assert!(!page.contains("secret"));
+        assert!(page.contains(&FieldError::InvalidMqttPort.to_string()));
+        assert!(!page.contains("{{"));
+    }
+
+    #[test]
+    fn render_settings_errors() {
+        let fields = form_urlencoded::parse("input_brightness=300");
+        let page = render_settings_form(&fields, &[FieldError::InvalidBrightness], false, false);
+
+        assert!(page.contains("value=\"300\""));
+        assert!(page.contains(&FieldError::InvalidBrightness.to_string()));
+        assert!(!page.contains("Settings saved."));
+        assert!(!page.contains("{{"));
+    }
+
+    #[test]
+    fn render_settings_pin() {
+        let fields = form_urlencoded::parse("input_pin=12&input_brightness=40");
+        let page = render_settings_form(&fields, &[FieldError::InvalidPin], false, true);
+
+        assert!(page.contains("<div class=\"config-card\" style=\"display: block\">"));
+        assert!(page.contains("name=\"input_pin\" inputmode=\"numeric\" autocomplete=\"off\" placeholder=\"0000\" value=\"12\""));
+        assert!(page.contains(&FieldError::InvalidPin.to_string()));
+        assert!(!page.contains("{{"));
+
+        let page = render_settings_form(&fields, &[], false, false);
+        assert!(page.contains("<div class=\"config-card\" style=\"display: none\">"));
+    }
+}
@@ -2,13 +2,59 @@
 * Copyright (c) 2023 Louis Mayencourt
 */
 
+use crate::api::{ApiAction, ApiStatus};
+use crate::configuration::{Configuration, FieldError};
+use crate::form_urlencoded::FormFields;
+use crate::network::ScanResult;
+use crate::notification::Notification;
+
 /// Interface to get a new pending configuration
 pub trait ConfigurationServer {
     // Check if a new configuration was received from the user
     // Used as a condition to exit configuration mode.
     fn is_configuration_received(&self) -> bool;
 
-    // Return the HTTP URI query string if `is_configuration_received()` returned `true`
-    // The URI can only be returned once. The received flag and URI will be cleaned after this call.
-    fn get_config_uri(&mut self) -> Option<String>;
+    // Return the decoded form fields if `is_configuration_received()` returned `true`
+    // The fields can only be returned once. The received flag and fields will be cleaned after this call.
+    fn get_configuration_fields(&mut self) -> Option<FormFields>;
+
+    // Report the validation result of the last returned fields, to be shown to the user.
+    // An empty list means the configuration is accepted.
+    fn report_configuration_errors(&mut self, errors: Vec<FieldError>);
+
+    // Check if the configuration page asked for the visible networks. The request
+    // is cleared by this call. Scanning blocks for a few seconds, and drops the
+    // clients of the access point, so it is only done on request.
+    fn is_scan_requested(&mut self) -> bool;
+
+    // Update the visible networks offered to the user in the configuration form.
+    fn set_scan_results(&mut self, results: Vec<ScanResult>);
+
+    // Set the PIN shown on the display. It is asked in the configuration form and,
+    // once configured, to change the clock from the network. `None` asks for no PIN.
+    fn set_pin(&mut self, pin: Option<String>);
+
+    // Check if the PIN must be shown on the display, e.g. the settings page was
+    // opened. The request is cleared by this call.
+    fn is_pin_requested(&mut self) -> bool;
+
+    // Set the configuration shown on the settings page, available once the clock is configured.
+    // `None` disables the settings page, e.g. in configuration mode.
+    fn set_settings(&mut self, configuration: Option<Configuration>);
+
+    // Return the decoded fields of the settings page, if submitted since the last call.
+    fn get_settings_fields(&mut self) -> Option<FormFields>;
+
+    // Report the validation result of the last returned settings fields. An
+    // empty list means the settings are applied.
+    fn report_settings_errors(&mut self, errors: Vec<FieldError>);
+
+    // Publish the status served by the REST API, refreshed after every event.
+    fn set_status(&mut self, status: ApiStatus);
+
+    // Return the next action requested through the REST API, if any.
+    fn get_action(&mut self) -> Option<ApiAction>;
+
+    // Return the notification requested through the REST API or MQTT, if any.
+    fn get_notification(&mut self) -> Option<Notification>;
 }
@@ -2,9 +2,125 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
-use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
 
-use crate::{time::Time, color::Color};
+use anyhow::{anyhow, Result};
+
+use crate::{time::Time, color::Color, error_recovery::ErrorKind};
+
+/// Brightness of the display, in percent
+pub const MAX_BRIGHTNESS: u8 = 100;
+pub const MIN_BRIGHTNESS: u8 = 1;
+
+/// Wording used to tell the time.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Dialect {
+    /// 20 and 40 minutes are told from the hour: "zwänzg ab eis", "zwänzg vor zwöi".
+    #[default]
+    Bern,
+    /// 20 and 40 minutes are told from the half hour: "zää vor haubi zwöi", "zää ab haubi zwöi".
+    HalfHour,
+}
+
+impl Dialect {
+    pub const ALL: [Dialect; 2] = [Dialect::Bern, Dialect::HalfHour];
+
+    /// Human readable name, with an example
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Bern => "Bärn (zwänzg ab drü)",
+            Self::HalfHour => "Half hour (zää vor haubi vieri)",
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bern => write!(f, "bern"),
+            Self::HalfHour => write!(f, "half_hour"),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|dialect| dialect.to_string() == s)
+            .ok_or_else(|| anyhow!("Unknown dialect {:?}", s))
+    }
+}
+
+/// Word of the clock face, in Bärn German
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Word {
+    Es,
+    Isch,
+    Uhr,
+    Fuef,
+    Zaeae,
+    Viertu,
+    Zwaenzg,
+    Haubi,
+    Ab,
+    Vor,
+    /// Hour, from 1 to 12.
+    Hour(u8),
+}
+
+/// Words telling the given time, followed by the number of minute dots to light.
+///
+/// The time is rounded down to 5 minutes, the dots tell the remaining minutes.
+pub fn time_words(time: Time, dialect: Dialect) -> (Vec<Word>, u8) {
+    let half_hour_dialect = dialect == Dialect::HalfHour;
+
+    // 20 and 40 minutes are told from the half hour of the next hour
+    let next_hour_minute = if half_hour_dialect { 20 } else { 25 };
+    let mut hour = time.hour;
+    if time.minute >= next_hour_minute {
+        hour += 1;
+    }
+    hour %= 12;
+    if hour == 0 {
+        hour = 12;
+    }
+
+    let five_minutes = |minutes: u8| match minutes {
+        5 => Word::Fuef,
+        10 => Word::Zaeae,
+        15 => Word::Viertu,
+        _ => Word::Zwaenzg,
+    };
+    let rounded = time.minute - time.minute % 5;
+    let mut words = match rounded {
+        0 => vec![Word::Es, Word::Isch, Word::Uhr],
+        20 if half_hour_dialect => vec![Word::Zaeae, Word::Vor, Word::Haubi],
+        5..=20 => vec![five_minutes(rounded), Word::Ab],
+        25 => vec![Word::Fuef, Word::Vor, Word::Haubi],
+        30 => vec![Word::Haubi],
+        35 => vec![Word::Fuef, Word::Ab, Word::Haubi],
+        40 if half_hour_dialect => vec![Word::Zaeae, Word::Ab, Word::Haubi],
+        _ => vec![five_minutes(60 - rounded), Word::Vor],
+    };
+    words.push(Word::Hour(hour));
+
+    (words, time.minute % 5)
+}
+
+/// Small pictograms that can be drawn on the display, e.g. for menu entries.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Icon {
+    /// Progress dots, from 0 to 4.
+    Dots(u8),
+    /// Question mark, used to ask for a confirmation.
+    Question,
+    /// PIN code of 4 digits, from 0 to 9.
+    Pin([u8; 4]),
+}
 
 /// Interface to draw various things on a display.
 /// # Errors
@@ -16,8 +132,8 @@ pub trait Display {
     /// Draw the given time on the display.
     fn draw_time(&mut self, time: Time) -> Result<()>;
 
-    /// Draw an error sign on the display.
-    fn draw_error(&mut self) -> Result<()>;
+    /// Draw the error sign matching the given error on the display.
+    fn draw_error(&mut self, kind: ErrorKind) -> Result<()>;
 
     /// Draw a progress bar, with 4 levels.
     ///
@@ -25,6 +141,65 @@ pub trait Display {
     /// Fails early if progress higher than 4 is provided.
     fn draw_progress(&mut self, progress: u8) -> Result<()>;
 
+    /// Draw the given icon on the display.
+    fn draw_icon(&mut self, icon: Icon) -> Result<()>;
+
+    /// Light all the pixels with the given color, e.g. to flash a notification.
+    /// The brightness applies, black turns the pixels off.
+    fn fill(&mut self, color: Color) -> Result<()>;
+
     /// Set the default color to be used to draw on the display.
     fn set_default_color(&mut self, color: Color);
+
+    /// Set the brightness, from `MIN_BRIGHTNESS` to `MAX_BRIGHTNESS` percent.
+    /// Applies from the next drawing.
+    fn set_brightness(&mut self, brightness: u8);
+
+    /// Set the wording used by `draw_time()`.
+    fn set_dialect(&mut self, dialect: Dialect);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dialect_from_string() {
+        for dialect in Dialect::ALL {
+            assert_eq!(Dialect::from_str(&dialect.to_string()).unwrap(), dialect);
+        }
+        assert!(Dialect::from_str("zurich").is_err());
+    }
+
+    fn words(hour: u8, minute: u8, dialect: Dialect) -> (Vec<Word>, u8) {
+        time_words(Time::new(hour, minute, 0).unwrap(), dialect)
+    }
+
+    #[test]
+    fn bern_time_words() {
+        use Word::*;
+        assert_eq!(words(0, 0, Dialect::Bern), (vec![Es, Isch, Uhr, Hour(12)], 0));
+        assert_eq!(words(13, 3, Dialect::Bern), (vec![Es, Isch, Uhr, Hour(1)], 3));
+        assert_eq!(words(3, 5, Dialect::Bern), (vec![Fuef, Ab, Hour(3)], 0));
+        assert_eq!(words(3, 17, Dialect::Bern), (vec![Viertu, Ab, Hour(3)], 2));
+        assert_eq!(words(3, 20, Dialect::Bern), (vec![Zwaenzg, Ab, Hour(3)], 0));
+        assert_eq!(words(3, 25, Dialect::Bern), (vec![Fuef, Vor, Haubi, Hour(4)], 0));
+        assert_eq!(words(3, 30, Dialect::Bern), (vec![Haubi, Hour(4)], 0));
+        assert_eq!(words(3, 36, Dialect::Bern), (vec![Fuef, Ab, Haubi, Hour(4)], 1));
+        assert_eq!(words(3, 40, Dialect::Bern), (vec![Zwaenzg, Vor, Hour(4)], 0));
+        assert_eq!(words(3, 45, Dialect::Bern), (vec![Viertu, Vor, Hour(4)], 0));
+        assert_eq!(words(11, 59, Dialect::Bern), (vec![Fuef, Vor, Hour(12)], 4));
+        assert_eq!(words(23, 55, Dialect::Bern), (vec![Fuef, Vor, Hour(12)], 0));
+    }
+
+    #[test]
+    fn half_hour_time_words() {
+        use Word::*;
+        assert_eq!(words(3, 15, Dialect::HalfHour), (vec![Viertu, Ab, Hour(3)], 0));
+        assert_eq!(words(3, 20, Dialect::HalfHour), (vec![Zaeae, Vor, Haubi, Hour(4)], 0));
+        assert_eq!(words(3, 24, Dialect::HalfHour), (vec![Zaeae, Vor, Haubi, Hour(4)], 4));
+        assert_eq!(words(3, 40, Dialect::HalfHour), (vec![Zaeae, Ab, Haubi, Hour(4)], 0));
+        assert_eq!(words(3, 45, Dialect::HalfHour), (vec![Viertu, Vor, Hour(4)], 0));
+        assert_eq!(words(11, 20, Dialect::HalfHour), (vec![Zaeae, Vor, Haubi, Hour(12)], 0));
+    }
 }
@@ -0,0 +1,246 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use log::*;
+
+/// Delay before the first retry, in ticks
+pub const RETRY_INITIAL_DELAY_TICKS: u32 = 5;
+/// Upper bound of the retry delay, in ticks
+pub const RETRY_MAX_DELAY_TICKS: u32 = 10 * 60;
+/// Number of retries of a `RecoveryPolicy::RetryThenHalt` before halting
+pub const MAX_BOUNDED_RETRIES: u32 = 3;
+
+/// Category of a failure, used to choose how to recover from it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorKind {
+    /// The configured WiFi network is not reachable.
+    WifiNotFound,
+    /// The configured WiFi network refused the credentials.
+    WifiAuth,
+    /// No time source could be synchronized.
+    TimeSync,
+    /// The persistent storage can't be read or written.
+    Storage,
+    /// The firmware update failed.
+    Ota,
+    /// A peripheral of the board is not working.
+    Hardware,
+}
+
+/// How to leave the error state
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RecoveryPolicy {
+    /// Restart the startup sequence, after an exponential backoff delay.
+    RetryWithBackoff,
+    /// Retry with backoff at most `MAX_BOUNDED_RETRIES` times, then halt.
+    RetryThenHalt,
+    /// Resume the time display, using the board RTC time.
+    FallbackToRtc,
+    /// Go back to configuration mode, to get new settings from the user.
+    Reconfigure,
+    /// No automatic recovery, the device must be restarted.
+    Halt,
+}
+
+impl ErrorKind {
+    pub fn recovery_policy(&self) -> RecoveryPolicy {
+        match self {
+            ErrorKind::WifiNotFound => RecoveryPolicy::RetryWithBackoff,
+            ErrorKind::WifiAuth => RecoveryPolicy::Reconfigure,
+            ErrorKind::TimeSync => RecoveryPolicy::FallbackToRtc,
+            ErrorKind::Storage => RecoveryPolicy::RetryThenHalt,
+            ErrorKind::Ota => RecoveryPolicy::FallbackToRtc,
+            ErrorKind::Hardware => RecoveryPolicy::Halt,
+        }
+    }
+}
+
+/// Recovery step requested by `ErrorRecovery`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RecoveryStep {
+    /// Nothing to do yet.
+    Wait,
+    Retry,
+    FallbackToRtc,
+    Reconfigure,
+}
+
+/// Track consecutive errors and decide when and how to recover.
+///
+/// The delay between retries is counted in ticks, and doubles after each
+/// failed attempt, up to `RETRY_MAX_DELAY_TICKS`. A fallback that fails again
+/// before the device recovered is escalated to a retry, and a bounded retry
+/// halts once its retries are used up.
+pub struct ErrorRecovery {
+    kind: Option<ErrorKind>,
+    attempts: u32,
+    fallback_used: bool,
+    remaining_ticks: u32,
+}
+
+impl ErrorRecovery {
+    pub fn new() -> Self {
+        Self {
+            kind: None,
+            attempts: 0,
+            fallback_used: false,
+            remaining_ticks: 0,
+        }
+    }
+
+    /// Start recovering from the given error.
+    ///
+    /// Return the step to carry out immediately.
+    pub fn start(&mut self, kind: ErrorKind) -> RecoveryStep {
+        self.kind = Some(kind);
+
+        let policy = match kind.recovery_policy() {
+            RecoveryPolicy::FallbackToRtc if self.fallback_used => RecoveryPolicy::RetryWithBackoff,
+            RecoveryPolicy::RetryThenHalt if self.attempts < MAX_BOUNDED_RETRIES => RecoveryPolicy::RetryWithBackoff,
+            RecoveryPolicy::RetryThenHalt => RecoveryPolicy::Halt,
+            policy => policy,
+        };
+
+        info!("Recover from {:?} with {:?}", kind, policy);
+        match policy {
+            RecoveryPolicy::RetryWithBackoff => {
+                self.remaining_ticks = self.retry_delay();
+                self.attempts = self.attempts.saturating_add(1);
+                RecoveryStep::Wait
+            }
+            RecoveryPolicy::FallbackToRtc => {
+                self.fallback_used = true;
+                RecoveryStep::FallbackToRtc
+            }
+            RecoveryPolicy::Reconfigure => RecoveryStep::Reconfigure,
+            RecoveryPolicy::Halt | RecoveryPolicy::RetryThenHalt => {
+                self.remaining_ticks = 0;
+                RecoveryStep::Wait
+            }
+        }
+    }
+
+    /// Must be called on every tick while in error.
+    pub fn tick(&mut self) -> RecoveryStep {
+        if self.remaining_ticks == 0 {
+            return RecoveryStep::Wait;
+        }
+
+        self.remaining_ticks -= 1;
+        if self.remaining_ticks == 0 {
+            RecoveryStep::Retry
+        } else {
+            RecoveryStep::Wait
+        }
+    }
+
+    /// The device works again, forget about previous errors.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Error currently being recovered
+    pub fn kind(&self) -> Option<ErrorKind> {
+        self.kind
+    }
+
+    /// Number of retries since the last successful recovery
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    fn retry_delay(&self) -> u32 {
+        RETRY_INITIAL_DELAY_TICKS
+            .checked_shl(self.attempts)
+            .unwrap_or(RETRY_MAX_DELAY_TICKS)
+            .min(RETRY_MAX_DELAY_TICKS)
+    }
+}
+
+impl Default for ErrorRecovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks_until_retry(recovery: &mut ErrorRecovery) -> u32 {
+        let mut ticks = 1;
+        while recovery.tick() != RecoveryStep::Retry {
+            ticks += 1;
+        }
+        ticks
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let mut recovery = ErrorRecovery::new();
+
+        assert_eq!(recovery.start(ErrorKind::WifiNotFound), RecoveryStep::Wait);
+        assert_eq!(ticks_until_retry(&mut recovery), 5);
+        recovery.start(ErrorKind::WifiNotFound);
+        assert_eq!(ticks_until_retry(&mut recovery), 10);
+        recovery.start(ErrorKind::WifiNotFound);
+        assert_eq!(ticks_until_retry(&mut recovery), 20);
+        assert_eq!(recovery.attempts(), 3);
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let mut recovery = ErrorRecovery::new();
+        for _ in 0..40 {
+            recovery.start(ErrorKind::WifiNotFound);
+        }
+        assert_eq!(ticks_until_retry(&mut recovery), RETRY_MAX_DELAY_TICKS);
+    }
+
+    #[test]
+    fn reset_backoff() {
+        let mut recovery = ErrorRecovery::new();
+        recovery.start(ErrorKind::WifiNotFound);
+        recovery.start(ErrorKind::WifiNotFound);
+        recovery.reset();
+
+        recovery.start(ErrorKind::WifiNotFound);
+        assert_eq!(ticks_until_retry(&mut recovery), RETRY_INITIAL_DELAY_TICKS);
+    }
+
+    #[test]
+    fn fallback_is_escalated_to_retry() {
+        let mut recovery = ErrorRecovery::new();
+        assert_eq!(recovery.start(ErrorKind::TimeSync), RecoveryStep::FallbackToRtc);
+        assert_eq!(recovery.start(ErrorKind::TimeSync), RecoveryStep::Wait);
+        assert_eq!(ticks_until_retry(&mut recovery), RETRY_INITIAL_DELAY_TICKS);
+    }
+
+    #[test]
+    fn reconfigure_and_halt() {
+        let mut recovery = ErrorRecovery::new();
+        assert_eq!(recovery.start(ErrorKind::WifiAuth), RecoveryStep::Reconfigure);
+
+        assert_eq!(recovery.start(ErrorKind::Hardware), RecoveryStep::Wait);
+        for _ in 0..RETRY_MAX_DELAY_TICKS * 2 {
+            assert_eq!(recovery.tick(), RecoveryStep::Wait);
+        }
+        assert_eq!(recovery.kind(), Some(ErrorKind::Hardware));
+    }
+
+    #[test]
+    fn storage_retries_then_halts() {
+        let mut recovery = ErrorRecovery::new();
+        for _ in 0..MAX_BOUNDED_RETRIES {
+            assert_eq!(recovery.start(ErrorKind::Storage), RecoveryStep::Wait);
+            ticks_until_retry(&mut recovery);
+        }
+
+        assert_eq!(recovery.start(ErrorKind::Storage), RecoveryStep::Wait);
+        for _ in 0..RETRY_MAX_DELAY_TICKS * 2 {
+            assert_eq!(recovery.tick(), RecoveryStep::Wait);
+        }
+        assert_eq!(recovery.attempts(), MAX_BOUNDED_RETRIES);
+    }
+}
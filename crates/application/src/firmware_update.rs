@@ -2,19 +2,198 @@
 * Copyright (c) 2023 Louis Mayencourt
 */
 
-use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
 
-use crate::version::Version;
+use anyhow::{anyhow, Result};
+
+use crate::ota_manifest::{FirmwareImage, OtaManifest};
+
+/// Revision of the clock hardware, picks the image from the release manifest
+pub const HARDWARE_REVISION: &str = "v2";
+
+/// Directory of the released images on GitHub, holding a manifest per channel
+pub const DEFAULT_UPDATE_SERVER: &str = "https://raw.githubusercontent.com/lmayencourt/wordclock/released/ota-image";
+
+/// Longest update server URL, fits in a NVS string
+pub const MAX_UPDATE_SERVER_LENGTH: usize = 128;
+
+/// Release channel followed by the clock
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum UpdateChannel {
+    /// Tagged releases
+    #[default]
+    Stable,
+    /// Release candidates
+    Beta,
+    /// Builds of the main branch
+    Nightly,
+}
+
+impl UpdateChannel {
+    pub const ALL: [UpdateChannel; 3] = [UpdateChannel::Stable, UpdateChannel::Beta, UpdateChannel::Nightly];
+
+    /// Human readable name
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Stable => "Stable",
+            Self::Beta => "Beta (release candidates)",
+            Self::Nightly => "Nightly (development builds)",
+        }
+    }
+
+    /// Manifest of the channel, in the update server directory
+    pub fn manifest_file(&self) -> &'static str {
+        match self {
+            Self::Stable => "manifest.json",
+            Self::Beta => "manifest-beta.json",
+            Self::Nightly => "manifest-nightly.json",
+        }
+    }
+}
+
+impl fmt::Display for UpdateChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stable => write!(f, "stable"),
+            Self::Beta => write!(f, "beta"),
+            Self::Nightly => write!(f, "nightly"),
+        }
+    }
+}
+
+impl FromStr for UpdateChannel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|channel| channel.to_string() == s)
+            .ok_or_else(|| anyhow!("Unknown update channel {:?}", s))
+    }
+}
+
+/// URL of the manifest of `channel` on the update server
+pub fn manifest_url(server: &str, channel: UpdateChannel) -> String {
+    format!("{}/{}", server.trim_end_matches('/'), channel.manifest_file())
+}
+
+/// `http://` or `https://` URL of a directory, without query. Plain HTTP is
+/// meant for a mirror on the local network, the images are signed anyway.
+pub fn is_valid_update_server(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://")) else {
+        return false;
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    url.len() <= MAX_UPDATE_SERVER_LENGTH
+        && !host.is_empty()
+        && !host.starts_with(':')
+        && url.chars().all(|c| c.is_ascii_graphic() && !matches!(c, '?' | '#' | '"' | '<' | '>' | '\\'))
+}
 
 /// Interface to perform a firmware update
 pub trait FirmwareUpdate {
-    /// Return the version of the latest update available to download.
+    /// Return the manifest at `url`, listing the released firmware images.
     ///
     /// # Error
     /// Return an error if HTTP request or file parsing fails.
-    fn read_update_version(&self) -> Result<Version>;
+    fn read_manifest(&self, url: &str) -> Result<OtaManifest>;
 
-    fn download_update(&self) -> Result<()>;
+    /// Download `image` from `url` to the update partition, checked against
+    /// the size and SHA-256 of the manifest.
+    ///
+    /// # Error
+    /// Return an error if the download fails or the image doesn't match.
+    fn download_update(&self, image: &FirmwareImage, url: &str) -> Result<()>;
 
     fn reboot_to_new_image(&self);
+
+    /// Whether the bootloader waits for the confirmation of the running image.
+    /// Until confirmed, the previous image is booted on the next reset.
+    fn is_image_pending(&self) -> bool;
+
+    /// Keep the running image on the next boots.
+    ///
+    /// # Error
+    /// Return an error if the bootloader state can't be written.
+    fn confirm_image(&self) -> Result<()>;
+}
+
+/// Status and length of the response to a GET request
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ResponseHead {
+    pub status: u16,
+    /// `None` when the server doesn't send a `Content-Length`
+    pub content_length: Option<usize>,
+}
+
+/// HTTP client downloading the manifest and images, one request at a time
+pub trait HttpClient {
+    /// Send a GET request to `url` and read the head of the response.
+    ///
+    /// # Error
+    /// Return an error if the server can't be reached.
+    fn get(&mut self, url: &str) -> Result<ResponseHead>;
+
+    /// Read the next part of the response body into `buffer`, possibly less
+    /// than its length. Return 0 at the end of the body.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize>;
+}
+
+/// Destination of a downloaded image, e.g. the next OTA partition
+pub trait FirmwareSink {
+    /// Prepare to receive an image of `size` bytes.
+    fn begin(&mut self, size: usize) -> Result<()>;
+
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Boot the written image on next restart.
+    fn complete(&mut self) -> Result<()>;
+
+    /// Discard the written data, the running image stays the boot image.
+    fn abort(&mut self) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_of_the_channel() {
+        assert_eq!(
+            manifest_url(DEFAULT_UPDATE_SERVER, UpdateChannel::Stable),
+            "https://raw.githubusercontent.com/lmayencourt/wordclock/released/ota-image/manifest.json"
+        );
+        assert_eq!(manifest_url("http://192.168.1.10:8000/", UpdateChannel::Beta), "http://192.168.1.10:8000/manifest-beta.json");
+        assert_eq!(manifest_url("http://mirror.lan/ota", UpdateChannel::Nightly), "http://mirror.lan/ota/manifest-nightly.json");
+    }
+
+    #[test]
+    fn update_channel_names() {
+        for channel in UpdateChannel::ALL {
+            assert_eq!(UpdateChannel::from_str(&channel.to_string()).unwrap(), channel);
+        }
+        assert!(UpdateChannel::from_str("Stable").is_err());
+        assert!(UpdateChannel::from_str("").is_err());
+    }
+
+    #[test]
+    fn update_server_urls() {
+        for url in [DEFAULT_UPDATE_SERVER, "http://192.168.1.10:8000", "http://mirror.lan/ota-image/"] {
+            assert!(is_valid_update_server(url), "{}", url);
+        }
+        for url in [
+            "",
+            "mirror.lan",
+            "ftp://mirror.lan",
+            "http://",
+            "http://:8000/ota",
+            "http://mirror.lan/ota image",
+            "http://mirror.lan/manifest.json?",
+            "http://mirror.lan/#ota",
+            &format!("http://mirror.lan/{}", "a".repeat(MAX_UPDATE_SERVER_LENGTH)),
+        ] {
+            assert!(!is_valid_update_server(url), "{}", url);
+        }
+    }
 }
@@ -0,0 +1,165 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+/// Decoded key/value pairs of a form, in the order they were received.
+pub type FormFields = Vec<(String, String)>;
+
+/// Maximum size of an encoded form body, enough for every field of the
+/// configuration form at its maximum length
+pub const MAX_FORM_BODY_LENGTH: usize = 4096;
+
+/// Streaming `application/x-www-form-urlencoded` parser
+///
+/// The input can be fed in chunks of any size, as read from an HTTP body. Raw
+/// bytes of the current pair are buffered until the next `&`, so escape
+/// sequences split across chunks are handled.
+pub struct FormParser {
+    fields: FormFields,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    in_value: bool,
+}
+
+impl FormParser {
+    pub fn new() -> Self {
+        Self {
+            fields: Vec::new(),
+            key: Vec::new(),
+            value: Vec::new(),
+            in_value: false,
+        }
+    }
+
+    /// Feed the next chunk of the encoded input.
+    pub fn push(&mut self, chunk: &[u8]) {
+        for byte in chunk {
+            match byte {
+                b'&' => self.end_pair(),
+                b'=' if !self.in_value => self.in_value = true,
+                _ if self.in_value => self.value.push(*byte),
+                _ => self.key.push(*byte),
+            }
+        }
+    }
+
+    /// End of input, return all decoded fields.
+    pub fn finish(mut self) -> FormFields {
+        self.end_pair();
+        self.fields
+    }
+
+    fn end_pair(&mut self) {
+        if !self.key.is_empty() || !self.value.is_empty() {
+            self.fields.push((decode(&self.key), decode(&self.value)));
+        }
+        self.key.clear();
+        self.value.clear();
+        self.in_value = false;
+    }
+}
+
+impl Default for FormParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse a complete encoded form, like a HTTP POST body or URI query string.
+pub fn parse(input: &str) -> FormFields {
+    let mut parser = FormParser::new();
+    parser.push(input.as_bytes());
+    parser.finish()
+}
+
+/// Return the value of the first field with the given key.
+pub fn get<'a>(fields: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+}
+
+/// Return the values of all fields with the given key, in order.
+pub fn get_all<'a>(fields: &'a [(String, String)], key: &str) -> Vec<&'a str> {
+    fields.iter().filter(|(k, _)| k == key).map(|(_, v)| v.as_str()).collect()
+}
+
+/// Decode `+` and percent escape sequences. Invalid sequences are kept as is.
+fn decode(raw: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(raw.len());
+    let mut index = 0;
+    while index < raw.len() {
+        let escaped = match raw.get(index..index + 3) {
+            Some([b'%', high, low]) => hex_value(*high).zip(hex_value(*low)),
+            _ => None,
+        };
+        match (escaped, raw[index]) {
+            (Some((high, low)), _) => {
+                decoded.push(high << 4 | low);
+                index += 3;
+                continue;
+            }
+            (None, b'+') => decoded.push(b' '),
+            (None, byte) => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (String::from(key), String::from(value))
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(
+            parse("ssid=home&password=1234&empty="),
+            vec![pair("ssid", "home"), pair("password", "1234"), pair("empty", "")]
+        );
+        assert_eq!(parse(""), vec![]);
+        assert_eq!(parse("flag&&a=b"), vec![pair("flag", ""), pair("a", "b")]);
+    }
+
+    #[test]
+    fn decode_escape_sequences() {
+        assert_eq!(
+            parse("pass=Secret%40-7&name=my+home&time=23%3a30&utf8=B%C3%A4rn"),
+            vec![pair("pass", "Secret@-7"), pair("name", "my home"), pair("time", "23:30"), pair("utf8", "Bärn")]
+        );
+        assert_eq!(parse("value=a%2=b%zz%"), vec![pair("value", "a%2=b%zz%")]);
+    }
+
+    #[test]
+    fn chunked_input() {
+        let mut parser = FormParser::new();
+        for chunk in ["ss", "id=ho", "me&pass=Secret%", "4", "0", "&color=%2300ff00"] {
+            parser.push(chunk.as_bytes());
+        }
+        assert_eq!(
+            parser.finish(),
+            vec![pair("ssid", "home"), pair("pass", "Secret@"), pair("color", "#00ff00")]
+        );
+    }
+
+    #[test]
+    fn get_first_value() {
+        let fields = parse("a=1&b=2&a=3");
+        assert_eq!(get(&fields, "a"), Some("1"));
+        assert_eq!(get(&fields, "b"), Some("2"));
+        assert_eq!(get(&fields, "c"), None);
+        assert_eq!(get_all(&fields, "a"), vec!["1", "3"]);
+        assert!(get_all(&fields, "c").is_empty());
+    }
+}
@@ -4,31 +4,49 @@
 
 use log::*;
 use std::collections::VecDeque;
-use std::thread;
 use std::time::Duration;
 
-use anyhow::Result;
-
+use api::{ApiAction, ApiStatus};
 use behaviour::*;
-use configuration::{Configuration, ConfigurationManager, PersistentStorage};
+use boot_validation::ImageState;
+use build_version::BUILD_VERSION_STRING;
+use configuration::{Configuration, ConfigurationManager, FieldError, PersistentStorage, FORM_PIN_KEY};
 use configuration_server::ConfigurationServer;
-use display::Display;
-use firmware_update::FirmwareUpdate;
-use network::Network;
+use display::{Display, Icon};
+use error_recovery::{ErrorKind, ErrorRecovery, RecoveryStep};
+use firmware_update::{manifest_url, FirmwareUpdate, DEFAULT_UPDATE_SERVER, HARDWARE_REVISION};
+use notification::Notification;
+use network::{rank_networks, setup_configuration_access_point, AccessPointSecurity, Network, NetworkError, DEFAULT_HOSTNAME};
 use power_manager::PowerManager;
 use time_source::TimeSource;
+use version::Version;
 
 use crate::time_source::TimeSourceError;
 
+pub mod api;
 pub mod behaviour;
+pub mod boot_validation;
 pub mod build_version;
+pub mod button_input;
+pub mod captive_portal;
 pub mod color;
 pub mod configuration;
+pub mod configuration_backup;
 pub mod configuration_form;
 pub mod configuration_server;
 pub mod display;
+pub mod error_recovery;
 pub mod firmware_update;
+pub mod form_urlencoded;
+pub mod mdns;
+pub mod menu;
+pub mod mqtt;
+pub mod mqtt_bridge;
 pub mod network;
+pub mod notification;
+pub mod ota_download;
+pub mod ota_manifest;
+pub mod ota_signature;
 pub mod power_manager;
 pub mod time;
 pub mod time_monotonic;
@@ -36,6 +54,22 @@ pub mod time_source;
 pub mod time_source_manager;
 pub mod version;
 
+/// Default time to wait for a new configuration, before retrying the stored one.
+/// One tick per second on the device.
+pub const DEFAULT_CONFIGURATION_TIMEOUT_TICKS: u32 = 5 * 60;
+
+/// Wrong PINs accepted before the configuration form is locked
+pub const MAX_PIN_ATTEMPTS: u32 = 5;
+/// First lockout after too many wrong PINs, doubled on each further wrong PIN
+pub const PIN_LOCKOUT_TICKS: u32 = 60;
+/// Longest lockout after wrong PINs
+pub const MAX_PIN_LOCKOUT_TICKS: u32 = 60 * 60;
+/// Ticks the PIN is shown in place of the time, once asked for from the network
+pub const PIN_DISPLAY_TICKS: u32 = 60;
+
+/// Connection error of each WiFi network tried, with its index in the configuration
+type NetworkErrors = Vec<(usize, anyhow::Error)>;
+
 pub struct Application<
     D: Display,
     T: TimeSource,
@@ -54,6 +88,20 @@ pub struct Application<
     pub power_manager: P,
     pub firmware_update: F,
     behaviour: Behaviour,
+    error_recovery: ErrorRecovery,
+    configuration_timeout: Option<u32>,
+    configuration_ticks: u32,
+    configuration_timeout_cancelled: bool,
+    access_point_security: AccessPointSecurity,
+    /// Wrong PINs since the last right one, and the remaining ticks of the lockout
+    pin_failures: u32,
+    pin_lockout_ticks: u32,
+    /// Remaining ticks to show the PIN in place of the time
+    pin_display_ticks: u32,
+    image_state: ImageState,
+    /// Notification to show, and the uptime when it was started
+    notification: Option<Notification>,
+    notification_start: Duration,
     event_queue: VecDeque<Event>,
 }
 
@@ -87,6 +135,17 @@ impl<
             power_manager,
             firmware_update,
             behaviour: Behaviour::new(),
+            error_recovery: ErrorRecovery::new(),
+            configuration_timeout: Some(DEFAULT_CONFIGURATION_TIMEOUT_TICKS),
+            configuration_ticks: 0,
+            configuration_timeout_cancelled: false,
+            access_point_security: AccessPointSecurity::Open,
+            pin_failures: 0,
+            pin_lockout_ticks: 0,
+            pin_display_ticks: 0,
+            image_state: ImageState::Valid,
+            notification: None,
+            notification_start: Duration::ZERO,
             event_queue: VecDeque::new(),
         }
     }
@@ -99,39 +158,90 @@ impl<
         // loop {
         if let Some(event) = self.event_queue.pop_front() {
             info!("Handling event {:?}", event);
-            self.behaviour.handle_event(event);
-            self.state_action();
+            for action in self.behaviour.handle_event(event) {
+                self.state_action(action);
+            }
+            self.publish_status();
         }
         // }
     }
 
-    pub fn state_action(&mut self) {
-        info!("Executing {:?} action", self.behaviour.current_state());
-        match self.behaviour.current_state() {
-            State::Startup => self.startup(),
-            State::DisplayTime => self.display_time(),
-            State::NightMode => self.night_mode(),
-            State::Configuration => self.configuration(),
-            State::MenuFota => {
-                let _ = self.display.draw_progress(1);
+    pub fn state_action(&mut self, action: StateAction) {
+        info!("Executing {:?} action", action);
+        match action {
+            StateAction::Startup => self.startup(),
+            StateAction::StartConfiguration => {
+                self.configuration_ticks = 0;
+                self.configuration_timeout_cancelled = false;
+                self.configuration_server.set_settings(None);
+                self.configuration();
+            }
+            StateAction::Configuration => self.configuration(),
+            StateAction::CancelConfigurationTimeout => {
+                info!("Configuration timeout cancelled, wait for a new configuration");
+                self.configuration_timeout_cancelled = true;
             }
-            State::MenuCleanConfig => {
-                let _ = self.display.draw_progress(2);
+            StateAction::DisplayTime => self.display_time(),
+            StateAction::NightMode => self.night_mode(),
+            StateAction::DrawMenu => {
+                let _ = self.display.draw_icon(self.behaviour.menu().icon());
             }
-            State::MenuExit => {
-                let _ = self.display.draw_progress(3);
+            StateAction::FirmwareUpdate => self.firmware_update(),
+            StateAction::CleanConfig => self.clean_config(),
+            StateAction::DrawError => self.error(),
+            StateAction::StartRecovery => self.start_recovery(),
+            StateAction::RecoveryTick => self.recovery_tick(),
+            StateAction::ClearDisplay => {
+                let _ = self.display.clear();
             }
-            State::Fota => self.firmware_update(),
-            State::CleanConfig => self.clean_config(),
-            _ => self.error(),
+            StateAction::ApplySettings => self.apply_settings(),
+            StateAction::HandleActions => self.handle_actions(),
+            StateAction::StartNotification => {
+                self.notification_start = self.power_manager.uptime();
+                self.draw_notification();
+            }
+            StateAction::DrawNotification => self.draw_notification(),
         }
-        info!("{:?} action Done", self.behaviour.current_state());
+        info!("{:?} action Done", action);
+    }
+
+    /// Set the number of ticks to wait in configuration mode, before retrying
+    /// the stored configuration. `None` waits forever.
+    pub fn set_configuration_timeout(&mut self, timeout: Option<u32>) {
+        self.configuration_timeout = timeout;
+    }
+
+    /// Set the protection of the configuration access point. It must match
+    /// the access point already started by the platform, if any.
+    ///
+    /// A PIN is also asked to change the clock from the network once configured.
+    pub fn set_access_point_security(&mut self, security: AccessPointSecurity) {
+        let pin = match security {
+            AccessPointSecurity::Pin(pin) => Some(pin.iter().map(|digit| char::from(b'0' + digit)).collect()),
+            _ => None,
+        };
+        self.configuration_server.set_pin(pin);
+        self.access_point_security = security;
+    }
+
+    /// Set the state of the running image, from `boot_validation::validate_boot`.
+    /// A pending image is confirmed once the time is displayed.
+    pub fn set_image_state(&mut self, state: ImageState) {
+        self.image_state = state;
     }
 
     pub fn get_current_state(&self) -> State {
         self.behaviour.current_state()
     }
 
+    /// Label of the highlighted menu entry, if the menu is shown.
+    pub fn get_menu_selection(&self) -> Option<&'static str> {
+        match self.behaviour.current_state() {
+            State::Menu => Some(self.behaviour.menu().current_entry().label),
+            _ => None,
+        }
+    }
+
     fn startup(&mut self) {
         let _ = self.display.draw_progress(1);
 
@@ -143,27 +253,27 @@ impl<
         if self.configuration.is_valid() {
             info!("Valid configuration");
 
-            self.display.set_default_color(self.configuration.get_display_color().unwrap());
+            self.apply_display_settings();
+            self.configuration_server.set_settings(Some(self.configuration.clone()));
 
-            if let Err(error) = self.network.configure(
-                &self.configuration.get_ssid().unwrap(),
-                &self.configuration.get_password().unwrap(),
-            ) {
-                error!("Failed to configure wifi: {}", error);
-                self.publish_event(Event::Error);
+            if let Err(errors) = self.connect_network() {
+                error!("Failed to connect to network: {:?}", errors);
+                let kind = errors.first().map_or(ErrorKind::WifiNotFound, |(_, error)| network_error_kind(error));
+                self.publish_event(Event::Error(kind));
+                return;
             }
-            if let Err(error) = self.network.connect() {
-                error!("Failed to connect to network: {}", error);
-                self.publish_event(Event::Error);
+            // The access point started at boot is not needed anymore, see Anomaly-002
+            if let Err(e) = self.network.stop_access_point() {
+                error!("Failed to stop access point: {}", e);
             }
-            if let Err(_) = self.time_source.synchronize() {
+            self.advertise();
+            if self.time_source.synchronize().is_err() {
                 error!("Failed to synch time source");
-                self.publish_event(Event::Error);
-            }
-            if let Err(error) = self.network.disconnect() {
-                error!("Failed to disconnect to network: {}", error);
-                self.publish_event(Event::Error);
+                let _ = self.network.disconnect();
+                self.publish_event(Event::Error(ErrorKind::TimeSync));
+                return;
             }
+            // Stay connected, to serve the settings page
             self.publish_event(Event::Start);
         } else {
             warn!("No valid configuration in persistent storage");
@@ -171,39 +281,71 @@ impl<
         }
     }
 
+    /// Poll the configuration server, called on every tick in configuration mode.
     fn configuration(&mut self) {
-        let _ = self.display.draw_progress(2);
+        self.pin_lockout_ticks = self.pin_lockout_ticks.saturating_sub(1);
+        let _ = match self.access_point_security {
+            AccessPointSecurity::Pin(pin) => self.display.draw_icon(Icon::Pin(pin)),
+            _ => self.display.draw_progress(2),
+        };
 
         if self.configuration.is_valid() {
             self.publish_event(Event::ValidConfiguration);
         } else {
             // Network is already configured as access point by main.rs, see Anomaly-002
 
-            loop {
-                thread::sleep(Duration::from_millis(200));
-                if self.configuration_server.is_configuration_received() {
-                    break;
+            let config_fields = if self.configuration_server.is_configuration_received() {
+                self.configuration_server.get_configuration_fields()
+            } else {
+                None
+            };
+            let Some(fields) = config_fields else {
+                self.wait_configuration();
+                return;
+            };
+
+            // On errors, stay in configuration mode. The user can fix the form and submit it again.
+            if self.pin_lockout_ticks > 0 {
+                warn!("Configuration form locked for {} ticks", self.pin_lockout_ticks);
+                self.configuration_server.report_configuration_errors(vec![FieldError::PinLocked]);
+                return;
+            }
+            if !self.is_pin_valid(&fields) {
+                warn!("Invalid PIN in configuration form");
+                self.pin_failures += 1;
+                if self.pin_failures >= MAX_PIN_ATTEMPTS {
+                    self.pin_lockout_ticks = pin_lockout_ticks(self.pin_failures);
+                    self.configuration_server.report_configuration_errors(vec![FieldError::PinLocked]);
+                } else {
+                    self.configuration_server.report_configuration_errors(vec![FieldError::InvalidPin]);
                 }
+                return;
             }
-
-            let config_uri = self.configuration_server.get_config_uri();
-            if let Some(uri) = config_uri {
-                match Configuration::from_uri_query_string(&uri) {
-                    Ok(config) => {
-                        info!("New config is {:?}", config);
-                        self.configuration = config;
-                    }
-                    Err(e) => {
-                        error!("failed to parse config uri: {}", e);
-                        self.publish_event(Event::Error);
-                        return;
-                    }
+            self.pin_failures = 0;
+            match Configuration::from_form_fields(&fields) {
+                Ok(config) => {
+                    info!("New config is {:?}", config);
+                    self.configuration = config;
+                }
+                Err(errors) => {
+                    warn!("Invalid configuration form: {:?}", errors);
+                    self.configuration_server.report_configuration_errors(errors);
+                    return;
                 }
             }
 
-            if let Err(e) = self.verify_network_configuration() {
-                error!("Invalid Network configuration provided: {}", e);
-                self.publish_event(Event::Error);
+            if let Err(errors) = self.verify_network_configuration() {
+                warn!("Invalid Network configuration provided: {:?}", errors);
+                self.configuration = Configuration::default();
+                let mut field_errors: Vec<FieldError> = errors
+                    .iter()
+                    .filter(|(_, error)| network_error_kind(error) == ErrorKind::WifiAuth)
+                    .map(|(index, _)| FieldError::NetworkAuthentication(*index))
+                    .collect();
+                if field_errors.is_empty() {
+                    field_errors.push(FieldError::NetworkNotFound);
+                }
+                self.configuration_server.report_configuration_errors(field_errors);
                 return;
             }
 
@@ -212,93 +354,360 @@ impl<
                 .store_to_persistent_storage(self.configuration.clone())
             {
                 error!("Failed to write to persistent storage: {}", e);
-                self.publish_event(Event::Error);
+                self.publish_event(Event::Error(ErrorKind::Storage));
+                return;
             }
 
+            self.configuration_server.report_configuration_errors(Vec::new());
+            if let Err(e) = self.network.stop_access_point() {
+                error!("Failed to stop access point: {}", e);
+            }
             self.publish_event(Event::ValidConfiguration);
         }
     }
 
-    fn verify_network_configuration(&mut self) -> Result<()>{
-        if let Err(error) = self.network.configure(
-            &self.configuration.get_ssid().unwrap(),
-            &self.configuration.get_password().unwrap(),
-        ) {
-            error!("Failed to configure wifi: {}", error);
-            self.publish_event(Event::Error);
+    /// PIN to show in place of the time, while it is asked for from the network
+    fn requested_pin(&mut self) -> Option<[u8; 4]> {
+        if self.pin_display_ticks == 0 {
+            return None;
+        }
+        self.pin_display_ticks -= 1;
+        match self.access_point_security {
+            AccessPointSecurity::Pin(pin) => Some(pin),
+            _ => None,
         }
-        if let Err(error) = self.network.connect() {
-            error!("Failed to connect to network: {}", error);
-            self.publish_event(Event::Error);
+    }
+
+    /// Check the PIN of the form, if the access point is protected by a PIN.
+    fn is_pin_valid(&self, fields: &[(String, String)]) -> bool {
+        let AccessPointSecurity::Pin(pin) = self.access_point_security else {
+            return true;
+        };
+        let pin: String = pin.iter().map(|digit| char::from(b'0' + digit)).collect();
+        form_urlencoded::get(fields, FORM_PIN_KEY).is_some_and(|value| value.trim() == pin)
+    }
+
+    /// Count the ticks without new configuration, and retry the stored
+    /// configuration after the timeout.
+    fn wait_configuration(&mut self) {
+        if self.configuration_server.is_scan_requested() {
+            self.refresh_scan_results();
+        }
+        self.configuration_ticks = self.configuration_ticks.saturating_add(1);
+
+        let Some(timeout) = self.configuration_timeout else {
+            return;
+        };
+        if self.configuration_timeout_cancelled || self.configuration_ticks < timeout {
+            return;
         }
-        if let Err(error) = self.network.disconnect() {
-            error!("Failed to disconnect to network: {}", error);
-            self.publish_event(Event::Error);
+
+        // Typically the router was not ready yet, its WiFi may be back now
+        if self.configuration_manager.load_from_persistent_storage().is_valid() {
+            info!("No configuration received, retry the stored configuration");
+            self.publish_event(Event::ConfigurationTimeout);
+        } else {
+            debug!("No configuration received, and no stored configuration");
+        }
+        self.configuration_ticks = 0;
+    }
+
+    /// Offer the visible networks in the configuration form.
+    fn refresh_scan_results(&mut self) {
+        match self.network.scan() {
+            Ok(results) => self.configuration_server.set_scan_results(results),
+            Err(e) => warn!("Failed to scan networks: {}", e),
+        }
+    }
+
+    fn verify_network_configuration(&mut self) -> std::result::Result<(), NetworkErrors> {
+        self.connect_network()?;
+        if let Err(e) = self.network.disconnect() {
+            error!("Failed to disconnect to network: {}", e);
         }
 
         Ok(())
     }
 
+    /// Connect to the best visible network of the configuration, falling back
+    /// to the next ones when the connection fails.
+    ///
+    /// # Errors
+    /// Return the error of each network tried. The list is empty when none of
+    /// the networks is visible.
+    fn connect_network(&mut self) -> std::result::Result<(), NetworkErrors> {
+        let networks = self.configuration.get_networks();
+        let candidates = match self.network.scan() {
+            Ok(visible) => rank_networks(&networks, &visible),
+            Err(e) => {
+                // Try all networks, the scan is only used to pick the best one
+                warn!("Failed to scan networks: {}", e);
+                let mut candidates: Vec<_> = networks.iter().collect();
+                candidates.sort_by_key(|network| std::cmp::Reverse(network.priority));
+                candidates
+            }
+        };
+
+        let mut errors = Vec::new();
+        for candidate in candidates {
+            // The configuration can hold the same SSID twice, index of the first one is enough
+            let index = networks.iter().position(|network| network == candidate).unwrap_or_default();
+            info!("Connect to network {:?}", candidate.ssid);
+            match self
+                .network
+                .configure(&candidate.ssid, &candidate.password)
+                .and_then(|_| self.network.connect())
+            {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!("Failed to connect to network {:?}: {}", candidate.ssid, e);
+                    errors.push((index, e));
+                }
+            }
+        }
+        Err(errors)
+    }
+
+    fn apply_display_settings(&mut self) {
+        if let Some(color) = self.configuration.get_display_color() {
+            self.display.set_default_color(color);
+        }
+        if let Some(brightness) = self.configuration.get_brightness() {
+            self.display.set_brightness(brightness);
+        }
+        if let Some(dialect) = self.configuration.get_dialect() {
+            self.display.set_dialect(dialect);
+        }
+    }
+
+    /// Advertise the clock on the local network under the configured host name.
+    fn advertise(&mut self) {
+        let hostname = self.configuration.get_hostname().unwrap_or_else(|| String::from(DEFAULT_HOSTNAME));
+        if let Err(e) = self.network.advertise(&hostname) {
+            error!("Failed to advertise {}.local: {}", hostname, e);
+        }
+    }
+
+    /// Apply and store the settings submitted from the settings page, if any.
+    /// The display is updated by the following action.
+    fn apply_settings(&mut self) {
+        if self.configuration_server.is_pin_requested() {
+            self.pin_display_ticks = PIN_DISPLAY_TICKS;
+        }
+        let Some(fields) = self.configuration_server.get_settings_fields() else {
+            return;
+        };
+
+        let mut configuration = self.configuration.clone();
+        if let Err(errors) = configuration.update_settings(&fields) {
+            warn!("Invalid settings: {:?}", errors);
+            self.configuration_server.report_settings_errors(errors);
+            return;
+        }
+        if let Err(e) = self.configuration_manager.store_to_persistent_storage(configuration.clone()) {
+            error!("Failed to write to persistent storage: {}", e);
+            self.publish_event(Event::Error(ErrorKind::Storage));
+            return;
+        }
+
+        info!("New settings are {:?}", configuration);
+        let hostname_changed = configuration.get_hostname() != self.configuration.get_hostname();
+        self.configuration = configuration;
+        self.apply_display_settings();
+        if hostname_changed {
+            self.advertise();
+        }
+        self.configuration_server.set_settings(Some(self.configuration.clone()));
+        self.configuration_server.report_settings_errors(Vec::new());
+    }
+
+    /// Carry out the actions requested through the REST API or MQTT.
+    fn handle_actions(&mut self) {
+        while let Some(action) = self.configuration_server.get_action() {
+            info!("Requested action {}", action);
+            match action {
+                ApiAction::Sync => {
+                    if let Err(e) = self.time_source.synchronize() {
+                        warn!("Failed to synchronize time: {:?}", e);
+                    }
+                }
+                ApiAction::Ota => self.publish_event(Event::FirmwareUpdateRequest),
+                ApiAction::Reboot => self.power_manager.reset(),
+                ApiAction::On => self.publish_event(Event::TurnOn),
+                ApiAction::Off => self.publish_event(Event::TurnOff),
+            }
+        }
+        // Only shown over the time, ignored in the other states. A notification
+        // requested while another is shown follows it.
+        if self.behaviour.current_state() == State::Notification {
+            return;
+        }
+        if let Some(notification) = self.configuration_server.get_notification() {
+            info!("Requested notification {:?}", notification);
+            self.notification = Some(notification);
+            self.publish_event(Event::Notify);
+        }
+    }
+
+    /// Draw the notification pattern, until the notification is over.
+    fn draw_notification(&mut self) {
+        let elapsed = self.power_manager.uptime().saturating_sub(self.notification_start);
+        match self.notification.and_then(|notification| notification.frame(elapsed)) {
+            Some(color) => {
+                let _ = self.display.fill(color);
+            }
+            None => {
+                self.notification = None;
+                self.publish_event(Event::NotificationDone);
+            }
+        }
+    }
+
+    /// Publish the status served by the REST API.
+    fn publish_status(&mut self) {
+        let rssi = if self.network.is_connected() { self.network.rssi().ok() } else { None };
+        let status = ApiStatus {
+            state: format!("{:?}", self.behaviour.current_state()),
+            time: self.time_source.get_time().ok().map(|time| time.to_string()),
+            time_source: self.time_source.health(),
+            version: String::from(BUILD_VERSION_STRING),
+            image: self.image_state.clone(),
+            uptime: self.power_manager.uptime().as_secs(),
+            rssi,
+        };
+        self.configuration_server.set_status(status);
+    }
+
     fn display_time(&mut self) {
         if let Err(TimeSourceError::NotSynchronized) = self.time_source.get_time() {
-            self.time_source.synchronize().unwrap();
+            let _ = self.time_source.synchronize();
         }
-        let time = self.time_source.get_time().unwrap();
+        let time = match self.time_source.get_time() {
+            Ok(time) => time,
+            Err(e) => {
+                error!("Failed to get time: {:?}", e);
+                self.publish_event(Event::Error(ErrorKind::TimeSync));
+                return;
+            }
+        };
         info!("Displaying time: {}", time);
 
-        let _ = self.display.draw_time(time);
+        if let Some(pin) = self.requested_pin() {
+            let _ = self.display.draw_icon(Icon::Pin(pin));
+        } else if self.display.draw_time(time).is_ok() {
+            self.error_recovery.reset();
+            self.confirm_image();
+        }
 
-        if let Some(night_start) = self.configuration.get_night_start() {
-            if time.hour >= night_start.hour {
-                if time.minute >= night_start.minute {
-                    self.publish_event(Event::Night);
-                }
+        if self.configuration.is_night(time) {
+            self.publish_event(Event::Night);
+        }
+    }
+
+    /// Health checkpoint: the display works, the configuration is loaded and
+    /// the time was drawn once.
+    fn confirm_image(&mut self) {
+        if self.image_state != ImageState::Pending {
+            return;
+        }
+        match boot_validation::confirm(self.configuration_manager.storage(), &self.firmware_update) {
+            Ok(()) => {
+                info!("Image v{} confirmed", BUILD_VERSION_STRING);
+                self.image_state = ImageState::Valid;
             }
+            Err(e) => error!("Failed to confirm the image: {}", e),
         }
     }
 
     fn night_mode(&mut self) {
-        let time = self.time_source.get_time().unwrap();
+        let time = match self.time_source.get_time() {
+            Ok(time) => time,
+            Err(e) => {
+                error!("Failed to get time: {:?}", e);
+                self.publish_event(Event::Error(ErrorKind::TimeSync));
+                return;
+            }
+        };
         info!("Currently in night {}", time);
 
-        if let Some(night_start) = self.configuration.get_night_start() {
-            if time.hour >= night_start.hour {
-                if time.minute >= night_start.minute {
-                    self.publish_event(Event::Day);
-                }
+        if self.pin_display_ticks > 0 {
+            if let Some(pin) = self.requested_pin() {
+                let _ = self.display.draw_icon(Icon::Pin(pin));
+            }
+            if self.pin_display_ticks == 0 {
+                let _ = self.display.clear();
             }
         }
+
+        if !self.configuration.is_night(time) {
+            self.publish_event(Event::Day);
+        }
     }
 
     fn firmware_update(&mut self) {
-        if let Err(error) = self.network.connect() {
-            error!("Failed to connect to network: {}", error);
-            self.publish_event(Event::Error);
+        if !self.network.is_connected() {
+            if let Err(error) = self.network.connect() {
+                error!("Failed to connect to network: {}", error);
+                self.publish_event(Event::Error(network_error_kind(&error)));
+                return;
+            }
         }
 
-        match self.firmware_update.read_update_version() {
-            Ok(version) => info!("Available version {}", version),
+        let server = self.configuration.get_update_server().unwrap_or_else(|| String::from(DEFAULT_UPDATE_SERVER));
+        let channel = self.configuration.get_update_channel().unwrap_or_default();
+        let url = manifest_url(&server, channel);
+        info!("Check {} updates on {}", channel, url);
+        let manifest = match self.firmware_update.read_manifest(&url) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                error!("Failed to read update manifest: {}", e);
+                self.publish_event(Event::Error(ErrorKind::Ota));
+                return;
+            }
+        };
+        let current = match Version::current() {
+            Ok(version) => version,
+            Err(e) => {
+                error!("Invalid firmware version {}: {}", BUILD_VERSION_STRING, e);
+                self.publish_event(Event::Error(ErrorKind::Ota));
+                return;
+            }
+        };
+        let image = match manifest.select(HARDWARE_REVISION, channel, &current) {
+            Ok(image) => image,
             Err(e) => {
-                error!("Failed to read update version: {}", e);
-                self.publish_event(Event::Error);
+                error!("No firmware update available: {}", e);
+                self.publish_event(Event::Error(ErrorKind::Ota));
+                return;
             }
+        };
+        if image.version <= current {
+            info!("Firmware {} is up to date, available version {}", current, image.version);
+            self.publish_event(Event::FirmwareUpToDate);
+            return;
+        }
+        info!("Update from {} to {}", current, image.version);
+        if !image.release_notes.is_empty() {
+            info!("Release notes: {}", image.release_notes);
         }
 
-        match self.firmware_update.download_update() {
+        let result = self.firmware_update.download_update(image, &image.resolve_url(&url));
+        // Without the record, a failing image would not be rolled back
+        let result = result.and_then(|()| boot_validation::mark_installed(self.configuration_manager.storage(), &image.version));
+        match result {
             Ok(()) => {
                 info!("Update ready, restart device");
                 // esp_idf_hal::delay::FreeRtos::delay_ms(5000);
 
                 if let Err(error) = self.network.disconnect() {
                     error!("Failed to disconnect to network: {}", error);
-                    self.publish_event(Event::Error);
                 }
 
                 self.firmware_update.reboot_to_new_image();
             }
             Err(e) => {
                 error!("Failed to download update: {}", e);
-                self.publish_event(Event::Error);
+                self.publish_event(Event::Error(ErrorKind::Ota));
             }
         }
     }
@@ -309,11 +718,54 @@ impl<
             self.publish_event(Event::InvalidConfiguration);
             self.power_manager.reset();
         } else {
-            self.publish_event(Event::Error);
+            self.publish_event(Event::Error(ErrorKind::Storage));
         }
     }
 
     fn error(&mut self) {
-        let _ = self.display.draw_error();
+        let kind = self.behaviour.last_error().unwrap_or(ErrorKind::Hardware);
+        let _ = self.display.draw_error(kind);
+    }
+
+    fn start_recovery(&mut self) {
+        let kind = self.behaviour.last_error().unwrap_or(ErrorKind::Hardware);
+        let step = self.error_recovery.start(kind);
+        self.recovery_step(step);
+    }
+
+    fn recovery_tick(&mut self) {
+        let step = self.error_recovery.tick();
+        self.recovery_step(step);
+    }
+
+    fn recovery_step(&mut self, step: RecoveryStep) {
+        match step {
+            RecoveryStep::Wait => (),
+            RecoveryStep::Retry => self.publish_event(Event::Retry),
+            RecoveryStep::FallbackToRtc => self.publish_event(Event::FallbackToRtc),
+            RecoveryStep::Reconfigure => {
+                // Forget the in-memory configuration, to wait for a new one from the user
+                self.configuration = Configuration::default();
+                if let Err(e) = setup_configuration_access_point(&mut self.network, &self.access_point_security) {
+                    error!("Failed to setup access point: {}", e);
+                }
+                self.publish_event(Event::Reconfigure);
+            }
+        }
+    }
+}
+
+/// Ticks of the lockout after the given number of wrong PINs, at least
+/// `MAX_PIN_ATTEMPTS`. Doubled on each further wrong PIN.
+pub fn pin_lockout_ticks(failures: u32) -> u32 {
+    let doublings = failures.saturating_sub(MAX_PIN_ATTEMPTS).min(16);
+    (PIN_LOCKOUT_TICKS << doublings).min(MAX_PIN_LOCKOUT_TICKS)
+}
+
+/// Map a network failure to the matching error kind.
+fn network_error_kind(error: &anyhow::Error) -> ErrorKind {
+    match error.downcast_ref::<NetworkError>() {
+        Some(NetworkError::AuthenticationFailed) => ErrorKind::WifiAuth,
+        _ => ErrorKind::WifiNotFound,
     }
 }
@@ -0,0 +1,495 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+/// mDNS port and IPv4 multicast group, see RFC 6762
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_MULTICAST_ADDRESS: [u8; 4] = [224, 0, 0, 251];
+
+/// Largest mDNS message handled, as sent over Ethernet or WiFi without fragmentation
+pub const MAX_MDNS_MESSAGE_LENGTH: usize = 1500;
+
+/// Time to live of the records holding a host name, in seconds (RFC 6762 section 10)
+const HOST_RECORD_TTL: u32 = 120;
+/// Time to live of the other records, in seconds
+const OTHER_RECORD_TTL: u32 = 4500;
+/// Maximum time to live of the answers to legacy unicast queries (RFC 6762 section 6.7)
+const LEGACY_UNICAST_TTL: u32 = 10;
+
+const HEADER_LENGTH: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const OPCODE_MASK: u16 = 0x7800;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+/// Top bit of the class: unicast response requested in questions, cache flush in records
+const CLASS_TOP_BIT: u16 = 0x8000;
+/// Compressed names point to a previous name, with both top bits of the length set
+const POINTER_MASK: u8 = 0xC0;
+/// Pointers followed in a single name, to stop on pointer loops
+const MAX_POINTERS: usize = 16;
+/// Maximum length of a label, and of a TXT string
+const MAX_LABEL_LENGTH: usize = 63;
+const MAX_STRING_LENGTH: usize = 255;
+
+const LOCAL_DOMAIN: &str = "local";
+const HTTP_SERVICE: [&str; 3] = ["_http", "_tcp", LOCAL_DOMAIN];
+/// DNS-SD service type enumeration (RFC 6763 section 9)
+const SERVICES_ENUMERATION: [&str; 4] = ["_services", "_dns-sd", "_udp", LOCAL_DOMAIN];
+
+/// Index of the records of `MdnsResponder::records()`, after the service type enumeration
+const SERVICE_RECORD: usize = 1;
+const SRV_RECORD: usize = 2;
+const TXT_RECORD: usize = 3;
+const ADDRESS_RECORD: usize = 4;
+
+/// Answer to an mDNS query
+#[derive(Debug, PartialEq)]
+pub struct MdnsResponse {
+    pub message: Vec<u8>,
+    /// Send to the querier only, instead of the multicast group
+    pub unicast: bool,
+}
+
+/// mDNS responder of the clock
+///
+/// Answer the queries for `<hostname>.local` and for the HTTP service of the
+/// settings page, advertised with DNS-SD. The service instance is named after
+/// the host. Name conflicts are not probed, each clock must get its own name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MdnsResponder {
+    hostname: String,
+    address: [u8; 4],
+    port: u16,
+    /// `key=value` strings of the TXT record
+    txt: Vec<String>,
+}
+
+/// Resource record, with a name given as labels
+struct Record {
+    name: Vec<String>,
+    kind: u16,
+    /// Unique record, replacing the cached records of the same name and type
+    cache_flush: bool,
+    ttl: u32,
+    data: Vec<u8>,
+}
+
+struct Question {
+    name: Vec<String>,
+    kind: u16,
+    class: u16,
+    unicast: bool,
+}
+
+impl MdnsResponder {
+    /// Responder for `<hostname>.local` at the IPv4 `address`, advertising an
+    /// HTTP service on `port` with the given TXT key/value pairs.
+    pub fn new(hostname: &str, address: [u8; 4], port: u16, txt: &[(&str, &str)]) -> Self {
+        Self {
+            hostname: hostname.to_ascii_lowercase(),
+            address,
+            port,
+            txt: txt.iter().map(|(key, value)| format!("{}={}", key, value)).collect(),
+        }
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// Answer a query received from `source_port`.
+    ///
+    /// Queries from another port than `MDNS_PORT` are legacy unicast queries,
+    /// answered like a unicast DNS server would. Return `None` when no question
+    /// is about the clock, and for messages that must be ignored, like responses.
+    pub fn response(&self, query: &[u8], source_port: u16) -> Option<MdnsResponse> {
+        let flags = read_u16(query, 2)?;
+        if flags & (FLAG_RESPONSE | OPCODE_MASK) != 0 {
+            return None;
+        }
+        let (questions, questions_end) = read_questions(query)?;
+        let legacy = source_port != MDNS_PORT;
+
+        let records = self.records();
+        let mut answers = Vec::new();
+        let mut unicast = legacy;
+        for question in &questions {
+            if question.class != CLASS_IN && question.class != CLASS_ANY {
+                continue;
+            }
+            for (index, record) in records.iter().enumerate() {
+                let matches = same_name(&record.name, &question.name)
+                    && (question.kind == record.kind || question.kind == TYPE_ANY);
+                if matches && !answers.contains(&index) {
+                    answers.push(index);
+                    unicast |= question.unicast;
+                }
+            }
+        }
+        if answers.is_empty() {
+            return None;
+        }
+
+        // Records the querier will need next, to save it more queries
+        let mut additionals = Vec::new();
+        for answer in &answers {
+            let related: &[usize] = match *answer {
+                SERVICE_RECORD => &[SRV_RECORD, TXT_RECORD, ADDRESS_RECORD],
+                SRV_RECORD => &[ADDRESS_RECORD],
+                _ => &[],
+            };
+            for index in related {
+                if !answers.contains(index) && !additionals.contains(index) {
+                    additionals.push(*index);
+                }
+            }
+        }
+
+        let mut message = Vec::with_capacity(MAX_MDNS_MESSAGE_LENGTH);
+        if legacy {
+            // Same ID and questions, as expected by a unicast DNS client
+            message.extend_from_slice(&query[0..2]);
+            message.extend_from_slice(&(FLAG_RESPONSE | FLAG_AUTHORITATIVE).to_be_bytes());
+            message.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+        } else {
+            message.extend_from_slice(&[0, 0]);
+            message.extend_from_slice(&(FLAG_RESPONSE | FLAG_AUTHORITATIVE).to_be_bytes());
+            message.extend_from_slice(&[0, 0]);
+        }
+        for count in [answers.len(), 0, additionals.len()] {
+            message.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+        if legacy {
+            message.extend_from_slice(&query[HEADER_LENGTH..questions_end]);
+        }
+        for index in answers.iter().chain(&additionals) {
+            write_record(&mut message, &records[*index], legacy);
+        }
+
+        Some(MdnsResponse { message, unicast })
+    }
+
+    /// Unsolicited response with all records, sent to the multicast group once
+    /// connected, so the caches are up to date.
+    pub fn announcement(&self) -> Vec<u8> {
+        self.unsolicited(self.records())
+    }
+
+    /// Announcement with a zero time to live, so the records are removed from
+    /// the caches, e.g. before changing the name.
+    pub fn goodbye(&self) -> Vec<u8> {
+        let mut records = self.records();
+        for record in &mut records {
+            record.ttl = 0;
+        }
+        self.unsolicited(records)
+    }
+
+    fn unsolicited(&self, records: Vec<Record>) -> Vec<u8> {
+        let mut message = Vec::with_capacity(MAX_MDNS_MESSAGE_LENGTH);
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&(FLAG_RESPONSE | FLAG_AUTHORITATIVE).to_be_bytes());
+        for count in [0, records.len(), 0, 0] {
+            message.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+        for record in &records {
+            write_record(&mut message, record, false);
+        }
+        message
+    }
+
+    /// All records of the clock, in the order of the `*_RECORD` indexes.
+    fn records(&self) -> Vec<Record> {
+        let host = labels(&[&self.hostname, LOCAL_DOMAIN]);
+        let service = labels(&HTTP_SERVICE);
+        let mut instance = labels(&[&self.hostname]);
+        instance.extend(service.iter().cloned());
+
+        let mut services_data = Vec::new();
+        write_name(&mut services_data, &service);
+        let mut service_data = Vec::new();
+        write_name(&mut service_data, &instance);
+
+        // Priority and weight are not used with a single server
+        let mut srv_data = Vec::new();
+        srv_data.extend_from_slice(&[0, 0, 0, 0]);
+        srv_data.extend_from_slice(&self.port.to_be_bytes());
+        write_name(&mut srv_data, &host);
+
+        // A TXT record holds at least one string, possibly empty
+        let mut txt_data = Vec::new();
+        for entry in &self.txt {
+            let entry = &entry.as_bytes()[..entry.len().min(MAX_STRING_LENGTH)];
+            txt_data.push(entry.len() as u8);
+            txt_data.extend_from_slice(entry);
+        }
+        if txt_data.is_empty() {
+            txt_data.push(0);
+        }
+
+        vec![
+            Record { name: labels(&SERVICES_ENUMERATION), kind: TYPE_PTR, cache_flush: false, ttl: OTHER_RECORD_TTL, data: services_data },
+            Record { name: service, kind: TYPE_PTR, cache_flush: false, ttl: OTHER_RECORD_TTL, data: service_data },
+            Record { name: instance.clone(), kind: TYPE_SRV, cache_flush: true, ttl: HOST_RECORD_TTL, data: srv_data },
+            Record { name: instance, kind: TYPE_TXT, cache_flush: true, ttl: OTHER_RECORD_TTL, data: txt_data },
+            Record { name: host, kind: TYPE_A, cache_flush: true, ttl: HOST_RECORD_TTL, data: self.address.to_vec() },
+        ]
+    }
+}
+
+fn labels(labels: &[&str]) -> Vec<String> {
+    labels.iter().map(|label| String::from(*label)).collect()
+}
+
+/// Names are compared without case, like all DNS names.
+fn same_name(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Write a name without compression.
+fn write_name(message: &mut Vec<u8>, name: &[String]) {
+    for label in name {
+        let label = &label.as_bytes()[..label.len().min(MAX_LABEL_LENGTH)];
+        message.push(label.len() as u8);
+        message.extend_from_slice(label);
+    }
+    message.push(0);
+}
+
+fn write_record(message: &mut Vec<u8>, record: &Record, legacy: bool) {
+    // Cache flush must not be set in legacy unicast answers, and the time to live is short
+    let (class, ttl) = match legacy {
+        true => (CLASS_IN, record.ttl.min(LEGACY_UNICAST_TTL)),
+        false if record.cache_flush => (CLASS_IN | CLASS_TOP_BIT, record.ttl),
+        false => (CLASS_IN, record.ttl),
+    };
+    write_name(message, &record.name);
+    message.extend_from_slice(&record.kind.to_be_bytes());
+    message.extend_from_slice(&class.to_be_bytes());
+    message.extend_from_slice(&ttl.to_be_bytes());
+    message.extend_from_slice(&(record.data.len() as u16).to_be_bytes());
+    message.extend_from_slice(&record.data);
+}
+
+/// Questions of a query, with the offset after the question section.
+fn read_questions(query: &[u8]) -> Option<(Vec<Question>, usize)> {
+    let count = read_u16(query, 4)?;
+    let mut questions = Vec::new();
+    let mut offset = HEADER_LENGTH;
+    for _ in 0..count {
+        let (name, end) = read_name(query, offset)?;
+        let kind = read_u16(query, end)?;
+        let class = read_u16(query, end + 2)?;
+        questions.push(Question { name, kind, class: class & !CLASS_TOP_BIT, unicast: class & CLASS_TOP_BIT != 0 });
+        offset = end + 4;
+    }
+    Some((questions, offset))
+}
+
+/// Read a possibly compressed name, return its labels and the offset after it.
+fn read_name(message: &[u8], start: usize) -> Option<(Vec<String>, usize)> {
+    let mut labels = Vec::new();
+    let mut offset = start;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *message.get(offset)?;
+        if length & POINTER_MASK == POINTER_MASK {
+            let pointer = u16::from_be_bytes([length & !POINTER_MASK, *message.get(offset + 1)?]);
+            end.get_or_insert(offset + 2);
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return None;
+            }
+            offset = usize::from(pointer);
+            continue;
+        }
+        if length & POINTER_MASK != 0 {
+            return None;
+        }
+        offset += 1;
+        if length == 0 {
+            break;
+        }
+        let label = message.get(offset..offset + usize::from(length))?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += usize::from(length);
+    }
+    Some((labels, end.unwrap_or(offset)))
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: [u8; 4] = [192, 168, 1, 42];
+
+    fn responder() -> MdnsResponder {
+        MdnsResponder::new("wordclock", ADDRESS, 80, &[("version", "2.0.1"), ("hardware", "v2")])
+    }
+
+    /// Query with the given questions: name, type and unicast response bit
+    fn query(id: u16, questions: &[(&str, u16, bool)]) -> Vec<u8> {
+        let mut query = Vec::new();
+        query.extend_from_slice(&id.to_be_bytes());
+        query.extend_from_slice(&[0, 0]);
+        query.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+        query.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        for (name, kind, unicast) in questions {
+            write_name(&mut query, &labels(&name.split('.').collect::<Vec<_>>()));
+            query.extend_from_slice(&kind.to_be_bytes());
+            let class = if *unicast { CLASS_IN | CLASS_TOP_BIT } else { CLASS_IN };
+            query.extend_from_slice(&class.to_be_bytes());
+        }
+        query
+    }
+
+    /// Records of a response: name, type, class, time to live and data
+    fn parse_records(message: &[u8]) -> Vec<(String, u16, u16, u32, Vec<u8>)> {
+        let (_, mut offset) = read_questions(message).unwrap();
+        let count: u16 = [6, 8, 10].iter().map(|offset| read_u16(message, *offset).unwrap()).sum();
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let (name, end) = read_name(message, offset).unwrap();
+            let length = usize::from(read_u16(message, end + 8).unwrap());
+            let ttl = u32::from_be_bytes(message[end + 4..end + 8].try_into().unwrap());
+            records.push((
+                name.join("."),
+                read_u16(message, end).unwrap(),
+                read_u16(message, end + 2).unwrap(),
+                ttl,
+                message[end + 10..end + 10 + length].to_vec(),
+            ));
+            offset = end + 10 + length;
+        }
+        assert_eq!(offset, message.len());
+        records
+    }
+
+    #[test]
+    fn answer_host_query() {
+        let response = responder().response(&query(0, &[("wordclock.local", TYPE_A, false)]), MDNS_PORT).unwrap();
+
+        assert!(!response.unicast);
+        assert_eq!(response.message[..HEADER_LENGTH], [0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(
+            response.message[HEADER_LENGTH..],
+            [
+                9, b'w', b'o', b'r', b'd', b'c', b'l', b'o', b'c', b'k', 5, b'l', b'o', b'c', b'a', b'l', 0,
+                0, 1, 0x80, 1, 0, 0, 0, 120, 0, 4, 192, 168, 1, 42
+            ]
+        );
+
+        // Names are case insensitive
+        let query = query(0, &[("WordClock.LOCAL", TYPE_ANY, false)]);
+        assert_eq!(parse_records(&responder().response(&query, MDNS_PORT).unwrap().message).len(), 1);
+    }
+
+    #[test]
+    fn browse_http_service() {
+        let response = responder().response(&query(0, &[("_http._tcp.local", TYPE_PTR, false)]), MDNS_PORT).unwrap();
+        let records = parse_records(&response.message);
+
+        assert_eq!(response.message[6..12], [0, 1, 0, 0, 0, 3]);
+        assert_eq!(records[0].0, "_http._tcp.local");
+        assert_eq!(records[0].4, b"\x09wordclock\x05_http\x04_tcp\x05local\x00");
+        assert_eq!(records[1].0, "wordclock._http._tcp.local");
+        assert_eq!(records[1].1, TYPE_SRV);
+        assert_eq!(records[1].4, b"\x00\x00\x00\x00\x00\x50\x09wordclock\x05local\x00");
+        assert_eq!(records[2].1, TYPE_TXT);
+        assert_eq!(records[2].4, b"\x0dversion=2.0.1\x0bhardware=v2");
+        assert_eq!(records[3].1, TYPE_A);
+    }
+
+    #[test]
+    fn enumerate_services() {
+        let query = query(0, &[("_services._dns-sd._udp.local", TYPE_PTR, false)]);
+        let records = parse_records(&responder().response(&query, MDNS_PORT).unwrap().message);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].2, CLASS_IN);
+        assert_eq!(records[0].3, OTHER_RECORD_TTL);
+        assert_eq!(records[0].4, b"\x05_http\x04_tcp\x05local\x00");
+    }
+
+    #[test]
+    fn unicast_response_requested() {
+        let response = responder().response(&query(0, &[("wordclock.local", TYPE_A, true)]), MDNS_PORT).unwrap();
+        assert!(response.unicast);
+    }
+
+    #[test]
+    fn legacy_unicast_query() {
+        let query = query(0x1234, &[("wordclock.local", TYPE_A, false)]);
+        let response = responder().response(&query, 53124).unwrap();
+
+        assert!(response.unicast);
+        assert_eq!(response.message[..HEADER_LENGTH], [0x12, 0x34, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response.message[HEADER_LENGTH..query.len()], query[HEADER_LENGTH..]);
+        assert_eq!(
+            parse_records(&response.message),
+            vec![(String::from("wordclock.local"), TYPE_A, CLASS_IN, LEGACY_UNICAST_TTL, ADDRESS.to_vec())]
+        );
+    }
+
+    #[test]
+    fn compressed_questions() {
+        // Second question "wordclock.local" points to "local" of the first one
+        let mut query = query(0, &[("printer.local", TYPE_A, false)]);
+        query[5] = 2;
+        query.extend_from_slice(b"\x09wordclock\xC0\x14");
+        query.extend_from_slice(&[0, 1, 0, 1]);
+
+        let records = parse_records(&responder().response(&query, MDNS_PORT).unwrap().message);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, "wordclock.local");
+    }
+
+    #[test]
+    fn ignored_messages() {
+        let responder = responder();
+        for question in [("printer.local", TYPE_A), ("wordclock.local", 28), ("_ipp._tcp.local", TYPE_PTR)] {
+            let query = query(0, &[(question.0, question.1, false)]);
+            assert_eq!(responder.response(&query, MDNS_PORT), None, "{}", question.0);
+        }
+
+        // Responses, truncated queries and pointer loops
+        let mut response = query(0, &[("wordclock.local", TYPE_A, false)]);
+        response[2] = 0x84;
+        assert_eq!(responder.response(&response, MDNS_PORT), None);
+        let query = query(0, &[("wordclock.local", TYPE_A, false)]);
+        assert_eq!(responder.response(&query[..query.len() - 1], MDNS_PORT), None);
+        assert_eq!(responder.response(b"\0\0\0\0\0\x01\0\0\0\0\0\0\xC0\x0C\0\x01\0\x01", MDNS_PORT), None);
+    }
+
+    #[test]
+    fn announcement_and_goodbye() {
+        let records = parse_records(&responder().announcement());
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[ADDRESS_RECORD].0, "wordclock.local");
+        assert_eq!(records[SRV_RECORD].2, CLASS_IN | CLASS_TOP_BIT);
+
+        let records = parse_records(&responder().goodbye());
+        assert!(records.iter().all(|record| record.3 == 0));
+    }
+
+    #[test]
+    fn empty_txt_record() {
+        let responder = MdnsResponder::new("Kitchen", ADDRESS, 8080, &[]);
+        assert_eq!(responder.hostname(), "kitchen");
+        let records = parse_records(&responder.announcement());
+        assert_eq!(records[TXT_RECORD].4, [0]);
+        assert_eq!(records[0].0, "_services._dns-sd._udp.local");
+    }
+}
@@ -0,0 +1,320 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use log::*;
+
+use crate::behaviour::State;
+use crate::display::Icon;
+
+/// What happens when a menu entry is selected
+#[derive(Debug, PartialEq)]
+pub enum MenuItem {
+    /// Go to the `target` state, after a confirmation if `confirm` is set.
+    Action { target: State, confirm: bool },
+    /// Open a nested menu.
+    Submenu(&'static [MenuEntry]),
+    /// Go back to the parent menu.
+    Back,
+}
+
+/// Single entry of a menu
+#[derive(Debug, PartialEq)]
+pub struct MenuEntry {
+    pub label: &'static str,
+    pub icon: Icon,
+    pub item: MenuItem,
+}
+
+/// Menu of the device, shown from the time display. An entry is added here
+/// only, the behaviour goes to the target state of the selected action.
+pub const DEVICE_MENU: &[MenuEntry] = &[
+    MenuEntry {
+        label: "firmware_update",
+        icon: Icon::Dots(1),
+        item: MenuItem::Action { target: State::Fota, confirm: false },
+    },
+    MenuEntry {
+        label: "clean_config",
+        icon: Icon::Dots(2),
+        item: MenuItem::Action { target: State::CleanConfig, confirm: true },
+    },
+    MenuEntry {
+        label: "exit",
+        icon: Icon::Dots(3),
+        item: MenuItem::Action { target: State::DisplayTime, confirm: false },
+    },
+];
+
+/// Label and target state of every action of `menu`, nested menus included.
+pub fn menu_actions(menu: &'static [MenuEntry]) -> Vec<(&'static str, State)> {
+    let mut actions = Vec::new();
+    for entry in menu {
+        match &entry.item {
+            MenuItem::Action { target, .. } => actions.push((entry.label, *target)),
+            MenuItem::Submenu(entries) => actions.extend(menu_actions(entries)),
+            MenuItem::Back => (),
+        }
+    }
+    actions
+}
+
+/// Result of a navigation in the menu
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MenuOutcome {
+    /// The selection changed, or nothing happened.
+    Navigated,
+    /// The action of the entry was triggered, go to the given state.
+    Action(State),
+    /// The user left the root menu.
+    Exit,
+}
+
+/// Generic navigation state-machine over a tree of `MenuEntry`
+///
+/// Entries of a menu level are cycled with `select_next()` and `select_previous()`.
+/// `select()` triggers the highlighted entry, and `back()` returns to the
+/// parent level or cancels a pending confirmation.
+pub struct MenuNavigator {
+    root: &'static [MenuEntry],
+    /// Parent levels, with the index of the entry used to enter the child level.
+    parents: Vec<(&'static [MenuEntry], usize)>,
+    entries: &'static [MenuEntry],
+    index: usize,
+    confirming: bool,
+}
+
+impl MenuNavigator {
+    pub fn new(root: &'static [MenuEntry]) -> Self {
+        Self {
+            root,
+            parents: Vec::new(),
+            entries: root,
+            index: 0,
+            confirming: false,
+        }
+    }
+
+    /// Go back to the first entry of the root menu.
+    pub fn reset(&mut self) {
+        self.parents.clear();
+        self.entries = self.root;
+        self.index = 0;
+        self.confirming = false;
+    }
+
+    /// Currently highlighted entry
+    pub fn current_entry(&self) -> &'static MenuEntry {
+        &self.entries[self.index]
+    }
+
+    /// Return `true` if the highlighted action waits for a confirmation.
+    pub fn is_confirming(&self) -> bool {
+        self.confirming
+    }
+
+    /// Target of the action that `select()` would trigger, without changing the navigation.
+    pub fn would_trigger(&self) -> Option<State> {
+        match &self.current_entry().item {
+            MenuItem::Action { target, confirm } if !*confirm || self.confirming => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// Return `true` if `back()` would leave the root menu.
+    pub fn would_exit(&self) -> bool {
+        !self.confirming && self.parents.is_empty()
+    }
+
+    /// Icon to draw for the current navigation state.
+    pub fn icon(&self) -> Icon {
+        if self.confirming {
+            Icon::Question
+        } else {
+            self.current_entry().icon
+        }
+    }
+
+    pub fn select_next(&mut self) -> MenuOutcome {
+        if self.confirming {
+            return self.back();
+        }
+        self.index = (self.index + 1) % self.entries.len();
+        MenuOutcome::Navigated
+    }
+
+    pub fn select_previous(&mut self) -> MenuOutcome {
+        if self.confirming {
+            return self.back();
+        }
+        self.index = (self.index + self.entries.len() - 1) % self.entries.len();
+        MenuOutcome::Navigated
+    }
+
+    pub fn select(&mut self) -> MenuOutcome {
+        match &self.current_entry().item {
+            MenuItem::Action { target, confirm } => {
+                if *confirm && !self.confirming {
+                    self.confirming = true;
+                    MenuOutcome::Navigated
+                } else {
+                    self.confirming = false;
+                    debug!("Menu action {} to {:?}", self.current_entry().label, target);
+                    MenuOutcome::Action(*target)
+                }
+            }
+            MenuItem::Submenu(entries) => {
+                self.parents.push((self.entries, self.index));
+                self.entries = entries;
+                self.index = 0;
+                MenuOutcome::Navigated
+            }
+            MenuItem::Back => self.back(),
+        }
+    }
+
+    pub fn back(&mut self) -> MenuOutcome {
+        if self.confirming {
+            self.confirming = false;
+            return MenuOutcome::Navigated;
+        }
+
+        match self.parents.pop() {
+            Some((entries, index)) => {
+                self.entries = entries;
+                self.index = index;
+                MenuOutcome::Navigated
+            }
+            None => MenuOutcome::Exit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBMENU: &[MenuEntry] = &[
+        MenuEntry {
+            label: "clean_config",
+            icon: Icon::Dots(1),
+            item: MenuItem::Action { target: State::CleanConfig, confirm: true },
+        },
+        MenuEntry {
+            label: "back",
+            icon: Icon::Dots(2),
+            item: MenuItem::Back,
+        },
+    ];
+
+    const MENU: &[MenuEntry] = &[
+        MenuEntry {
+            label: "firmware_update",
+            icon: Icon::Dots(1),
+            item: MenuItem::Action { target: State::Fota, confirm: false },
+        },
+        MenuEntry {
+            label: "settings",
+            icon: Icon::Dots(2),
+            item: MenuItem::Submenu(SUBMENU),
+        },
+        MenuEntry {
+            label: "exit",
+            icon: Icon::Dots(3),
+            item: MenuItem::Action { target: State::DisplayTime, confirm: false },
+        },
+    ];
+
+    #[test]
+    fn cycle_entries() {
+        let mut menu = MenuNavigator::new(MENU);
+        assert_eq!(menu.current_entry().label, "firmware_update");
+
+        menu.select_next();
+        assert_eq!(menu.current_entry().label, "settings");
+        menu.select_next();
+        menu.select_next();
+        assert_eq!(menu.current_entry().label, "firmware_update");
+
+        menu.select_previous();
+        assert_eq!(menu.current_entry().label, "exit");
+        assert_eq!(menu.icon(), Icon::Dots(3));
+    }
+
+    #[test]
+    fn select_action() {
+        let mut menu = MenuNavigator::new(MENU);
+        assert_eq!(menu.select(), MenuOutcome::Action(State::Fota));
+    }
+
+    #[test]
+    fn nested_menu() {
+        let mut menu = MenuNavigator::new(MENU);
+        menu.select_next();
+        assert_eq!(menu.select(), MenuOutcome::Navigated);
+        assert_eq!(menu.current_entry().label, "clean_config");
+
+        menu.select_next();
+        assert_eq!(menu.select(), MenuOutcome::Navigated);
+        assert_eq!(menu.current_entry().label, "settings");
+
+        menu.select();
+        assert_eq!(menu.back(), MenuOutcome::Navigated);
+        assert_eq!(menu.current_entry().label, "settings");
+        assert_eq!(menu.back(), MenuOutcome::Exit);
+    }
+
+    #[test]
+    fn confirm_action() {
+        let mut menu = MenuNavigator::new(MENU);
+        menu.select_next();
+        menu.select();
+
+        assert_eq!(menu.select(), MenuOutcome::Navigated);
+        assert!(menu.is_confirming());
+        assert_eq!(menu.icon(), Icon::Question);
+
+        assert_eq!(menu.select(), MenuOutcome::Action(State::CleanConfig));
+        assert!(!menu.is_confirming());
+    }
+
+    #[test]
+    fn cancel_confirmation() {
+        let mut menu = MenuNavigator::new(MENU);
+        menu.select_next();
+        menu.select();
+        menu.select();
+        assert!(menu.is_confirming());
+
+        assert_eq!(menu.select_next(), MenuOutcome::Navigated);
+        assert!(!menu.is_confirming());
+        assert_eq!(menu.current_entry().label, "clean_config");
+    }
+
+    #[test]
+    fn peek_without_navigation() {
+        let mut menu = MenuNavigator::new(MENU);
+        assert_eq!(menu.would_trigger(), Some(State::Fota));
+        assert!(menu.would_exit());
+
+        menu.select_next();
+        assert_eq!(menu.would_trigger(), None);
+        menu.select();
+        assert!(!menu.would_exit());
+
+        // Confirmation needed first
+        assert_eq!(menu.would_trigger(), None);
+        menu.select();
+        assert_eq!(menu.would_trigger(), Some(State::CleanConfig));
+    }
+
+    #[test]
+    fn reset_to_root() {
+        let mut menu = MenuNavigator::new(MENU);
+        menu.select_next();
+        menu.select();
+        menu.reset();
+        assert_eq!(menu.current_entry().label, "firmware_update");
+        assert_eq!(menu.back(), MenuOutcome::Exit);
+    }
+}
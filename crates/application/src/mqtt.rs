@@ -0,0 +1,558 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use log::*;
+
+/// Default port of an MQTT broker, without TLS
+pub const MQTT_PORT: u16 = 1883;
+
+/// Largest packet accepted from the broker, bigger ones close the connection
+pub const MAX_MQTT_PACKET_LENGTH: usize = 4096;
+
+/// Maximum time to wait for the broker to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// MQTT 3.1.1 protocol name and level
+const PROTOCOL_NAME: &str = "MQTT";
+const PROTOCOL_LEVEL: u8 = 4;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Flags of the fixed header of a SUBSCRIBE packet, required by the protocol
+const SUBSCRIBE_FLAGS: u8 = 0b0010;
+const PUBLISH_RETAIN: u8 = 0b0001;
+const PUBLISH_QOS_MASK: u8 = 0b0110;
+
+const CONNECT_USERNAME: u8 = 0x80;
+const CONNECT_PASSWORD: u8 = 0x40;
+const CONNECT_WILL_RETAIN: u8 = 0x20;
+const CONNECT_WILL: u8 = 0x04;
+const CONNECT_CLEAN_SESSION: u8 = 0x02;
+
+/// Return code of a refused subscription in a SUBACK packet
+const SUBSCRIPTION_FAILURE: u8 = 0x80;
+
+/// Remaining length is encoded on 1 to 4 bytes of 7 bits
+const MAX_REMAINING_LENGTH_BYTES: usize = 4;
+
+/// Application message, always sent with QoS 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// The broker keeps the last retained message of a topic for new subscribers.
+    pub retain: bool,
+}
+
+impl Message {
+    pub fn new(topic: &str, payload: &str, retain: bool) -> Self {
+        Self { topic: String::from(topic), payload: payload.as_bytes().to_vec(), retain }
+    }
+}
+
+/// Parameters of the connection to the broker
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectOptions {
+    pub client_id: String,
+    /// The broker closes the connection after 1.5 times this delay without packet.
+    pub keep_alive: Duration,
+    /// Username and password, if any
+    pub credentials: Option<(String, String)>,
+    /// Message published by the broker when the connection is lost
+    pub will: Option<Message>,
+}
+
+/// Packet received from the broker
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    ConnAck { session_present: bool, return_code: u8 },
+    /// Packet identifier is set for QoS 1 and 2 messages.
+    Publish { message: Message, packet_id: Option<u16> },
+    SubAck { packet_id: u16, return_codes: Vec<u8> },
+    PingResp,
+    /// Other packets, unexpected for a client using QoS 0
+    Other(u8),
+}
+
+/// Encode a CONNECT packet, with a clean session.
+pub fn encode_connect(options: &ConnectOptions) -> Vec<u8> {
+    let mut flags = CONNECT_CLEAN_SESSION;
+    let mut payload = Vec::new();
+    write_string(&mut payload, options.client_id.as_bytes());
+    if let Some(will) = &options.will {
+        flags |= CONNECT_WILL;
+        if will.retain {
+            flags |= CONNECT_WILL_RETAIN;
+        }
+        write_string(&mut payload, will.topic.as_bytes());
+        write_string(&mut payload, &will.payload);
+    }
+    if let Some((username, password)) = &options.credentials {
+        flags |= CONNECT_USERNAME | CONNECT_PASSWORD;
+        write_string(&mut payload, username.as_bytes());
+        write_string(&mut payload, password.as_bytes());
+    }
+
+    let mut body = Vec::new();
+    write_string(&mut body, PROTOCOL_NAME.as_bytes());
+    body.push(PROTOCOL_LEVEL);
+    body.push(flags);
+    let keep_alive = u16::try_from(options.keep_alive.as_secs()).unwrap_or(u16::MAX);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+    body.extend_from_slice(&payload);
+    packet(CONNECT << 4, &body)
+}
+
+/// Encode a PUBLISH packet with QoS 0.
+pub fn encode_publish(message: &Message) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_string(&mut body, message.topic.as_bytes());
+    body.extend_from_slice(&message.payload);
+    let retain = if message.retain { PUBLISH_RETAIN } else { 0 };
+    packet(PUBLISH << 4 | retain, &body)
+}
+
+/// Encode a SUBSCRIBE packet, requesting QoS 0 for all topic filters.
+pub fn encode_subscribe(packet_id: u16, filters: &[&str]) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    for filter in filters {
+        write_string(&mut body, filter.as_bytes());
+        body.push(0);
+    }
+    packet(SUBSCRIBE << 4 | SUBSCRIBE_FLAGS, &body)
+}
+
+pub fn encode_pingreq() -> Vec<u8> {
+    packet(PINGREQ << 4, &[])
+}
+
+pub fn encode_disconnect() -> Vec<u8> {
+    packet(DISCONNECT << 4, &[])
+}
+
+/// Decode the first packet of `buffer`.
+///
+/// Return the packet and its length, or `None` if the packet is not complete yet.
+pub fn decode_packet(buffer: &[u8]) -> Result<Option<(Packet, usize)>> {
+    let Some(&header) = buffer.first() else {
+        return Ok(None);
+    };
+
+    let mut remaining_length = 0;
+    let mut offset = 1;
+    loop {
+        let Some(&byte) = buffer.get(offset) else {
+            return Ok(None);
+        };
+        remaining_length |= usize::from(byte & 0x7F) << (7 * (offset - 1));
+        offset += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if offset > MAX_REMAINING_LENGTH_BYTES {
+            return Err(anyhow!("Invalid remaining length"));
+        }
+    }
+    if remaining_length > MAX_MQTT_PACKET_LENGTH {
+        return Err(anyhow!("Packet of {} bytes is too large", remaining_length));
+    }
+    let Some(body) = buffer.get(offset..offset + remaining_length) else {
+        return Ok(None);
+    };
+
+    let packet = match header >> 4 {
+        CONNACK => match body {
+            [flags, return_code] => Packet::ConnAck { session_present: flags & 1 != 0, return_code: *return_code },
+            _ => return Err(anyhow!("Invalid CONNACK")),
+        },
+        PUBLISH => {
+            let (topic, mut rest) = read_string(body)?;
+            let packet_id = if header & PUBLISH_QOS_MASK != 0 {
+                let (id, payload) = read_u16(rest)?;
+                rest = payload;
+                Some(id)
+            } else {
+                None
+            };
+            let message = Message {
+                topic: String::from_utf8(topic.to_vec())?,
+                payload: rest.to_vec(),
+                retain: header & PUBLISH_RETAIN != 0,
+            };
+            Packet::Publish { message, packet_id }
+        }
+        SUBACK => {
+            let (packet_id, return_codes) = read_u16(body)?;
+            Packet::SubAck { packet_id, return_codes: return_codes.to_vec() }
+        }
+        PINGRESP => Packet::PingResp,
+        kind => Packet::Other(kind),
+    };
+    Ok(Some((packet, offset + remaining_length)))
+}
+
+/// Reason of a refused connection, from the CONNACK return code
+fn connection_refused(return_code: u8) -> &'static str {
+    match return_code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Strings and binary data are prefixed by their length on 2 bytes.
+fn write_string(buffer: &mut Vec<u8>, value: &[u8]) {
+    let length = value.len().min(usize::from(u16::MAX));
+    buffer.extend_from_slice(&(length as u16).to_be_bytes());
+    buffer.extend_from_slice(&value[..length]);
+}
+
+fn read_string(buffer: &[u8]) -> Result<(&[u8], &[u8])> {
+    let (length, rest) = read_u16(buffer)?;
+    let length = usize::from(length);
+    if rest.len() < length {
+        return Err(anyhow!("Truncated string"));
+    }
+    Ok(rest.split_at(length))
+}
+
+fn read_u16(buffer: &[u8]) -> Result<(u16, &[u8])> {
+    match buffer {
+        [high, low, rest @ ..] => Ok((u16::from_be_bytes([*high, *low]), rest)),
+        _ => Err(anyhow!("Truncated packet")),
+    }
+}
+
+/// MQTT 3.1.1 client session over a stream, limited to QoS 0
+///
+/// The read timeout of the stream sets how long `poll()` waits for a message.
+/// Keep alive pings are sent from `poll()`, call it at least every few seconds.
+pub struct MqttClient<S: Read + Write> {
+    stream: S,
+    /// Received bytes, not decoded yet
+    buffer: Vec<u8>,
+    keep_alive: Duration,
+    last_sent: Instant,
+    /// Time of the ping waiting for a response
+    ping_sent: Option<Instant>,
+    next_packet_id: u16,
+}
+
+impl<S: Read + Write> MqttClient<S> {
+    /// Open a session on `stream`, and wait for the broker to accept it.
+    pub fn connect(stream: S, options: &ConnectOptions) -> Result<Self> {
+        let mut client = Self {
+            stream,
+            buffer: Vec::new(),
+            keep_alive: options.keep_alive,
+            last_sent: Instant::now(),
+            ping_sent: None,
+            next_packet_id: 1,
+        };
+        client.send(&encode_connect(options))?;
+
+        let start = Instant::now();
+        while start.elapsed() < CONNECT_TIMEOUT {
+            match client.receive()? {
+                Some(Packet::ConnAck { return_code: 0, .. }) => return Ok(client),
+                Some(Packet::ConnAck { return_code, .. }) => {
+                    return Err(anyhow!("Connection refused: {}", connection_refused(return_code)))
+                }
+                Some(packet) => return Err(anyhow!("Unexpected packet {:?}", packet)),
+                None => (),
+            }
+        }
+        Err(anyhow!("No answer from the broker"))
+    }
+
+    pub fn publish(&mut self, message: &Message) -> Result<()> {
+        self.send(&encode_publish(message))
+    }
+
+    /// Subscribe to the topic filters. Refused subscriptions are logged when
+    /// the broker answers.
+    pub fn subscribe(&mut self, filters: &[&str]) -> Result<()> {
+        let packet_id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        self.send(&encode_subscribe(packet_id, filters))
+    }
+
+    /// Wait for a message from the broker, at most the read timeout of the stream.
+    ///
+    /// # Errors
+    /// The connection is lost, or the broker doesn't answer the pings.
+    pub fn poll(&mut self) -> Result<Option<Message>> {
+        if let Some(ping_sent) = self.ping_sent {
+            if ping_sent.elapsed() > self.keep_alive {
+                return Err(anyhow!("No ping response from the broker"));
+            }
+        } else if self.last_sent.elapsed() >= self.keep_alive / 2 {
+            self.send(&encode_pingreq())?;
+            self.ping_sent = Some(Instant::now());
+        }
+
+        match self.receive()? {
+            Some(Packet::Publish { message, packet_id }) => {
+                // Only QoS 0 is subscribed, acknowledge anyway if the broker upgraded it
+                if let Some(packet_id) = packet_id {
+                    let mut puback = vec![PUBACK << 4, 2];
+                    puback.extend_from_slice(&packet_id.to_be_bytes());
+                    self.send(&puback)?;
+                }
+                Ok(Some(message))
+            }
+            Some(Packet::SubAck { packet_id, return_codes }) => {
+                if return_codes.contains(&SUBSCRIPTION_FAILURE) {
+                    warn!("Broker refused subscription {}: {:?}", packet_id, return_codes);
+                }
+                Ok(None)
+            }
+            Some(Packet::PingResp) => {
+                self.ping_sent = None;
+                Ok(None)
+            }
+            Some(packet) => {
+                debug!("Ignore MQTT packet {:?}", packet);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Close the session, the will message is not published.
+    pub fn disconnect(mut self) -> Result<()> {
+        self.send(&encode_disconnect())
+    }
+
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self.stream.write_all(packet)?;
+        self.stream.flush()?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Decode the next packet, reading from the stream if none is buffered.
+    fn receive(&mut self) -> Result<Option<Packet>> {
+        if let Some((packet, length)) = decode_packet(&self.buffer)? {
+            self.buffer.drain(..length);
+            return Ok(Some(packet));
+        }
+
+        let mut chunk = [0_u8; 512];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => return Err(anyhow!("Connection closed by the broker")),
+            Ok(length) => self.buffer.extend_from_slice(&chunk[..length]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        match decode_packet(&self.buffer)? {
+            Some((packet, length)) => {
+                self.buffer.drain(..length);
+                Ok(Some(packet))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Stream answering with the given bytes, one read at a time, then timing out
+    #[derive(Default)]
+    struct FakeStream {
+        reads: VecDeque<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for FakeStream {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            match self.reads.pop_front() {
+                Some(data) => {
+                    buffer[..data.len()].copy_from_slice(&data);
+                    Ok(data.len())
+                }
+                None => Err(ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for FakeStream {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn options() -> ConnectOptions {
+        ConnectOptions {
+            client_id: String::from("clock"),
+            keep_alive: Duration::from_secs(60),
+            credentials: None,
+            will: None,
+        }
+    }
+
+    #[test]
+    fn connect_packet() {
+        assert_eq!(
+            encode_connect(&options()),
+            [0x10, 17, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60, 0, 5, b'c', b'l', b'o', b'c', b'k']
+        );
+
+        let options = ConnectOptions {
+            credentials: Some((String::from("u"), String::from("p"))),
+            will: Some(Message::new("t", "off", true)),
+            ..options()
+        };
+        let packet = encode_connect(&options);
+        assert_eq!(packet[9], CONNECT_USERNAME | CONNECT_PASSWORD | CONNECT_WILL_RETAIN | CONNECT_WILL | CONNECT_CLEAN_SESSION);
+        assert_eq!(packet[19..], [0, 1, b't', 0, 3, b'o', b'f', b'f', 0, 1, b'u', 0, 1, b'p']);
+    }
+
+    #[test]
+    fn publish_and_subscribe_packets() {
+        assert_eq!(encode_publish(&Message::new("a/b", "on", true)), [0x31, 7, 0, 3, b'a', b'/', b'b', b'o', b'n']);
+        assert_eq!(encode_subscribe(10, &["a/#"]), [0x82, 8, 0, 10, 0, 3, b'a', b'/', b'#', 0]);
+        assert_eq!(encode_pingreq(), [0xC0, 0]);
+        assert_eq!(encode_disconnect(), [0xE0, 0]);
+    }
+
+    #[test]
+    fn remaining_length() {
+        let message = Message { topic: String::from("t"), payload: vec![b'x'; 200], retain: false };
+        let packet = encode_publish(&message);
+        assert_eq!(packet[..3], [0x30, 0xCB, 0x01]);
+
+        let (decoded, length) = decode_packet(&packet).unwrap().unwrap();
+        assert_eq!(decoded, Packet::Publish { message, packet_id: None });
+        assert_eq!(length, packet.len());
+    }
+
+    #[test]
+    fn decode_packets() {
+        assert_eq!(
+            decode_packet(&[0x20, 2, 0, 0]).unwrap(),
+            Some((Packet::ConnAck { session_present: false, return_code: 0 }, 4))
+        );
+        assert_eq!(
+            decode_packet(&[0x90, 3, 0, 1, 0x80]).unwrap(),
+            Some((Packet::SubAck { packet_id: 1, return_codes: vec![SUBSCRIPTION_FAILURE] }, 5))
+        );
+        assert_eq!(decode_packet(&[0xD0, 0, 0x20]).unwrap(), Some((Packet::PingResp, 2)));
+
+        // QoS 1 message, with a packet identifier
+        let (packet, _) = decode_packet(&[0x32, 6, 0, 1, b't', 0, 7, b'x']).unwrap().unwrap();
+        assert_eq!(packet, Packet::Publish { message: Message::new("t", "x", false), packet_id: Some(7) });
+    }
+
+    #[test]
+    fn incomplete_and_invalid_packets() {
+        assert_eq!(decode_packet(&[]).unwrap(), None);
+        assert_eq!(decode_packet(&[0x30]).unwrap(), None);
+        assert_eq!(decode_packet(&[0x30, 0x80]).unwrap(), None);
+        assert_eq!(decode_packet(&[0x30, 5, 0, 1, b't']).unwrap(), None);
+
+        assert!(decode_packet(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).is_err());
+        assert!(decode_packet(&[0x30, 0x80, 0x80, 0x01]).is_err());
+        assert!(decode_packet(&[0x20, 1, 0]).is_err());
+        assert!(decode_packet(&[0x30, 3, 0, 5, b't']).is_err());
+    }
+
+    #[test]
+    fn session() {
+        let stream = FakeStream {
+            // Acknowledge and first message in a single read, the second split in two reads
+            reads: VecDeque::from([
+                vec![0x20, 2, 0, 0, 0x30, 4, 0, 1, b't', b'a'],
+                vec![0x30, 4, 0, 1],
+                vec![b't', b'b'],
+            ]),
+            written: Vec::new(),
+        };
+        let mut client = MqttClient::connect(stream, &options()).unwrap();
+        client.subscribe(&["t"]).unwrap();
+
+        assert_eq!(client.poll().unwrap(), Some(Message::new("t", "a", false)));
+        assert_eq!(client.poll().unwrap(), None);
+        assert_eq!(client.poll().unwrap(), Some(Message::new("t", "b", false)));
+        assert_eq!(client.poll().unwrap(), None);
+
+        let connect_length = encode_connect(&options()).len();
+        assert_eq!(client.stream.written[connect_length..], encode_subscribe(1, &["t"]));
+    }
+
+    #[test]
+    fn refused_connection() {
+        let stream = FakeStream { reads: VecDeque::from([vec![0x20, 2, 0, 4]]), written: Vec::new() };
+        let error = MqttClient::connect(stream, &options()).err().unwrap();
+        assert_eq!(error.to_string(), "Connection refused: bad user name or password");
+
+        let stream = FakeStream { reads: VecDeque::from([vec![]]), written: Vec::new() };
+        assert!(MqttClient::connect(stream, &options()).is_err());
+    }
+
+    #[test]
+    fn keep_alive() {
+        let stream = FakeStream { reads: VecDeque::from([vec![0x20, 2, 0, 0]]), written: Vec::new() };
+        let options = ConnectOptions { keep_alive: Duration::from_millis(20), ..options() };
+        let mut client = MqttClient::connect(stream, &options).unwrap();
+        let written = client.stream.written.len();
+
+        std::thread::sleep(Duration::from_millis(10));
+        client.poll().unwrap();
+        assert_eq!(client.stream.written[written..], encode_pingreq());
+
+        // Answered ping
+        client.stream.reads.push_back(vec![0xD0, 0]);
+        client.poll().unwrap();
+        assert_eq!(client.ping_sent, None);
+
+        // Unanswered ping
+        std::thread::sleep(Duration::from_millis(10));
+        client.poll().unwrap();
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(client.poll().is_err());
+    }
+}
@@ -0,0 +1,533 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::{notification_from_json, ApiAction, ApiState, ApiStatus};
+use crate::build_version::BUILD_VERSION_STRING;
+use crate::color::Color;
+use crate::configuration::{Configuration, MqttBroker, FORM_BRIGHTNESS_KEY, FORM_DISPLAY_COLOR_KEY};
+use crate::mqtt::{ConnectOptions, Message, MqttClient};
+use crate::network::DEFAULT_HOSTNAME;
+use crate::notification::Notification;
+
+/// Topic prefix of the Home Assistant discovery messages
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Topics of a clock are under `wordclock/<hostname>/`.
+const TOPIC_ROOT: &str = "wordclock";
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Maximum time to wait for a message from the broker, in each `step()`
+const POLL_TIMEOUT: Duration = Duration::from_millis(500);
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay before connecting again after a failure
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Period of `run()` while not connected
+const IDLE_PERIOD: Duration = Duration::from_secs(1);
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// MQTT topics of a clock
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topics {
+    base: String,
+    /// Identifier of the clock in Home Assistant
+    node_id: String,
+}
+
+impl Topics {
+    pub fn new(hostname: &str) -> Self {
+        Self { base: format!("{}/{}", TOPIC_ROOT, hostname), node_id: format!("{}_{}", TOPIC_ROOT, hostname) }
+    }
+
+    /// `online` or `offline`, set by the broker when the connection is lost
+    pub fn availability(&self) -> String {
+        format!("{}/availability", self.base)
+    }
+
+    /// State of the display as Home Assistant JSON light
+    pub fn light_state(&self) -> String {
+        format!("{}/light", self.base)
+    }
+
+    pub fn light_command(&self) -> String {
+        format!("{}/light/set", self.base)
+    }
+
+    /// State of the clock, see `clock_status()`
+    pub fn status(&self) -> String {
+        format!("{}/status", self.base)
+    }
+
+    /// Any message synchronizes the time.
+    pub fn sync_command(&self) -> String {
+        format!("{}/sync", self.base)
+    }
+
+    /// Notification to show, as the body of `POST /api/notify`
+    pub fn notify_command(&self) -> String {
+        format!("{}/notify", self.base)
+    }
+}
+
+/// Command received from the broker
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Members of a JSON light command, missing ones are unchanged
+    Light { on: Option<bool>, brightness: Option<u8>, color: Option<Color> },
+    Sync,
+    Notify(Notification),
+}
+
+#[derive(Deserialize)]
+struct LightCommand {
+    state: Option<String>,
+    brightness: Option<u8>,
+    color: Option<LightColor>,
+}
+
+#[derive(Deserialize)]
+struct LightColor {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+/// Short state of the clock published on the status topic, from the state
+/// of the behaviour: `on`, `night`, `off`, `error`, `update` or `startup`.
+pub fn clock_status(state: &str) -> &'static str {
+    match state {
+        "DisplayTime" | "Menu" | "Notification" => "on",
+        "NightMode" => "night",
+        "DisplayOff" => "off",
+        "Error" => "error",
+        "Fota" => "update",
+        _ => "startup",
+    }
+}
+
+/// Home Assistant discovery messages of the clock: a light for the display,
+/// a sensor for the status and a button to synchronize the time.
+pub fn discovery_messages(topics: &Topics, hostname: &str) -> Vec<Message> {
+    let device = json!({
+        "identifiers": [topics.node_id],
+        "name": format!("WordClock {}", hostname),
+        "model": "WordClock",
+        "sw_version": BUILD_VERSION_STRING,
+    });
+    let entities = [
+        (
+            "light",
+            "display",
+            json!({
+                "name": "Display",
+                "schema": "json",
+                "state_topic": topics.light_state(),
+                "command_topic": topics.light_command(),
+                "brightness": true,
+                "brightness_scale": 100,
+                "supported_color_modes": ["rgb"],
+            }),
+        ),
+        ("sensor", "status", json!({ "name": "Status", "state_topic": topics.status() })),
+        ("button", "sync", json!({ "name": "Synchronize time", "command_topic": topics.sync_command() })),
+    ];
+
+    entities
+        .into_iter()
+        .map(|(component, object_id, mut config)| {
+            config["unique_id"] = json!(format!("{}_{}", topics.node_id, object_id));
+            config["availability_topic"] = json!(topics.availability());
+            config["device"] = device.clone();
+            let topic = format!("{}/{}/{}/{}/config", DISCOVERY_PREFIX, component, topics.node_id, object_id);
+            Message::new(&topic, &config.to_string(), true)
+        })
+        .collect()
+}
+
+/// State of the display as Home Assistant JSON light. The display is on while
+/// showing the time or the menu.
+pub fn light_state(status: &ApiStatus, settings: &Configuration) -> String {
+    let color = settings.get_display_color().unwrap_or_default();
+    json!({
+        "state": if clock_status(&status.state) == "on" { "ON" } else { "OFF" },
+        "brightness": settings.get_brightness(),
+        "color_mode": "rgb",
+        "color": { "r": color.rgb.r, "g": color.rgb.g, "b": color.rgb.b },
+    })
+    .to_string()
+}
+
+/// Command of a received message, `None` for messages of other topics.
+pub fn parse_command(topics: &Topics, message: &Message) -> Result<Option<Command>> {
+    if message.topic == topics.sync_command() {
+        return Ok(Some(Command::Sync));
+    }
+    if message.topic == topics.notify_command() {
+        return notification_from_json(&message.payload).map(|notification| Some(Command::Notify(notification)));
+    }
+    if message.topic != topics.light_command() {
+        return Ok(None);
+    }
+
+    let command: LightCommand = serde_json::from_slice(&message.payload)?;
+    let on = match command.state.as_deref() {
+        Some("ON") => Some(true),
+        Some("OFF") => Some(false),
+        Some(state) => return Err(anyhow!("Unknown light state {:?}", state)),
+        None => None,
+    };
+    Ok(Some(Command::Light {
+        on,
+        brightness: command.brightness,
+        color: command.color.map(|color| Color::new(color.r, color.g, color.b)),
+    }))
+}
+
+/// Hand a command over to the application, like a REST API request.
+///
+/// Color and brightness are validated and applied like the settings page.
+pub fn handle_command(state: &Mutex<ApiState>, command: Command) {
+    let mut state = state.lock().unwrap();
+    match command {
+        Command::Sync => state.request_action(ApiAction::Sync),
+        Command::Notify(notification) => {
+            if let Err(e) = state.notify(notification) {
+                warn!("Notification dropped: {}", e);
+            }
+        }
+        Command::Light { on, brightness, color } => {
+            if brightness.is_some() || color.is_some() {
+                if let Some(current) = &state.settings {
+                    let mut fields = current.settings_fields();
+                    for (key, value) in fields.iter_mut() {
+                        match (key.as_str(), brightness, color) {
+                            (FORM_BRIGHTNESS_KEY, Some(brightness), _) => *value = brightness.to_string(),
+                            (FORM_DISPLAY_COLOR_KEY, _, Some(color)) => *value = format!("#{}", color),
+                            _ => (),
+                        }
+                    }
+                    state.submit_settings(fields);
+                }
+            }
+            match on {
+                Some(true) => state.request_action(ApiAction::On),
+                Some(false) => state.request_action(ApiAction::Off),
+                None => (),
+            }
+        }
+    }
+}
+
+/// Connection of the clock to its MQTT broker
+struct Session {
+    client: MqttClient<TcpStream>,
+    broker: MqttBroker,
+    topics: Topics,
+    /// Last published light state and status, published again on change
+    light_state: Option<String>,
+    status: Option<&'static str>,
+}
+
+impl Session {
+    fn open(broker: &MqttBroker, hostname: &str) -> Result<Self> {
+        let Some(address) = (broker.host.as_str(), broker.port).to_socket_addrs()?.next() else {
+            return Err(anyhow!("Unknown host {}", broker.host));
+        };
+        let stream = TcpStream::connect_timeout(&address, CONNECTION_TIMEOUT)?;
+        stream.set_read_timeout(Some(POLL_TIMEOUT))?;
+
+        let topics = Topics::new(hostname);
+        let options = ConnectOptions {
+            client_id: format!("{}-{}", TOPIC_ROOT, hostname),
+            keep_alive: KEEP_ALIVE,
+            credentials: match broker.username.is_empty() {
+                true => None,
+                false => Some((broker.username.clone(), broker.password.clone())),
+            },
+            will: Some(Message::new(&topics.availability(), OFFLINE, true)),
+        };
+        let mut client = MqttClient::connect(stream, &options)?;
+
+        for message in discovery_messages(&topics, hostname) {
+            client.publish(&message)?;
+        }
+        client.publish(&Message::new(&topics.availability(), ONLINE, true))?;
+        client.subscribe(&[&topics.light_command(), &topics.sync_command(), &topics.notify_command()])?;
+
+        Ok(Self { client, broker: broker.clone(), topics, light_state: None, status: None })
+    }
+
+    /// Handle a received command, and publish the state if it changed.
+    fn step(&mut self, state: &Mutex<ApiState>, settings: &Configuration, status: Option<&ApiStatus>) -> Result<()> {
+        if let Some(message) = self.client.poll()? {
+            match parse_command(&self.topics, &message) {
+                Ok(Some(command)) => {
+                    info!("MQTT command {:?}", command);
+                    handle_command(state, command);
+                }
+                Ok(None) => (),
+                Err(e) => warn!("Invalid MQTT command on {}: {}", message.topic, e),
+            }
+        }
+
+        let Some(status) = status else {
+            return Ok(());
+        };
+        let light_state = light_state(status, settings);
+        if self.light_state.as_ref() != Some(&light_state) {
+            self.client.publish(&Message::new(&self.topics.light_state(), &light_state, true))?;
+            self.light_state = Some(light_state);
+        }
+        let clock_status = clock_status(&status.state);
+        if self.status != Some(clock_status) {
+            self.client.publish(&Message::new(&self.topics.status(), clock_status, true))?;
+            self.status = Some(clock_status);
+        }
+        Ok(())
+    }
+
+    /// Leave the broker, marking the clock offline.
+    fn close(mut self) {
+        let offline = Message::new(&self.topics.availability(), OFFLINE, true);
+        if let Err(e) = self.client.publish(&offline).and_then(|_| self.client.disconnect()) {
+            warn!("Failed to disconnect from MQTT broker: {}", e);
+        }
+    }
+}
+
+/// Bridge between the MQTT broker set in the configuration and the application
+///
+/// Runs on its own thread, and exchanges with the application through the
+/// state of the REST API: the clock is registered in Home Assistant, its state
+/// is published, and the received commands are handed over like API requests.
+#[derive(Default)]
+pub struct MqttBridge {
+    session: Option<Session>,
+    /// Earliest time of the next connection attempt
+    retry_at: Option<Instant>,
+}
+
+impl MqttBridge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the bridge forever.
+    pub fn run(&mut self, state: &Mutex<ApiState>) -> ! {
+        loop {
+            if !self.step(state) {
+                thread::sleep(IDLE_PERIOD);
+            }
+        }
+    }
+
+    /// Connect to the broker if needed, handle a received command and publish
+    /// the state of the clock. Waits for a command at most `POLL_TIMEOUT`.
+    ///
+    /// Return whether connected to the broker. The connection is opened again
+    /// when the broker or the host name of the clock changes.
+    pub fn step(&mut self, state: &Mutex<ApiState>) -> bool {
+        let (settings, status) = {
+            let state = state.lock().unwrap();
+            (state.settings.clone(), state.status.clone())
+        };
+        let broker = settings.as_ref().and_then(|settings| settings.get_mqtt_broker());
+        let hostname = settings
+            .as_ref()
+            .and_then(|settings| settings.get_hostname())
+            .unwrap_or_else(|| String::from(DEFAULT_HOSTNAME));
+
+        let changed = self
+            .session
+            .as_ref()
+            .is_some_and(|session| Some(&session.broker) != broker.as_ref() || session.topics != Topics::new(&hostname));
+        if changed {
+            info!("MQTT settings changed, disconnect");
+            if let Some(session) = self.session.take() {
+                session.close();
+            }
+            self.retry_at = None;
+        }
+        let (Some(settings), Some(broker)) = (settings, broker) else {
+            return false;
+        };
+
+        if self.session.is_none() {
+            if self.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+                return false;
+            }
+            match Session::open(&broker, &hostname) {
+                Ok(session) => {
+                    info!("Connected to MQTT broker {}:{}", broker.host, broker.port);
+                    self.session = Some(session);
+                }
+                Err(e) => {
+                    warn!("Failed to connect to MQTT broker {}:{}: {}", broker.host, broker.port, e);
+                    self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                    return false;
+                }
+            }
+        }
+
+        let Some(session) = self.session.as_mut() else {
+            return false;
+        };
+        if let Err(e) = session.step(state, &settings, status.as_ref()) {
+            warn!("MQTT connection lost: {}", e);
+            self.session = None;
+            self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notification::Pattern;
+    use crate::boot_validation::ImageState;
+    use crate::time_source::TimeSourceHealth;
+
+    fn status(state: &str) -> ApiStatus {
+        ApiStatus {
+            state: String::from(state),
+            time: None,
+            time_source: TimeSourceHealth { synchronized: true, last_network_sync: None, board_synchronized: None },
+            version: String::from(BUILD_VERSION_STRING),
+            image: ImageState::Valid,
+            uptime: 0,
+            rssi: None,
+        }
+    }
+
+    fn configuration_with_color(color: Color) -> Configuration {
+        Configuration::new(String::from("home"), String::from("secret"), None, None, color)
+            .with_brightness(40)
+            .with_mqtt_broker(Some(MqttBroker::new("broker.lan")))
+    }
+
+    fn configuration() -> Configuration {
+        configuration_with_color(Color::new(255, 128, 0))
+    }
+
+    #[test]
+    fn topics() {
+        let topics = Topics::new("kitchen");
+        assert_eq!(topics.availability(), "wordclock/kitchen/availability");
+        assert_eq!(topics.light_state(), "wordclock/kitchen/light");
+        assert_eq!(topics.light_command(), "wordclock/kitchen/light/set");
+        assert_eq!(topics.status(), "wordclock/kitchen/status");
+        assert_eq!(topics.sync_command(), "wordclock/kitchen/sync");
+        assert_eq!(topics.notify_command(), "wordclock/kitchen/notify");
+    }
+
+    #[test]
+    fn discovery() {
+        let messages = discovery_messages(&Topics::new("kitchen"), "kitchen");
+        let topics: Vec<&str> = messages.iter().map(|message| message.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/light/wordclock_kitchen/display/config",
+                "homeassistant/sensor/wordclock_kitchen/status/config",
+                "homeassistant/button/wordclock_kitchen/sync/config",
+            ]
+        );
+        assert!(messages.iter().all(|message| message.retain));
+
+        let light: serde_json::Value = serde_json::from_slice(&messages[0].payload).unwrap();
+        assert_eq!(light["unique_id"], "wordclock_kitchen_display");
+        assert_eq!(light["command_topic"], "wordclock/kitchen/light/set");
+        assert_eq!(light["availability_topic"], "wordclock/kitchen/availability");
+        assert_eq!(light["brightness_scale"], 100);
+        assert_eq!(light["device"]["identifiers"], json!(["wordclock_kitchen"]));
+        assert_eq!(light["device"]["sw_version"], BUILD_VERSION_STRING);
+    }
+
+    #[test]
+    fn published_state() {
+        let state: serde_json::Value = serde_json::from_str(&light_state(&status("DisplayTime"), &configuration())).unwrap();
+        assert_eq!(
+            state,
+            json!({"state": "ON", "brightness": 40, "color_mode": "rgb", "color": {"r": 255, "g": 128, "b": 0}})
+        );
+        let state: serde_json::Value = serde_json::from_str(&light_state(&status("NightMode"), &configuration())).unwrap();
+        assert_eq!(state["state"], "OFF");
+
+        assert_eq!(clock_status("DisplayTime"), "on");
+        assert_eq!(clock_status("NightMode"), "night");
+        assert_eq!(clock_status("DisplayOff"), "off");
+        assert_eq!(clock_status("Error"), "error");
+        assert_eq!(clock_status("Configuration"), "startup");
+    }
+
+    #[test]
+    fn commands() {
+        let topics = Topics::new("wordclock");
+        let command = |topic: &str, payload: &str| parse_command(&topics, &Message::new(topic, payload, false));
+
+        assert_eq!(
+            command("wordclock/wordclock/light/set", r#"{"state": "ON", "color": {"r": 0, "g": 0, "b": 255}}"#).unwrap(),
+            Some(Command::Light { on: Some(true), brightness: None, color: Some(Color::new(0, 0, 255)) })
+        );
+        assert_eq!(
+            command("wordclock/wordclock/light/set", r#"{"brightness": 20}"#).unwrap(),
+            Some(Command::Light { on: None, brightness: Some(20), color: None })
+        );
+        assert_eq!(command("wordclock/wordclock/sync", "").unwrap(), Some(Command::Sync));
+        let notification = Notification::new(Color::new(255, 0, 0), Pattern::Pulse, Duration::from_secs(3)).unwrap();
+        assert_eq!(
+            command("wordclock/wordclock/notify", r#"{"color": "FF0000", "pattern": "pulse", "duration": 3}"#).unwrap(),
+            Some(Command::Notify(notification))
+        );
+        assert!(command("wordclock/wordclock/notify", "ring").is_err());
+        assert_eq!(command("wordclock/kitchen/sync", "").unwrap(), None);
+
+        assert!(command("wordclock/wordclock/light/set", r#"{"state": "DIM"}"#).is_err());
+        assert!(command("wordclock/wordclock/light/set", "ON").is_err());
+    }
+
+    #[test]
+    fn commands_are_handed_over() {
+        let state = Mutex::new(ApiState::new());
+        state.lock().unwrap().settings = Some(configuration());
+
+        let color = Some(Color::new(0, 0, 255));
+        handle_command(&state, Command::Light { on: Some(false), brightness: Some(20), color });
+        handle_command(&state, Command::Sync);
+
+        let state = state.lock().unwrap();
+        assert_eq!(state.actions, [ApiAction::Off, ApiAction::Sync]);
+        let mut updated = configuration();
+        updated.update_settings(state.settings_fields.as_ref().unwrap()).unwrap();
+        assert_eq!(updated, configuration_with_color(Color::new(0, 0, 255)).with_brightness(20));
+    }
+
+    #[test]
+    fn notification_is_handed_over_while_showing_time() {
+        let state = Mutex::new(ApiState::new());
+        state.lock().unwrap().settings = Some(configuration());
+        let notification = Notification::new(Color::new(255, 0, 0), Pattern::Flash, Duration::from_secs(5)).unwrap();
+
+        state.lock().unwrap().status = Some(status("DisplayOff"));
+        handle_command(&state, Command::Notify(notification));
+        assert_eq!(state.lock().unwrap().notification, None);
+
+        state.lock().unwrap().status = Some(status("DisplayTime"));
+        handle_command(&state, Command::Notify(notification));
+        assert_eq!(state.lock().unwrap().notification, Some(notification));
+    }
+}
@@ -2,7 +2,167 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
-use anyhow::Result;
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::configuration::WifiCredentials;
+
+/// Name of the WiFi access point used in configuration mode, followed by a
+/// suffix unique to each clock. See `access_point_name()`.
+pub const ACCESS_POINT_NAME: &str = "WordClock Configuration";
+
+/// Default mDNS host name of the clock once connected, the settings page is
+/// served on `http://wordclock.local`.
+pub const DEFAULT_HOSTNAME: &str = "wordclock";
+
+/// Maximum length of a host name, as a single DNS label
+pub const MAX_HOSTNAME_LENGTH: usize = 63;
+
+/// Length limits of a WPA2 passphrase
+pub const MIN_PASSPHRASE_LENGTH: usize = 8;
+pub const MAX_PASSPHRASE_LENGTH: usize = 63;
+
+/// IPv4 address of the clock on its access point
+pub const ACCESS_POINT_ADDRESS: [u8; 4] = [192, 168, 71, 1];
+
+/// Protection of the configuration access point
+#[derive(Debug, PartialEq, Clone)]
+pub enum AccessPointSecurity {
+    Open,
+    /// WPA2 passphrase required to join the access point
+    Passphrase(String),
+    /// Open access point, the PIN shown on the display must be entered in the
+    /// configuration form.
+    Pin([u8; 4]),
+}
+
+impl AccessPointSecurity {
+    /// Passphrase protection, for a valid WPA2 passphrase.
+    pub fn passphrase(passphrase: &str) -> Result<Self> {
+        if !(MIN_PASSPHRASE_LENGTH..=MAX_PASSPHRASE_LENGTH).contains(&passphrase.len()) || !passphrase.is_ascii() {
+            return Err(anyhow!(
+                "Access point passphrase must have {} to {} ASCII characters",
+                MIN_PASSPHRASE_LENGTH,
+                MAX_PASSPHRASE_LENGTH
+            ));
+        }
+        Ok(Self::Passphrase(String::from(passphrase)))
+    }
+
+    /// PIN protection, with a PIN derived from a random number.
+    pub fn pin(random: u32) -> Self {
+        let mut pin = [0; 4];
+        let mut value = random % 10_000;
+        for digit in pin.iter_mut().rev() {
+            *digit = (value % 10) as u8;
+            value /= 10;
+        }
+        Self::Pin(pin)
+    }
+}
+
+/// Whether the name is a valid host name: a single DNS label of ASCII letters,
+/// digits and hyphens, not starting nor ending with a hyphen.
+pub fn is_valid_hostname(name: &str) -> bool {
+    (1..=MAX_HOSTNAME_LENGTH).contains(&name.len())
+        && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Name of the access point of the clock with the given MAC address.
+///
+/// Suffixed with the last bytes of the address, to tell several clocks apart.
+pub fn access_point_name(mac_address: [u8; 6]) -> String {
+    format!("{} {:02X}{:02X}", ACCESS_POINT_NAME, mac_address[4], mac_address[5])
+}
+
+/// Start the configuration access point, named after the MAC address of the network interface.
+pub fn setup_configuration_access_point<N: Network>(network: &mut N, security: &AccessPointSecurity) -> Result<()> {
+    let name = access_point_name(network.mac_address()?);
+    let passphrase = match security {
+        AccessPointSecurity::Passphrase(passphrase) => Some(passphrase.as_str()),
+        _ => None,
+    };
+    network.setup_access_point(&name, passphrase)
+}
+
+/// Network failures the application can react to.
+///
+/// Implementations of `Network` should return these errors when possible, so
+/// the application can choose the proper recovery.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NetworkError {
+    NotFound,
+    AuthenticationFailed,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Could not find network"),
+            Self::AuthenticationFailed => write!(f, "Network authentication failed"),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+/// Security of a WiFi network
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WifiSecurity {
+    Open,
+    Wep,
+    /// WPA, WPA2 or WPA3 personal, using a password
+    Wpa,
+    /// Not supported by the clock
+    Enterprise,
+}
+
+/// WiFi network found by a scan
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ScanResult {
+    pub ssid: String,
+    /// Signal strength in dBm
+    pub rssi: i8,
+    pub security: WifiSecurity,
+}
+
+/// Serialize the scan results for the configuration form, strongest first.
+///
+/// Each SSID is listed once, with its strongest access point. Hidden networks
+/// are left out.
+pub fn scan_results_to_json(results: &[ScanResult]) -> String {
+    let mut networks: Vec<&ScanResult> = Vec::new();
+    for result in results.iter().filter(|result| !result.ssid.is_empty()) {
+        match networks.iter_mut().find(|network| network.ssid == result.ssid) {
+            Some(network) if network.rssi < result.rssi => *network = result,
+            Some(_) => (),
+            None => networks.push(result),
+        }
+    }
+    networks.sort_by_key(|network| std::cmp::Reverse(network.rssi));
+    serde_json::to_string(&networks).unwrap_or_else(|_| String::from("[]"))
+}
+
+/// Order the known networks visible in the scan, best first.
+///
+/// Networks are ordered by priority, then by signal strength. Known networks
+/// not found by the scan are left out.
+pub fn rank_networks<'a>(known: &'a [WifiCredentials], visible: &[ScanResult]) -> Vec<&'a WifiCredentials> {
+    let mut candidates: Vec<(&WifiCredentials, i8)> = known
+        .iter()
+        .filter_map(|network| {
+            let rssi = visible.iter().filter(|scan| scan.ssid == network.ssid).map(|scan| scan.rssi).max()?;
+            Some((network, rssi))
+        })
+        .collect();
+    candidates.sort_by(|(a, a_rssi), (b, b_rssi)| b.priority.cmp(&a.priority).then(b_rssi.cmp(a_rssi)));
+    candidates.into_iter().map(|(network, _)| network).collect()
+}
 
 /// Interface to interact with network connection
 /// # Errors
@@ -12,5 +172,100 @@ pub trait Network {
     fn connect(&mut self) -> Result<()>;
     fn disconnect(&mut self) -> Result<()>;
     fn is_connected(&self) -> bool;
-    fn setup_access_point(&mut self, ssid: &str) -> Result<()>;
+    /// Scan the visible WiFi networks. Hidden networks have an empty SSID.
+    fn scan(&mut self) -> Result<Vec<ScanResult>>;
+    /// Start an access point, protected with WPA2 when a passphrase is given.
+    /// It stays active when configuring and connecting to a network, until
+    /// `stop_access_point()` is called.
+    fn setup_access_point(&mut self, ssid: &str, passphrase: Option<&str>) -> Result<()>;
+    fn stop_access_point(&mut self) -> Result<()>;
+    /// MAC address of the WiFi interface
+    fn mac_address(&self) -> Result<[u8; 6]>;
+    /// Signal strength of the connected network, in dBm
+    fn rssi(&self) -> Result<i8>;
+    /// Advertise the clock as `<hostname>.local` with mDNS, along with its
+    /// HTTP service. Called once connected, and again when the name changes.
+    fn advertise(&mut self, hostname: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(ssid: &str, rssi: i8) -> ScanResult {
+        ScanResult { ssid: String::from(ssid), rssi, security: WifiSecurity::Wpa }
+    }
+
+    #[test]
+    fn rank_by_priority_then_signal() {
+        let known = [
+            WifiCredentials::new("home", "1", 1),
+            WifiCredentials::new("holiday", "2", 1),
+            WifiCredentials::new("phone", "3", 0),
+            WifiCredentials::new("office", "4", 5),
+        ];
+        let visible = [scan("phone", -30), scan("home", -80), scan("neighbour", -20), scan("holiday", -60)];
+
+        let ranked: Vec<&str> = rank_networks(&known, &visible).iter().map(|n| n.ssid.as_str()).collect();
+        assert_eq!(ranked, vec!["holiday", "home", "phone"]);
+    }
+
+    #[test]
+    fn rank_uses_strongest_access_point() {
+        let known = [WifiCredentials::new("home", "1", 0), WifiCredentials::new("mesh", "2", 0)];
+        let visible = [scan("mesh", -85), scan("home", -60), scan("mesh", -40)];
+
+        let ranked: Vec<&str> = rank_networks(&known, &visible).iter().map(|n| n.ssid.as_str()).collect();
+        assert_eq!(ranked, vec!["mesh", "home"]);
+        assert!(rank_networks(&known, &[]).is_empty());
+    }
+
+    #[test]
+    fn unique_access_point_name() {
+        assert_eq!(
+            access_point_name([0x24, 0x6F, 0x28, 0x01, 0xA2, 0x0B]),
+            "WordClock Configuration A20B"
+        );
+        // SSIDs are limited to 32 bytes
+        assert!(access_point_name([0xFF; 6]).len() <= 32);
+    }
+
+    #[test]
+    fn hostname_validation() {
+        assert!(is_valid_hostname(DEFAULT_HOSTNAME));
+        assert!(is_valid_hostname("Kitchen-Clock-2"));
+        assert!(is_valid_hostname(&"a".repeat(MAX_HOSTNAME_LENGTH)));
+        for name in ["", "-clock", "clock-", "my.clock", "my clock", "grüezi"] {
+            assert!(!is_valid_hostname(name), "{:?}", name);
+        }
+        assert!(!is_valid_hostname(&"a".repeat(MAX_HOSTNAME_LENGTH + 1)));
+    }
+
+    #[test]
+    fn access_point_security() {
+        assert_eq!(AccessPointSecurity::pin(123_456_789), AccessPointSecurity::Pin([6, 7, 8, 9]));
+        assert_eq!(AccessPointSecurity::pin(42), AccessPointSecurity::Pin([0, 0, 4, 2]));
+        assert!(AccessPointSecurity::passphrase("1234567").is_err());
+        assert!(AccessPointSecurity::passphrase(&"x".repeat(64)).is_err());
+        assert!(AccessPointSecurity::passphrase("grüezi mitenand").is_err());
+        assert_eq!(
+            AccessPointSecurity::passphrase("correct horse").unwrap(),
+            AccessPointSecurity::Passphrase(String::from("correct horse"))
+        );
+    }
+
+    #[test]
+    fn scan_results_json() {
+        let results = [
+            scan("home", -70),
+            ScanResult { ssid: String::from("cafe"), rssi: -50, security: WifiSecurity::Open },
+            scan("", -30),
+            scan("home", -40),
+        ];
+        assert_eq!(
+            scan_results_to_json(&results),
+            r#"[{"ssid":"home","rssi":-40,"security":"wpa"},{"ssid":"cafe","rssi":-50,"security":"open"}]"#
+        );
+        assert_eq!(scan_results_to_json(&[]), "[]");
+    }
 }
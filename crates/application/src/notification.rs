@@ -0,0 +1,144 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use crate::color::Color;
+
+/// Duration of a notification when not given
+pub const DEFAULT_NOTIFICATION_DURATION: Duration = Duration::from_secs(5);
+/// Longest notification, the time is hidden meanwhile
+pub const MAX_NOTIFICATION_DURATION: Duration = Duration::from_secs(60);
+
+/// Period of the flash pattern, lit the first half
+const FLASH_PERIOD: Duration = Duration::from_millis(500);
+/// Period of the pulse pattern, fading in then out
+const PULSE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Animation of a notification
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Pattern {
+    /// Blink the whole display
+    #[default]
+    Flash,
+    /// Fade the whole display in and out
+    Pulse,
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Flash => write!(f, "flash"),
+            Self::Pulse => write!(f, "pulse"),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "flash" => Ok(Self::Flash),
+            "pulse" => Ok(Self::Pulse),
+            _ => Err(anyhow!("Unknown pattern {:?}, expected flash or pulse", s)),
+        }
+    }
+}
+
+/// Colored pattern shown over the time for a while, e.g. when the doorbell rings
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Notification {
+    pub color: Color,
+    pub pattern: Pattern,
+    pub duration: Duration,
+}
+
+impl Notification {
+    /// # Errors
+    /// The duration is zero or longer than `MAX_NOTIFICATION_DURATION`.
+    pub fn new(color: Color, pattern: Pattern, duration: Duration) -> Result<Self> {
+        if duration.is_zero() || duration > MAX_NOTIFICATION_DURATION {
+            return Err(anyhow!(
+                "Duration must be between 1 and {} seconds",
+                MAX_NOTIFICATION_DURATION.as_secs()
+            ));
+        }
+        Ok(Self { color, pattern, duration })
+    }
+
+    /// Color of the whole display, `elapsed` since the notification started.
+    /// Black while the pattern is off, `None` once the notification is over.
+    pub fn frame(&self, elapsed: Duration) -> Option<Color> {
+        if elapsed >= self.duration {
+            return None;
+        }
+        let frame = match self.pattern {
+            Pattern::Flash => {
+                let lit = elapsed.as_millis() % FLASH_PERIOD.as_millis() < FLASH_PERIOD.as_millis() / 2;
+                if lit { self.color } else { Color::new(0, 0, 0) }
+            }
+            Pattern::Pulse => {
+                // Triangle wave, from 0 to 100 % and back
+                let period = PULSE_PERIOD.as_millis();
+                let phase = elapsed.as_millis() % period;
+                let level = 200 * phase.min(period - phase) / period;
+                let scale = |channel: u8| (u128::from(channel) * level / 100) as u8;
+                Color::new(scale(self.color.rgb.r), scale(self.color.rgb.g), scale(self.color.rgb.b))
+            }
+        };
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color { rgb: rgb::RGB8 { r: 255, g: 0, b: 0 } };
+
+    #[test]
+    fn pattern_from_string() {
+        assert_eq!("flash".parse::<Pattern>().unwrap(), Pattern::Flash);
+        assert_eq!("pulse".parse::<Pattern>().unwrap(), Pattern::Pulse);
+        assert!("blink".parse::<Pattern>().is_err());
+        assert_eq!(Pattern::Pulse.to_string(), "pulse");
+    }
+
+    #[test]
+    fn duration_is_limited() {
+        assert!(Notification::new(RED, Pattern::Flash, Duration::ZERO).is_err());
+        assert!(Notification::new(RED, Pattern::Flash, Duration::from_secs(61)).is_err());
+        assert!(Notification::new(RED, Pattern::Flash, MAX_NOTIFICATION_DURATION).is_ok());
+    }
+
+    #[test]
+    fn flash() {
+        let notification = Notification::new(RED, Pattern::Flash, Duration::from_secs(1)).unwrap();
+        let black = Color::new(0, 0, 0);
+
+        assert_eq!(notification.frame(Duration::ZERO), Some(RED));
+        assert_eq!(notification.frame(Duration::from_millis(249)), Some(RED));
+        assert_eq!(notification.frame(Duration::from_millis(250)), Some(black));
+        assert_eq!(notification.frame(Duration::from_millis(600)), Some(RED));
+        assert_eq!(notification.frame(Duration::from_millis(999)), Some(black));
+        assert_eq!(notification.frame(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn pulse() {
+        let notification = Notification::new(RED, Pattern::Pulse, Duration::from_secs(4)).unwrap();
+
+        assert_eq!(notification.frame(Duration::ZERO), Some(Color::new(0, 0, 0)));
+        assert_eq!(notification.frame(Duration::from_millis(500)), Some(Color::new(127, 0, 0)));
+        assert_eq!(notification.frame(Duration::from_secs(1)), Some(RED));
+        assert_eq!(notification.frame(Duration::from_millis(1500)), Some(Color::new(127, 0, 0)));
+        assert_eq!(notification.frame(Duration::from_secs(3)), Some(RED));
+        assert_eq!(notification.frame(Duration::from_secs(4)), None);
+    }
+}
@@ -0,0 +1,219 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use anyhow::{anyhow, Result};
+use log::*;
+
+use crate::firmware_update::{FirmwareSink, HttpClient, ResponseHead};
+use crate::ota_manifest::{FirmwareImage, ImageCheck, OtaManifest};
+use crate::ota_signature::PublicKey;
+
+/// Size of the chunks read from the server and written to the sink
+pub const DOWNLOAD_BUFFER_SIZE: usize = 4096;
+
+/// Largest manifest accepted
+pub const MAX_MANIFEST_SIZE: usize = 4096;
+
+/// First byte of an ESP32 image
+const IMAGE_MAGIC: u8 = 0xe9;
+/// Chip identifier of the ESP32 in the image header
+const ESP32_CHIP_ID: u16 = 0x0000;
+/// First word of the application description
+const APP_DESC_MAGIC: u32 = 0xabcd_5432;
+
+/// Length of `esp_image_header_t`
+const IMAGE_HEADER_LENGTH: usize = 24;
+/// Length of `esp_image_segment_header_t`, before the application description
+const SEGMENT_HEADER_LENGTH: usize = 8;
+/// Length of `esp_app_desc_t`
+const APP_DESC_LENGTH: usize = 256;
+/// Bytes received before the image is written to the sink
+pub const HEADERS_LENGTH: usize = IMAGE_HEADER_LENGTH + SEGMENT_HEADER_LENGTH + APP_DESC_LENGTH;
+
+/// Application description read from the headers of an ESP32 image
+#[derive(Debug, PartialEq, Clone)]
+pub struct ImageHeader {
+    pub project_name: String,
+    pub version: String,
+}
+
+impl ImageHeader {
+    /// # Errors
+    /// The headers are too short, or not the ones of an ESP32 application.
+    pub fn parse(headers: &[u8]) -> Result<Self> {
+        if headers.len() < HEADERS_LENGTH {
+            return Err(anyhow!("Image of {} bytes is shorter than its headers", headers.len()));
+        }
+        if headers[0] != IMAGE_MAGIC {
+            return Err(anyhow!("Invalid image magic {:#04x}, not an ESP32 image", headers[0]));
+        }
+        let chip_id = u16::from_le_bytes([headers[12], headers[13]]);
+        if chip_id != ESP32_CHIP_ID {
+            return Err(anyhow!("Image built for chip {:#06x}, not the ESP32", chip_id));
+        }
+
+        let app_desc = &headers[IMAGE_HEADER_LENGTH + SEGMENT_HEADER_LENGTH..];
+        let magic = u32::from_le_bytes([app_desc[0], app_desc[1], app_desc[2], app_desc[3]]);
+        if magic != APP_DESC_MAGIC {
+            return Err(anyhow!("Invalid application description magic {:#010x}", magic));
+        }
+        // Fixed size, null terminated strings
+        let string = |field: &[u8]| {
+            let end = field.iter().position(|&c| c == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).into_owned()
+        };
+        Ok(Self { version: string(&app_desc[16..48]), project_name: string(&app_desc[48..80]) })
+    }
+}
+
+fn check_response(head: &ResponseHead, url: &str) -> Result<()> {
+    if head.status != 200 {
+        return Err(anyhow!("HTTP error {} for {}", head.status, url));
+    }
+    Ok(())
+}
+
+/// Download and parse the manifest at `url`.
+///
+/// # Errors
+/// The request fails, or the manifest is too large, truncated or invalid.
+pub fn read_manifest(client: &mut impl HttpClient, url: &str) -> Result<OtaManifest> {
+    let head = client.get(url)?;
+    check_response(&head, url)?;
+    if head.content_length.is_some_and(|length| length > MAX_MANIFEST_SIZE) {
+        return Err(anyhow!("Manifest of {:?} bytes is too large", head.content_length));
+    }
+
+    let mut manifest = Vec::new();
+    let mut buffer = [0; 512];
+    loop {
+        let length = client.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        manifest.extend_from_slice(&buffer[..length]);
+        if manifest.len() > MAX_MANIFEST_SIZE {
+            return Err(anyhow!("Manifest is larger than {} bytes", MAX_MANIFEST_SIZE));
+        }
+    }
+    if head.content_length.is_some_and(|length| length != manifest.len()) {
+        return Err(anyhow!("Manifest truncated after {} bytes", manifest.len()));
+    }
+    OtaManifest::from_json(&manifest)
+}
+
+/// Download `image` from `url` into `sink`.
+///
+/// The signature is verified before anything is written. The headers of the
+/// image are validated before being written, the size and SHA-256 before the
+/// image is completed. The sink is aborted on any error.
+///
+/// # Errors
+/// The image is unsigned, tampered, truncated, corrupted, not an ESP32
+/// application, or the transfer fails.
+pub fn download_image(
+    client: &mut impl HttpClient,
+    sink: &mut impl FirmwareSink,
+    image: &FirmwareImage,
+    url: &str,
+    public_key: &PublicKey,
+) -> Result<()> {
+    public_key.verify(image)?;
+    info!("Image {} signature verified", image.version);
+
+    let head = client.get(url)?;
+    check_response(&head, url)?;
+    if head.content_length.is_some_and(|length| length != image.size) {
+        return Err(anyhow!("Image of {:?} bytes, {} expected by the manifest", head.content_length, image.size));
+    }
+
+    sink.begin(image.size)?;
+    let result = stream_image(client, sink, image);
+    let result = result.and_then(|()| sink.complete());
+    if result.is_err() {
+        if let Err(e) = sink.abort() {
+            warn!("Failed to abort the firmware update: {}", e);
+        }
+    }
+    result
+}
+
+fn stream_image(client: &mut impl HttpClient, sink: &mut impl FirmwareSink, image: &FirmwareImage) -> Result<()> {
+    let mut check = ImageCheck::new(image);
+    let mut headers = Vec::with_capacity(HEADERS_LENGTH);
+    let mut headers_checked = false;
+    let mut buffer = vec![0; DOWNLOAD_BUFFER_SIZE];
+
+    loop {
+        // A short read is not the end of the body, only 0 is
+        let length = client.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        check.update(&buffer[..length])?;
+
+        let mut data = &buffer[..length];
+        if !headers_checked {
+            let missing = (HEADERS_LENGTH - headers.len()).min(data.len());
+            headers.extend_from_slice(&data[..missing]);
+            data = &data[missing..];
+            if headers.len() < HEADERS_LENGTH {
+                continue;
+            }
+            let header = ImageHeader::parse(&headers)?;
+            info!("Downloading {} {}", header.project_name, header.version);
+            sink.write(&headers)?;
+            headers_checked = true;
+        }
+        if !data.is_empty() {
+            sink.write(data)?;
+        }
+    }
+
+    if !headers_checked {
+        ImageHeader::parse(&headers)?;
+    }
+    check.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Headers of an ESP32 image, as built by `espflash save-image`
+    fn image_headers(version: &str) -> Vec<u8> {
+        let mut headers = vec![0; HEADERS_LENGTH];
+        headers[0] = IMAGE_MAGIC;
+        headers[1] = 4;
+        let app_desc = IMAGE_HEADER_LENGTH + SEGMENT_HEADER_LENGTH;
+        headers[app_desc..app_desc + 4].copy_from_slice(&APP_DESC_MAGIC.to_le_bytes());
+        headers[app_desc + 16..app_desc + 16 + version.len()].copy_from_slice(version.as_bytes());
+        headers[app_desc + 48..app_desc + 62].copy_from_slice(b"cross_compiled");
+        headers
+    }
+
+    #[test]
+    fn parse_image_header() {
+        let header = ImageHeader::parse(&image_headers("v3.2.0")).unwrap();
+        assert_eq!(header, ImageHeader { project_name: String::from("cross_compiled"), version: String::from("v3.2.0") });
+    }
+
+    #[test]
+    fn invalid_image_header() {
+        assert!(ImageHeader::parse(&image_headers("v3.2.0")[..HEADERS_LENGTH - 1]).is_err());
+
+        let mut headers = image_headers("v3.2.0");
+        headers[0] = 0x7f;
+        assert!(ImageHeader::parse(&headers).unwrap_err().to_string().contains("not an ESP32 image"));
+
+        // ESP32-C3
+        let mut headers = image_headers("v3.2.0");
+        headers[12] = 0x05;
+        assert!(ImageHeader::parse(&headers).unwrap_err().to_string().contains("not the ESP32"));
+
+        let mut headers = image_headers("v3.2.0");
+        headers[IMAGE_HEADER_LENGTH + SEGMENT_HEADER_LENGTH] = 0;
+        assert!(ImageHeader::parse(&headers).unwrap_err().to_string().contains("application description"));
+    }
+}
@@ -0,0 +1,432 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::firmware_update::UpdateChannel;
+use crate::ota_signature::SIGNATURE_LENGTH;
+use crate::version::Version;
+
+/// Format of the manifest, bumped on breaking changes
+pub const MANIFEST_FORMAT: u32 = 1;
+
+/// Length of a SHA-256 digest in bytes
+pub const SHA256_LENGTH: usize = 32;
+
+/// Firmware image released for one hardware revision
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirmwareImage {
+    pub version: Version,
+    /// Hardware revision running the image, e.g. "v2"
+    pub hardware: String,
+    /// Channel the image is released on, signed so that a nightly image can't
+    /// be served to the stable channel
+    pub channel: UpdateChannel,
+    /// Absolute, or relative to the manifest
+    pub url: String,
+    /// Size in bytes
+    pub size: usize,
+    pub sha256: [u8; SHA256_LENGTH],
+    /// Oldest running version able to install the image, e.g. before a
+    /// partition layout change
+    pub min_version: Option<Version>,
+    pub release_notes: String,
+    /// Ed25519 signature of the image, see `ota_signature`
+    pub signature: Option<[u8; SIGNATURE_LENGTH]>,
+}
+
+impl FirmwareImage {
+    /// URL of the image, resolved against the URL of the manifest.
+    pub fn resolve_url(&self, manifest_url: &str) -> String {
+        if self.url.contains("://") {
+            return self.url.clone();
+        }
+        let manifest_url = manifest_url.split(['?', '#']).next().unwrap_or_default();
+        let directory = manifest_url.rfind('/').map_or("", |end| &manifest_url[..=end]);
+        format!("{}{}", directory, self.url)
+    }
+
+    /// Whether the image is newer than `other`, for all the clocks able to install `other`.
+    fn supersedes(&self, other: &FirmwareImage) -> bool {
+        let accepts_all = match (&self.min_version, &other.min_version) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(ours), Some(theirs)) => ours <= theirs,
+        };
+        self.hardware == other.hardware && self.channel == other.channel && self.version > other.version && accepts_all
+    }
+
+    /// Whether `current` can be upgraded to the image.
+    pub fn accepts(&self, current: &Version) -> bool {
+        match &self.min_version {
+            Some(min_version) => current >= min_version,
+            None => true,
+        }
+    }
+}
+
+/// List of the released firmware images, published next to the images
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OtaManifest {
+    pub images: Vec<FirmwareImage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestFile {
+    format: u32,
+    images: Vec<ImageEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ImageEntry {
+    version: String,
+    hardware: String,
+    /// Stable if missing, as in the manifests written before the channels
+    #[serde(default)]
+    channel: Option<String>,
+    url: String,
+    size: usize,
+    sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_version: Option<String>,
+    #[serde(default)]
+    release_notes: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+impl OtaManifest {
+    /// # Errors
+    /// The JSON is invalid, of another format, or an image is not fully described.
+    pub fn from_json(json: &[u8]) -> Result<Self> {
+        let file: ManifestFile = serde_json::from_slice(json)?;
+        if file.format != MANIFEST_FORMAT {
+            return Err(anyhow!("Unsupported manifest format {}, expected {}", file.format, MANIFEST_FORMAT));
+        }
+
+        let images = file.images.into_iter().map(|entry| {
+            if entry.hardware.is_empty() || entry.url.is_empty() || entry.size == 0 {
+                return Err(anyhow!("Incomplete image {} in manifest", entry.version));
+            }
+            Ok(FirmwareImage {
+                version: Version::from_string(&entry.version)?,
+                hardware: entry.hardware,
+                channel: entry.channel.as_deref().map(str::parse).transpose()?.unwrap_or_default(),
+                url: entry.url,
+                size: entry.size,
+                sha256: from_hex(&entry.sha256)?,
+                min_version: entry.min_version.as_deref().map(Version::from_string).transpose()?,
+                release_notes: entry.release_notes,
+                signature: entry.signature.as_deref().map(from_hex).transpose()?,
+            })
+        });
+        Ok(Self { images: images.collect::<Result<_>>()? })
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let images = self.images.iter().map(|image| ImageEntry {
+            version: version_string(&image.version),
+            hardware: image.hardware.clone(),
+            channel: Some(image.channel.to_string()),
+            url: image.url.clone(),
+            size: image.size,
+            sha256: to_hex(&image.sha256),
+            min_version: image.min_version.as_ref().map(version_string),
+            release_notes: image.release_notes.clone(),
+            signature: image.signature.as_ref().map(|signature| to_hex(signature)),
+        });
+        let file = ManifestFile { format: MANIFEST_FORMAT, images: images.collect() };
+        Ok(serde_json::to_string_pretty(&file)?)
+    }
+
+    /// Newest image for `hardware` on `channel` that `current` can be
+    /// upgraded to.
+    ///
+    /// # Errors
+    /// No image is released for the hardware on the channel, or all require a
+    /// newer version than `current` to be installed.
+    pub fn select(&self, hardware: &str, channel: UpdateChannel, current: &Version) -> Result<&FirmwareImage> {
+        let mut images =
+            self.images.iter().filter(|image| image.hardware == hardware && image.channel == channel).peekable();
+        if images.peek().is_none() {
+            return Err(anyhow!("No image for hardware {} on the {} channel in manifest", hardware, channel));
+        }
+        images
+            .filter(|image| image.accepts(current))
+            .max_by(|a, b| a.version.cmp(&b.version))
+            .ok_or_else(|| anyhow!("No image for hardware {} can be installed from {}", hardware, current))
+    }
+
+    /// Add `image`, dropping the images of the hardware and channel it supersedes. An
+    /// older image is kept for the clocks too old to install `image`.
+    pub fn release(&mut self, image: FirmwareImage) {
+        self.images.retain(|released| {
+            released.hardware != image.hardware || released.channel != image.channel || released.version != image.version
+        });
+        self.images.push(image);
+
+        let released = self.images.clone();
+        self.images.retain(|old| !released.iter().any(|new| new.supersedes(old)));
+    }
+}
+
+/// Check of a downloaded image against its manifest entry, fed chunk by chunk
+pub struct ImageCheck {
+    hasher: Sha256,
+    received: usize,
+    size: usize,
+    sha256: [u8; SHA256_LENGTH],
+}
+
+impl ImageCheck {
+    pub fn new(image: &FirmwareImage) -> Self {
+        Self { hasher: Sha256::new(), received: 0, size: image.size, sha256: image.sha256 }
+    }
+
+    /// # Errors
+    /// More data than the size of the image is received.
+    pub fn update(&mut self, chunk: &[u8]) -> Result<()> {
+        self.received += chunk.len();
+        if self.received > self.size {
+            return Err(anyhow!("Image is larger than the expected {} bytes", self.size));
+        }
+        self.hasher.update(chunk);
+        Ok(())
+    }
+
+    /// # Errors
+    /// The image is truncated, or its hash differs from the manifest.
+    pub fn finish(self) -> Result<()> {
+        if self.received != self.size {
+            return Err(anyhow!("Image is truncated, {} of {} bytes received", self.received, self.size));
+        }
+        let sha256: [u8; SHA256_LENGTH] = self.hasher.finalize().into();
+        if sha256 != self.sha256 {
+            return Err(anyhow!("Image SHA-256 {} differs from {}", to_hex(&sha256), to_hex(&self.sha256)));
+        }
+        Ok(())
+    }
+}
+
+/// SHA-256 digest of `data`
+pub fn sha256(data: &[u8]) -> [u8; SHA256_LENGTH] {
+    Sha256::digest(data).into()
+}
+
+/// Lower case hexadecimal representation of a digest or key
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// # Errors
+/// The string is not made of `2 * N` hexadecimal digits.
+pub fn from_hex<const N: usize>(hex: &str) -> Result<[u8; N]> {
+    if hex.len() != 2 * N || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid {:?}, expected {} hexadecimal digits", hex, 2 * N));
+    }
+    let mut bytes = [0; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * index..2 * index + 2], 16)?;
+    }
+    Ok(bytes)
+}
+
+/// Version as written by `git describe`, without the leading 'v'
+pub(crate) fn version_string(version: &Version) -> String {
+    version.to_string().trim_start_matches('v').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST_URL: &str = "https://example.com/wordclock/ota-image/manifest.json?";
+    const STABLE: UpdateChannel = UpdateChannel::Stable;
+
+    fn image(version: &str, hardware: &str, min_version: Option<&str>) -> FirmwareImage {
+        FirmwareImage {
+            version: Version::from_string(version).unwrap(),
+            hardware: String::from(hardware),
+            channel: STABLE,
+            url: format!("hardware-{}/firmware-ota.bin", hardware),
+            size: 5,
+            sha256: sha256(b"image"),
+            min_version: min_version.map(|version| Version::from_string(version).unwrap()),
+            release_notes: String::new(),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let mut manifest = OtaManifest::default();
+        manifest.release(image("2.1.0-3-gabc123", "v2", Some("2.0.0")));
+        manifest.images[0].release_notes = String::from("Notifications");
+        manifest.images[0].signature = Some([0xa5; SIGNATURE_LENGTH]);
+        manifest.release(image("1.4.0", "v1", None));
+
+        let json = manifest.to_json().unwrap();
+        assert!(json.contains("\"version\": \"2.1.0-3-gabc123\""));
+        assert!(json.contains("\"channel\": \"stable\""));
+        assert!(json.contains(&format!("\"sha256\": \"{}\"", to_hex(&sha256(b"image")))));
+        assert_eq!(OtaManifest::from_json(json.as_bytes()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn parse_manifest() {
+        let json = br#"{
+            "format": 1,
+            "images": [{
+                "version": "v2.0.1-117-gf7f35a",
+                "hardware": "v2",
+                "url": "https://example.com/firmware.bin",
+                "size": 1048576,
+                "sha256": "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855"
+            }]
+        }"#;
+        let manifest = OtaManifest::from_json(json).unwrap();
+        let image = &manifest.images[0];
+        assert_eq!(image.version.to_string(), "v2.0.1-117-gf7f35a");
+        assert_eq!(image.size, 1048576);
+        assert_eq!(image.sha256, sha256(b""));
+        assert_eq!(image.channel, UpdateChannel::Stable);
+        assert_eq!(image.min_version, None);
+        assert_eq!(image.release_notes, "");
+        assert_eq!(image.signature, None);
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let entry = |members: &str| {
+            format!(
+                r#"{{"format": 1, "images": [{{"version": "2.0.1", "hardware": "v2", "url": "a.bin", {}}}]}}"#,
+                members
+            )
+        };
+        let sha256 = to_hex(&sha256(b""));
+        let invalid = [
+            String::from("not json"),
+            String::from(r#"{"format": 2, "images": []}"#),
+            entry(r#""size": 10"#),
+            entry(&format!(r#""size": 0, "sha256": "{}""#, sha256)),
+            entry(r#""size": 10, "sha256": "e3b0""#),
+            entry(&format!(r#""size": 10, "sha256": "{}""#, sha256.replace('e', "g"))),
+            entry(&format!(r#""size": 10, "sha256": "{}", "min_version": "2""#, sha256)),
+            entry(&format!(r#""size": 10, "sha256": "{}", "signature": "{}""#, sha256, sha256)),
+            entry(&format!(r#""size": 10, "sha256": "{}", "channel": "alpha""#, sha256)),
+            entry(&format!(r#""size": 10, "sha256": "{}""#, sha256)).replace("2.0.1", "latest"),
+        ];
+        for json in invalid {
+            assert!(OtaManifest::from_json(json.as_bytes()).is_err(), "{}", json);
+        }
+        assert!(OtaManifest::from_json(entry(&format!(r#""size": 10, "sha256": "{}""#, sha256)).as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn newest_image_of_the_hardware_is_selected() {
+        let manifest = OtaManifest {
+            images: vec![
+                image("2.1.0", "v2", None),
+                image("3.0.0", "v1", None),
+                image("2.2.0-rc.1", "v2", None),
+                image("2.0.5", "v2", None),
+            ],
+        };
+        let current = Version::new(2, 0, 1, None);
+
+        assert_eq!(manifest.select("v2", STABLE, &current).unwrap().version, Version::new(2, 2, 0, Some("rc.1")));
+        assert_eq!(manifest.select("v1", STABLE, &current).unwrap().version, Version::new(3, 0, 0, None));
+        assert!(manifest.select("v3", STABLE, &current).is_err());
+    }
+
+    #[test]
+    fn minimum_version_is_required() {
+        let manifest = OtaManifest { images: vec![image("3.0.0", "v2", Some("2.5.0")), image("2.5.0", "v2", None)] };
+
+        // Intermediate update first
+        assert_eq!(manifest.select("v2", STABLE, &Version::new(2, 0, 1, None)).unwrap().version, Version::new(2, 5, 0, None));
+        assert_eq!(manifest.select("v2", STABLE, &Version::new(2, 5, 0, None)).unwrap().version, Version::new(3, 0, 0, None));
+
+        let manifest = OtaManifest { images: vec![image("3.0.0", "v2", Some("2.5.0"))] };
+        assert!(manifest.select("v2", STABLE, &Version::new(2, 5, 0, Some("rc.1"))).is_err());
+    }
+
+    #[test]
+    fn images_of_other_channels_are_ignored() {
+        let mut nightly = image("2.2.0-4-gabc123", "v2", None);
+        nightly.channel = UpdateChannel::Nightly;
+        let manifest = OtaManifest { images: vec![image("2.1.0", "v2", None), nightly] };
+        let current = Version::new(2, 0, 1, None);
+
+        assert_eq!(manifest.select("v2", STABLE, &current).unwrap().version, Version::new(2, 1, 0, None));
+        assert_eq!(manifest.select("v2", UpdateChannel::Nightly, &current).unwrap().version.to_string(), "v2.2.0-4-gabc123");
+        assert!(manifest.select("v2", UpdateChannel::Beta, &current).is_err());
+    }
+
+    #[test]
+    fn release_replaces_the_image_of_the_hardware() {
+        let mut manifest = OtaManifest { images: vec![image("2.0.0", "v2", None), image("1.4.0", "v1", None)] };
+        manifest.release(image("2.1.0", "v2", None));
+
+        assert_eq!(manifest.images.len(), 2);
+        assert_eq!(manifest.select("v2", STABLE, &Version::new(2, 0, 0, None)).unwrap().version, Version::new(2, 1, 0, None));
+        assert_eq!(manifest.images[0].hardware, "v1");
+
+        // Rebuilt image of the same version
+        manifest.release(image("2.1.0", "v2", None));
+        assert_eq!(manifest.images.len(), 2);
+    }
+
+    #[test]
+    fn release_keeps_the_image_for_older_clocks() {
+        let mut manifest = OtaManifest { images: vec![image("2.4.0", "v2", None), image("2.5.0", "v2", None)] };
+        manifest.release(image("3.0.0", "v2", Some("2.5.0")));
+        let versions: Vec<String> = manifest.images.iter().map(|image| image.version.to_string()).collect();
+        assert_eq!(versions, ["v2.5.0", "v3.0.0"]);
+
+        manifest.release(image("3.1.0", "v2", Some("2.5.0")));
+        let versions: Vec<String> = manifest.images.iter().map(|image| image.version.to_string()).collect();
+        assert_eq!(versions, ["v2.5.0", "v3.1.0"]);
+
+        manifest.release(image("3.2.0", "v2", None));
+        assert_eq!(manifest.images, [image("3.2.0", "v2", None)]);
+    }
+
+    #[test]
+    fn image_url_is_resolved() {
+        let mut image = image("2.1.0", "v2", None);
+        assert_eq!(
+            image.resolve_url(MANIFEST_URL),
+            "https://example.com/wordclock/ota-image/hardware-v2/firmware-ota.bin"
+        );
+        assert_eq!(image.resolve_url("manifest.json"), "hardware-v2/firmware-ota.bin");
+
+        image.url = String::from("http://192.168.1.10:8000/firmware-ota.bin");
+        assert_eq!(image.resolve_url(MANIFEST_URL), "http://192.168.1.10:8000/firmware-ota.bin");
+    }
+
+    #[test]
+    fn downloaded_image_is_checked() {
+        let image = image("2.1.0", "v2", None);
+
+        let mut check = ImageCheck::new(&image);
+        check.update(b"im").unwrap();
+        check.update(b"").unwrap();
+        check.update(b"age").unwrap();
+        assert!(check.finish().is_ok());
+
+        let mut check = ImageCheck::new(&image);
+        check.update(b"imag").unwrap();
+        assert!(check.finish().unwrap_err().to_string().contains("truncated"));
+
+        let mut check = ImageCheck::new(&image);
+        check.update(b"imagE").unwrap();
+        assert!(check.finish().unwrap_err().to_string().contains("SHA-256"));
+
+        let mut check = ImageCheck::new(&image);
+        assert!(check.update(b"images").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::ota_manifest::{from_hex, to_hex, version_string, FirmwareImage};
+
+/// Length of an Ed25519 public key or private key seed in bytes
+pub const KEY_LENGTH: usize = 32;
+/// Length of an Ed25519 signature in bytes
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// Signed content of an image: its SHA-256, bound to the channel, hardware and
+/// version so that a signed image can't be served as another release.
+fn signed_message(image: &FirmwareImage) -> String {
+    format!(
+        "wordclock-ota {} {} {} {}",
+        image.channel,
+        image.hardware,
+        version_string(&image.version),
+        to_hex(&image.sha256)
+    )
+}
+
+/// Key embedded in the firmware, to verify the downloaded images
+pub struct PublicKey(VerifyingKey);
+
+impl PublicKey {
+    /// # Errors
+    /// The string is not 64 hexadecimal digits, or not a valid Ed25519 key.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let key = VerifyingKey::from_bytes(&from_hex(hex.trim())?).map_err(|e| anyhow!("Invalid public key: {}", e))?;
+        Ok(Self(key))
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(self.0.as_bytes())
+    }
+
+    /// # Errors
+    /// The image is not signed, or not by the private key of this key.
+    pub fn verify(&self, image: &FirmwareImage) -> Result<()> {
+        let Some(signature) = &image.signature else {
+            return Err(anyhow!("Image {} is not signed", image.version));
+        };
+        self.0
+            .verify(signed_message(image).as_bytes(), &Signature::from_bytes(signature))
+            .map_err(|_| anyhow!("Invalid signature of image {}, the image or its manifest was tampered", image.version))
+    }
+}
+
+/// Release key, kept by the maintainers to sign the images with `cargo xtask sign`
+pub struct PrivateKey(SigningKey);
+
+impl PrivateKey {
+    /// Key from its seed, as generated by `openssl rand -hex 32`
+    ///
+    /// # Errors
+    /// The string is not 64 hexadecimal digits.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        Ok(Self(SigningKey::from_bytes(&from_hex(hex.trim())?)))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0.verifying_key())
+    }
+
+    /// Sign `image`, replacing a previous signature.
+    pub fn sign(&self, image: &mut FirmwareImage) {
+        image.signature = Some(self.0.sign(signed_message(image).as_bytes()).to_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware_update::UpdateChannel;
+    use crate::ota_manifest::sha256;
+    use crate::version::Version;
+
+    /// Seed of test vector 1 of RFC 8032
+    const PRIVATE_KEY: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    fn image() -> FirmwareImage {
+        FirmwareImage {
+            version: Version::new(2, 1, 0, None),
+            hardware: String::from("v2"),
+            channel: UpdateChannel::Stable,
+            url: String::from("hardware-v2/firmware-2.1.0.bin"),
+            size: 5,
+            sha256: sha256(b"image"),
+            min_version: None,
+            release_notes: String::new(),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn public_key_of_the_private_key() {
+        let private_key = PrivateKey::from_hex(PRIVATE_KEY).unwrap();
+        assert_eq!(private_key.public_key().to_hex(), PUBLIC_KEY);
+        assert_eq!(PublicKey::from_hex(&format!("{}\n", PUBLIC_KEY)).unwrap().to_hex(), PUBLIC_KEY);
+
+        assert!(PrivateKey::from_hex("9d61b19d").is_err());
+        assert!(PublicKey::from_hex(&PUBLIC_KEY.replace('d', "x")).is_err());
+    }
+
+    #[test]
+    fn signed_image_is_verified() {
+        let mut image = image();
+        PrivateKey::from_hex(PRIVATE_KEY).unwrap().sign(&mut image);
+
+        assert!(PublicKey::from_hex(PUBLIC_KEY).unwrap().verify(&image).is_ok());
+    }
+
+    #[test]
+    fn unsigned_image_is_rejected() {
+        let error = PublicKey::from_hex(PUBLIC_KEY).unwrap().verify(&image()).unwrap_err();
+        assert_eq!(error.to_string(), "Image v2.1.0 is not signed");
+    }
+
+    #[test]
+    fn tampered_image_is_rejected() {
+        let public_key = PublicKey::from_hex(PUBLIC_KEY).unwrap();
+        let mut signed = image();
+        PrivateKey::from_hex(PRIVATE_KEY).unwrap().sign(&mut signed);
+
+        let mut tampered = signed.clone();
+        tampered.sha256[0] ^= 1;
+        assert!(public_key.verify(&tampered).unwrap_err().to_string().contains("tampered"));
+
+        let mut tampered = signed.clone();
+        tampered.version = Version::new(9, 0, 0, None);
+        assert!(public_key.verify(&tampered).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.hardware = String::from("v1");
+        assert!(public_key.verify(&tampered).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.channel = UpdateChannel::Nightly;
+        assert!(public_key.verify(&tampered).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.signature.as_mut().unwrap()[0] ^= 1;
+        assert!(public_key.verify(&tampered).is_err());
+
+        // Signed with another key
+        let other_key = PrivateKey::from_hex(&"01".repeat(KEY_LENGTH)).unwrap();
+        let mut tampered = signed;
+        other_key.sign(&mut tampered);
+        assert!(public_key.verify(&tampered).is_err());
+    }
+}
@@ -2,8 +2,12 @@
 * Copyright (c) 2023 Louis Mayencourt
 */
 
+use std::time::Duration;
+
 /// Interface to interact with board power mode
 pub trait PowerManager {
     /// Restart the system
     fn reset(&self);
+    /// Time since the system started
+    fn uptime(&self) -> Duration;
 }
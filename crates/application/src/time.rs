@@ -31,6 +31,11 @@ impl Time {
             second,
         })
     }
+
+    /// Number of whole minutes since midnight
+    pub fn minutes_since_midnight(&self) -> u16 {
+        u16::from(self.hour) * 60 + u16::from(self.minute)
+    }
 }
 
 impl fmt::Display for Time {
@@ -51,9 +56,9 @@ impl FromStr for Time {
     /// # Errors
     /// An error is return if provided arguments doesn't represent a valid time.
     fn from_str(time: &str) -> std::result::Result<Self, Self::Err> {
-        let hour = u8::from_str_radix(&time[0..2], 10)?;
-        let minute = u8::from_str_radix(&time[3..5], 10)?;
-        let second = u8::from_str_radix(&time[6..8], 10)?;
+        let hour = time[0..2].parse::<u8>()?;
+        let minute = time[3..5].parse::<u8>()?;
+        let second = time[6..8].parse::<u8>()?;
         Ok(Time {
             hour,
             minute,
@@ -90,4 +95,11 @@ mod tests {
             Time::from_str("23:59:59").unwrap()
         );
     }
+
+    #[test]
+    fn minutes_since_midnight() {
+        assert_eq!(Time::new(0, 0, 59).unwrap().minutes_since_midnight(), 0);
+        assert_eq!(Time::new(22, 30, 0).unwrap().minutes_since_midnight(), 1350);
+        assert_eq!(Time::new(23, 59, 59).unwrap().minutes_since_midnight(), 1439);
+    }
 }
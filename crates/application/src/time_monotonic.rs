@@ -8,10 +8,10 @@ pub trait TimeMonotonic {
     fn now(&self) -> Instant;
 }
 
-struct MonotonicSystemTime;
+pub struct MonotonicSystemTime;
 
 impl TimeMonotonic for MonotonicSystemTime{
     fn now(&self) -> Instant {
-        return Instant::now();
+        Instant::now()
     }
 }
\ No newline at end of file
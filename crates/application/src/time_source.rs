@@ -3,6 +3,7 @@
  */
 
 use anyhow::Result;
+use serde::Serialize;
 
 use crate::time::Time;
 
@@ -13,6 +14,17 @@ pub enum TimeSourceError {
     NotAvailable,
 }
 
+/// Health of a time source, reported by the status API
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct TimeSourceHealth {
+    /// A time can be read
+    pub synchronized: bool,
+    /// Seconds since the last network time synchronization, `None` if not synchronized yet
+    pub last_network_sync: Option<u64>,
+    /// Board RTC in sync with the network time, `None` without board RTC or network time
+    pub board_synchronized: Option<bool>,
+}
+
 /// Interface to get local time from a time source
 /// # Errors
 /// The functions will return an error if the hardware fails to carry the operation.
@@ -20,4 +32,13 @@ pub trait TimeSource {
     fn synchronize(&mut self) -> Result<(), TimeSourceError>;
     fn get_time(&self) -> Result<Time, TimeSourceError>;
     fn set_time(&mut self, now: Time) -> Result<(), TimeSourceError>;
+
+    /// Health of the time source, a source that can read the time is synchronized by default.
+    fn health(&self) -> TimeSourceHealth {
+        TimeSourceHealth {
+            synchronized: self.get_time().is_ok(),
+            last_network_sync: None,
+            board_synchronized: None,
+        }
+    }
 }
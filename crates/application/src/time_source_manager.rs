@@ -11,7 +11,10 @@ use log::warn;
 
 use crate::time::Time;
 use crate::time_monotonic::TimeMonotonic;
-use crate::time_source::{TimeSource, TimeSourceError};
+use crate::time_source::{TimeSource, TimeSourceError, TimeSourceHealth};
+
+pub const CPU_SYNC_TIMEOUT: Duration = Duration::from_secs(60*5);
+pub const RTC_SYNC_TIMEOUT: Duration = Duration::from_secs(60*60*24);
 
 /// Manage multiple time sources
 ///
@@ -31,10 +34,6 @@ use crate::time_source::{TimeSource, TimeSourceError};
 ///
 /// It is difficult to set the cpu time manually, as the Time struct must be converted to epoch time...
 /// This can be done apparently with mktime function!
-
-pub const CPU_SYNC_TIMEOUT: Duration = Duration::from_secs(60*5);
-pub const RTC_SYNC_TIMEOUT: Duration = Duration::from_secs(60*60*24);
-
 pub struct TimeSourceManager<T: TimeMonotonic>{
     // The time sources are only public for testing
     // This is simpler than use sharable mutability
@@ -110,6 +109,11 @@ impl<T: TimeMonotonic> TimeSourceManager<T>{
 }
 
 impl<T:TimeMonotonic> TimeSource for TimeSourceManager<T> {
+    /// Synchronize the time sources, falling back to the board RTC time if the
+    /// network time can't be fetched.
+    ///
+    /// # Errors
+    /// Return an error if neither the network time nor the board time is available.
     fn synchronize(&mut self) -> Result<(), TimeSourceError> {
         // Only read network time if available and needed
         match self.is_board_synchronized() {
@@ -118,8 +122,7 @@ impl<T:TimeMonotonic> TimeSource for TimeSourceManager<T> {
                 let current_network_time: Result<Time, TimeSourceError>;
                 // Try to read the network time if out of sync
                 if let Some(network) = &mut self.network_time {
-                    network.synchronize()?;
-                    current_network_time = network.get_time();
+                    current_network_time = network.synchronize().and_then(|_| network.get_time());
                     if current_network_time.is_ok() {
                         // Network time is "read only", so reading it count as a sync
                         self.last_network_sync = Some(self.time_monotonic.now());
@@ -153,30 +156,36 @@ impl<T:TimeMonotonic> TimeSource for TimeSourceManager<T> {
             current_board_time = Err(TimeSourceError::NotAvailable);
         }
 
-        if let Ok(board_time) = current_board_time {
-            self.cpu_time.set_time(board_time)?;
-            self.last_cpu_sync = Some(self.time_monotonic.now());
-        } else {
-            warn!("Can't fetch time from board RTC, use network time as primary source");
+        match current_board_time {
+            Ok(board_time) => {
+                self.cpu_time.set_time(board_time)?;
+                self.last_cpu_sync = Some(self.time_monotonic.now());
+                Ok(())
+            }
+            Err(error) => {
+                warn!("Can't fetch time from board RTC: {:?}", error);
+                Err(TimeSourceError::SynchronizationError)
+            }
         }
-
-        Ok(())
     }
 
     /// For now, always return board RTC time if available, or CPU time for simplicity
     ///
+    /// The board RTC keeps running when the network time is unavailable, so only the
+    /// CPU synchronization is required to return a time.
+    ///
     /// It should be possible to save a bit of power by checking the last sync of the CPU
     /// time and returning it instead of the board RTC time, as no I2C transaction is needed
     /// for the cpu time.
     fn get_time(&self) -> Result<crate::time::Time, TimeSourceError> {
-        if self.is_synchronized() {
+        if self.is_cpu_synchronized() {
             if let Some(rtc) = &self.board_time {
                 rtc.get_time()
             } else {
                 self.cpu_time.get_time()
             }
         } else {
-            return Err(TimeSourceError::NotSynchronized);
+            Err(TimeSourceError::NotSynchronized)
         }
     }
 
@@ -199,4 +208,15 @@ impl<T:TimeMonotonic> TimeSource for TimeSourceManager<T> {
 
         Ok(())
     }
+
+    /// The time can be read as long as the CPU time is synchronized, see `get_time()`.
+    fn health(&self) -> TimeSourceHealth {
+        TimeSourceHealth {
+            synchronized: self.is_cpu_synchronized(),
+            last_network_sync: self
+                .last_network_sync
+                .map(|network_sync| (self.time_monotonic.now() - network_sync).as_secs()),
+            board_synchronized: self.is_board_synchronized(),
+        }
+    }
 }
@@ -2,20 +2,28 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
+use std::cmp::Ordering;
 use std::fmt;
 
 use anyhow::{anyhow, Result};
-use regex::Regex;
 
-/// Regex for semantic version, as provided by https://semver.org
-const SEMANTIC_REGEX: &str = r"^(?P<major>0|[1-9]\d*)\.(?P<minor>0|[1-9]\d*)\.(?P<patch>0|[1-9]\d*)(?:-(?P<prerelease>(?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*)(?:\.(?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*))*))?(?:\+(?P<buildmetadata>[0-9a-zA-Z-]+(?:\.[0-9a-zA-Z-]+)*))?$";
+use crate::build_version::BUILD_VERSION_STRING;
 
 /// Semantic version representation
+///
+/// Ordered by semantic version 2.0 precedence: a pre-release is lower than its
+/// release, and the build metadata is ignored when parsing. A `git describe`
+/// development build is higher than its tag, and than the builds with fewer
+/// commits since the tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Version {
     major: u8,
     minor: u8,
     patch: u8,
+    /// Pre-release identifiers, separated by dots
     identifiers: Option<String>,
+    /// Commits since the tag and abbreviated commit hash of a development build
+    development: Option<(u32, String)>,
 }
 
 impl Version {
@@ -27,6 +35,7 @@ impl Version {
                 minor,
                 patch,
                 identifiers: Some(String::from(x)),
+                development: None,
             };
         }
 
@@ -35,77 +44,185 @@ impl Version {
             minor,
             patch,
             identifiers: None,
+            development: None,
         }
     }
 
     /// Return a version represented by the given string
     ///
-    /// The function used a regex to parse the provided string slice.
-    /// The memory usage for this parsing is currently huge ~25kB !!
+    /// Follows the grammar of https://semver.org, with an optional leading 'v'
+    /// as given by `git describe` (e.g. `v2.0.1-117-gf7f35a`).
+    /// The build metadata is validated, then dropped. The `-<commits>-g<hash>`
+    /// suffix of `git describe` is kept apart from the pre-release identifiers.
     ///
     /// # Error
     /// Return an error if provided string doesn't represent a semantic version.
     pub fn from_string(version: &str) -> Result<Self> {
-        let re = Regex::new(SEMANTIC_REGEX)?;
-
-        if let Some(cap) = re.captures(version) {
-            let major: u8 = cap[1].parse()?;
-            let minor: u8 = cap[2].parse()?;
-            let patch: u8 = cap[3].parse()?;
-            if let Some(meta) = cap.name("prerelease") {
-                return Ok(Version {
-                    major,
-                    minor,
-                    patch,
-                    identifiers: Some(String::from(meta.as_str())),
-                });
+        let invalid = || anyhow!("Provided input is not a valid version {}", version);
+        let text = version.strip_prefix('v').unwrap_or(version);
+
+        let (text, build) = match text.split_once('+') {
+            Some((text, build)) => (text, Some(build)),
+            None => (text, None),
+        };
+        if build.is_some_and(|build| !build.split('.').all(is_identifier)) {
+            return Err(invalid());
+        }
+
+        let (text, development) = split_git_describe_suffix(text);
+
+        // The core only holds digits and dots, the first hyphen starts the pre-release
+        let (core, prerelease) = match text.split_once('-') {
+            Some((core, prerelease)) => (core, Some(prerelease)),
+            None => (text, None),
+        };
+        if prerelease.is_some_and(|prerelease| !prerelease.split('.').all(is_pre_release_identifier)) {
+            return Err(invalid());
+        }
+
+        let mut numbers = core.split('.').map(|number| {
+            if is_numeric_identifier(number) {
+                number.parse::<u8>().map_err(|_| invalid())
             } else {
-                return Ok(Version {
-                    major,
-                    minor,
-                    patch,
-                    identifiers: None,
-                });
+                Err(invalid())
             }
-        } else {
-            return Err(anyhow!("Provided input is not a valid version {}", version));
-        }
+        });
+        let (Some(major), Some(minor), Some(patch), None) = (numbers.next(), numbers.next(), numbers.next(), numbers.next())
+        else {
+            return Err(invalid());
+        };
+
+        Ok(Version { development, ..Version::new(major?, minor?, patch?, prerelease) })
+    }
+
+    /// Return the version of the running firmware, given by `git describe` at build time.
+    ///
+    /// # Error
+    /// Return an error if the build version is not a semantic version.
+    pub fn current() -> Result<Self> {
+        Self::from_string(BUILD_VERSION_STRING)
+    }
+
+    pub fn major(&self) -> u8 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u8 {
+        self.minor
     }
 
+    pub fn patch(&self) -> u8 {
+        self.patch
+    }
+
+    /// Whether the version is a pre-release or a development build, not a release.
+    pub fn is_pre_release(&self) -> bool {
+        self.identifiers.is_some() || self.development.is_some()
+    }
+
+    /// Return the version held by a file, surrounding whitespace is ignored.
     pub fn from_utf8(version: &[u8]) -> Result<Self> {
-        Self::from_string(std::str::from_utf8(version)?)
+        Self::from_string(std::str::from_utf8(version)?.trim())
     }
+}
 
-    /// Compare against the provided version
-    ///
-    /// Return `true` if provided version is older.
-    /// Ignore the 'identifiers' part.
-    pub fn is_greater_than(&self, other: &Version) -> bool {
-        if self.major > other.major {
-            return true;
-        } else if self.minor > other.minor {
-            return true;
-        } else if self.patch > other.patch {
-            return true;
-        } else {
-            return false;
+/// Split the `-<commits>-g<hash>` suffix of `git describe` from the tag, if any.
+fn split_git_describe_suffix(text: &str) -> (&str, Option<(u32, String)>) {
+    let mut parts = text.rsplitn(3, '-');
+    if let (Some(hash), Some(commits), Some(tag)) = (parts.next(), parts.next(), parts.next()) {
+        let hash = hash.strip_prefix('g').filter(|hash| !hash.is_empty() && hash.bytes().all(|c| c.is_ascii_hexdigit()));
+        if let (Some(hash), true) = (hash, is_numeric_identifier(commits)) {
+            if let Ok(commits) = commits.parse() {
+                return (tag, Some((commits, String::from(hash))));
+            }
         }
     }
+    (text, None)
+}
+
+/// Non-empty run of ASCII alphanumerics and hyphens
+fn is_identifier(identifier: &str) -> bool {
+    !identifier.is_empty() && identifier.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-')
+}
+
+/// Digits only, without leading zero
+fn is_numeric_identifier(identifier: &str) -> bool {
+    !identifier.is_empty()
+        && identifier.bytes().all(|c| c.is_ascii_digit())
+        && (identifier == "0" || !identifier.starts_with('0'))
+}
+
+/// Numeric identifiers of a pre-release must not have a leading zero
+fn is_pre_release_identifier(identifier: &str) -> bool {
+    if identifier.bytes().all(|c| c.is_ascii_digit()) {
+        is_numeric_identifier(identifier)
+    } else {
+        is_identifier(identifier)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.identifiers, &other.identifiers) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(ours), Some(theirs)) => compare_pre_releases(ours, theirs),
+            })
+            .then_with(|| self.development.cmp(&other.development))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare the pre-release identifiers one by one. When all are equal, the
+/// larger set of identifiers is the greater one.
+fn compare_pre_releases(ours: &str, theirs: &str) -> Ordering {
+    let (ours, theirs): (Vec<&str>, Vec<&str>) = (ours.split('.').collect(), theirs.split('.').collect());
+    ours.iter()
+        .zip(&theirs)
+        .map(|(ours, theirs)| compare_identifiers(ours, theirs))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| ours.len().cmp(&theirs.len()))
+}
+
+/// Compare two pre-release identifiers: numeric ones numerically, and lower
+/// than alphanumeric ones, which are compared in ASCII order.
+fn compare_identifiers(ours: &str, theirs: &str) -> Ordering {
+    let is_numeric = |identifier: &str| !identifier.is_empty() && identifier.bytes().all(|c| c.is_ascii_digit());
+    match (is_numeric(ours), is_numeric(theirs)) {
+        // Without leading zeros, the longer number is the greater one
+        (true, true) => ours.len().cmp(&theirs.len()).then_with(|| ours.cmp(theirs)),
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => ours.cmp(theirs),
+    }
 }
 
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let Some(id) = &self.identifiers {
-            write!(f, "v{}.{}.{}-{}", self.major, self.minor, self.patch, id)
+            write!(f, "v{}.{}.{}-{}", self.major, self.minor, self.patch, id)?;
         } else {
-            write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+            write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)?;
+        }
+        if let Some((commits, hash)) = &self.development {
+            write!(f, "-{}-g{}", commits, hash)?;
         }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn simple_version() {
@@ -134,7 +251,8 @@ mod tests {
         assert_eq!(version.major, 2);
         assert_eq!(version.minor, 0);
         assert_eq!(version.patch, 1);
-        assert_eq!(version.identifiers, Some(String::from("117-gf7f35a")));
+        assert_eq!(version.identifiers, None);
+        assert_eq!(version.development, Some((117, String::from("f7f35a"))));
     }
 
     #[test]
@@ -153,21 +271,177 @@ mod tests {
         assert_eq!(version.minor, 2);
         assert_eq!(version.patch, 3);
         assert_eq!(version.identifiers, Some(String::from("rc1")));
+        assert!(version.is_pre_release());
+        assert!(Version::from_string("v1.2.3-4-gabc123").unwrap().is_pre_release());
+        assert!(!Version::from_string("v1.2.3+build.5").unwrap().is_pre_release());
     }
 
     #[test]
     fn compare_two_version() {
         let v1 = Version::new(1, 0, 0, None);
         let v2 = Version::new(2, 0, 0, None);
-        assert!(v2.is_greater_than(&v1));
+        assert!(v2 > v1);
 
         let v2 = Version::new(2, 1, 0, None);
-        assert!(v2.is_greater_than(&v1));
+        assert!(v2 > v1);
 
         let v2 = Version::new(2, 0, 1, None);
-        assert!(v2.is_greater_than(&v1));
+        assert!(v2 > v1);
 
         let v2 = Version::new(1, 0, 0, None);
-        assert_eq!(v2.is_greater_than(&v1), false);
+        assert!(v2 <= v1);
+
+        // Major takes precedence over minor and patch
+        assert!(Version::new(2, 0, 0, None) > Version::new(1, 5, 0, None));
+        assert!(Version::new(1, 5, 0, None) < Version::new(2, 0, 0, None));
+        assert!(Version::new(1, 2, 0, None) > Version::new(1, 1, 9, None));
+    }
+
+    #[test]
+    fn pre_release_precedence() {
+        // Example of https://semver.org/#spec-item-11
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+        ]
+        .map(|version| Version::from_string(version).unwrap());
+        for pair in ordered.windows(2) {
+            assert!(pair[0] < pair[1], "{} < {}", pair[0], pair[1]);
+        }
+
+        assert!(Version::new(3, 0, 0, Some("rc1")) < Version::new(3, 0, 0, None));
+        assert!(Version::new(3, 0, 0, Some("rc1")) > Version::new(2, 9, 9, None));
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        let version = Version::from_string("1.2.3-rc.1+build.5").unwrap();
+        assert_eq!(version, Version::new(1, 2, 3, Some("rc.1")));
+        assert_eq!(Version::from_string("1.2.3+001").unwrap().cmp(&Version::new(1, 2, 3, None)), Ordering::Equal);
+    }
+
+    #[test]
+    fn git_describe_output() {
+        let version = Version::from_string("v2.0.1-117-gf7f35a").unwrap();
+        assert_eq!(version.to_string(), "v2.0.1-117-gf7f35a");
+        assert_eq!(Version::from_utf8(b"v2.0.1-117-gf7f35a\n").unwrap(), version);
+        assert_eq!(Version::from_utf8(b" 2.0.1\r\n").unwrap(), Version::new(2, 0, 1, None));
+
+        // Development builds follow their tag
+        let ordered = ["2.1.0-rc.1", "2.1.0-rc.1-12-g0a1b2c3", "2.1.0", "2.1.0-3-gabcdef", "2.1.0-10-g0123ab", "2.1.1-beta"]
+            .map(|version| Version::from_string(version).unwrap());
+        for pair in ordered.windows(2) {
+            assert!(pair[0] < pair[1], "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(ordered[1].to_string(), "v2.1.0-rc.1-12-g0a1b2c3");
+
+        // Pre-releases that only look alike
+        let version = Version::from_string("2.0.1-3-beta").unwrap();
+        assert_eq!(version, Version::new(2, 0, 1, Some("3-beta")));
+        assert!(version < Version::new(2, 0, 1, None));
+    }
+
+    #[test]
+    fn invalid_versions() {
+        let invalid = [
+            "",
+            "v",
+            "1",
+            "1.2",
+            "1.2.3.4",
+            "1..3",
+            "01.2.3",
+            "1.02.3",
+            "1.2.03",
+            "256.0.0",
+            "+1.2.3",
+            "-1.2.3",
+            "1.2.3-",
+            "1.2.3-rc..1",
+            "1.2.3-rc.01",
+            "1.2.3-rc_1",
+            "1.2.3+",
+            "1.2.3+build..1",
+            "1.2.3+build+1",
+            "a.b.c",
+            "V1.2.3",
+            " 1.2.3",
+            "1.2.3\n",
+            "1.2.3-é",
+        ];
+        for version in invalid {
+            assert!(Version::from_string(version).is_err(), "{:?} is not a version", version);
+        }
+        assert!(Version::from_utf8(&[0xff, b'1']).is_err());
+    }
+
+    /// Pre-release identifiers, numeric or alphanumeric
+    fn identifiers() -> impl Strategy<Value = Option<String>> {
+        let identifier = prop_oneof!["0|[1-9][0-9]{0,3}", "[0-9]{0,2}[a-zA-Z-][0-9a-zA-Z-]{0,3}"];
+        proptest::option::of(proptest::collection::vec(identifier, 1..4).prop_map(|identifiers| identifiers.join(".")))
+    }
+
+    fn version() -> impl Strategy<Value = Version> {
+        (0..4_u8, 0..4_u8, 0..4_u8, identifiers())
+            .prop_map(|(major, minor, patch, identifiers)| Version::new(major, minor, patch, identifiers.as_deref()))
+    }
+
+    proptest! {
+        #[test]
+        fn ordering_is_antisymmetric(a in version(), b in version()) {
+            prop_assert_eq!(a.cmp(&b), b.cmp(&a).reverse());
+            prop_assert_eq!(a.cmp(&b) == Ordering::Equal, a == b);
+        }
+
+        #[test]
+        fn displayed_version_is_parsed_back(v in version()) {
+            prop_assert_eq!(Version::from_string(&v.to_string()).unwrap(), v);
+        }
+
+        #[test]
+        fn parsing_never_panics(text in "v?[0-9.a-z+-]{0,16}") {
+            let _ = Version::from_string(&text);
+        }
+
+        #[test]
+        fn ordering_is_transitive(a in version(), b in version(), c in version()) {
+            if a <= b && b <= c {
+                prop_assert!(a <= c);
+            }
+        }
+
+        #[test]
+        fn release_is_ordered_by_numbers(a in version(), b in version()) {
+            let numbers = |v: &Version| (v.major, v.minor, v.patch);
+            if numbers(&a) != numbers(&b) {
+                prop_assert_eq!(a.cmp(&b), numbers(&a).cmp(&numbers(&b)));
+            }
+        }
+
+        #[test]
+        fn pre_release_is_lower_than_release(a in version(), identifiers in "[a-z]{1,5}(\\.[0-9]{1,3})?") {
+            let release = Version::new(a.major, a.minor, a.patch, None);
+            let pre_release = Version::new(a.major, a.minor, a.patch, Some(&identifiers));
+            prop_assert!(pre_release < release);
+        }
+
+        #[test]
+        fn numeric_identifiers_are_compared_numerically(a in 0..10_000_u32, b in 0..10_000_u32) {
+            let rc = |n: u32| Version::new(1, 0, 0, Some(&format!("rc.{}", n)));
+            prop_assert_eq!(rc(a).cmp(&rc(b)), a.cmp(&b));
+        }
+
+        #[test]
+        fn parsed_version_keeps_its_order(a in version(), b in version(), build in "[0-9a-z]{1,6}") {
+            let parse = |v: &Version| Version::from_string(&format!("{}+{}", v.to_string().trim_start_matches('v'), build)).unwrap();
+            prop_assert_eq!(parse(&a), a.clone());
+            prop_assert_eq!(parse(&a).cmp(&parse(&b)), a.cmp(&b));
+        }
     }
 }
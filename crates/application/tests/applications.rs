@@ -2,15 +2,24 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 
+use application::api::{ApiAction, ApiStatus};
+use application::boot_validation::{validate_boot, ImageState};
 use application::behaviour::*;
 use application::color::Color;
-use application::configuration::Configuration;
+use application::configuration::{Configuration, FieldError, WifiCredentials};
 use application::configuration_server::ConfigurationServer;
-use application::firmware_update::FirmwareUpdate;
+use application::error_recovery::{ErrorKind, RETRY_INITIAL_DELAY_TICKS};
+use application::firmware_update::{FirmwareUpdate, UpdateChannel, DEFAULT_UPDATE_SERVER, HARDWARE_REVISION};
+use application::form_urlencoded::{self, FormFields};
+use application::network::{AccessPointSecurity, NetworkError, ScanResult, WifiSecurity};
+use application::notification::{Notification, Pattern};
+use application::ota_manifest::{sha256, FirmwareImage, OtaManifest};
 use application::power_manager::PowerManager;
 use application::time_source::TimeSourceError;
 use application::*;
@@ -20,11 +29,16 @@ use time::Time;
 enum FakeDisplayState {
     Clean,
     Progress(u8),
-    Error,
+    Icon(display::Icon),
+    Error(ErrorKind),
     Time(time::Time),
+    Filled(Color),
 }
 struct FakeDisplay {
     state: FakeDisplayState,
+    color: Color,
+    brightness: u8,
+    dialect: display::Dialect,
 }
 
 impl display::Display for FakeDisplay {
@@ -32,19 +46,34 @@ impl display::Display for FakeDisplay {
         self.state = FakeDisplayState::Clean;
         Ok(())
     }
-    fn draw_error(&mut self) -> anyhow::Result<()> {
-        self.state = FakeDisplayState::Error;
+    fn draw_error(&mut self, kind: ErrorKind) -> anyhow::Result<()> {
+        self.state = FakeDisplayState::Error(kind);
         Ok(())
     }
     fn draw_progress(&mut self, progress: u8) -> anyhow::Result<()> {
         self.state = FakeDisplayState::Progress(progress);
         Ok(())
     }
+    fn draw_icon(&mut self, icon: display::Icon) -> anyhow::Result<()> {
+        self.state = FakeDisplayState::Icon(icon);
+        Ok(())
+    }
     fn draw_time(&mut self, time: time::Time) -> anyhow::Result<()> {
         self.state = FakeDisplayState::Time(time);
         Ok(())
     }
-    fn set_default_color(&mut self, _color: Color) {
+    fn fill(&mut self, color: Color) -> anyhow::Result<()> {
+        self.state = FakeDisplayState::Filled(color);
+        Ok(())
+    }
+    fn set_default_color(&mut self, color: Color) {
+        self.color = color;
+    }
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+    fn set_dialect(&mut self, dialect: display::Dialect) {
+        self.dialect = dialect;
     }
 }
 
@@ -97,20 +126,36 @@ struct FakeNetwork {
     is_configured: bool,
     is_connected: bool,
     is_access_point: bool,
+    connect_error: Option<NetworkError>,
+    visible: Vec<ScanResult>,
+    /// SSID of every network configured, in order
+    configured: Vec<String>,
+    /// Networks refusing the connection
+    refused: Vec<String>,
+    /// SSID and passphrase of the last access point started
+    access_point: Option<(String, Option<String>)>,
+    /// Host name of every mDNS advertisement, in order
+    advertised: Vec<String>,
 }
 
 impl network::Network for FakeNetwork {
-    fn configure(&mut self, _ssid: &str, _password: &str) -> Result<()> {
+    fn configure(&mut self, ssid: &str, _password: &str) -> Result<()> {
         self.is_configured = true;
+        self.configured.push(String::from(ssid));
         Ok(())
     }
 
     fn connect(&mut self) -> Result<()> {
-        if self.is_configured == false {
+        if !self.is_configured {
             return Err(anyhow!("Network not configured properly"));
         }
+        if let Some(error) = self.connect_error {
+            return Err(anyhow!(error));
+        }
+        if self.configured.last().is_some_and(|ssid| self.refused.contains(ssid)) {
+            return Err(anyhow!(NetworkError::AuthenticationFailed));
+        }
 
-        self.is_access_point = false;
         self.is_connected = true;
         Ok(())
     }
@@ -124,21 +169,73 @@ impl network::Network for FakeNetwork {
         self.is_connected
     }
 
-    fn setup_access_point(&mut self, _ssid: &str) -> Result<()> {
+    fn scan(&mut self) -> Result<Vec<ScanResult>> {
+        Ok(self.visible.clone())
+    }
+
+    fn setup_access_point(&mut self, ssid: &str, passphrase: Option<&str>) -> Result<()> {
         self.is_connected = false;
         self.is_access_point = true;
+        self.access_point = Some((String::from(ssid), passphrase.map(String::from)));
+        Ok(())
+    }
+
+    fn stop_access_point(&mut self) -> Result<()> {
+        self.is_access_point = false;
+        Ok(())
+    }
+
+    fn mac_address(&self) -> Result<[u8; 6]> {
+        Ok([0x24, 0x6F, 0x28, 0x01, 0xA2, 0x0B])
+    }
+
+    fn rssi(&self) -> Result<i8> {
+        if self.is_connected {
+            Ok(-55)
+        } else {
+            Err(anyhow!("Not connected"))
+        }
+    }
+
+    fn advertise(&mut self, hostname: &str) -> Result<()> {
+        self.advertised.push(String::from(hostname));
         Ok(())
     }
 }
 
+const VALID_CONFIGURATION_FORM: &str = "favcolor=%2300ff00&input_wifi_ssid=myhomenetwork&input_wifi_password=1234&input_night_mode_start=23%3A30&input_night_mode_end=04%3A40";
+
 struct FakeConfigServer {
     is_config_received: bool,
+    form: &'static str,
+    errors: Option<Vec<FieldError>>,
+    scan_requested: bool,
+    scan_results: Vec<ScanResult>,
+    pin: Option<String>,
+    pin_requested: bool,
+    settings: Option<Configuration>,
+    settings_form: Option<&'static str>,
+    settings_errors: Option<Vec<FieldError>>,
+    status: Option<ApiStatus>,
+    actions: VecDeque<ApiAction>,
+    notification: Option<Notification>,
 }
 
 impl FakeConfigServer {
     fn set_receive_config(&mut self) {
+        self.receive_form(VALID_CONFIGURATION_FORM);
+    }
+
+    fn receive_form(&mut self, form: &'static str) {
+        self.form = form;
+        self.errors = None;
         self.is_config_received = true;
     }
+
+    fn receive_settings(&mut self, form: &'static str) {
+        self.settings_form = Some(form);
+        self.settings_errors = None;
+    }
 }
 
 impl ConfigurationServer for FakeConfigServer {
@@ -146,34 +243,127 @@ impl ConfigurationServer for FakeConfigServer {
         self.is_config_received
     }
 
-    fn get_config_uri(&mut self) -> Option<String> {
+    fn get_configuration_fields(&mut self) -> Option<FormFields> {
         if self.is_config_received {
             self.is_config_received = false;
-            Some(String::from("/get?input_wifi_ssid=myhomenetwork&input_wifi_password=1234&input_night_mode_start=23%3A30&input_night_mode_end=04%3A40&favcolor=%2300ff00"))
+            Some(form_urlencoded::parse(self.form))
         } else {
             None
         }
     }
+
+    fn report_configuration_errors(&mut self, errors: Vec<FieldError>) {
+        self.errors = Some(errors);
+    }
+
+    fn is_scan_requested(&mut self) -> bool {
+        std::mem::take(&mut self.scan_requested)
+    }
+
+    fn set_scan_results(&mut self, results: Vec<ScanResult>) {
+        self.scan_results = results;
+    }
+
+    fn set_pin(&mut self, pin: Option<String>) {
+        self.pin = pin;
+    }
+
+    fn is_pin_requested(&mut self) -> bool {
+        std::mem::take(&mut self.pin_requested)
+    }
+
+    fn set_settings(&mut self, configuration: Option<Configuration>) {
+        self.settings = configuration;
+    }
+
+    fn get_settings_fields(&mut self) -> Option<FormFields> {
+        self.settings_form.take().map(form_urlencoded::parse)
+    }
+
+    fn report_settings_errors(&mut self, errors: Vec<FieldError>) {
+        self.settings_errors = Some(errors);
+    }
+
+    fn set_status(&mut self, status: ApiStatus) {
+        self.status = Some(status);
+    }
+
+    fn get_action(&mut self) -> Option<ApiAction> {
+        self.actions.pop_front()
+    }
+
+    fn get_notification(&mut self) -> Option<Notification> {
+        self.notification.take()
+    }
 }
 
-struct FakePowerManager;
+struct FakePowerManager {
+    resets: Cell<u32>,
+    uptime: Cell<Duration>,
+}
 
 impl PowerManager for FakePowerManager {
-    fn reset(&self) {}
+    fn reset(&self) {
+        self.resets.set(self.resets.get() + 1);
+    }
+
+    fn uptime(&self) -> Duration {
+        self.uptime.get()
+    }
 }
 
-struct FakeFirmwareUpdate;
+struct FakeFirmwareUpdate {
+    manifest: OtaManifest,
+    downloads: RefCell<Vec<version::Version>>,
+    /// Manifest and image URLs requested, in order
+    urls: RefCell<Vec<String>>,
+    reboots: Cell<u32>,
+    /// Bootloader waiting for the confirmation of the running image
+    pending: Cell<bool>,
+}
 
 impl FirmwareUpdate for FakeFirmwareUpdate {
-    fn read_update_version(&self) -> Result<version::Version> {
-        Ok(version::Version::new(1, 1, 0, None))
+    fn read_manifest(&self, url: &str) -> Result<OtaManifest> {
+        self.urls.borrow_mut().push(String::from(url));
+        Ok(self.manifest.clone())
     }
 
-    fn download_update(&self) -> Result<()> {
+    fn download_update(&self, image: &FirmwareImage, url: &str) -> Result<()> {
+        self.urls.borrow_mut().push(String::from(url));
+        self.downloads.borrow_mut().push(image.version.clone());
         Ok(())
     }
 
-    fn reboot_to_new_image(&self) {}
+    fn reboot_to_new_image(&self) {
+        self.reboots.set(self.reboots.get() + 1);
+    }
+
+    fn is_image_pending(&self) -> bool {
+        self.pending.get()
+    }
+
+    fn confirm_image(&self) -> Result<()> {
+        self.pending.set(false);
+        Ok(())
+    }
+}
+
+fn firmware_image(version: &str, hardware: &str, min_version: Option<&str>) -> FirmwareImage {
+    FirmwareImage {
+        version: version::Version::from_string(version).unwrap(),
+        hardware: String::from(hardware),
+        channel: UpdateChannel::Stable,
+        url: String::from("firmware-ota.bin"),
+        size: 5,
+        sha256: sha256(b"image"),
+        min_version: min_version.map(|version| version::Version::from_string(version).unwrap()),
+        release_notes: String::new(),
+        signature: None,
+    }
+}
+
+fn scan_result(ssid: &str, rssi: i8) -> ScanResult {
+    ScanResult { ssid: String::from(ssid), rssi, security: WifiSecurity::Wpa }
 }
 
 fn get_application() -> Application<
@@ -187,6 +377,9 @@ fn get_application() -> Application<
 > {
     let display = FakeDisplay {
         state: FakeDisplayState::Clean,
+        color: Color::default(),
+        brightness: display::MAX_BRIGHTNESS,
+        dialect: display::Dialect::default(),
     };
     let time_source = MockTime {
         current: time::Time::new(11, 22, 33).unwrap(),
@@ -198,12 +391,38 @@ fn get_application() -> Application<
         is_configured: false,
         is_connected: false,
         is_access_point: true, // to reflect Anomaly-002
+        connect_error: None,
+        visible: ["home wifi", "myhomenetwork", "holiday", "neighbour"]
+            .map(|ssid| scan_result(ssid, -60))
+            .to_vec(),
+        configured: Vec::new(),
+        refused: Vec::new(),
+        access_point: None,
+        advertised: Vec::new(),
     };
     let configuration_server = FakeConfigServer {
         is_config_received: false,
+        form: VALID_CONFIGURATION_FORM,
+        errors: None,
+        scan_requested: false,
+        scan_results: Vec::new(),
+        pin: None,
+        pin_requested: false,
+        settings: None,
+        settings_form: None,
+        settings_errors: None,
+        status: None,
+        actions: VecDeque::new(),
+        notification: None,
+    };
+    let power_manager = FakePowerManager { resets: Cell::new(0), uptime: Cell::new(Duration::from_secs(42)) };
+    let firmware_update = FakeFirmwareUpdate {
+        manifest: OtaManifest { images: vec![firmware_image("1.1.0", HARDWARE_REVISION, None)] },
+        downloads: RefCell::new(Vec::new()),
+        urls: RefCell::new(Vec::new()),
+        reboots: Cell::new(0),
+        pending: Cell::new(false),
     };
-    let power_manager = FakePowerManager;
-    let firmware_update = FakeFirmwareUpdate;
 
     Application::new(
         display,
@@ -287,7 +506,8 @@ fn goto_menu(
     goto_display_time(app);
     app.publish_event(Event::EnterShortPush);
     app.run();
-    assert_eq!(app.get_current_state(), State::MenuFota);
+    assert_eq!(app.get_current_state(), State::Menu);
+    assert_eq!(app.get_menu_selection(), Some("firmware_update"));
 }
 
 #[test]
@@ -351,7 +571,7 @@ fn valid_config_from_configuration_start_displaying_time() {
 
     app.run();
     assert_eq!(app.get_current_state(), State::Startup);
-    assert_eq!(app.configuration_server.get_config_uri(), None);
+    assert_eq!(app.configuration_server.get_configuration_fields(), None);
 
     app.run();
     assert_eq!(app.get_current_state(), State::DisplayTime);
@@ -361,7 +581,7 @@ fn valid_config_from_configuration_start_displaying_time() {
 fn network_is_ready_in_display_time() {
     let mut app = get_application();
     // // app.time_source.set_time(None);
-    assert_eq!(app.network.is_connected, false);
+    assert!(!app.network.is_connected);
     goto_display_time(&mut app);
 
     assert!(app.network.is_configured);
@@ -376,7 +596,9 @@ fn enter_menu_with_short_push() {
     app.publish_event(Event::Tick);
     app.run();
 
-    assert_eq!(app.get_current_state(), State::MenuFota);
+    assert_eq!(app.get_current_state(), State::Menu);
+    assert_eq!(app.get_menu_selection(), Some("firmware_update"));
+    assert_eq!(app.display.state, FakeDisplayState::Icon(display::Icon::Dots(1)));
 }
 
 #[test]
@@ -388,7 +610,8 @@ fn enter_menu_with_long_push() {
     app.publish_event(Event::Tick);
     app.run();
 
-    assert_eq!(app.get_current_state(), State::MenuFota);
+    assert_eq!(app.get_current_state(), State::Menu);
+    assert_eq!(app.get_menu_selection(), Some("firmware_update"));
 }
 
 #[test]
@@ -399,17 +622,50 @@ fn short_push_goes_to_next_menu_item() {
     app.publish_event(Event::EnterShortPush);
     app.run();
 
-    assert_eq!(app.get_current_state(), State::MenuCleanConfig);
+    assert_eq!(app.get_menu_selection(), Some("clean_config"));
+    assert_eq!(app.display.state, FakeDisplayState::Icon(display::Icon::Dots(2)));
 
     app.publish_event(Event::EnterShortPush);
     app.run();
 
-    assert_eq!(app.get_current_state(), State::MenuExit);
+    assert_eq!(app.get_menu_selection(), Some("exit"));
 
     app.publish_event(Event::EnterShortPush);
     app.run();
 
-    assert_eq!(app.get_current_state(), State::MenuFota);
+    assert_eq!(app.get_menu_selection(), Some("firmware_update"));
+}
+
+#[test]
+fn up_down_push_navigate_menu_items() {
+    let mut app = get_application();
+    goto_menu(&mut app);
+
+    app.publish_event(Event::UpShortPush);
+    app.run();
+    assert_eq!(app.get_menu_selection(), Some("exit"));
+
+    app.publish_event(Event::DownShortPush);
+    app.run();
+    assert_eq!(app.get_menu_selection(), Some("firmware_update"));
+
+    app.publish_event(Event::DownShortPush);
+    app.run();
+    assert_eq!(app.get_menu_selection(), Some("clean_config"));
+}
+
+#[test]
+fn unused_push_is_ignored() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    app.publish_event(Event::UpLongPush);
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+
+    app.publish_event(Event::EnterDoublePush);
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
 }
 
 #[test]
@@ -431,6 +687,12 @@ fn menu_clean_configuration() {
     app.publish_event(Event::EnterShortPush);
     app.run();
 
+    // Cleaning the configuration must be confirmed
+    app.publish_event(Event::EnterLongPush);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Menu);
+    assert_eq!(app.display.state, FakeDisplayState::Icon(display::Icon::Question));
+
     app.publish_event(Event::EnterLongPush);
     app.run();
 
@@ -445,6 +707,37 @@ fn menu_clean_configuration() {
     assert_eq!(app.get_current_state(), State::Startup);
 }
 
+#[test]
+fn menu_clean_configuration_can_be_cancelled() {
+    let mut app = get_application();
+    goto_menu(&mut app);
+
+    app.publish_event(Event::EnterShortPush);
+    app.publish_event(Event::EnterLongPush);
+    app.publish_event(Event::EnterShortPush);
+    app.run();
+    app.run();
+    app.run();
+
+    assert_eq!(app.get_current_state(), State::Menu);
+    assert_eq!(app.get_menu_selection(), Some("clean_config"));
+    assert!(app.configuration.is_valid());
+}
+
+#[test]
+fn menu_exit() {
+    let mut app = get_application();
+    goto_menu(&mut app);
+
+    app.publish_event(Event::UpShortPush);
+    app.run();
+    app.publish_event(Event::EnterLongPush);
+    app.run();
+
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert_eq!(app.get_menu_selection(), None);
+}
+
 #[test]
 fn enter_night_mode() {
     let mut app = get_application();
@@ -475,13 +768,932 @@ fn exit_night_mode() {
     app.run();
 
     assert_eq!(app.get_current_state(), State::NightMode);
+
+    // Still night after midnight
+    app.time_source
+        .set_time(time::Time::new(0, 10, 00).unwrap());
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::NightMode);
+
     app.time_source
         .set_time(time::Time::new(4, 30, 00).unwrap());
+    app.publish_event(Event::Tick);
+    app.run();
+    // Need an extra run to process the "Day" event
+    app.run();
 
-    // app.publish_event(Event::Tick);
-    // app.run();
-    // Need an extra run to process the "Night" event
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+}
+
+#[test]
+fn night_window_is_edited_live() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    // Before the new start, the night of the stored window is left
+    app.time_source
+        .set_time(time::Time::new(23, 10, 00).unwrap());
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::NightMode);
+
+    app.configuration_server
+        .receive_settings("input_night_mode_start=23%3A30&input_night_mode_end=05%3A00");
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.configuration_server.settings_errors, Some(Vec::new()));
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+
+    // The new window starts at 23:30, and ends at 05:00 instead of 04:30
+    for (hour, minute, state) in [
+        (23, 20, State::DisplayTime),
+        (23, 40, State::NightMode),
+        (4, 45, State::NightMode),
+        (5, 0, State::DisplayTime),
+    ] {
+        app.time_source
+            .set_time(time::Time::new(hour, minute, 00).unwrap());
+        app.publish_event(Event::Tick);
+        app.run();
+        app.run();
+        assert_eq!(app.get_current_state(), state, "{}:{}", hour, minute);
+    }
+}
+
+#[test]
+fn unreachable_network_is_retried() {
+    let mut app = get_application();
+    preset_configuration(&mut app);
+    app.network.connect_error = Some(NetworkError::NotFound);
+    run_startup(&mut app);
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::Error);
+    assert_eq!(app.display.state, FakeDisplayState::Error(ErrorKind::WifiNotFound));
+
+    // Router is back before the retry delay elapsed
+    app.network.connect_error = None;
+    for _ in 0..RETRY_INITIAL_DELAY_TICKS {
+        assert_eq!(app.get_current_state(), State::Error);
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::Startup);
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+}
+
+#[test]
+fn refused_credentials_go_back_to_configuration() {
+    let mut app = get_application();
+    preset_configuration(&mut app);
+    app.network.connect_error = Some(NetworkError::AuthenticationFailed);
+    run_startup(&mut app);
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::Error);
+    assert_eq!(app.display.state, FakeDisplayState::Error(ErrorKind::WifiAuth));
+
+    app.network.connect_error = None;
+    app.configuration_server.set_receive_config();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+    assert!(app.network.is_configured);
+}
+
+#[test]
+fn time_sync_failure_falls_back_to_rtc() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    app.publish_event(Event::Error(ErrorKind::TimeSync));
+    app.run();
+    assert_eq!(app.get_current_state(), State::Error);
+    assert_eq!(app.display.state, FakeDisplayState::Error(ErrorKind::TimeSync));
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert_eq!(
+        app.display.state,
+        FakeDisplayState::Time(time::Time::new(11, 22, 33).unwrap())
+    );
+}
+
+#[test]
+fn hardware_error_halts() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    app.publish_event(Event::Error(ErrorKind::Hardware));
+    app.run();
+    for _ in 0..100 {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    assert_eq!(app.get_current_state(), State::Error);
+    assert_eq!(app.display.state, FakeDisplayState::Error(ErrorKind::Hardware));
+}
+
+fn goto_reconfiguration(
+    app: &mut Application<
+        FakeDisplay,
+        MockTime,
+        FakePersistentStorage,
+        FakeNetwork,
+        FakeConfigServer,
+        FakePowerManager,
+        FakeFirmwareUpdate,
+    >,
+) {
+    preset_configuration(app);
+    app.network.connect_error = Some(NetworkError::AuthenticationFailed);
+    run_startup(app);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+    app.network.connect_error = None;
+}
+
+#[test]
+fn configuration_mode_does_not_block() {
+    let mut app = get_application();
+    run_startup(&mut app);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+    assert_eq!(app.display.state, FakeDisplayState::Progress(2));
+
+    app.configuration_server.set_receive_config();
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Startup);
+}
+
+#[test]
+fn configuration_timeout_retries_stored_configuration() {
+    let mut app = get_application();
+    app.set_configuration_timeout(Some(3));
+    goto_reconfiguration(&mut app);
+
+    for _ in 0..3 {
+        assert_eq!(app.get_current_state(), State::Configuration);
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::Startup);
     app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+}
+
+#[test]
+fn configuration_timeout_can_be_cancelled() {
+    let mut app = get_application();
+    app.set_configuration_timeout(Some(3));
+    goto_reconfiguration(&mut app);
+
+    app.publish_event(Event::EnterShortPush);
+    app.run();
+    for _ in 0..10 {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    assert_eq!(app.get_current_state(), State::Configuration);
+}
+
+#[test]
+fn configuration_timeout_without_stored_configuration() {
+    let mut app = get_application();
+    app.set_configuration_timeout(Some(3));
+    run_startup(&mut app);
 
+    for _ in 0..10 {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    assert_eq!(app.get_current_state(), State::Configuration);
+}
+
+#[test]
+fn invalid_form_is_reported_back() {
+    let mut app = get_application();
+    run_startup(&mut app);
+    app.run();
+
+    app.configuration_server
+        .receive_form("input_wifi_ssid=&input_night_mode_start=08%3A00");
+    app.publish_event(Event::Tick);
+    app.run();
+
+    assert_eq!(
+        app.configuration_server.errors,
+        Some(vec![FieldError::EmptySsid(0), FieldError::NightStartOutOfRange])
+    );
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+    assert!(app.configuration.is_invalid());
+}
+
+#[test]
+fn unreachable_network_is_reported_back() {
+    let mut app = get_application();
+    run_startup(&mut app);
+    app.run();
+
+    app.network.connect_error = Some(NetworkError::AuthenticationFailed);
+    app.configuration_server.set_receive_config();
+    app.publish_event(Event::Tick);
+    app.run();
+
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::NetworkAuthentication(0)]));
+    assert!(app.network.is_access_point);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+
+    app.network.connect_error = None;
+    app.configuration_server.set_receive_config();
+    app.publish_event(Event::Tick);
+    app.run();
+
+    assert_eq!(app.configuration_server.errors, Some(vec![]));
+    assert!(!app.network.is_access_point);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Startup);
+}
+
+fn preset_networks(
+    app: &mut Application<
+        FakeDisplay,
+        MockTime,
+        FakePersistentStorage,
+        FakeNetwork,
+        FakeConfigServer,
+        FakePowerManager,
+        FakeFirmwareUpdate,
+    >,
+) {
+    let configuration = Configuration::with_networks(
+        vec![
+            WifiCredentials::new("home wifi", "secret", 1),
+            WifiCredentials::new("office", "1234", 5),
+            WifiCredentials::new("holiday", "abcd", 1),
+        ],
+        None,
+        None,
+        Color::default(),
+    );
+    app.configuration_manager
+        .store_to_persistent_storage(configuration)
+        .unwrap();
+}
+
+#[test]
+fn best_visible_network_is_selected() {
+    let mut app = get_application();
+    preset_networks(&mut app);
+    app.network.visible = vec![
+        scan_result("home wifi", -80),
+        scan_result("holiday", -50),
+    ];
+    run_startup(&mut app);
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert_eq!(app.network.configured, vec![String::from("holiday")]);
+}
+
+#[test]
+fn next_network_is_tried_on_failure() {
+    let mut app = get_application();
+    preset_networks(&mut app);
+    app.network.refused = vec![String::from("home wifi")];
+    app.network.visible = vec![
+        scan_result("home wifi", -40),
+        scan_result("holiday", -70),
+    ];
+    run_startup(&mut app);
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert_eq!(app.network.configured, vec![String::from("home wifi"), String::from("holiday")]);
+}
+
+#[test]
+fn no_visible_network_is_retried() {
+    let mut app = get_application();
+    preset_networks(&mut app);
+    app.network.visible = vec![scan_result("neighbour", -40)];
+    run_startup(&mut app);
+
+    app.run();
+    assert_eq!(app.get_current_state(), State::Error);
+    assert_eq!(app.display.state, FakeDisplayState::Error(ErrorKind::WifiNotFound));
+    assert!(app.network.configured.is_empty());
+}
+
+#[test]
+fn refused_networks_are_reported_back() {
+    let mut app = get_application();
+    run_startup(&mut app);
+    app.run();
+
+    app.network.refused = vec![String::from("holiday")];
+    app.configuration_server.receive_form(
+        "input_wifi_ssid=office&input_wifi_password=1&input_wifi_ssid=holiday&input_wifi_password=2",
+    );
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::NetworkAuthentication(1)]));
+
+    app.configuration_server.receive_form("input_wifi_ssid=office&input_wifi_password=1");
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::NetworkNotFound]));
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+}
+
+#[test]
+fn visible_networks_are_offered_in_configuration() {
+    let mut app = get_application();
+    run_startup(&mut app);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+    assert!(app.configuration_server.scan_results.is_empty());
+
+    // Scanned once requested by the configuration page
+    app.configuration_server.scan_requested = true;
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.scan_results.len(), 4);
+
+    // Kept until the next request
+    app.network.visible = vec![scan_result("holiday", -50)];
+    for _ in 0..100 {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    assert_eq!(app.configuration_server.scan_results.len(), 4);
+    app.configuration_server.scan_requested = true;
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.scan_results, vec![scan_result("holiday", -50)]);
+}
+
+#[test]
+fn configuration_requires_pin() {
+    let mut app = get_application();
+    app.set_access_point_security(AccessPointSecurity::Pin([0, 4, 2, 7]));
+    run_startup(&mut app);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+    assert_eq!(app.configuration_server.pin, Some(String::from("0427")));
+    assert_eq!(app.display.state, FakeDisplayState::Icon(display::Icon::Pin([0, 4, 2, 7])));
+
+    app.configuration_server.set_receive_config();
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::InvalidPin]));
+    app.configuration_server.receive_form("input_pin=0427&input_wifi_ssid=myhomenetwork&input_wifi_password=1234");
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![]));
+    app.run();
+    assert_eq!(app.get_current_state(), State::Startup);
+}
+
+#[test]
+fn configuration_is_locked_after_wrong_pins() {
+    let mut app = get_application();
+    app.set_access_point_security(AccessPointSecurity::Pin([0, 4, 2, 7]));
+    run_startup(&mut app);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+
+    let wrong_pin = "input_pin=1234&input_wifi_ssid=myhomenetwork&input_wifi_password=1234";
+    let right_pin = "input_pin=0427&input_wifi_ssid=myhomenetwork&input_wifi_password=1234";
+    for _ in 1..MAX_PIN_ATTEMPTS {
+        app.configuration_server.receive_form(wrong_pin);
+        app.publish_event(Event::Tick);
+        app.run();
+        assert_eq!(app.configuration_server.errors, Some(vec![FieldError::InvalidPin]));
+    }
+    app.configuration_server.receive_form(wrong_pin);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::PinLocked]));
+
+    // Even the right PIN is refused during the lockout
+    app.configuration_server.receive_form(right_pin);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::PinLocked]));
+    assert_eq!(app.get_current_state(), State::Configuration);
+
+    // Each further wrong PIN doubles the lockout
+    for _ in 0..PIN_LOCKOUT_TICKS {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    app.configuration_server.receive_form(wrong_pin);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::PinLocked]));
+    for _ in 0..PIN_LOCKOUT_TICKS {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    app.configuration_server.receive_form(right_pin);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![FieldError::PinLocked]));
+
+    for _ in 0..PIN_LOCKOUT_TICKS {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    app.configuration_server.receive_form(right_pin);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.errors, Some(vec![]));
+}
+
+#[test]
+fn reconfiguration_starts_unique_access_point() {
+    let mut app = get_application();
+    app.set_access_point_security(AccessPointSecurity::passphrase("clock secret").unwrap());
+    goto_reconfiguration(&mut app);
+
+    assert_eq!(
+        app.network.access_point,
+        Some((String::from("WordClock Configuration A20B"), Some(String::from("clock secret"))))
+    );
+}
+
+#[test]
+fn network_stays_connected_in_display_time() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    assert!(app.network.is_connected);
+    assert!(!app.network.is_access_point);
+
+    app.publish_event(Event::Tick);
+    app.run();
+    assert!(app.network.is_connected);
+}
+
+#[test]
+fn settings_page_follows_the_mode() {
+    let mut app = get_application();
+    run_startup(&mut app);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Configuration);
+    assert_eq!(app.configuration_server.settings, None);
+
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    assert_eq!(app.configuration_server.settings, Some(app.configuration.clone()));
+}
+
+#[test]
+fn settings_are_applied_live() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    app.configuration_server.receive_settings(
+        "favcolor=%23ff0000&input_brightness=40&input_night_mode_start=23%3A00&input_night_mode_end=&input_dialect=half_hour",
+    );
+    app.publish_event(Event::Tick);
+    app.run();
+
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert_eq!(app.configuration_server.settings_errors, Some(Vec::new()));
+    assert_eq!(app.display.color, Color::new(255, 0, 0));
+    assert_eq!(app.display.brightness, 40);
+    assert_eq!(app.display.dialect, display::Dialect::HalfHour);
+    assert!(matches!(app.display.state, FakeDisplayState::Time(_)));
+
+    // Stored without touching the WiFi networks, and offered on the settings page
+    let stored = app.configuration_manager.load_from_persistent_storage();
+    assert_eq!(stored, app.configuration);
+    assert_eq!(stored.get_night_start(), Some(Time::new(23, 0, 0).unwrap()));
+    assert_eq!(stored.get_networks(), vec![WifiCredentials::new("home wifi", "secret", 0)]);
+    assert_eq!(app.configuration_server.settings, Some(stored));
+}
+
+#[test]
+fn pin_is_shown_when_requested() {
+    let mut app = get_application();
+    app.set_access_point_security(AccessPointSecurity::Pin([0, 4, 2, 7]));
+    goto_display_time(&mut app);
+    assert!(matches!(app.display.state, FakeDisplayState::Time(_)));
+
+    // Shown in place of the time for a while, e.g. once the settings page is opened
+    app.configuration_server.pin_requested = true;
+    for _ in 0..PIN_DISPLAY_TICKS {
+        app.publish_event(Event::Tick);
+        app.run();
+        assert_eq!(app.display.state, FakeDisplayState::Icon(display::Icon::Pin([0, 4, 2, 7])));
+    }
+    app.publish_event(Event::Tick);
+    app.run();
+    assert!(matches!(app.display.state, FakeDisplayState::Time(_)));
+
+    // Also in night mode, the display is cleared again afterwards
+    app.time_source.set_time(time::Time::new(23, 0, 0).unwrap());
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::NightMode);
+    app.configuration_server.pin_requested = true;
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.display.state, FakeDisplayState::Icon(display::Icon::Pin([0, 4, 2, 7])));
+    for _ in 1..PIN_DISPLAY_TICKS {
+        app.publish_event(Event::Tick);
+        app.run();
+    }
+    assert_eq!(app.display.state, FakeDisplayState::Clean);
+}
+
+#[test]
+fn hostname_is_advertised() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    assert_eq!(app.network.advertised, vec![String::from(network::DEFAULT_HOSTNAME)]);
+
+    // Advertised again only when the name changes
+    app.configuration_server.receive_settings("favcolor=%23ff0000&input_brightness=40&input_hostname=wordclock");
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.network.advertised.len(), 1);
+
+    app.configuration_server.receive_settings("favcolor=%23ff0000&input_brightness=40&input_hostname=Kitchen");
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.network.advertised, vec![String::from(network::DEFAULT_HOSTNAME), String::from("kitchen")]);
+}
+
+#[test]
+fn invalid_settings_are_reported_back() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    let configuration = app.configuration.clone();
+
+    app.configuration_server.receive_settings("favcolor=%23ff0000&input_brightness=0");
+    app.publish_event(Event::Tick);
+    app.run();
+
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert_eq!(app.configuration_server.settings_errors, Some(vec![FieldError::InvalidBrightness]));
+    assert_eq!(app.configuration, configuration);
+    assert_eq!(app.configuration_manager.load_from_persistent_storage(), configuration);
+}
+
+#[test]
+fn status_is_published() {
+    let mut app = get_application();
+    run_startup(&mut app);
+    let status = app.configuration_server.status.clone().unwrap();
+    assert_eq!(status.state, "Startup");
+    assert_eq!(status.rssi, None);
+
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    let status = app.configuration_server.status.clone().unwrap();
+    assert_eq!(status.state, "DisplayTime");
+    assert_eq!(status.time, Some(String::from("11:22:33")));
+    assert!(status.time_source.synchronized);
+    assert_eq!(status.version, build_version::BUILD_VERSION_STRING);
+    assert_eq!(status.uptime, 42);
+    assert_eq!(status.rssi, Some(-55));
+}
+
+#[test]
+fn requested_actions_are_handled() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    app.configuration_server.actions.extend([ApiAction::Sync, ApiAction::Reboot]);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.power_manager.resets.get(), 1);
+    assert!(app.configuration_server.actions.is_empty());
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+
+    app.configuration_server.actions.push_back(ApiAction::Ota);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Fota);
+}
+
+#[test]
+fn display_can_be_turned_off_and_on() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    app.configuration_server.actions.push_back(ApiAction::Off);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayOff);
+    assert_eq!(app.display.state, FakeDisplayState::Clean);
+
+    // The time is not drawn while off
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.display.state, FakeDisplayState::Clean);
+
+    app.configuration_server.actions.push_back(ApiAction::On);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert!(matches!(app.display.state, FakeDisplayState::Time(_)));
+}
+
+#[test]
+fn short_push_turns_display_on() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    app.configuration_server.actions.push_back(ApiAction::Off);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayOff);
+
+    app.publish_event(Event::EnterShortPush);
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+}
+
+#[test]
+fn notification_is_shown_over_the_time() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    let red = Color::new(255, 0, 0);
+
+    app.configuration_server.notification = Some(Notification::new(red, Pattern::Flash, Duration::from_secs(1)).unwrap());
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Notification);
+    assert_eq!(app.display.state, FakeDisplayState::Filled(red));
+
+    let start = app.power_manager.uptime.get();
+    app.power_manager.uptime.set(start + Duration::from_millis(300));
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.display.state, FakeDisplayState::Filled(Color::new(0, 0, 0)));
+
+    // The time is shown again once over
+    app.power_manager.uptime.set(start + Duration::from_secs(1));
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert!(matches!(app.display.state, FakeDisplayState::Time(_)));
+}
+
+#[test]
+fn notification_is_dismissed_with_a_push() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    let notification = Notification::new(Color::new(0, 255, 0), Pattern::Pulse, Duration::from_secs(30)).unwrap();
+    app.configuration_server.notification = Some(notification);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Notification);
+
+    app.publish_event(Event::EnterShortPush);
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert!(matches!(app.display.state, FakeDisplayState::Time(_)));
+}
+
+#[test]
+fn settings_and_actions_are_handled_during_notification() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+
+    let green = Color::new(0, 255, 0);
+    app.configuration_server.notification = Some(Notification::new(green, Pattern::Flash, Duration::from_secs(60)).unwrap());
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Notification);
+
+    // Applied without waiting for the end of the notification
+    app.configuration_server.receive_settings("favcolor=%23ff0000&input_brightness=40&input_dialect=half_hour");
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.configuration_server.settings_errors, Some(Vec::new()));
+    assert_eq!(app.display.brightness, 40);
+    assert_eq!(app.get_current_state(), State::Notification);
+
+    // A newer notification waits for the shown one
+    let blue = Color::new(0, 0, 255);
+    app.configuration_server.notification = Some(Notification::new(blue, Pattern::Flash, Duration::from_secs(1)).unwrap());
+    app.publish_event(Event::Tick);
+    app.run();
+    assert!(app.configuration_server.notification.is_some());
+    assert_eq!(app.display.state, FakeDisplayState::Filled(green));
+
+    app.configuration_server.actions.push_back(ApiAction::Off);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayOff);
+}
+
+fn request_firmware_update(images: Vec<FirmwareImage>) -> Application<
+    FakeDisplay,
+    MockTime,
+    FakePersistentStorage,
+    FakeNetwork,
+    FakeConfigServer,
+    FakePowerManager,
+    FakeFirmwareUpdate,
+> {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    app.firmware_update.manifest = OtaManifest { images };
+
+    app.configuration_server.actions.push_back(ApiAction::Ota);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Fota);
+    app
+}
+
+#[test]
+fn firmware_update_is_skipped_when_up_to_date() {
+    let current = version::Version::current().unwrap();
+    let (major, minor, patch) = (current.major(), current.minor(), current.patch());
+    let older = [
+        String::from("0.0.1"),
+        String::from(build_version::BUILD_VERSION_STRING),
+        format!("{}.{}.{}-0", major, minor, patch),
+        format!("{}+build.7", current),
+    ];
+    for available in &older {
+        let mut app = request_firmware_update(vec![firmware_image(available, HARDWARE_REVISION, None)]);
+        app.run();
+        assert_eq!(app.get_current_state(), State::DisplayTime, "{}", available);
+        assert!(app.firmware_update.downloads.borrow().is_empty(), "{}", available);
+    }
+}
+
+#[test]
+fn up_to_date_check_keeps_the_display_off() {
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    app.firmware_update.manifest = OtaManifest { images: vec![firmware_image("0.0.1", HARDWARE_REVISION, None)] };
+
+    app.configuration_server.actions.push_back(ApiAction::Off);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayOff);
+
+    app.configuration_server.actions.push_back(ApiAction::Ota);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayOff);
+    assert_eq!(app.display.state, FakeDisplayState::Clean);
+}
+
+#[test]
+fn newer_firmware_is_installed() {
+    let current = version::Version::current().unwrap();
+    let (major, minor, patch) = (current.major(), current.minor(), current.patch());
+    let newer = [
+        format!("{}.{}.{}", major, minor, patch + 1),
+        format!("{}.{}.0-beta", major, minor + 1),
+        format!("{}.0.0", major + 1),
+    ];
+    for available in &newer {
+        let app = request_firmware_update(vec![firmware_image(available, HARDWARE_REVISION, None)]);
+        assert_eq!(app.firmware_update.downloads.borrow().len(), 1, "{}", available);
+        assert_eq!(app.firmware_update.reboots.get(), 1, "{}", available);
+    }
+}
+
+#[test]
+fn firmware_of_the_hardware_is_installed() {
+    let app = request_firmware_update(vec![
+        firmware_image("9.0.0", "v1", None),
+        firmware_image("2.2.0", HARDWARE_REVISION, None),
+        firmware_image("3.0.0", HARDWARE_REVISION, Some("2.2.0")),
+    ]);
+    assert_eq!(*app.firmware_update.downloads.borrow(), [version::Version::new(2, 2, 0, None)]);
+}
+
+#[test]
+fn updates_come_from_the_configured_server() {
+    let app = request_firmware_update(vec![firmware_image("2.2.0", HARDWARE_REVISION, None)]);
+    assert_eq!(
+        *app.firmware_update.urls.borrow(),
+        [format!("{}/manifest.json", DEFAULT_UPDATE_SERVER), format!("{}/firmware-ota.bin", DEFAULT_UPDATE_SERVER)]
+    );
+
+    let mut app = get_application();
+    goto_display_time(&mut app);
+    app.configuration = app
+        .configuration
+        .clone()
+        .with_update_server("http://192.168.1.10:8000/ota-image/")
+        .with_update_channel(UpdateChannel::Beta);
+    let release_candidate =
+        FirmwareImage { channel: UpdateChannel::Beta, ..firmware_image("2.2.0-rc.1", HARDWARE_REVISION, None) };
+    app.firmware_update.manifest = OtaManifest { images: vec![release_candidate] };
+    app.configuration_server.actions.push_back(ApiAction::Ota);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(
+        *app.firmware_update.urls.borrow(),
+        ["http://192.168.1.10:8000/ota-image/manifest-beta.json", "http://192.168.1.10:8000/ota-image/firmware-ota.bin"]
+    );
+    assert_eq!(app.firmware_update.reboots.get(), 1);
+}
+
+#[test]
+fn missing_firmware_is_an_ota_error() {
+    let manifests = [
+        vec![],
+        vec![firmware_image("9.0.0", "v1", None)],
+        vec![firmware_image("3.0.0", HARDWARE_REVISION, Some("2.2.0"))],
+    ];
+    for images in manifests {
+        let mut app = request_firmware_update(images);
+        app.run();
+        assert_eq!(app.get_current_state(), State::Error);
+        assert_eq!(app.display.state, FakeDisplayState::Error(ErrorKind::Ota));
+        assert!(app.firmware_update.downloads.borrow().is_empty());
+    }
+}
+
+#[test]
+fn installed_update_is_validated_on_boot() {
+    let mut app = request_firmware_update(vec![firmware_image("2.2.0", HARDWARE_REVISION, None)]);
+    assert_eq!(app.firmware_update.reboots.get(), 1);
+
+    // First boot of the installed image
+    let installed = version::Version::new(2, 2, 0, None);
+    app.firmware_update.pending.set(true);
+    let storage = app.configuration_manager.storage();
+    assert_eq!(validate_boot(storage, &app.firmware_update, &installed), ImageState::Pending);
+
+    // Reset before the health checkpoint, the bootloader boots the previous image
+    app.firmware_update.pending.set(false);
+    let previous = version::Version::from_string(build_version::BUILD_VERSION_STRING).unwrap();
+    assert_eq!(
+        validate_boot(storage, &app.firmware_update, &previous),
+        ImageState::RolledBack { version: String::from("v2.2.0") }
+    );
+}
+
+#[test]
+fn pending_image_is_confirmed_by_the_time_display() {
+    let mut updated = request_firmware_update(vec![firmware_image("2.2.0", HARDWARE_REVISION, None)]);
+    let installed = version::Version::new(2, 2, 0, None);
+
+    // Next boot, on the installed image
+    let mut app = get_application();
+    let storage = updated.configuration_manager.storage().string_storage.clone();
+    app.configuration_manager.storage().string_storage = storage;
+    app.firmware_update.pending.set(true);
+    let state = validate_boot(app.configuration_manager.storage(), &app.firmware_update, &installed);
+    app.set_image_state(state);
+    preset_configuration(&mut app);
+    run_startup(&mut app);
+    assert_eq!(app.configuration_server.status.clone().unwrap().image, ImageState::Pending);
+    assert!(app.firmware_update.pending.get());
+
+    app.run();
     assert_eq!(app.get_current_state(), State::DisplayTime);
+    assert_eq!(app.configuration_server.status.clone().unwrap().image, ImageState::Valid);
+    assert!(!app.firmware_update.pending.get());
+    assert_eq!(validate_boot(app.configuration_manager.storage(), &app.firmware_update, &installed), ImageState::Valid);
 }
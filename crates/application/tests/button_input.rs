@@ -0,0 +1,134 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use application::behaviour::Event;
+use application::button_input::*;
+use application::time_monotonic::TimeMonotonic;
+
+struct MockMonotonicTime {
+    now: Instant,
+}
+
+impl MockMonotonicTime {
+    fn elapsed(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+impl TimeMonotonic for MockMonotonicTime {
+    fn now(&self) -> Instant {
+        self.now
+    }
+}
+
+struct MockButton {
+    pressed: Rc<Cell<bool>>,
+}
+
+impl PushButton for MockButton {
+    fn is_pressed(&self) -> bool {
+        self.pressed.get()
+    }
+}
+
+fn add_mock_button(
+    input: &mut ButtonInput<MockMonotonicTime>,
+    button: Button,
+    timings: ButtonTimings,
+) -> Rc<Cell<bool>> {
+    let pressed = Rc::new(Cell::new(false));
+    input.add_button(button, Box::new(MockButton { pressed: pressed.clone() }), timings);
+    pressed
+}
+
+/// Poll every 10ms for the given duration, and collect all generated events.
+fn poll_for(input: &mut ButtonInput<MockMonotonicTime>, duration_ms: u64) -> Vec<Event> {
+    let mut events = Vec::new();
+    for _ in 0..duration_ms / 10 {
+        input.time_monotonic.elapsed(Duration::from_millis(10));
+        events.append(&mut input.poll());
+    }
+    events
+}
+
+fn get_button_input() -> ButtonInput<MockMonotonicTime> {
+    ButtonInput::new(MockMonotonicTime { now: Instant::now() })
+}
+
+#[test]
+fn enter_short_push() {
+    let mut input = get_button_input();
+    let enter = add_mock_button(&mut input, Button::Enter, ButtonTimings::default());
+
+    enter.set(true);
+    assert!(poll_for(&mut input, 100).is_empty());
+    enter.set(false);
+    assert_eq!(poll_for(&mut input, 500), vec![Event::EnterShortPush]);
+}
+
+#[test]
+fn enter_long_push_is_reported_while_held() {
+    let mut input = get_button_input();
+    let enter = add_mock_button(&mut input, Button::Enter, ButtonTimings::default());
+
+    enter.set(true);
+    assert!(poll_for(&mut input, 1900).is_empty());
+    assert_eq!(poll_for(&mut input, 200), vec![Event::EnterLongPush]);
+    assert!(poll_for(&mut input, 1000).is_empty());
+    enter.set(false);
+    assert!(poll_for(&mut input, 1000).is_empty());
+}
+
+#[test]
+fn enter_double_push() {
+    let mut input = get_button_input();
+    let enter = add_mock_button(&mut input, Button::Enter, ButtonTimings::default());
+
+    enter.set(true);
+    let mut events = poll_for(&mut input, 80);
+    enter.set(false);
+    events.append(&mut poll_for(&mut input, 100));
+    enter.set(true);
+    events.append(&mut poll_for(&mut input, 80));
+    enter.set(false);
+    events.append(&mut poll_for(&mut input, 500));
+
+    assert_eq!(events, vec![Event::EnterDoublePush]);
+}
+
+#[test]
+fn up_and_down_buttons() {
+    let mut input = get_button_input();
+    let timings = ButtonTimings {
+        double_press_window: Duration::ZERO,
+        ..Default::default()
+    };
+    let up = add_mock_button(&mut input, Button::Up, timings);
+    let down = add_mock_button(&mut input, Button::Down, timings);
+
+    up.set(true);
+    poll_for(&mut input, 100);
+    up.set(false);
+    assert_eq!(poll_for(&mut input, 50), vec![Event::UpShortPush]);
+
+    down.set(true);
+    poll_for(&mut input, 100);
+    down.set(false);
+    assert_eq!(poll_for(&mut input, 50), vec![Event::DownShortPush]);
+
+    down.set(true);
+    assert_eq!(poll_for(&mut input, 2100), vec![Event::DownLongPush]);
+    down.set(false);
+    assert!(poll_for(&mut input, 100).is_empty());
+}
+
+#[test]
+fn no_event_without_button() {
+    let mut input = get_button_input();
+    assert!(poll_for(&mut input, 1000).is_empty());
+}
@@ -0,0 +1,282 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+
+use application::color::Color;
+use application::configuration::*;
+use application::display::Dialect;
+use application::firmware_update::{UpdateChannel, DEFAULT_UPDATE_SERVER, MAX_UPDATE_SERVER_LENGTH};
+use application::network::{DEFAULT_HOSTNAME, MAX_HOSTNAME_LENGTH};
+use application::time::Time;
+
+const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/configuration");
+
+/// Storage shared with the test, to inspect the stored keys.
+#[derive(Clone, Default)]
+struct FakePersistentStorage {
+    string_storage: Rc<RefCell<HashMap<String, String>>>,
+}
+
+impl FakePersistentStorage {
+    /// Load a fixture, storing one `key=value` per line.
+    fn from_fixture(name: &str) -> Self {
+        let content = fs::read_to_string(format!("{}/{}", FIXTURES_DIR, name)).unwrap();
+        let string_storage = content
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Self { string_storage: Rc::new(RefCell::new(string_storage)) }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.string_storage.borrow().get(key).cloned()
+    }
+}
+
+impl PersistentStorage for FakePersistentStorage {
+    fn load_string(&mut self, key: &str) -> Result<String> {
+        self.get(key).ok_or_else(|| anyhow!("invalid query"))
+    }
+
+    /// Refuse the values the device couldn't read back.
+    fn store_string(&mut self, key: &str, value: &str) -> Result<()> {
+        if value.len() > MAX_STORED_STRING_LENGTH {
+            return Err(anyhow!("value of {} too long", key));
+        }
+        self.string_storage.borrow_mut().insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+fn load_fixture(name: &str) -> (Configuration, FakePersistentStorage) {
+    let storage = FakePersistentStorage::from_fixture(name);
+    let mut manager = ConfigurationManager::new(storage.clone());
+    (manager.load_from_persistent_storage(), storage)
+}
+
+fn home_configuration(night_end: Option<Time>, display_color: Color) -> Configuration {
+    Configuration::new(
+        String::from("home wifi"),
+        String::from("secret"),
+        Some(Time::new(22, 0, 0).unwrap()),
+        night_end,
+        display_color,
+    )
+}
+
+#[test]
+fn load_version_0() {
+    let (configuration, storage) = load_fixture("v0.txt");
+    // Blue, as displayed by the clocks before the color setting
+    assert_eq!(
+        configuration,
+        home_configuration(Some(Time::new(6, 30, 0).unwrap()), Color::new(0, 0, 255))
+    );
+    assert_eq!(storage.get("display_color"), Some(String::from("0000FF")));
+}
+
+#[test]
+fn load_version_1() {
+    let (configuration, _) = load_fixture("v1.txt");
+    assert_eq!(
+        configuration,
+        home_configuration(Some(Time::new(6, 30, 0).unwrap()), Color::new(0, 255, 0))
+    );
+}
+
+#[test]
+fn load_version_1_without_night_mode() {
+    let (configuration, _) = load_fixture("v1_without_night_mode.txt");
+    assert_eq!(
+        configuration,
+        Configuration::new(
+            String::from("home wifi"),
+            String::from("secret"),
+            None,
+            None,
+            Color::new(255, 0, 0),
+        )
+    );
+}
+
+#[test]
+fn load_version_2() {
+    let (configuration, _) = load_fixture("v2.txt");
+    assert_eq!(configuration, home_configuration(None, Color::new(0, 255, 0)));
+}
+
+#[test]
+fn load_version_3() {
+    let (configuration, _) = load_fixture("v3.txt");
+    assert_eq!(
+        configuration,
+        Configuration::with_networks(
+            vec![
+                WifiCredentials::new("home wifi", "secret", 1),
+                WifiCredentials::new("holiday flat", "sea", 0),
+            ],
+            Some(Time::new(22, 0, 0).unwrap()),
+            None,
+            Color::new(0, 255, 0),
+        )
+    );
+}
+
+#[test]
+fn load_version_4() {
+    let (configuration, _) = load_fixture("v4.txt");
+    assert_eq!(
+        configuration,
+        home_configuration(None, Color::new(0, 255, 0))
+            .with_brightness(35)
+            .with_dialect(Dialect::HalfHour)
+    );
+}
+
+#[test]
+fn load_version_5() {
+    let (configuration, _) = load_fixture("v5.txt");
+    assert_eq!(
+        configuration,
+        home_configuration(None, Color::new(0, 255, 0))
+            .with_brightness(35)
+            .with_dialect(Dialect::HalfHour)
+            .with_hostname("kitchen")
+    );
+}
+
+#[test]
+fn load_version_6() {
+    let (configuration, _) = load_fixture("v6.txt");
+    let broker = MqttBroker {
+        port: 1884,
+        username: String::from("clock"),
+        password: String::from("secret"),
+        ..MqttBroker::new("broker.lan")
+    };
+    assert_eq!(
+        configuration,
+        home_configuration(None, Color::new(0, 255, 0))
+            .with_brightness(35)
+            .with_dialect(Dialect::HalfHour)
+            .with_hostname("kitchen")
+            .with_mqtt_broker(Some(broker))
+    );
+}
+
+#[test]
+fn load_version_7() {
+    let (configuration, _) = load_fixture("v7.txt");
+    assert_eq!(configuration.get_update_server(), Some(String::from("http://192.168.1.10:8000/ota-image")));
+    assert_eq!(configuration.get_update_channel(), Some(UpdateChannel::Beta));
+    assert_eq!(configuration.get_hostname(), Some(String::from("kitchen")));
+    assert!(configuration.get_mqtt_broker().is_some());
+}
+
+#[test]
+fn older_versions_get_official_updates() {
+    let (configuration, storage) = load_fixture("v6.txt");
+    assert_eq!(configuration.get_update_server(), Some(String::from(DEFAULT_UPDATE_SERVER)));
+    assert_eq!(configuration.get_update_channel(), Some(UpdateChannel::Stable));
+    assert_eq!(storage.get("update_server"), Some(String::from(DEFAULT_UPDATE_SERVER)));
+    assert_eq!(storage.get("update_channel"), Some(String::from("stable")));
+}
+
+#[test]
+fn older_versions_have_mqtt_disabled() {
+    let (configuration, storage) = load_fixture("v5.txt");
+    assert_eq!(configuration.get_mqtt_broker(), None);
+    assert_eq!(storage.get("mqtt_host"), Some(String::new()));
+}
+
+#[test]
+fn older_versions_get_default_hostname() {
+    let (configuration, storage) = load_fixture("v4.txt");
+    assert_eq!(configuration.get_hostname(), Some(String::from(DEFAULT_HOSTNAME)));
+    assert_eq!(storage.get("hostname"), Some(String::from(DEFAULT_HOSTNAME)));
+}
+
+#[test]
+fn older_versions_get_default_display_settings() {
+    let (configuration, storage) = load_fixture("v3.txt");
+    assert_eq!(configuration.get_brightness(), Some(100));
+    assert_eq!(configuration.get_dialect(), Some(Dialect::Bern));
+    assert_eq!(storage.get("brightness"), Some(String::from("100")));
+    assert_eq!(storage.get("dialect"), Some(String::from("bern")));
+}
+
+#[test]
+fn migration_is_persisted() {
+    for fixture in ["v0.txt", "v1.txt", "v1_without_night_mode.txt", "v2.txt", "v3.txt", "v4.txt", "v5.txt", "v6.txt", "v7.txt"] {
+        let (configuration, storage) = load_fixture(fixture);
+        assert!(configuration.is_valid(), "{}", fixture);
+        assert_eq!(storage.get("config_version"), Some(CURRENT_CONFIG_VERSION.to_string()), "{}", fixture);
+
+        // Loading the migrated layout again gives the same configuration
+        let mut manager = ConfigurationManager::new(storage);
+        assert_eq!(manager.load_from_persistent_storage(), configuration, "{}", fixture);
+    }
+}
+
+#[test]
+fn store_current_version() {
+    let storage = FakePersistentStorage::default();
+    let mut manager = ConfigurationManager::new(storage.clone());
+    let configuration = home_configuration(None, Color::new(1, 2, 3));
+    manager.store_to_persistent_storage(configuration.clone()).unwrap();
+
+    assert_eq!(storage.get("config_version"), Some(CURRENT_CONFIG_VERSION.to_string()));
+    assert_eq!(storage.get("night_end"), Some(String::new()));
+    assert_eq!(storage.get("wifi_count"), Some(String::from("1")));
+    assert_eq!(storage.get("wifi_ssid_0"), Some(String::from("home wifi")));
+    assert_eq!(manager.load_from_persistent_storage(), configuration);
+}
+
+#[test]
+fn store_longest_values() {
+    let storage = FakePersistentStorage::default();
+    let mut manager = ConfigurationManager::new(storage.clone());
+    let host = format!("{}.lan", "b".repeat(MAX_BROKER_HOST_LENGTH - 4));
+    let broker = MqttBroker { username: "u".repeat(64), password: "p".repeat(64), ..MqttBroker::new(&host) };
+    let server = format!("https://{}", "s".repeat(MAX_UPDATE_SERVER_LENGTH - 8));
+    let configuration = Configuration::with_networks(
+        (0..MAX_WIFI_NETWORKS).map(|index| WifiCredentials::new(&format!("{:w<32}", index), &"x".repeat(63), 255)).collect(),
+        None,
+        None,
+        Color::new(255, 255, 255),
+    )
+    .with_hostname(&"h".repeat(MAX_HOSTNAME_LENGTH))
+    .with_mqtt_broker(Some(broker))
+    .with_update_server(&server);
+    assert!(configuration.is_valid());
+
+    manager.store_to_persistent_storage(configuration.clone()).unwrap();
+    assert_eq!(storage.get("mqtt_host"), Some(host));
+    assert_eq!(manager.load_from_persistent_storage(), configuration);
+}
+
+#[test]
+fn unsupported_version_is_invalid() {
+    let mut storage = FakePersistentStorage::from_fixture("v2.txt");
+    storage
+        .store_string("config_version", &(CURRENT_CONFIG_VERSION + 1).to_string())
+        .unwrap();
+    let mut manager = ConfigurationManager::new(storage);
+    assert!(manager.load_from_persistent_storage().is_invalid());
+}
+
+#[test]
+fn missing_credentials_are_invalid() {
+    let storage = FakePersistentStorage::from_fixture("v1.txt");
+    storage.string_storage.borrow_mut().remove("wifi_password");
+    let mut manager = ConfigurationManager::new(storage);
+    assert!(manager.load_from_persistent_storage().is_invalid());
+}
@@ -0,0 +1,231 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use application::api::{ApiAction, ApiState, ApiStatus};
+use application::boot_validation::ImageState;
+use application::build_version::BUILD_VERSION_STRING;
+use application::color::Color;
+use application::configuration::{Configuration, MqttBroker};
+use application::mqtt::{decode_packet, encode_publish, ConnectOptions, Message, MqttClient, Packet, MQTT_PORT};
+use application::mqtt_bridge::MqttBridge;
+use application::time_source::TimeSourceHealth;
+
+const CONNECT: u8 = 1;
+const SUBSCRIBE: u8 = 8;
+const DISCONNECT: u8 = 14;
+
+/// Broker accepting a single client, forwarding the received packets to the test.
+///
+/// Connections are accepted and subscriptions granted, the stream is handed
+/// over to the test to send messages to the client.
+struct FakeBroker {
+    address: SocketAddr,
+    packets: Receiver<Packet>,
+    client: Receiver<TcpStream>,
+}
+
+impl FakeBroker {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (packet_sender, packets) = mpsc::channel();
+        let (client_sender, client) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            client_sender.send(stream.try_clone().unwrap()).unwrap();
+            let mut buffer = Vec::new();
+            let mut chunk = [0_u8; 512];
+            loop {
+                while let Some((packet, length)) = decode_packet(&buffer).unwrap() {
+                    buffer.drain(..length);
+                    match packet {
+                        Packet::Other(CONNECT) => stream.write_all(&[0x20, 2, 0, 0]).unwrap(),
+                        Packet::Other(SUBSCRIBE) => stream.write_all(&[0x90, 4, 0, 1, 0, 0]).unwrap(),
+                        _ => (),
+                    }
+                    if packet_sender.send(packet).is_err() {
+                        return;
+                    }
+                }
+                match stream.read(&mut chunk) {
+                    Ok(0) | Err(_) => return,
+                    Ok(length) => buffer.extend_from_slice(&chunk[..length]),
+                }
+            }
+        });
+
+        Self { address, packets, client }
+    }
+
+    fn broker(&self) -> MqttBroker {
+        MqttBroker { port: self.address.port(), ..MqttBroker::new("127.0.0.1") }
+    }
+
+    fn receive(&self) -> Packet {
+        self.packets.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    fn receive_message(&self) -> Message {
+        match self.receive() {
+            Packet::Publish { message, .. } => message,
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+}
+
+fn api_state(configuration: Configuration) -> Mutex<ApiState> {
+    let mut state = ApiState::new();
+    state.settings = Some(configuration);
+    state.status = Some(ApiStatus {
+        state: String::from("DisplayTime"),
+        time: Some(String::from("08:15:00")),
+        time_source: TimeSourceHealth { synchronized: true, last_network_sync: None, board_synchronized: None },
+        version: String::from(BUILD_VERSION_STRING),
+        image: ImageState::Valid,
+        uptime: 120,
+        rssi: Some(-67),
+    });
+    Mutex::new(state)
+}
+
+fn configuration(broker: Option<MqttBroker>) -> Configuration {
+    Configuration::new(String::from("home"), String::from("secret"), None, None, Color::new(255, 0, 0))
+        .with_hostname("kitchen")
+        .with_mqtt_broker(broker)
+}
+
+fn payload(message: &Message) -> serde_json::Value {
+    serde_json::from_slice(&message.payload).unwrap()
+}
+
+#[test]
+fn clock_is_discovered_and_publishes_its_state() {
+    let broker = FakeBroker::start();
+    let state = api_state(configuration(Some(broker.broker())));
+    let mut bridge = MqttBridge::new();
+
+    assert!(bridge.step(&state));
+    assert_eq!(broker.receive(), Packet::Other(CONNECT));
+    let discovery: Vec<Message> = (0..3).map(|_| broker.receive_message()).collect();
+    assert!(discovery.iter().all(|message| message.retain));
+    assert_eq!(discovery[0].topic, "homeassistant/light/wordclock_kitchen/display/config");
+    assert_eq!(payload(&discovery[0])["device"]["name"], "WordClock kitchen");
+    assert_eq!(broker.receive_message(), Message::new("wordclock/kitchen/availability", "online", true));
+    assert_eq!(broker.receive(), Packet::Other(SUBSCRIBE));
+
+    let light = broker.receive_message();
+    assert_eq!(light.topic, "wordclock/kitchen/light");
+    assert_eq!(
+        payload(&light),
+        json!({"state": "ON", "brightness": 100, "color_mode": "rgb", "color": {"r": 255, "g": 0, "b": 0}})
+    );
+    assert_eq!(broker.receive_message(), Message::new("wordclock/kitchen/status", "on", true));
+
+    // Published again only on change
+    assert!(bridge.step(&state));
+    assert!(broker.packets.try_recv().is_err());
+    state.lock().unwrap().status.as_mut().unwrap().state = String::from("NightMode");
+    assert!(bridge.step(&state));
+    assert_eq!(payload(&broker.receive_message())["state"], "OFF");
+    assert_eq!(broker.receive_message(), Message::new("wordclock/kitchen/status", "night", true));
+}
+
+#[test]
+fn commands_are_handed_over() {
+    let broker = FakeBroker::start();
+    let state = api_state(configuration(Some(broker.broker())));
+    let mut bridge = MqttBridge::new();
+    assert!(bridge.step(&state));
+
+    let mut client = broker.client.recv_timeout(Duration::from_secs(5)).unwrap();
+    let command = Message::new("wordclock/kitchen/light/set", r#"{"state": "OFF", "brightness": 20}"#, false);
+    client.write_all(&encode_publish(&command)).unwrap();
+    client.write_all(&encode_publish(&Message::new("wordclock/kitchen/sync", "PRESS", false))).unwrap();
+    for _ in 0..5 {
+        assert!(bridge.step(&state));
+        if state.lock().unwrap().actions.len() == 2 {
+            break;
+        }
+    }
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.actions, [ApiAction::Off, ApiAction::Sync]);
+    let mut updated = configuration(Some(broker.broker()));
+    updated.update_settings(state.settings_fields.as_ref().unwrap()).unwrap();
+    assert_eq!(updated.get_brightness(), Some(20));
+}
+
+#[test]
+fn disabling_mqtt_leaves_the_broker() {
+    let broker = FakeBroker::start();
+    let state = api_state(configuration(Some(broker.broker())));
+    let mut bridge = MqttBridge::new();
+    assert!(bridge.step(&state));
+    // Connection, discovery, online, subscription and state
+    for _ in 0..8 {
+        broker.receive();
+    }
+
+    state.lock().unwrap().settings = Some(configuration(None));
+    assert!(!bridge.step(&state));
+    assert_eq!(broker.receive_message(), Message::new("wordclock/kitchen/availability", "offline", true));
+    assert_eq!(broker.receive(), Packet::Other(DISCONNECT));
+}
+
+#[test]
+fn unreachable_broker_is_not_connected() {
+    // Nothing listens on the port once the listener is dropped
+    let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let broker = MqttBroker { port: address.port(), ..MqttBroker::new("127.0.0.1") };
+    let state = api_state(configuration(Some(broker)));
+    let mut bridge = MqttBridge::new();
+
+    assert!(!bridge.step(&state));
+    // Not retried right away
+    assert!(!bridge.step(&state));
+}
+
+/// Round trip through a real broker, run with `cargo test -- --ignored` and
+/// mosquitto listening on the default port.
+#[test]
+#[ignore]
+fn local_mosquitto_broker() {
+    let connect = |client_id: &str| {
+        let stream = TcpStream::connect(("127.0.0.1", MQTT_PORT)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let options = ConnectOptions {
+            client_id: String::from(client_id),
+            keep_alive: Duration::from_secs(30),
+            credentials: None,
+            will: None,
+        };
+        MqttClient::connect(stream, &options).unwrap()
+    };
+    let mut subscriber = connect("wordclock-test-subscriber");
+    subscriber.subscribe(&["wordclock/test/#"]).unwrap();
+    let mut publisher = connect("wordclock-test-publisher");
+
+    let message = Message::new("wordclock/test/light", "hello", false);
+    let mut received = None;
+    for _ in 0..25 {
+        publisher.publish(&message).unwrap();
+        received = subscriber.poll().unwrap();
+        if received.is_some() {
+            break;
+        }
+    }
+    assert_eq!(received, Some(message));
+    subscriber.disconnect().unwrap();
+    publisher.disconnect().unwrap();
+}
@@ -0,0 +1,345 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+
+use application::firmware_update::{manifest_url, FirmwareSink, HttpClient, ResponseHead, UpdateChannel, HARDWARE_REVISION};
+use application::ota_download::{download_image, read_manifest, HEADERS_LENGTH};
+use application::ota_manifest::{sha256, FirmwareImage, OtaManifest};
+use application::ota_signature::{PrivateKey, PublicKey};
+use application::version::Version;
+
+mod std_http_client;
+use std_http_client::StdHttpClient;
+
+/// Seed of test vector 1 of RFC 8032
+const PRIVATE_KEY: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+/// Response of the stand-in server, cut after `sent` bytes of the body
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+    sent: usize,
+}
+
+impl Response {
+    fn ok(body: &[u8]) -> Self {
+        Self { status: 200, body: body.to_vec(), sent: body.len() }
+    }
+}
+
+/// Serve a single request on a local port, the body is written in small
+/// pieces. Return the URL to request.
+fn serve(response: Response) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", response.status, response.body.len())
+            .unwrap();
+        for piece in response.body[..response.sent].chunks(700) {
+            stream.write_all(piece).unwrap();
+            stream.flush().unwrap();
+        }
+    });
+    format!("http://{}/ota-image/hardware-v2/firmware-3.2.0.bin", address)
+}
+
+/// Serve `files` by path on a local port, like a mirror of the `ota-image`
+/// directory. Return the base URL.
+fn serve_directory(files: Vec<(&'static str, Vec<u8>)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let (status, body) = match files.iter().find(|(file, _)| path == format!("/ota-image/{}", file)) {
+                Some((_, body)) => (200, body.as_slice()),
+                None => (404, b"Not found".as_slice()),
+            };
+            write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len()).unwrap();
+            stream.write_all(body).unwrap();
+        }
+    });
+    format!("http://{}/ota-image/", address)
+}
+
+/// ESP32 image, with valid headers
+fn esp32_image(length: usize) -> Vec<u8> {
+    let mut image: Vec<u8> = (0..length).map(|index| (index % 251) as u8).collect();
+    image[..HEADERS_LENGTH].fill(0);
+    image[0] = 0xe9;
+    image[32..36].copy_from_slice(&0xabcd_5432_u32.to_le_bytes());
+    image[48..54].copy_from_slice(b"v3.2.0");
+    image
+}
+
+/// Manifest entry of `data`, signed with the test key
+fn signed_image(data: &[u8]) -> FirmwareImage {
+    let mut image = FirmwareImage {
+        version: Version::new(3, 2, 0, None),
+        hardware: String::from("v2"),
+        channel: UpdateChannel::Stable,
+        url: String::from("hardware-v2/firmware-3.2.0.bin"),
+        size: data.len(),
+        sha256: sha256(data),
+        min_version: None,
+        release_notes: String::new(),
+        signature: None,
+    };
+    PrivateKey::from_hex(PRIVATE_KEY).unwrap().sign(&mut image);
+    image
+}
+
+fn public_key() -> PublicKey {
+    PrivateKey::from_hex(PRIVATE_KEY).unwrap().public_key()
+}
+
+/// Update partition recording the calls
+#[derive(Default)]
+struct RecordingSink {
+    begun: Option<usize>,
+    data: Vec<u8>,
+    completed: bool,
+    aborted: bool,
+}
+
+impl FirmwareSink for RecordingSink {
+    fn begin(&mut self, size: usize) -> Result<()> {
+        self.begun = Some(size);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn complete(&mut self) -> Result<()> {
+        self.completed = true;
+        Ok(())
+    }
+
+    fn abort(&mut self) -> Result<()> {
+        self.aborted = true;
+        Ok(())
+    }
+}
+
+/// Client returning the body in chunks of the given lengths
+struct ChunkedClient {
+    body: Vec<u8>,
+    chunks: Vec<usize>,
+}
+
+impl HttpClient for ChunkedClient {
+    fn get(&mut self, _url: &str) -> Result<ResponseHead> {
+        Ok(ResponseHead { status: 200, content_length: None })
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let chunk = if self.chunks.is_empty() { self.body.len() } else { self.chunks.remove(0) };
+        let length = chunk.min(buffer.len()).min(self.body.len());
+        buffer[..length].copy_from_slice(&self.body[..length]);
+        self.body.drain(..length);
+        Ok(length)
+    }
+}
+
+fn download(url: &str, image: &FirmwareImage) -> (Result<()>, RecordingSink) {
+    let mut sink = RecordingSink::default();
+    let result = download_image(&mut StdHttpClient::new(), &mut sink, image, url, &public_key());
+    (result, sink)
+}
+
+#[test]
+fn image_is_written_and_completed() {
+    let data = esp32_image(20_000);
+    let (result, sink) = download(&serve(Response::ok(&data)), &signed_image(&data));
+
+    result.unwrap();
+    assert_eq!(sink.begun, Some(data.len()));
+    assert!(sink.data == data);
+    assert!(sink.completed);
+    assert!(!sink.aborted);
+}
+
+#[test]
+fn short_reads_are_not_the_end_of_the_image() {
+    let data = esp32_image(9_000);
+    // Headers split over several reads, then reads shorter than the buffer
+    let mut client = ChunkedClient { body: data.clone(), chunks: vec![1, 100, 3, 287, 500, 4096, 7] };
+    let mut sink = RecordingSink::default();
+
+    download_image(&mut client, &mut sink, &signed_image(&data), "http://mirror.lan/firmware.bin", &public_key()).unwrap();
+    assert!(sink.data == data);
+    assert!(sink.completed);
+}
+
+#[test]
+fn truncated_image_is_aborted() {
+    let data = esp32_image(20_000);
+    let (result, sink) = download(&serve(Response { sent: 12_345, ..Response::ok(&data) }), &signed_image(&data));
+
+    assert!(result.unwrap_err().to_string().contains("truncated"));
+    assert!(sink.aborted);
+    assert!(!sink.completed);
+}
+
+#[test]
+fn corrupt_image_is_aborted() {
+    let data = esp32_image(20_000);
+    let image = signed_image(&data);
+    let mut corrupt = data;
+    corrupt[15_000] ^= 0x10;
+    let (result, sink) = download(&serve(Response::ok(&corrupt)), &image);
+
+    assert!(result.unwrap_err().to_string().contains("SHA-256"));
+    assert!(sink.aborted);
+    assert!(!sink.completed);
+}
+
+#[test]
+fn image_for_another_chip_is_not_written() {
+    let mut data = esp32_image(20_000);
+    data[0] = 0x7f;
+    let (result, sink) = download(&serve(Response::ok(&data)), &signed_image(&data));
+
+    assert!(result.unwrap_err().to_string().contains("not an ESP32 image"));
+    assert!(sink.data.is_empty());
+    assert!(sink.aborted);
+
+    let mut data = esp32_image(HEADERS_LENGTH);
+    data.pop();
+    let (result, sink) = download(&serve(Response::ok(&data)), &signed_image(&data));
+    assert!(result.unwrap_err().to_string().contains("shorter than its headers"));
+    assert!(sink.aborted);
+}
+
+#[test]
+fn unsigned_image_is_not_downloaded() {
+    let data = esp32_image(20_000);
+    let mut image = signed_image(&data);
+    image.signature = None;
+    let mut sink = RecordingSink::default();
+    let mut client = ChunkedClient { body: data, chunks: Vec::new() };
+
+    let result = download_image(&mut client, &mut sink, &image, "http://mirror.lan/firmware.bin", &public_key());
+    assert!(result.unwrap_err().to_string().contains("not signed"));
+    assert_eq!(sink.begun, None);
+    assert_eq!(client.body.len(), 20_000);
+}
+
+#[test]
+fn unexpected_response_is_not_written() {
+    let data = esp32_image(20_000);
+    let image = signed_image(&data);
+
+    let (result, sink) = download(&serve(Response { status: 404, ..Response::ok(b"Not found") }), &image);
+    assert!(result.unwrap_err().to_string().contains("HTTP error 404"));
+    assert_eq!(sink.begun, None);
+
+    let (result, sink) = download(&serve(Response::ok(&data[..19_999])), &image);
+    assert!(result.unwrap_err().to_string().contains("expected by the manifest"));
+    assert_eq!(sink.begun, None);
+}
+
+#[test]
+fn manifest_is_read() {
+    let mut manifest = OtaManifest::default();
+    manifest.release(signed_image(&esp32_image(20_000)));
+    let json = manifest.to_json().unwrap();
+
+    assert_eq!(read_manifest(&mut StdHttpClient::new(), &serve(Response::ok(json.as_bytes()))).unwrap(), manifest);
+
+    let truncated = Response { sent: json.len() / 2, ..Response::ok(json.as_bytes()) };
+    let error = read_manifest(&mut StdHttpClient::new(), &serve(truncated)).unwrap_err();
+    assert!(error.to_string().contains("truncated"));
+
+    let oversized = vec![b' '; 5000];
+    assert!(read_manifest(&mut StdHttpClient::new(), &serve(Response::ok(&oversized))).is_err());
+}
+
+#[test]
+fn failing_sink_is_aborted() {
+    struct FullPartition(RecordingSink);
+    impl FirmwareSink for FullPartition {
+        fn begin(&mut self, size: usize) -> Result<()> {
+            self.0.begin(size)
+        }
+        fn write(&mut self, _data: &[u8]) -> Result<()> {
+            Err(anyhow!("Partition full"))
+        }
+        fn complete(&mut self) -> Result<()> {
+            self.0.complete()
+        }
+        fn abort(&mut self) -> Result<()> {
+            self.0.abort()
+        }
+    }
+
+    let data = esp32_image(20_000);
+    let mut sink = FullPartition(RecordingSink::default());
+    let mut client = ChunkedClient { body: data.clone(), chunks: Vec::new() };
+    let result = download_image(&mut client, &mut sink, &signed_image(&data), "http://mirror.lan/firmware.bin", &public_key());
+
+    assert_eq!(result.unwrap_err().to_string(), "Partition full");
+    assert!(sink.0.aborted);
+    assert!(!sink.0.completed);
+}
+
+#[test]
+fn update_is_staged_on_a_local_mirror() {
+    let stable = esp32_image(20_000);
+    let beta = esp32_image(30_000);
+    let mut stable_manifest = OtaManifest::default();
+    stable_manifest.release(signed_image(&stable));
+    let mut release_candidate = FirmwareImage {
+        version: Version::from_string("3.3.0-rc.1").unwrap(),
+        channel: UpdateChannel::Beta,
+        url: String::from("hardware-v2/firmware-3.3.0-rc.1.bin"),
+        ..signed_image(&beta)
+    };
+    PrivateKey::from_hex(PRIVATE_KEY).unwrap().sign(&mut release_candidate);
+    let mut beta_manifest = OtaManifest::default();
+    beta_manifest.release(release_candidate);
+
+    let server = serve_directory(vec![
+        ("manifest.json", stable_manifest.to_json().unwrap().into_bytes()),
+        ("manifest-beta.json", beta_manifest.to_json().unwrap().into_bytes()),
+        ("hardware-v2/firmware-3.2.0.bin", stable),
+        ("hardware-v2/firmware-3.3.0-rc.1.bin", beta.clone()),
+    ]);
+
+    // Same steps as the application, over plain HTTP
+    let url = manifest_url(&server, UpdateChannel::Beta);
+    let manifest = read_manifest(&mut StdHttpClient::new(), &url).unwrap();
+    assert_eq!(manifest, beta_manifest);
+    let image = manifest.select(HARDWARE_REVISION, UpdateChannel::Beta, &Version::new(3, 2, 0, None)).unwrap();
+    let mut sink = RecordingSink::default();
+    download_image(&mut StdHttpClient::new(), &mut sink, image, &image.resolve_url(&url), &public_key()).unwrap();
+    assert!(sink.data == beta);
+    assert!(sink.completed);
+
+    let url = manifest_url(&server, UpdateChannel::Nightly);
+    let error = read_manifest(&mut StdHttpClient::new(), &url).unwrap_err();
+    assert!(error.to_string().contains("HTTP error 404"));
+}
@@ -0,0 +1,31 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::fs;
+use std::path::Path;
+
+use application::firmware_update::UpdateChannel;
+use application::ota_manifest::{ImageCheck, OtaManifest};
+
+/// The published manifests must describe the published images, signed by the
+/// maintainer. A manifest is only published with a signed release.
+#[test]
+fn released_manifests_describe_the_images() {
+    let ota_image = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../ota-image");
+    for channel in UpdateChannel::ALL {
+        let Ok(json) = fs::read(ota_image.join(channel.manifest_file())) else {
+            continue;
+        };
+        let manifest = OtaManifest::from_json(&json).unwrap();
+        for image in &manifest.images {
+            assert_eq!(image.channel, channel, "{} listed in {}", image.version, channel.manifest_file());
+            assert!(!(channel == UpdateChannel::Stable && image.version.is_pre_release()), "{} is not a release", image.version);
+            assert!(image.signature.is_some(), "{} is not signed, run `cargo sign`", image.version);
+
+            let mut check = ImageCheck::new(image);
+            check.update(&fs::read(ota_image.join(&image.url)).unwrap()).unwrap();
+            check.finish().expect("manifest is outdated, run `cargo generate_ota`");
+        }
+    }
+}
@@ -0,0 +1,420 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use application::behaviour::{Event, State};
+use application::color::Color;
+use application::configuration::{Configuration, PersistentStorage};
+use application::display::{Dialect, Display, Icon};
+use application::error_recovery::ErrorKind;
+use application::firmware_update::{FirmwareUpdate, DEFAULT_UPDATE_SERVER};
+use application::network::{AccessPointSecurity, Network, ScanResult};
+use application::ota_manifest::{FirmwareImage, OtaManifest};
+use application::power_manager::PowerManager;
+use application::time::Time;
+use application::time_source::{TimeSource, TimeSourceError};
+use application::Application;
+
+mod std_http_server;
+use std_http_server::StdHttpServer;
+
+#[derive(Default)]
+struct FakeDisplay {
+    brightness: u8,
+    dialect: Dialect,
+    icon: Option<Icon>,
+}
+
+impl Display for FakeDisplay {
+    fn clear(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn draw_time(&mut self, _time: Time) -> Result<()> {
+        Ok(())
+    }
+    fn draw_error(&mut self, _kind: ErrorKind) -> Result<()> {
+        Ok(())
+    }
+    fn draw_progress(&mut self, _progress: u8) -> Result<()> {
+        Ok(())
+    }
+    fn draw_icon(&mut self, icon: Icon) -> Result<()> {
+        self.icon = Some(icon);
+        Ok(())
+    }
+    fn fill(&mut self, _color: Color) -> Result<()> {
+        Ok(())
+    }
+    fn set_default_color(&mut self, _color: Color) {}
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+    fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+}
+
+#[derive(Default)]
+struct FakeTime {
+    synchronizations: u32,
+}
+
+impl TimeSource for FakeTime {
+    fn synchronize(&mut self) -> Result<(), TimeSourceError> {
+        self.synchronizations += 1;
+        Ok(())
+    }
+
+    fn get_time(&self) -> Result<Time, TimeSourceError> {
+        Time::new(8, 15, 0).map_err(|_| TimeSourceError::NotAvailable)
+    }
+
+    fn set_time(&mut self, _now: Time) -> Result<(), TimeSourceError> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FakePersistentStorage {
+    string_storage: HashMap<String, String>,
+}
+
+impl PersistentStorage for FakePersistentStorage {
+    fn load_string(&mut self, key: &str) -> Result<String> {
+        self.string_storage.get(key).cloned().ok_or_else(|| anyhow!("invalid query"))
+    }
+
+    fn store_string(&mut self, key: &str, value: &str) -> Result<()> {
+        self.string_storage.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FakeNetwork {
+    is_connected: bool,
+}
+
+impl Network for FakeNetwork {
+    fn configure(&mut self, _ssid: &str, _password: &str) -> Result<()> {
+        Ok(())
+    }
+    fn connect(&mut self) -> Result<()> {
+        self.is_connected = true;
+        Ok(())
+    }
+    fn disconnect(&mut self) -> Result<()> {
+        self.is_connected = false;
+        Ok(())
+    }
+    fn is_connected(&self) -> bool {
+        self.is_connected
+    }
+    fn scan(&mut self) -> Result<Vec<ScanResult>> {
+        Err(anyhow!("Scan not supported"))
+    }
+    fn setup_access_point(&mut self, _ssid: &str, _passphrase: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+    fn stop_access_point(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn mac_address(&self) -> Result<[u8; 6]> {
+        Ok([0; 6])
+    }
+    fn rssi(&self) -> Result<i8> {
+        Ok(-67)
+    }
+    fn advertise(&mut self, _hostname: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FakePowerManager {
+    resets: Cell<u32>,
+}
+
+impl PowerManager for FakePowerManager {
+    fn reset(&self) {
+        self.resets.set(self.resets.get() + 1);
+    }
+
+    fn uptime(&self) -> Duration {
+        Duration::from_secs(120)
+    }
+}
+
+struct FakeFirmwareUpdate;
+
+impl FirmwareUpdate for FakeFirmwareUpdate {
+    fn read_manifest(&self, _url: &str) -> Result<OtaManifest> {
+        Err(anyhow!("No update server"))
+    }
+
+    fn download_update(&self, _image: &FirmwareImage, _url: &str) -> Result<()> {
+        Err(anyhow!("No update server"))
+    }
+
+    fn reboot_to_new_image(&self) {}
+
+    fn is_image_pending(&self) -> bool {
+        false
+    }
+
+    fn confirm_image(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+type TestApplication =
+    Application<FakeDisplay, FakeTime, FakePersistentStorage, FakeNetwork, StdHttpServer, FakePowerManager, FakeFirmwareUpdate>;
+
+/// Application displaying the time, with the REST API served on a free local port
+fn get_application() -> TestApplication {
+    let mut app = Application::new(
+        FakeDisplay::default(),
+        FakeTime::default(),
+        FakePersistentStorage::default(),
+        FakeNetwork::default(),
+        StdHttpServer::new("127.0.0.1:0").unwrap(),
+        FakePowerManager::default(),
+        FakeFirmwareUpdate,
+    );
+    let configuration = Configuration::new(
+        String::from("home wifi"),
+        String::from("secret"),
+        Some(Time::new(22, 0, 0).unwrap()),
+        Some(Time::new(6, 0, 0).unwrap()),
+        Color::new(255, 0, 0),
+    );
+    app.configuration_manager.store_to_persistent_storage(configuration).unwrap();
+
+    app.publish_event(Event::Init);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    app
+}
+
+/// Send a JSON request and return the status code with the decoded JSON body.
+fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+    request_with_content_type(address, method, path, "application/json", body)
+}
+
+fn request_with_content_type(address: SocketAddr, method: &str, path: &str, content_type: &str, body: &str) -> (u16, Value) {
+    request_with_headers(address, method, path, &format!("Content-Type: {}\r\n", content_type), body)
+}
+
+fn request_with_pin(address: SocketAddr, method: &str, path: &str, pin: &str, body: &str) -> (u16, Value) {
+    let headers = format!("Content-Type: application/json\r\nX-Pin: {}\r\n", pin);
+    request_with_headers(address, method, path, &headers, body)
+}
+
+fn request_with_headers(address: SocketAddr, method: &str, path: &str, headers: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: wordclock.local\r\n{}Content-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        headers,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+/// Send a request from another thread, while the application keeps ticking
+/// to handle it.
+fn request_while_running(app: &mut TestApplication, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let address = app.configuration_server.local_address();
+    let (method, path, body) = (method.to_string(), path.to_string(), body.to_string());
+    let client = thread::spawn(move || request(address, &method, &path, &body));
+    while !client.is_finished() {
+        app.publish_event(Event::Tick);
+        app.run();
+        thread::sleep(Duration::from_millis(10));
+    }
+    client.join().unwrap()
+}
+
+#[test]
+fn get_status() {
+    let app = get_application();
+    let (status, body) = request(app.configuration_server.local_address(), "GET", "/api/status", "");
+
+    assert_eq!(status, 200);
+    assert_eq!(body["state"], "DisplayTime");
+    assert_eq!(body["time"], "08:15:00");
+    assert_eq!(body["time_source"]["synchronized"], true);
+    assert_eq!(body["version"], application::build_version::BUILD_VERSION_STRING);
+    assert_eq!(body["uptime"], 120);
+    assert_eq!(body["rssi"], -67);
+}
+
+#[test]
+fn get_config() {
+    let app = get_application();
+    let (status, body) = request(app.configuration_server.local_address(), "GET", "/api/config", "");
+
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "display_color": "FF0000",
+            "brightness": 100,
+            "dialect": "bern",
+            "night_mode": {"start": "22:00", "end": "06:00"},
+            "update": {
+                "server": "https://raw.githubusercontent.com/lmayencourt/wordclock/released/ota-image",
+                "channel": "stable"
+            },
+            "hostname": "wordclock",
+            "mqtt": null,
+        })
+    );
+}
+
+#[test]
+fn put_config() {
+    let mut app = get_application();
+    let (status, body) =
+        request_while_running(&mut app, "PUT", "/api/config", r#"{"brightness": 30, "dialect": "half_hour"}"#);
+
+    assert_eq!(status, 200);
+    assert_eq!(body["brightness"], 30);
+    assert_eq!(body["display_color"], "FF0000");
+    assert_eq!(app.display.brightness, 30);
+    assert_eq!(app.display.dialect, Dialect::HalfHour);
+    assert_eq!(app.configuration_manager.load_from_persistent_storage().get_brightness(), Some(30));
+}
+
+#[test]
+fn put_invalid_config() {
+    let mut app = get_application();
+    let (status, body) = request_while_running(&mut app, "PUT", "/api/config", r#"{"night_mode": {"start": "09:00"}}"#);
+
+    assert_eq!(status, 422);
+    assert_eq!(body["errors"][0]["field"], "night_mode.start");
+    assert_eq!(app.configuration.get_night_start(), Some(Time::new(22, 0, 0).unwrap()));
+
+    let address = app.configuration_server.local_address();
+    assert_eq!(request(address, "PUT", "/api/config", r#"{"wifi": []}"#).0, 400);
+
+    // Protected by the PIN of the configuration form
+    let (status, body) = request(address, "PUT", "/api/config", r#"{"update": {"server": "http://mirror.lan/ota"}}"#);
+    assert_eq!(status, 400);
+    assert!(body["error"].as_str().unwrap().contains("configuration form"));
+    assert_eq!(app.configuration.get_update_server(), Some(String::from(DEFAULT_UPDATE_SERVER)));
+}
+
+#[test]
+fn post_actions() {
+    let mut app = get_application();
+    let address = app.configuration_server.local_address();
+    let synchronizations = app.time_source.synchronizations;
+
+    assert_eq!(request(address, "POST", "/api/actions/sync", ""), (202, json!({"action": "sync"})));
+    assert_eq!(request(address, "POST", "/api/actions/reboot", "").0, 202);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.time_source.synchronizations, synchronizations + 1);
+    assert_eq!(app.power_manager.resets.get(), 1);
+
+    // The update fails without update server
+    assert_eq!(request(address, "POST", "/api/actions/ota", "").0, 202);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Fota);
+    app.run();
+    assert_eq!(app.get_current_state(), State::Error);
+}
+
+#[test]
+fn post_notify() {
+    let mut app = get_application();
+    let address = app.configuration_server.local_address();
+
+    let (status, body) = request(address, "POST", "/api/notify", r#"{"color": "00FF00", "pattern": "pulse"}"#);
+    assert_eq!(status, 202);
+    assert_eq!(body, json!({"notification": {"color": "00FF00", "pattern": "pulse", "duration": 5}}));
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::Notification);
+
+    let (status, body) = request(address, "POST", "/api/notify", r#"{"color": "00FF00", "duration": 120}"#);
+    assert_eq!(status, 400);
+    assert_eq!(body["error"], "Duration must be between 1 and 60 seconds");
+}
+
+#[test]
+fn changes_require_pin() {
+    let mut app = get_application();
+    app.set_access_point_security(AccessPointSecurity::Pin([4, 2, 0, 7]));
+    let address = app.configuration_server.local_address();
+
+    // Reading is still open
+    assert_eq!(request(address, "GET", "/api/config", "").0, 200);
+
+    let (status, body) = request(address, "POST", "/api/actions/off", "");
+    assert_eq!(status, 401);
+    assert_eq!(body["error"], "Wrong PIN, enter the code shown on the clock");
+    assert_eq!(request_with_pin(address, "PUT", "/api/config", "1234", r#"{"brightness": 30}"#).0, 401);
+    assert_eq!(request_with_pin(address, "POST", "/api/notify", "1234", r#"{"color": "00FF00"}"#).0, 401);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+    // The PIN to use is shown on the clock
+    assert_eq!(app.display.icon, Some(Icon::Pin([4, 2, 0, 7])));
+
+    assert_eq!(request_with_pin(address, "POST", "/api/actions/off", "4207", "").0, 202);
+    app.publish_event(Event::Tick);
+    app.run();
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayOff);
+}
+
+#[test]
+fn unknown_requests() {
+    let app = get_application();
+    let address = app.configuration_server.local_address();
+
+    assert_eq!(request(address, "GET", "/api/unknown", "").0, 404);
+    assert_eq!(request(address, "GET", "/index.html", "").0, 404);
+    assert_eq!(request(address, "DELETE", "/api/config", "").0, 405);
+    assert_eq!(request(address, "POST", "/api/actions/format", "").0, 404);
+}
+
+#[test]
+fn form_requests_are_refused() {
+    let mut app = get_application();
+    let address = app.configuration_server.local_address();
+
+    // As sent by a form of another web page
+    let form = "application/x-www-form-urlencoded";
+    assert_eq!(request_with_content_type(address, "POST", "/api/actions/off", form, "").0, 415);
+    assert_eq!(request_with_content_type(address, "PUT", "/api/config", form, "brightness=1").0, 415);
+    assert_eq!(request_with_content_type(address, "POST", "/api/notify", "text/plain", r#"{"color": "FF0000"}"#).0, 415);
+    app.publish_event(Event::Tick);
+    app.run();
+    assert_eq!(app.get_current_state(), State::DisplayTime);
+}
@@ -0,0 +1,22 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::fs;
+use std::path::Path;
+
+use application::behaviour::state_diagram_plantuml;
+
+/// The documented state diagram must be the one of the transition table.
+#[test]
+fn documented_state_diagram_is_up_to_date() {
+    let uml_file = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../doc/uml/1_problem_description/use_case/system_state.puml");
+    let documented = fs::read_to_string(uml_file).unwrap();
+
+    assert_eq!(
+        documented,
+        state_diagram_plantuml(),
+        "system_state.puml is outdated, run `cargo xtask state_uml`"
+    );
+}
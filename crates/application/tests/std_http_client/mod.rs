@@ -0,0 +1,119 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use application::firmware_update::{HttpClient, ResponseHead};
+
+/// Maximum number of header lines of a response
+const MAX_HEADER_LINES: usize = 64;
+
+/// Timeout of the connection and of each read
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HTTP client based on the standard library, for plain `http://` URLs
+///
+/// Used to test the firmware download on the host. Each request opens a new
+/// connection, closed once the response is received.
+#[derive(Default)]
+pub struct StdHttpClient {
+    response: Option<BufReader<TcpStream>>,
+    /// Bytes of the body left to read, `None` until the connection is closed
+    remaining: Option<usize>,
+}
+
+impl StdHttpClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Host, port and path of an `http://` URL
+fn split_url(url: &str) -> Result<(&str, u16, &str)> {
+    let Some(url) = url.strip_prefix("http://") else {
+        return Err(anyhow!("Unsupported URL {:?}, only http:// is supported", url));
+    };
+    let (authority, path) = match url.find('/') {
+        Some(start) => (&url[..start], &url[start..]),
+        None => (url, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse()?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(anyhow!("Missing host in URL {:?}", url));
+    }
+    Ok((host, port, path))
+}
+
+impl HttpClient for StdHttpClient {
+    fn get(&mut self, url: &str) -> Result<ResponseHead> {
+        let (host, port, path) = split_url(url)?;
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        write!(&stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = match line.split_whitespace().nth(1) {
+            Some(status) => status.parse()?,
+            None => return Err(anyhow!("Invalid status line {:?}", line)),
+        };
+
+        let mut content_length = None;
+        for _ in 0..MAX_HEADER_LINES {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                self.response = Some(reader);
+                self.remaining = content_length;
+                return Ok(ResponseHead { status, content_length });
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = Some(value.trim().parse()?);
+                } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                    return Err(anyhow!("Unsupported transfer encoding {}", value.trim()));
+                }
+            }
+        }
+        Err(anyhow!("Too many headers"))
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let Some(response) = self.response.as_mut() else {
+            return Err(anyhow!("No request sent"));
+        };
+        let length = match self.remaining {
+            Some(remaining) => buffer.len().min(remaining),
+            None => buffer.len(),
+        };
+        let length = response.read(&mut buffer[..length])?;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= length;
+        }
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_http_url() {
+        assert_eq!(split_url("http://192.168.1.10:8000/ota/manifest.json").unwrap(), ("192.168.1.10", 8000, "/ota/manifest.json"));
+        assert_eq!(split_url("http://mirror.lan").unwrap(), ("mirror.lan", 80, "/"));
+        assert!(split_url("https://raw.githubusercontent.com/manifest.json").is_err());
+        assert!(split_url("http://:80/").is_err());
+        assert!(split_url("http://mirror.lan:http/").is_err());
+    }
+}
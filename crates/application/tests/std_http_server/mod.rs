@@ -0,0 +1,210 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use log::*;
+
+use application::api::{handle_api_request, is_api_path, ApiAction, ApiResponse, ApiState, ApiStatus, MAX_API_BODY_LENGTH};
+use application::configuration::{Configuration, FieldError};
+use application::configuration_server::ConfigurationServer;
+use application::form_urlencoded::FormFields;
+use application::network::ScanResult;
+use application::notification::Notification;
+
+/// Maximum number of header lines of a request
+const MAX_HEADER_LINES: usize = 64;
+
+/// HTTP server based on the standard library, serving the REST API
+///
+/// Used to test the REST API on the host, with the same routing and JSON
+/// handling as on the device. The configuration form is not served, so the
+/// configuration mode never receives a configuration.
+pub struct StdHttpServer {
+    state: Arc<Mutex<ApiState>>,
+    address: SocketAddr,
+}
+
+/// Request line, content type and body of an HTTP request
+struct Request {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    pin: Option<String>,
+    body: Vec<u8>,
+}
+
+impl StdHttpServer {
+    /// Listen on `address`, use port 0 to get a free port. Each connection is
+    /// handled on its own thread.
+    pub fn new(address: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ApiState::new()));
+
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = shared.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = handle_connection(stream, &state) {
+                                warn!("Failed to handle HTTP request: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept HTTP connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { state, address })
+    }
+
+    /// Address the server listens on
+    pub fn local_address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Mutex<ApiState>) -> Result<()> {
+    let response = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) if is_api_path(&request.path) => {
+            info!("Processing '{} {}' request", request.method, request.path);
+            handle_api_request(
+                state,
+                &request.method,
+                &request.path,
+                request.content_type.as_deref(),
+                request.pin.as_deref(),
+                &request.body,
+            )
+        }
+        Ok(_) => ApiResponse { status: 404, body: String::from(r#"{"error":"Not found"}"#) },
+        Err(e) => {
+            warn!("Invalid HTTP request: {}", e);
+            ApiResponse { status: 400, body: String::from(r#"{"error":"Bad request"}"#) }
+        }
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason_phrase(response.status),
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(anyhow!("Invalid request line {:?}", line));
+    };
+    let (method, path) = (String::from(method), String::from(path));
+
+    let mut content_length = 0;
+    let mut content_type = None;
+    let mut pin = None;
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+            return Ok(Request { method, path, content_type, pin, body });
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+                if content_length > MAX_API_BODY_LENGTH {
+                    return Err(anyhow!("Request body too large"));
+                }
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = Some(String::from(value.trim()));
+            } else if name.eq_ignore_ascii_case("x-pin") {
+                pin = Some(String::from(value.trim()));
+            }
+        }
+    }
+    Err(anyhow!("Too many headers"))
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
+
+impl ConfigurationServer for StdHttpServer {
+    fn is_configuration_received(&self) -> bool {
+        false
+    }
+
+    fn get_configuration_fields(&mut self) -> Option<FormFields> {
+        None
+    }
+
+    fn report_configuration_errors(&mut self, _errors: Vec<FieldError>) {}
+
+    fn is_scan_requested(&mut self) -> bool {
+        false
+    }
+
+    fn set_scan_results(&mut self, _results: Vec<ScanResult>) {}
+
+    fn set_pin(&mut self, pin: Option<String>) {
+        self.state.lock().unwrap().pin = pin;
+    }
+
+    fn is_pin_requested(&mut self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().pin_requested)
+    }
+
+    fn set_settings(&mut self, configuration: Option<Configuration>) {
+        self.state.lock().unwrap().settings = configuration;
+    }
+
+    fn get_settings_fields(&mut self) -> Option<FormFields> {
+        self.state.lock().unwrap().settings_fields.take()
+    }
+
+    fn report_settings_errors(&mut self, errors: Vec<FieldError>) {
+        self.state.lock().unwrap().settings_errors = Some(errors);
+    }
+
+    fn set_status(&mut self, status: ApiStatus) {
+        self.state.lock().unwrap().status = Some(status);
+    }
+
+    fn get_action(&mut self) -> Option<ApiAction> {
+        self.state.lock().unwrap().actions.pop_front()
+    }
+
+    fn get_notification(&mut self) -> Option<Notification> {
+        self.state.lock().unwrap().notification.take()
+    }
+}
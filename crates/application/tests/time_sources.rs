@@ -7,7 +7,7 @@ use std::time::{Instant, Duration};
 use application::*;
 use application::time::Time;
 use application::time_monotonic::TimeMonotonic;
-use application::time_source::{TimeSource,TimeSourceError};
+use application::time_source::{TimeSource,TimeSourceError,TimeSourceHealth};
 use application::time_source_manager::{TimeSourceManager, CPU_SYNC_TIMEOUT, RTC_SYNC_TIMEOUT};
 
 const INITIAL_RTC_TIME: Time = Time{hour:1, minute:2, second:3};
@@ -21,13 +21,13 @@ struct MockMonotonicTime {
 
 impl MockMonotonicTime {
     fn elapsed(&mut self, duration: Duration) {
-        self.now = self.now + duration;
+        self.now += duration;
     }
 }
 
 impl TimeMonotonic for MockMonotonicTime {
     fn now(&self) -> std::time::Instant {
-        return self.now
+        self.now
     }
 }
 
@@ -35,12 +35,6 @@ struct MockTime {
     current: Time,
 }
 
-impl MockTime {
-    fn set_time(&mut self, time: Time) {
-        self.current = time;
-    }
-}
-
 impl time_source::TimeSource for MockTime {
     fn synchronize(&mut self) -> Result<(), TimeSourceError> {
         Ok(())
@@ -56,6 +50,22 @@ impl time_source::TimeSource for MockTime {
     }
 }
 
+struct UnreachableTime;
+
+impl time_source::TimeSource for UnreachableTime {
+    fn synchronize(&mut self) -> Result<(), TimeSourceError> {
+        Err(TimeSourceError::SynchronizationError)
+    }
+
+    fn get_time(&self) -> Result<Time, TimeSourceError> {
+        Err(TimeSourceError::NotSynchronized)
+    }
+
+    fn set_time(&mut self, _now: Time) -> Result<(), TimeSourceError> {
+        Err(TimeSourceError::NotAvailable)
+    }
+}
+
 mod board_time_only {
     use super::*;
 
@@ -231,6 +241,22 @@ mod board_and_network {
         assert_eq!(time_source_manager.get_time().unwrap(), INITIAL_NETWORK_TIME);
     }
 
+    #[test]
+    fn health() {
+        let mut time_source_manager = get_time_source_manager();
+        assert_eq!(
+            time_source_manager.health(),
+            TimeSourceHealth{synchronized: false, last_network_sync: None, board_synchronized: Some(false)}
+        );
+
+        time_source_manager.synchronize().unwrap();
+        time_source_manager.time_monotonic.elapsed(Duration::from_secs(42));
+        assert_eq!(
+            time_source_manager.health(),
+            TimeSourceHealth{synchronized: true, last_network_sync: Some(42), board_synchronized: Some(true)}
+        );
+    }
+
     fn get_time_source_manager() -> TimeSourceManager<MockMonotonicTime> {
         let sys_time:MockMonotonicTime = MockMonotonicTime{now:Instant::now()};
         let cpu_time = Box::new(MockTime{current:Time::new(0, 0, 0).unwrap()});
@@ -240,3 +266,38 @@ mod board_and_network {
         TimeSourceManager::new(sys_time, cpu_time, Some(board_time), Some(network_time))
     }
 }
+
+mod unreachable_network {
+    use super::*;
+
+    #[test]
+    fn fallback_to_board_time() {
+        let mut time_source_manager = get_time_source_manager(Some(INITIAL_RTC_TIME));
+
+        assert!(time_source_manager.synchronize().is_ok());
+        assert_eq!(time_source_manager.get_time().unwrap(), INITIAL_RTC_TIME);
+        // The board time can't be refreshed from the network
+        assert_eq!(time_source_manager.is_board_synchronized(), Some(false));
+        assert_eq!(
+            time_source_manager.health(),
+            TimeSourceHealth{synchronized: true, last_network_sync: None, board_synchronized: Some(false)}
+        );
+    }
+
+    #[test]
+    fn fail_without_board_time() {
+        let mut time_source_manager = get_time_source_manager(None);
+
+        assert_eq!(time_source_manager.synchronize(), Err(TimeSourceError::SynchronizationError));
+        assert_eq!(time_source_manager.get_time(), Err(TimeSourceError::NotSynchronized));
+    }
+
+    fn get_time_source_manager(board: Option<Time>) -> TimeSourceManager<MockMonotonicTime> {
+        let sys_time = MockMonotonicTime{now:Instant::now()};
+        let cpu_time = Box::new(MockTime{current:Time::new(0, 0, 0).unwrap()});
+        let board_time = board.map(|time| Box::new(MockTime{current:time}) as Box<dyn TimeSource>);
+        let network_time = Box::new(UnreachableTime);
+
+        TimeSourceManager::new(sys_time, cpu_time, board_time, Some(network_time))
+    }
+}
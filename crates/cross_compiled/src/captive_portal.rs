@@ -0,0 +1,59 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+
+use anyhow::Result;
+use log::*;
+
+use application::captive_portal::{dns_response, DNS_PORT, MAX_DNS_MESSAGE_LENGTH};
+use application::network::ACCESS_POINT_ADDRESS;
+
+/// Stack size of the DNS server thread
+const DNS_SERVER_STACK_SIZE: usize = 4096;
+
+/// Captive portal DNS server
+///
+/// Answer every A query of the access point clients with the clock address, so
+/// their connectivity check reaches the configuration page.
+pub fn start_dns_server() -> Result<()> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DNS_PORT))?;
+    thread::Builder::new()
+        .name(String::from("dns_server"))
+        .stack_size(DNS_SERVER_STACK_SIZE)
+        .spawn(move || serve(socket))?;
+
+    Ok(())
+}
+
+fn serve(socket: UdpSocket) {
+    let mut buffer = [0_u8; MAX_DNS_MESSAGE_LENGTH];
+    loop {
+        let (length, client) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("DNS server failed to receive: {}", e);
+                continue;
+            }
+        };
+        // Only answer the access point clients, not the home network
+        if !is_access_point_client(client) {
+            continue;
+        }
+
+        if let Some(response) = dns_response(&buffer[..length], ACCESS_POINT_ADDRESS) {
+            if let Err(e) = socket.send_to(&response, client) {
+                warn!("DNS server failed to answer {}: {}", client, e);
+            }
+        }
+    }
+}
+
+fn is_access_point_client(client: SocketAddr) -> bool {
+    match client.ip() {
+        IpAddr::V4(ip) => ip.octets()[..3] == ACCESS_POINT_ADDRESS[..3],
+        IpAddr::V6(_) => false,
+    }
+}
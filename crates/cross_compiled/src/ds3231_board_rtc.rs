@@ -38,6 +38,12 @@ impl<'a> Ds3231Rtc<'a> {
         Ds3231Rtc {i2c_master: Rc::new(RefCell::new(i2c_master))}
     }
 
+    /// Check the chip answers on the I2C bus
+    pub fn probe(&self) -> Result<()> {
+        self.read_register(DS3231_RTC_SECONDES_REG)?;
+        Ok(())
+    }
+
     pub fn set_time(&self, time: Time) -> Result<()> {
         self.write_register(DS3231_RTC_SECONDES_REG, Self::decimal_to_packed_bcd(time.second))?;
         self.write_register(DS3231_RTC_MINUTES_REG, Self::decimal_to_packed_bcd(time.minute))?;
@@ -2,11 +2,11 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use log::*;
 
-use esp_idf_sys::{self as _, esp_restart};
+use esp_idf_sys::{self as _, esp_restart, esp_timer_get_time};
 
 use application::power_manager::PowerManager;
 use application::time::Time;
@@ -21,6 +21,12 @@ impl PowerManager for Esp32Soc {
             esp_restart();
         }
     }
+
+    fn uptime(&self) -> Duration {
+        // Microseconds since boot
+        let uptime = unsafe { esp_timer_get_time() };
+        Duration::from_micros(uptime as u64)
+    }
 }
 
 pub struct Esp32SocSystemTime;
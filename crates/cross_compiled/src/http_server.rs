@@ -2,95 +2,450 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use log::*;
 
 use esp_idf_svc::http::server::{Configuration, EspHttpServer};
 use esp_idf_svc::http::server::EspHttpConnection;
-use embedded_svc::{http::server::Request, io::Write, utils::http::Headers};
+use embedded_svc::{http::server::Request, io::{Read, Write}, utils::http::Headers};
 
+use application::api::{handle_api_request, wait_settings_errors, ApiAction, ApiState, ApiStatus, MAX_API_BODY_LENGTH};
+use application::configuration::{self as clock_configuration, ConfigurationManager, FieldError, FORM_PIN_KEY};
+use application::configuration_backup::{backup_to_form_fields, Secrets, MAX_BACKUP_LENGTH};
 use application::configuration_server::ConfigurationServer;
-use application::configuration_form::CONFIGURATION_FORM;
+use application::configuration_form::{render_configuration_form, render_settings_form, CONFIGURATION_SUBMITTED};
+use application::form_urlencoded::{self, FormFields, FormParser, MAX_FORM_BODY_LENGTH};
+use application::captive_portal::CONNECTIVITY_CHECK_PATHS;
+use application::network::{scan_results_to_json, ScanResult, ACCESS_POINT_ADDRESS};
+use application::notification::Notification;
+
+use crate::persistent_settings::NonVolatileStorage;
+
+/// Size of the chunks read from the request body
+const FORM_BODY_CHUNK_LENGTH: usize = 128;
+/// Maximum time to wait for the application to validate the configuration,
+/// including the network connection check.
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum time to wait for the application to scan the visible networks
+const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct ServerGlobalData {
     pub configuration_received: bool,
-    pub uri: Option<String>,
+    pub fields: Option<FormFields>,
+    /// Validation result of the last configuration, set by the application.
+    pub errors: Option<Vec<FieldError>>,
+    /// Visible networks, scanned by the application on request.
+    pub scan_results: Vec<ScanResult>,
+    /// Number of scans done, to wait for the result of a new one
+    pub scan_count: u32,
+    pub scan_requested: bool,
+    /// The PIN shown on the display must be entered in the form.
+    pub pin_required: bool,
 }
 
-/// Global variable to store HTTP URI query string to be parsed later on
+/// Global variable to store the received form fields to be handled later on
 ///
 /// Only available on this module. Use "is_configuration_received()" and
-/// "get_config_uri()" for public access to the data.
+/// "get_configuration_fields()" for public access to the data.
 static GLOBAL_CONFIG_SERVER_STATE: Mutex<ServerGlobalData> = Mutex::new(ServerGlobalData {
     configuration_received: false,
-    uri: None,
+    fields: None,
+    errors: None,
+    scan_results: Vec::new(),
+    scan_count: 0,
+    scan_requested: false,
+    pin_required: false,
 });
 
+/// Settings, status and actions, shared by the settings page, the REST API and the MQTT bridge
+pub static GLOBAL_API_STATE: Mutex<ApiState> = Mutex::new(ApiState::new());
+
 /// HTTP server
 ///
-/// Provide a single home page, containing the WordClock configuration form.
-/// Handle the "/get" request when the "submit" button is pressed by the user.
+/// Provide a single home page, containing the WordClock configuration form in
+/// configuration mode, and the settings page once configured.
+/// Handle the "/config" POST request when the "submit" button is pressed by the user.
+/// Handle the "/settings" POST request to change the settings live.
+/// Handle "/backup" and "/restore" to download and upload the configuration as JSON.
+/// Serve the visible networks on "/networks", as JSON.
+/// Serve the REST API on "/api/...", see `doc/rest_api.md`.
+/// Redirect the connectivity checks of the operating systems to the home page.
 pub struct HttpServer {
     _server: EspHttpServer,
 }
 
 impl HttpServer {
     pub fn new() -> Result<Self> {
-        let mut server = EspHttpServer::new(&Configuration::default())?;
+        // Wildcards match the actions of the REST API
+        let mut server = EspHttpServer::new(&Configuration { uri_match_wildcard: true, ..Default::default() })?;
 
         server.fn_handler("/", embedded_svc::http::Method::Get, move |req| {
             home_page_handler(req)
         })?;
 
-        server.fn_handler("/get", embedded_svc::http::Method::Get, move |req| {get_handler(req)})?;
+        server.fn_handler("/config", embedded_svc::http::Method::Post, move |req| {config_handler(req)})?;
+        server.fn_handler("/settings", embedded_svc::http::Method::Post, move |req| {settings_handler(req)})?;
+        server.fn_handler("/networks", embedded_svc::http::Method::Get, move |req| {networks_handler(req)})?;
+        server.fn_handler("/backup", embedded_svc::http::Method::Get, move |req| {backup_handler(req)})?;
+        server.fn_handler("/restore", embedded_svc::http::Method::Post, move |req| {restore_handler(req)})?;
+
+        server.fn_handler("/api/status", embedded_svc::http::Method::Get, move |req| {api_handler(req, "GET")})?;
+        server.fn_handler("/api/config", embedded_svc::http::Method::Get, move |req| {api_handler(req, "GET")})?;
+        server.fn_handler("/api/config", embedded_svc::http::Method::Put, move |req| {api_handler(req, "PUT")})?;
+        server.fn_handler("/api/actions/*", embedded_svc::http::Method::Post, move |req| {api_handler(req, "POST")})?;
+        server.fn_handler("/api/notify", embedded_svc::http::Method::Post, move |req| {api_handler(req, "POST")})?;
+
+        for path in CONNECTIVITY_CHECK_PATHS {
+            server.fn_handler(path, embedded_svc::http::Method::Get, move |req| {connectivity_check_handler(req)})?;
+        }
 
         Ok(Self{_server: server})
     }
 }
 
-/// Display the configuration form on http "/" page request
+/// Display the configuration form, or the settings page once configured, on http "/" page request
+///
+/// The settings page asks for the PIN to be shown on the display.
 fn home_page_handler(req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
     let mut headers = Headers::<1>::new();
     headers.set_cache_control("no-store");
     
     info!("Processing '/' request");
+    let mut state = GLOBAL_API_STATE.lock().unwrap();
+    let pin_required = state.pin.is_some();
+    let page = match state.settings.clone() {
+        Some(configuration) => {
+            state.pin_requested = pin_required;
+            render_settings_form(&configuration.settings_fields(), &[], false, pin_required)
+        }
+        None => render_configuration_form(&[], &[], GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().pin_required),
+    };
+    drop(state);
+
     let mut response = req.into_response(200, None, headers.as_slice())?;
-    response.write_all(CONFIGURATION_FORM.as_bytes())?;
+    response.write_all(page.as_bytes())?;
+
+    Ok(())
+}
+
+/// Redirect a connectivity check to the home page, so the system opens the configuration page
+fn connectivity_check_handler(req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
+    info!("Processing connectivity check '{}'", req.uri());
+
+    let [a, b, c, d] = ACCESS_POINT_ADDRESS;
+    let location = format!("http://{}.{}.{}.{}/", a, b, c, d);
+    let headers = [("Location", location.as_str()), ("Cache-Control", "no-store")];
+    req.into_response(302, Some("Found"), &headers)?;
 
     Ok(())
 }
 
-/// Handle the "/get" request when user press the "submit" button in the configuration form
-fn get_handler(mut req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
-    info!("Processing '/get' request");
+/// Handle the "/config" request when user press the "submit" button in the configuration form
+///
+/// The form is sent as `application/x-www-form-urlencoded` body, parsed while it is read.
+fn config_handler(mut req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
+    info!("Processing '/config' request");
+
+    let mut parser = FormParser::new();
+    if read_body(&mut req, MAX_FORM_BODY_LENGTH, |chunk| parser.push(chunk)).is_err() {
+        req.into_status_response(413)?;
+        return Ok(());
+    }
+
+    submit_fields(req, parser.finish())
+}
+
+/// Handle the "/settings" request when the user saves the settings page
+///
+/// Only available once configured. The settings are applied by the application
+/// on its next tick, the answer is the settings page with the validation result.
+/// Without the PIN shown on the display, if any, the settings are refused.
+fn settings_handler(mut req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
+    info!("Processing '/settings' request");
+
+    if GLOBAL_API_STATE.lock().unwrap().settings.is_none() {
+        req.into_status_response(404)?;
+        return Ok(());
+    }
+
+    let mut parser = FormParser::new();
+    if read_body(&mut req, MAX_FORM_BODY_LENGTH, |chunk| parser.push(chunk)).is_err() {
+        req.into_status_response(413)?;
+        return Ok(());
+    }
+    let fields = parser.finish();
+
+    let mut state = GLOBAL_API_STATE.lock().unwrap();
+    let pin_required = state.pin.is_some();
+    let pin_check = state.check_pin(form_urlencoded::get(&fields, FORM_PIN_KEY));
+    if pin_check.is_ok() {
+        state.submit_settings(fields.clone());
+    }
+    drop(state);
+
+    let page = match pin_check.map(|()| wait_settings_errors(&GLOBAL_API_STATE, VALIDATION_TIMEOUT)) {
+        Err(error) => {
+            warn!("Settings refused: {}", error);
+            render_settings_form(&fields, &[error], false, pin_required)
+        }
+        Ok(Some(errors)) if errors.is_empty() => {
+            let settings = GLOBAL_API_STATE.lock().unwrap().settings.clone();
+            let fields = settings.map_or(fields, |configuration| configuration.settings_fields());
+            render_settings_form(&fields, &[], true, pin_required)
+        }
+        Ok(Some(errors)) => render_settings_form(&fields, &errors, false, pin_required),
+        Ok(None) => {
+            warn!("No settings validation result received");
+            GLOBAL_API_STATE.lock().unwrap().settings_fields = None;
+            render_settings_form(&fields, &[], false, pin_required)
+        }
+    };
 
     let mut headers = Headers::<1>::new();
     headers.set_cache_control("no-store");
+    let mut response = req.into_response(200, None, headers.as_slice())?;
+    response.write_all(page.as_bytes())?;
+
+    Ok(())
+}
+
+/// List the visible networks, strongest first
+///
+/// The networks are scanned on the first request, then on `/networks?rescan=1`
+/// only. Otherwise the result of the last scan is served. Scans are only done
+/// in configuration mode, the last results are served once configured instead
+/// of blocking the server until the scan timeout.
+fn networks_handler(req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
+    info!("Processing '/networks' request");
+
+    let query = req.uri().split_once('?').map_or("", |(_, query)| query);
+    let rescan = form_urlencoded::get(&form_urlencoded::parse(query), "rescan").is_some();
+    let configured = GLOBAL_API_STATE.lock().unwrap().settings.is_some();
+    let mut state = GLOBAL_CONFIG_SERVER_STATE.lock().unwrap();
+    if !configured && (rescan || state.scan_count == 0) {
+        let scan_count = state.scan_count;
+        state.scan_requested = true;
+        drop(state);
+
+        let start = Instant::now();
+        while GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().scan_count == scan_count && start.elapsed() < SCAN_TIMEOUT {
+            thread::sleep(Duration::from_millis(100));
+        }
+    } else {
+        drop(state);
+    }
+
+    let networks = scan_results_to_json(&GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().scan_results);
+    let headers = [("Content-Type", "application/json"), ("Cache-Control", "no-store")];
+    let mut response = req.into_response(200, None, &headers)?;
+    response.write_all(networks.as_bytes())?;
 
-    let uri = req.connection().uri();
-    info!("got {:?}", uri);
+    Ok(())
+}
+
+/// Download the stored configuration as JSON backup
+///
+/// The passwords are always redacted: the page is served without
+/// authentication, on the configuration access point or the home network.
+fn backup_handler(req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
+    info!("Processing '/backup' request");
 
-    // do not perform memory intensive parsing here. Store the value in a global
-    // variable to be handled by the main thread.
-    GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().uri = Some(uri.to_string());
-    GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().configuration_received = true;
+    let configuration = ConfigurationManager::new(NonVolatileStorage).load_from_persistent_storage();
+    let Ok(backup) = configuration.to_json(Secrets::Redact) else {
+        req.into_status_response(404)?;
+        return Ok(());
+    };
 
-    let mut response = req.into_ok_response()?;
-    response.write_all("".as_bytes())?;
+    let headers = [
+        ("Content-Type", "application/json"),
+        ("Content-Disposition", "attachment; filename=\"wordclock-configuration.json\""),
+        ("Cache-Control", "no-store"),
+    ];
+    let mut response = req.into_response(200, None, &headers)?;
+    response.write_all(backup.as_bytes())?;
 
     Ok(())
 }
 
+/// Restore a JSON backup, validated like a submitted configuration form
+///
+/// The PIN, if required, is given as `/restore?input_pin=1234`. The redacted
+/// WiFi passwords are given the same way, as SSID and password fields.
+fn restore_handler(mut req: Request<&mut EspHttpConnection>) -> embedded_svc::http::server::HandlerResult {
+    info!("Processing '/restore' request");
+
+    let mut body = Vec::new();
+    if read_body(&mut req, MAX_BACKUP_LENGTH, |chunk| body.extend_from_slice(chunk)).is_err() {
+        req.into_status_response(413)?;
+        return Ok(());
+    }
+
+    let query = req.uri().split_once('?').map_or("", |(_, query)| query);
+    let query_fields = form_urlencoded::parse(query);
+    let pin = form_urlencoded::get(&query_fields, FORM_PIN_KEY).map(String::from);
+
+    // A redacted password not given with the backup is restored from the stored configuration
+    let current = ConfigurationManager::new(NonVolatileStorage).load_from_persistent_storage();
+    match backup_to_form_fields(&String::from_utf8_lossy(&body), &current, &query_fields) {
+        Ok(mut fields) => {
+            if let Some(pin) = pin {
+                fields.push((String::from(FORM_PIN_KEY), pin));
+            }
+            submit_fields(req, fields)
+        }
+        Err(e) => {
+            warn!("Invalid configuration backup: {}", e);
+            req.into_status_response(400)?;
+            Ok(())
+        }
+    }
+}
+
+/// Handle a request of the REST API, the routing and JSON handling are shared
+/// with the host tests.
+fn api_handler(mut req: Request<&mut EspHttpConnection>, method: &str) -> embedded_svc::http::server::HandlerResult {
+    let path = req.uri().to_string();
+    info!("Processing '{} {}' request", method, path);
+
+    let content_type = req.content_type().map(String::from);
+    let pin = req.header("X-Pin").map(String::from);
+    let mut body = Vec::new();
+    if read_body(&mut req, MAX_API_BODY_LENGTH, |chunk| body.extend_from_slice(chunk)).is_err() {
+        req.into_status_response(413)?;
+        return Ok(());
+    }
+
+    let api_response = handle_api_request(&GLOBAL_API_STATE, method, &path, content_type.as_deref(), pin.as_deref(), &body);
+    let headers = [("Content-Type", "application/json"), ("Cache-Control", "no-store")];
+    let mut response = req.into_response(api_response.status, None, &headers)?;
+    response.write_all(api_response.body.as_bytes())?;
+
+    Ok(())
+}
+
+/// Read the request body by chunks, up to `max_length` bytes.
+fn read_body(req: &mut Request<&mut EspHttpConnection>, max_length: usize, mut handle_chunk: impl FnMut(&[u8])) -> anyhow::Result<()> {
+    let mut buffer = [0_u8; FORM_BODY_CHUNK_LENGTH];
+    let mut body_length = 0;
+    loop {
+        let length = req.read(&mut buffer)?;
+        if length == 0 {
+            return Ok(());
+        }
+        body_length += length;
+        if body_length > max_length {
+            warn!("Request body too large");
+            return Err(anyhow::anyhow!("Request body too large"));
+        }
+        handle_chunk(&buffer[..length]);
+    }
+}
+
+/// Hand over the fields to the application, and answer with its validation result.
+///
+/// Only available in configuration mode, use the settings page once configured.
+fn submit_fields(req: Request<&mut EspHttpConnection>, fields: FormFields) -> embedded_svc::http::server::HandlerResult {
+    // Store the fields in a global variable to be handled by the main thread,
+    // then wait for its verdict.
+    if GLOBAL_API_STATE.lock().unwrap().settings.is_some() {
+        req.into_status_response(409)?;
+        return Ok(());
+    }
+    let mut state = GLOBAL_CONFIG_SERVER_STATE.lock().unwrap();
+    state.fields = Some(fields.clone());
+    state.errors = None;
+    state.configuration_received = true;
+    drop(state);
+
+    let pin_required = GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().pin_required;
+    let page = match wait_for(|state| state.errors.take()) {
+        Some(errors) if errors.is_empty() => String::from(CONFIGURATION_SUBMITTED),
+        Some(errors) => render_configuration_form(&fields, &errors, pin_required),
+        None => {
+            warn!("No validation result received");
+            render_configuration_form(&fields, &[], pin_required)
+        }
+    };
+
+    let mut headers = Headers::<1>::new();
+    headers.set_cache_control("no-store");
+    let mut response = req.into_response(200, None, headers.as_slice())?;
+    response.write_all(page.as_bytes())?;
+
+    Ok(())
+}
+
+/// Wait for the application to report a validation result, taken from the
+/// state by `take_result`, or `VALIDATION_TIMEOUT`.
+fn wait_for(take_result: impl Fn(&mut ServerGlobalData) -> Option<Vec<FieldError>>) -> Option<Vec<FieldError>> {
+    let start = Instant::now();
+    while start.elapsed() < VALIDATION_TIMEOUT {
+        if let Some(errors) = take_result(&mut GLOBAL_CONFIG_SERVER_STATE.lock().unwrap()) {
+            return Some(errors);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    None
+}
+
 impl ConfigurationServer for HttpServer {
     fn is_configuration_received(&self) -> bool {
         GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().configuration_received
     }
 
-    fn get_config_uri(&mut self) -> Option<String> {
-        let uri = GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().uri.clone();
-        GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().uri = None;
-        GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().configuration_received = false;
-        uri
+    fn get_configuration_fields(&mut self) -> Option<FormFields> {
+        let mut state = GLOBAL_CONFIG_SERVER_STATE.lock().unwrap();
+        state.configuration_received = false;
+        state.fields.take()
     }
-}
\ No newline at end of file
+
+    fn report_configuration_errors(&mut self, errors: Vec<FieldError>) {
+        GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().errors = Some(errors);
+    }
+
+    fn is_scan_requested(&mut self) -> bool {
+        std::mem::take(&mut GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().scan_requested)
+    }
+
+    fn set_scan_results(&mut self, results: Vec<ScanResult>) {
+        let mut state = GLOBAL_CONFIG_SERVER_STATE.lock().unwrap();
+        state.scan_results = results;
+        state.scan_count = state.scan_count.wrapping_add(1);
+    }
+
+    fn set_pin(&mut self, pin: Option<String>) {
+        GLOBAL_CONFIG_SERVER_STATE.lock().unwrap().pin_required = pin.is_some();
+        GLOBAL_API_STATE.lock().unwrap().pin = pin;
+    }
+
+    fn is_pin_requested(&mut self) -> bool {
+        std::mem::take(&mut GLOBAL_API_STATE.lock().unwrap().pin_requested)
+    }
+
+    fn set_settings(&mut self, configuration: Option<clock_configuration::Configuration>) {
+        GLOBAL_API_STATE.lock().unwrap().settings = configuration;
+    }
+
+    fn get_settings_fields(&mut self) -> Option<FormFields> {
+        GLOBAL_API_STATE.lock().unwrap().settings_fields.take()
+    }
+
+    fn report_settings_errors(&mut self, errors: Vec<FieldError>) {
+        GLOBAL_API_STATE.lock().unwrap().settings_errors = Some(errors);
+    }
+
+    fn set_status(&mut self, status: ApiStatus) {
+        GLOBAL_API_STATE.lock().unwrap().status = Some(status);
+    }
+
+    fn get_action(&mut self) -> Option<ApiAction> {
+        GLOBAL_API_STATE.lock().unwrap().actions.pop_front()
+    }
+
+    fn get_notification(&mut self) -> Option<Notification> {
+        GLOBAL_API_STATE.lock().unwrap().notification.take()
+    }
+}
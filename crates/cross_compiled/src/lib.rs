@@ -2,12 +2,18 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
+/// Revision of the clock hardware, advertised with mDNS
+pub use application::firmware_update::HARDWARE_REVISION;
+
+pub mod captive_portal;
 pub mod esp32_soc;
 pub mod ds3231_board_rtc;
 pub mod ota_update;
 pub mod led_driver;
 pub mod http_server;
+pub mod mdns;
 pub mod network;
 pub mod network_time;
 pub mod persistent_settings;
+pub mod push_button;
 pub mod rgb_led_strip_matrix;
\ No newline at end of file
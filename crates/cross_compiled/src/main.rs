@@ -29,13 +29,19 @@ use esp_idf_svc::systime::EspSystemTime;
 
 use application::Application;
 use application::behaviour::*;
+use application::boot_validation;
 use application::build_version::BUILD_VERSION_STRING;
-use application::network::Network;
+use application::button_input::{Button, ButtonInput, ButtonTimings};
+use application::display::Display;
+use application::error_recovery::ErrorKind;
+use application::mqtt_bridge::MqttBridge;
+use application::network::{setup_configuration_access_point, AccessPointSecurity, Network};
 use application::time_source_manager::TimeSourceManager;
 use application::version::Version;
 
 use cross_compiled::esp32_soc::Esp32Soc;
 use cross_compiled::esp32_soc::Esp32SocSystemTime;
+use cross_compiled::captive_portal;
 use cross_compiled::ds3231_board_rtc::Ds3231Rtc;
 use cross_compiled::led_driver::WS2812;
 use cross_compiled::http_server;
@@ -43,9 +49,15 @@ use cross_compiled::network;
 use cross_compiled::network_time;
 use cross_compiled::ota_update::OtaUpdate;
 use cross_compiled::persistent_settings::NonVolatileStorage;
+use cross_compiled::push_button::ActiveLowButton;
 use cross_compiled::rgb_led_strip_matrix;
 
-const ACCESS_POINT_NAME: &str = "WordClock Configuration";
+/// Main loop period. Short enough to sample the push-buttons properly.
+const MAIN_LOOP_PERIOD: Duration = Duration::from_millis(10);
+/// Number of main loop iterations in 100ms, used for slower periodic tasks.
+const MAIN_LOOP_ITERATIONS_PER_100MS: u32 = 10;
+/// Stack size of the MQTT bridge thread, the discovery messages are built on the heap
+const MQTT_STACK_SIZE: usize = 8192;
 
 fn main() -> Result<()> {
     // It is necessary to call this function once. Otherwise some patches to the runtime
@@ -53,20 +65,27 @@ fn main() -> Result<()> {
     esp_idf_sys::link_patches();
 
     esp_idf_svc::log::EspLogger::initialize_default();
-    info!("WordClock firmware ({}) - ESP32!", Version::from_string(BUILD_VERSION_STRING)?);
+    let version = Version::from_string(BUILD_VERSION_STRING)?;
+    info!("WordClock firmware ({}) - ESP32!", version);
 
     let peripherals = Peripherals::take().unwrap();
     let led = PinDriver::output(peripherals.pins.gpio2)?;
     let mut heart_beat = HearthBeat::new(led);
 
-    let push_button = PinDriver::input(peripherals.pins.gpio0)?;
+    let enter_button = ActiveLowButton::new(PinDriver::input(peripherals.pins.gpio0)?);
+    let mut button_input = ButtonInput::new(Esp32SocSystemTime::new());
+    button_input.add_button(Button::Enter, Box::new(enter_button), ButtonTimings::default());
 
+    // The clock can't show the time without its display and RTC, the
+    // application halts on a hardware error once started.
     let led_driver = WS2812::new(114, peripherals.pins.gpio15, peripherals.rmt.channel0)?;
-    let display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver)?;
+    let mut display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver);
+    let mut hardware_ready = display.clear().inspect_err(|e| error!("Display not responding: {}", e)).is_ok();
 
     let i2c_config = I2cConfig::new().baudrate(100.kHz().into());
     let i2c_master = I2cDriver::new(peripherals.i2c0, peripherals.pins.gpio21, peripherals.pins.gpio22, &i2c_config)?;
     let board_time = Box::new(Ds3231Rtc::new(i2c_master));
+    hardware_ready &= board_time.probe().inspect_err(|e| error!("RTC not responding: {}", e)).is_ok();
 
     let mut network = network::WifiNetwork::new(peripherals.modem)?;
 
@@ -83,8 +102,21 @@ fn main() -> Result<()> {
     // partition fails due to an invalid NVS handle in the WiFi driver.
     // Start the WiFi soft AP here to provide an already configured network to the application.
     // The application code can switch to Station mode afterward without issue.
-    network.setup_access_point(ACCESS_POINT_NAME)?;
+    let access_point_security = access_point_security()?;
+    setup_configuration_access_point(&mut network, &access_point_security)?;
+
+    // The bootloader rolls an updated image back if it resets before showing
+    // the time, whatever the failing step. The NVS only names the images.
+    let image_state = boot_validation::validate_boot(&mut NonVolatileStorage, &OtaUpdate, &version);
+    info!("Running image {}: {:?}", version, image_state);
+
     let http = http_server::HttpServer::new()?;
+    captive_portal::start_dns_server()?;
+    // Idle until a broker is set in the settings
+    thread::Builder::new()
+        .name(String::from("mqtt"))
+        .stack_size(MQTT_STACK_SIZE)
+        .spawn(|| MqttBridge::new().run(&http_server::GLOBAL_API_STATE))?;
 
     let system_time = Esp32SocSystemTime::new();
     let cpu_time = Box::new(Esp32SocCpuTime::new());
@@ -95,9 +127,14 @@ fn main() -> Result<()> {
     let power_manager = Esp32Soc;
     let firmware_update = OtaUpdate;
     let mut application = Application::new(display, time_source, persistent_storage, network, http, power_manager, firmware_update);
+    application.set_access_point_security(access_point_security);
+    application.set_image_state(image_state);
 
     application.publish_event(Event::Init);
     application.run();
+    if !hardware_ready {
+        application.publish_event(Event::Error(ErrorKind::Hardware));
+    }
 
     // Display check
     // for n in 0..1 {
@@ -108,29 +145,30 @@ fn main() -> Result<()> {
     // }
 
     let mut tick_counter:u32 = 0;
-    let mut push_duration:u32;
+    let mut loop_counter:u32 = 0;
 
     loop {
         application.run();
-        heart_beat.run();
 
-        // Blocking "Enter" push-button detection
-        if push_button.is_low() {
-            push_duration = 0;
-
-            while push_button.is_low() {
-                thread::sleep(Duration::from_millis(100));
-                push_duration += 100;
-            }
+        for event in button_input.poll() {
+            application.publish_event(event);
+        }
 
-            if push_duration < 2000 {
-                application.publish_event(Event::EnterShortPush);
-            } else {
-                application.publish_event(Event::EnterLongPush);
-            }
+        loop_counter += 1;
+        if loop_counter < MAIN_LOOP_ITERATIONS_PER_100MS {
+            thread::sleep(MAIN_LOOP_PERIOD);
+            continue;
         }
+        loop_counter = 0;
+
+        heart_beat.run();
 
-        if application.get_current_state() == State::DisplayTime {
+        // Configuration and Error states need ticks to poll and count down their timeouts
+        let state = application.get_current_state();
+        if state == State::Notification {
+            // Animate the notification pattern every 100ms
+            application.publish_event(Event::Tick);
+        } else if matches!(state, State::DisplayTime | State::NightMode | State::DisplayOff | State::Configuration | State::Error) {
             if tick_counter >=10 {
                 tick_counter = 0;
                 application.publish_event(Event::Tick);
@@ -142,8 +180,19 @@ fn main() -> Result<()> {
             }
         }
 
-        // Main loop iterate every 100ms
-        thread::sleep(Duration::from_millis(100));
+        thread::sleep(MAIN_LOOP_PERIOD);
+    }
+}
+
+/// Protection of the configuration access point, selected at build time
+///
+/// `WORDCLOCK_AP_PASSPHRASE` protects the access point with WPA2. Otherwise,
+/// `WORDCLOCK_AP_PIN` asks for a random PIN shown on the display.
+fn access_point_security() -> Result<AccessPointSecurity> {
+    match (option_env!("WORDCLOCK_AP_PASSPHRASE"), option_env!("WORDCLOCK_AP_PIN")) {
+        (Some(passphrase), _) => AccessPointSecurity::passphrase(passphrase),
+        (None, Some(_)) => Ok(AccessPointSecurity::pin(unsafe { esp_idf_sys::esp_random() })),
+        (None, None) => Ok(AccessPointSecurity::Open),
     }
 }
 
@@ -0,0 +1,87 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+use log::*;
+
+use application::mdns::{MdnsResponder, MAX_MDNS_MESSAGE_LENGTH, MDNS_MULTICAST_ADDRESS, MDNS_PORT};
+
+/// Stack size of the mDNS responder thread, the receive buffer is on the heap
+const MDNS_STACK_SIZE: usize = 6144;
+/// Multicast messages must be sent with a TTL of 255 (RFC 6762 section 11)
+const MDNS_MULTICAST_TTL: u32 = 255;
+
+/// mDNS responder, answering the queries on the home network
+///
+/// The records are set by `advertise()`, nothing is answered before.
+pub struct MdnsService {
+    socket: UdpSocket,
+    responder: Arc<Mutex<Option<MdnsResponder>>>,
+}
+
+impl MdnsService {
+    /// Join the mDNS multicast group, to be called once connected to the home network.
+    pub fn start() -> Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MDNS_PORT))?;
+        socket.join_multicast_v4(&Ipv4Addr::from(MDNS_MULTICAST_ADDRESS), &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_ttl_v4(MDNS_MULTICAST_TTL)?;
+
+        let responder = Arc::new(Mutex::new(None));
+        let (server_socket, server_responder) = (socket.try_clone()?, responder.clone());
+        thread::Builder::new()
+            .name(String::from("mdns"))
+            .stack_size(MDNS_STACK_SIZE)
+            .spawn(move || serve(server_socket, &server_responder))?;
+
+        Ok(Self { socket, responder })
+    }
+
+    /// Answer with the records of `responder` from now on, and announce them.
+    /// The records of a previous host name are withdrawn first.
+    pub fn advertise(&self, responder: MdnsResponder) -> Result<()> {
+        let announcement = responder.announcement();
+        let hostname = String::from(responder.hostname());
+        let previous = self.responder.lock().unwrap().replace(responder);
+
+        if let Some(previous) = previous.filter(|previous| previous.hostname() != hostname) {
+            info!("Withdraw {}.local", previous.hostname());
+            self.socket.send_to(&previous.goodbye(), multicast_group())?;
+        }
+        info!("Advertise {}.local", hostname);
+        self.socket.send_to(&announcement, multicast_group())?;
+        Ok(())
+    }
+}
+
+fn serve(socket: UdpSocket, responder: &Mutex<Option<MdnsResponder>>) {
+    let mut buffer = vec![0_u8; MAX_MDNS_MESSAGE_LENGTH];
+    loop {
+        let (length, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("mDNS responder failed to receive: {}", e);
+                continue;
+            }
+        };
+
+        let response = match responder.lock().unwrap().as_ref() {
+            Some(responder) => responder.response(&buffer[..length], source.port()),
+            None => None,
+        };
+        if let Some(response) = response {
+            let destination = if response.unicast { source } else { multicast_group() };
+            if let Err(e) = socket.send_to(&response.message, destination) {
+                warn!("mDNS responder failed to answer {}: {}", source, e);
+            }
+        }
+    }
+}
+
+fn multicast_group() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::from(MDNS_MULTICAST_ADDRESS)), MDNS_PORT)
+}
@@ -6,7 +6,7 @@ use std::thread;
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use esp_idf_sys::EspError;
+use esp_idf_sys::{esp, esp_mac_type_t_ESP_MAC_WIFI_SOFTAP, esp_read_mac, esp_wifi_sta_get_ap_info, wifi_ap_record_t, EspError};
 use log::*;
 
 use embedded_svc::wifi;
@@ -16,57 +16,38 @@ use esp_idf_svc::wifi::*;
 use esp_idf_svc::eventloop::*;
 use esp_idf_svc::nvs::*;
 
-use application::network::Network;
+use application::build_version::BUILD_VERSION_STRING;
+use application::mdns::MdnsResponder;
+use application::network::{Network, NetworkError, ScanResult, WifiSecurity};
+
+use crate::mdns::MdnsService;
+use crate::HARDWARE_REVISION;
+
+/// Connection attempts before giving up
+const CONNECT_ATTEMPTS: u32 = 5;
+/// Port of the settings page, advertised with mDNS
+const HTTP_PORT: u16 = 80;
 
 pub struct WifiNetwork<'a> {
     ssid: Option<String>,
     password: Option<String>,
+    /// Configuration of the active access point
+    access_point: Option<wifi::AccessPointConfiguration>,
+    /// Started on the first advertisement, once connected
+    mdns: Option<MdnsService>,
     wifi: EspWifi<'a>,
 }
 
-#[derive(Debug)]
-enum WifiErrors {
-    CouldNotFindNetwork,
-}
-
-impl std::fmt::Display for WifiErrors {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::CouldNotFindNetwork => write!(f, "Could not find Network"),
-        }
-    }
-}
-
 impl<'a> WifiNetwork<'a> {
     pub fn new(modem: Modem) -> Result<Self, EspError> {
         let sys_loop_stack = EspSystemEventLoop::take()?;
         let nvs = EspDefaultNvsPartition::take().ok();
         let wifi = EspWifi::new(modem, sys_loop_stack, nvs)?;
-        Ok(Self { ssid:None, password:None, wifi })
+        Ok(Self { ssid:None, password:None, access_point:None, mdns:None, wifi })
     }
 
-    pub fn setup_and_connect(&mut self, ssid: &str, password: &str) -> Result<()> {
-        let scan_result = self.wifi.scan()?;
-        let home_network = scan_result.into_iter().find(|a| a.ssid == ssid);
-        if home_network.is_some() {
-            info!("Detected home network {:?}", ssid);
-        } else {
-            error!("Error: Failed to detect home network {:?}", ssid);
-            return Err(anyhow!(WifiErrors::CouldNotFindNetwork));
-        }
-
-        self.wifi
-            .set_configuration(&wifi::Configuration::Client(wifi::ClientConfiguration {
-                ssid: ssid.into(),
-                password: password.into(),
-                // channel,
-                ..Default::default()
-            }))?;
-
-        self.wifi.start()?;
-        self.wifi.connect()?;
-
-        Ok(())
+    fn is_network_visible(&mut self, ssid: &str) -> Result<bool> {
+        Ok(self.scan()?.into_iter().any(|network| network.ssid == ssid))
     }
 
     pub fn fake_connect(&mut self) -> Result<()> {
@@ -83,32 +64,51 @@ impl<'a> Network for WifiNetwork<'a> {
         self.ssid = Some(String::from(ssid));
         self.password = Some(String::from(password));
 
-        self.wifi
-            .set_configuration(&wifi::Configuration::Client(wifi::ClientConfiguration {
-                ssid: ssid.into(),
-                password: password.into(),
-                // channel,
-                ..Default::default()
-            }))?;
+        let client = wifi::ClientConfiguration {
+            ssid: ssid.into(),
+            password: password.into(),
+            // channel,
+            ..Default::default()
+        };
+        // Keep the access point up, the user may wait for the result of the connection
+        match &self.access_point {
+            Some(access_point) => self.wifi.set_configuration(&wifi::Configuration::Mixed(
+                client,
+                access_point.clone(),
+            ))?,
+            None => self.wifi.set_configuration(&wifi::Configuration::Client(client))?,
+        }
 
         self.wifi.start()?;
 
         Ok(())
     }
 
+    /// Connect to the configured network, with a bounded number of attempts.
+    ///
+    /// Return `NetworkError::NotFound` if the network is not visible, or
+    /// `NetworkError::AuthenticationFailed` if it is visible but refuses the connection.
     fn connect(&mut self) -> Result<()> {
-        self.wifi.connect()?;
-        thread::sleep(Duration::from_millis(2000));
+        for _ in 0..CONNECT_ATTEMPTS {
+            self.wifi.connect()?;
+            thread::sleep(Duration::from_millis(2000));
+            if self.is_connected() {
+                return Ok(());
+            }
 
-        while self.is_connected() == false {
             info!("Waiting for network connection...");
             self.wifi.disconnect()?;
             thread::sleep(Duration::from_millis(500));
-            self.wifi.connect()?;
-            thread::sleep(Duration::from_millis(2000));
         }
 
-        Ok(())
+        let ssid = self.ssid.clone().unwrap_or_default();
+        if self.is_network_visible(&ssid)? {
+            error!("Network {:?} refused the connection", ssid);
+            Err(anyhow!(NetworkError::AuthenticationFailed))
+        } else {
+            error!("Failed to detect network {:?}", ssid);
+            Err(anyhow!(NetworkError::NotFound))
+        }
     }
 
     fn disconnect(&mut self) -> Result<()> {
@@ -127,14 +127,94 @@ impl<'a> Network for WifiNetwork<'a> {
         }
     }
 
-    fn setup_access_point(&mut self, ssid: &str) -> Result<()> {
-        self.wifi.set_configuration(&wifi::Configuration::AccessPoint(wifi::AccessPointConfiguration {
-            ssid: ssid.into(),
-            ..Default::default()
-        }))?;
+    /// Scan the visible networks, starting the WiFi driver if needed.
+    fn scan(&mut self) -> Result<Vec<ScanResult>> {
+        if !self.wifi.is_started()? {
+            self.wifi.set_configuration(&wifi::Configuration::Client(Default::default()))?;
+            self.wifi.start()?;
+        }
+
+        let scan_result = self.wifi.scan()?;
+        Ok(scan_result
+            .into_iter()
+            .map(|access_point| ScanResult {
+                ssid: access_point.ssid.to_string(),
+                rssi: access_point.signal_strength,
+                security: wifi_security(access_point.auth_method),
+            })
+            .collect())
+    }
+
+    /// The station interface stays enabled, to scan the networks from the configuration mode.
+    fn setup_access_point(&mut self, ssid: &str, passphrase: Option<&str>) -> Result<()> {
+        let access_point = access_point_configuration(ssid, passphrase);
+        self.wifi.set_configuration(&wifi::Configuration::Mixed(
+            Default::default(),
+            access_point.clone(),
+        ))?;
+        self.access_point = Some(access_point);
 
         self.wifi.start()?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn stop_access_point(&mut self) -> Result<()> {
+        if self.access_point.take().is_none() {
+            return Ok(());
+        }
+
+        let ssid = self.ssid.clone().unwrap_or_default();
+        let password = self.password.clone().unwrap_or_default();
+        self.configure(&ssid, &password)
+    }
+
+    fn mac_address(&self) -> Result<[u8; 6]> {
+        let mut mac_address = [0_u8; 6];
+        esp!(unsafe { esp_read_mac(mac_address.as_mut_ptr(), esp_mac_type_t_ESP_MAC_WIFI_SOFTAP) })?;
+        Ok(mac_address)
+    }
+
+    fn rssi(&self) -> Result<i8> {
+        let mut access_point: wifi_ap_record_t = Default::default();
+        esp!(unsafe { esp_wifi_sta_get_ap_info(&mut access_point) })?;
+        Ok(access_point.rssi)
+    }
+
+    /// Advertise the station address, the name is also given to the DHCP server.
+    fn advertise(&mut self, hostname: &str) -> Result<()> {
+        self.wifi.sta_netif_mut().set_hostname(hostname)?;
+        let address = self.wifi.sta_netif().get_ip_info()?.ip;
+
+        let txt = [("version", BUILD_VERSION_STRING), ("hardware", HARDWARE_REVISION), ("path", "/")];
+        let responder = MdnsResponder::new(hostname, address.octets(), HTTP_PORT, &txt);
+        if self.mdns.is_none() {
+            self.mdns = Some(MdnsService::start()?);
+        }
+        self.mdns.as_ref().unwrap().advertise(responder)
+    }
+}
+
+fn access_point_configuration(ssid: &str, passphrase: Option<&str>) -> wifi::AccessPointConfiguration {
+    match passphrase {
+        Some(passphrase) => wifi::AccessPointConfiguration {
+            ssid: ssid.into(),
+            password: passphrase.into(),
+            auth_method: wifi::AuthMethod::WPA2Personal,
+            ..Default::default()
+        },
+        None => wifi::AccessPointConfiguration {
+            ssid: ssid.into(),
+            ..Default::default()
+        },
+    }
+}
+
+fn wifi_security(auth_method: wifi::AuthMethod) -> WifiSecurity {
+    match auth_method {
+        wifi::AuthMethod::None => WifiSecurity::Open,
+        wifi::AuthMethod::WEP => WifiSecurity::Wep,
+        wifi::AuthMethod::WPA2Enterprise => WifiSecurity::Enterprise,
+        _ => WifiSecurity::Wpa,
+    }
+}
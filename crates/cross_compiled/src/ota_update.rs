@@ -6,316 +6,153 @@
  * 
  * Copyright (c) 2023 Louis Mayencourt
  */
-use core::mem;
 use core::ptr;
+use std::ffi::CStr;
 
 use anyhow::{anyhow, Result};
 use log::*; 
 
-use embedded_svc::ota::{FirmwareInfo, FirmwareInfoLoader, LoadResult, Slot};
 use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
-use esp_idf_svc::ota::{EspFirmwareInfoLoader, EspOta};
 use esp_idf_sys::*;
 
-use application::firmware_update::FirmwareUpdate;
-use application::version::Version;
+use application::firmware_update::{FirmwareSink, FirmwareUpdate, HttpClient, ResponseHead};
+use application::ota_download::{download_image, read_manifest, DOWNLOAD_BUFFER_SIZE};
+use application::ota_manifest::{FirmwareImage, OtaManifest};
+use application::ota_signature::PublicKey;
 
-/// HTTP read buffer size
-const WRITE_DATA_BUF_SIZE: usize = 8196;
 /// HTTP transmit buffer size
 const TX_BUF_SIZE: usize = 4096;
 
-/// URL of the update version file hosted in GitHub
-/// The file contains the version of the latest update available to download.
-const OTA_VERSION_FILE_URL: &str = "https://raw.githubusercontent.com/lmayencourt/wordclock/released/ota-image/hardware-v2/version.txt?";
+/// Public key of the release signing key, set at build time
+const OTA_PUBLIC_KEY: Option<&str> = option_env!("WORDCLOCK_OTA_PUBLIC_KEY");
 
-/// URL of the update firmware files hosted in GitHub
-const OTA_FIRMWARE_FILE_URL: &str = "https://raw.githubusercontent.com/lmayencourt/wordclock/released/ota-image/hardware-v2/firmware-ota.bin?";
-
-/// Root CA certificate used for TLS connection.
-const ROOT_CA_CERTIFICATE: &[u8;1391] = b" \
- -----BEGIN CERTIFICATE-----\n \
- MIIDxTCCAq2gAwIBAgIQAqxcJmoLQJuPC3nyrkYldzANBgkqhkiG9w0BAQUFADBs\n \
- MQswCQYDVQQGEwJVUzEVMBMGA1UEChMMRGlnaUNlcnQgSW5jMRkwFwYDVQQLExB3\n \
- d3cuZGlnaWNlcnQuY29tMSswKQYDVQQDEyJEaWdpQ2VydCBIaWdoIEFzc3VyYW5j\n \
- ZSBFViBSb290IENBMB4XDTA2MTExMDAwMDAwMFoXDTMxMTExMDAwMDAwMFowbDEL\n \
- MAkGA1UEBhMCVVMxFTATBgNVBAoTDERpZ2lDZXJ0IEluYzEZMBcGA1UECxMQd3d3\n \
- LmRpZ2ljZXJ0LmNvbTErMCkGA1UEAxMiRGlnaUNlcnQgSGlnaCBBc3N1cmFuY2Ug\n \
- RVYgUm9vdCBDQTCCASIwDQYJKoZIhvcNAQEBBQADggEPADCCAQoCggEBAMbM5XPm\n \
- +9S75S0tMqbf5YE/yc0lSbZxKsPVlDRnogocsF9ppkCxxLeyj9CYpKlBWTrT3JTW\n \
- PNt0OKRKzE0lgvdKpVMSOO7zSW1xkX5jtqumX8OkhPhPYlG++MXs2ziS4wblCJEM\n \
- xChBVfvLWokVfnHoNb9Ncgk9vjo4UFt3MRuNs8ckRZqnrG0AFFoEt7oT61EKmEFB\n \
- Ik5lYYeBQVCmeVyJ3hlKV9Uu5l0cUyx+mM0aBhakaHPQNAQTXKFx01p8VdteZOE3\n \
- hzBWBOURtCmAEvF5OYiiAhF8J2a3iLd48soKqDirCmTCv2ZdlYTBoSUeh10aUAsg\n \
- EsxBu24LUTi4S8sCAwEAAaNjMGEwDgYDVR0PAQH/BAQDAgGGMA8GA1UdEwEB/wQF\n \
- MAMBAf8wHQYDVR0OBBYEFLE+w2kD+L9HAdSYJhoIAu9jZCvDMB8GA1UdIwQYMBaA\n \
- FLE+w2kD+L9HAdSYJhoIAu9jZCvDMA0GCSqGSIb3DQEBBQUAA4IBAQAcGgaX3Nec\n \
- nzyIZgYIVyHbIUf4KmeqvxgydkAQV8GK83rZEWWONfqe/EW1ntlMMUu4kehDLI6z\n \
- eM7b41N5cdblIZQB2lWHmiRk9opmzN6cN82oNLFpmyPInngiK3BD41VHMWEZ71jF\n \
- hS9OMPagMRYjyOfiZRYzy78aG6A9+MpeizGLYAiJLQwGXFK3xPkKmNEVX58Svnw2\n \
- Yzi9RKR/5CYrCsSXaQ3pjOLAEFe4yHYSkVXySGnYvCoCWw9E1CAx2/S6cCZdkGCe\n \
- vEsXCS+0yx5DaMkHJ8HSXPfqIbloEpw8nL+e/IBcm2PN7EeqJSdnoDfzAIJ9VNep\n \
- +OkuE6N36B9K\n \
- -----END CERTIFICATE-----\n\0";
- 
 pub struct OtaUpdate;
 
 impl FirmwareUpdate for OtaUpdate {
 
-    fn read_update_version(&self) -> Result<Version> {
-        let certificate = esp_idf_svc::tls::X509::pem_until_nul(ROOT_CA_CERTIFICATE);
-
-        info!("Init HTTP client");
-        let mut client = EspHttpConnection::new(&Configuration {
-            buffer_size: Some(WRITE_DATA_BUF_SIZE),
-            buffer_size_tx: Some(TX_BUF_SIZE),
-            crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
-            client_certificate: Some(certificate),
-            ..Default::default()
-        })?;
-
-        info!("Send request");
-        client.initiate_request(embedded_svc::http::Method::Get, OTA_VERSION_FILE_URL, &[])?;
+    fn read_manifest(&self, url: &str) -> Result<OtaManifest> {
+        read_manifest(&mut EspHttpClient::new(url)?, url)
+    }
 
-        info!("Get response");
-        client.initiate_response()?;
+    fn download_update(&self, image: &FirmwareImage, url: &str) -> Result<()> {
+        let Some(public_key) = OTA_PUBLIC_KEY else {
+            return Err(anyhow!("Firmware built without WORDCLOCK_OTA_PUBLIC_KEY, images can't be verified"));
+        };
+        let public_key = PublicKey::from_hex(public_key)?;
 
-        if client.status() != 200 {
-            return Err(anyhow!("HTTP return error code 200"));
+        let mut partition = OtaPartition { partition: ptr::null(), handle: 0 };
+        if let Err(err) = download_image(&mut EspHttpClient::new(url)?, &mut partition, image, url, &public_key) {
+            error!("Firmware update failed: {}", err);
+            return Err(err);
         }
+        Ok(())
+    }
 
-        info!("Read file content-length");
-        let content_length:usize;
-        if let Some(len) = client.header("Content-Length") {
-            content_length = len.parse().unwrap();
-            info!("Response size is {}", content_length);
-        } else {
-            return  Err(anyhow!("Reading content length from head failed"));
+    fn reboot_to_new_image(&self) {
+        unsafe {
+            esp_idf_sys::esp_restart();
         }
+    }
 
-        info!("Get file content");
-        let mut ota_version_file: [u8; WRITE_DATA_BUF_SIZE] = [0; WRITE_DATA_BUF_SIZE];
-        if let Ok(ota_version_len) = client.read(&mut ota_version_file) {
-            // Important to strip down the buffer to only the read length.
-            // String parsing will fail otherwise...
-            let ota_version_string = String::from_utf8(ota_version_file[0..ota_version_len].to_vec()).unwrap();
-
-            // Anomaly-003: Stack overflow when parsing OTA version
-            //
-            // This need ~24kB of heap memory. It will fail if heap usage is to
-            // high. Changing CONFIG_ESP_MAIN_TASK_STACK_SIZE higher than 20kB
-            // may affect the heap available at this stage. The configuration
-            // server need ~25kB of main stack to parse properly the
-            // configuration URI. There is then an unresolved conflict here.
-            // To see how much heap is available, uncomment:
-            // unsafe { heap_caps_print_heap_info(0); }
-            //
-            // Currently only print the version here, and return a dummy value.
-            info!("Available version {}", ota_version_string);
-            // Ok(Version::from_utf8(&ota_version_string)?)
-            Ok(Version::new(2, 1, 0, Some("dummy")))
-        } else {
-            return Err(anyhow!("Failed to read OTA version file"));
+    fn is_image_pending(&self) -> bool {
+        // Requires CONFIG_BOOTLOADER_APP_ROLLBACK_ENABLE, see sdkconfig.defaults
+        let mut state: esp_ota_img_states_t = 0;
+        let running = unsafe { esp_ota_get_running_partition() };
+        match esp!(unsafe { esp_ota_get_state_partition(running, &mut state) }) {
+            Ok(()) => state == esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY,
+            // Factory partition, or flashed over USB without OTA data
+            Err(_) => false,
         }
     }
 
-    fn download_update(&self) -> Result<()> {
-        let mut ota_write_data: [u8; WRITE_DATA_BUF_SIZE] = [0; WRITE_DATA_BUF_SIZE];
-        let invalid_fw_version: String = String::new();
-        let found_invalid_fw = false;
-        let mut update_summary: String = String::new();
-        let certificate = esp_idf_svc::tls::X509::pem_until_nul(ROOT_CA_CERTIFICATE);
+    fn confirm_image(&self) -> Result<()> {
+        esp!(unsafe { esp_ota_mark_app_valid_cancel_rollback() })?;
+        Ok(())
+    }
+}
+
+/// HTTP client of the ESP-IDF
+///
+/// `https://` servers are verified with the certificate bundle of the ESP-IDF,
+/// `http://` is left for a mirror on the local network.
+struct EspHttpClient(EspHttpConnection);
+
+impl EspHttpClient {
+    /// Client for the server of `url`
+    fn new(url: &str) -> Result<Self> {
+        let crt_bundle_attach = if url.starts_with("https://") {
+            Some(esp_idf_sys::esp_crt_bundle_attach)
+        } else if url.starts_with("http://") {
+            None
+        } else {
+            return Err(anyhow!("Unsupported update URL {:?}", url));
+        };
 
         info!("Init HTTP client");
-        let mut client = EspHttpConnection::new(&Configuration {
-            buffer_size: Some(WRITE_DATA_BUF_SIZE),
+        let client = EspHttpConnection::new(&Configuration {
+            buffer_size: Some(DOWNLOAD_BUFFER_SIZE),
             buffer_size_tx: Some(TX_BUF_SIZE),
-            crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
-            client_certificate: Some(certificate),
+            crt_bundle_attach,
             ..Default::default()
         })?;
+        Ok(Self(client))
+    }
+}
 
-        info!("Send request");
-        client.initiate_request(embedded_svc::http::Method::Get, OTA_FIRMWARE_FILE_URL, &[])?;
+impl HttpClient for EspHttpClient {
+    fn get(&mut self, url: &str) -> Result<ResponseHead> {
+        info!("Send request {}", url);
+        self.0.initiate_request(embedded_svc::http::Method::Get, url, &[])?;
+        self.0.initiate_response()?;
 
-        info!("Get response");
-        client.initiate_response()?;
+        let content_length = match self.0.header("Content-Length") {
+            Some(length) => Some(length.parse()?),
+            None => None,
+        };
+        info!("Response {} of {:?} bytes", self.0.status(), content_length);
+        Ok(ResponseHead { status: self.0.status(), content_length })
+    }
 
-        if client.status() != 200 {
-            return Err(anyhow!("HTTP return error code 200"));
-        }
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        Ok(self.0.read(buffer)?)
+    }
+}
 
-        info!("Read file content-length");
-        let content_length:usize;
-        if let Some(len) = client.header("Content-Length") {
-            content_length = len.parse().unwrap();
-            info!("Response size is {}", content_length);
-        } else {
-            return  Err(anyhow!("Reading content length from head failed"));
-        }
+/// Next OTA partition, written with the OTA API of the ESP-IDF
+struct OtaPartition {
+    partition: *const esp_partition_t,
+    handle: esp_ota_handle_t,
+}
 
-        info!("Initialize OTA update");
-        let update_partition: esp_partition_t =
-        unsafe { *esp_ota_get_next_update_partition(ptr::null()) };
-        let partition_label =
-            std::str::from_utf8(unsafe { std::mem::transmute(&update_partition.label as &[i8]) })
-                .unwrap()
-                .trim_matches(char::from(0));
+impl FirmwareSink for OtaPartition {
+    fn begin(&mut self, size: usize) -> Result<()> {
+        self.partition = unsafe { esp_ota_get_next_update_partition(ptr::null()) };
+        if self.partition.is_null() {
+            return Err(anyhow!("No OTA partition to update"));
+        }
+        let partition = unsafe { &*self.partition };
+        let label = unsafe { CStr::from_ptr(partition.label.as_ptr()) }.to_string_lossy();
         info!(
-            "Writing to partition {} subtype {:#4x} size {:#10x} at offset {:#10x}",
-            partition_label, update_partition.subtype, update_partition.size, update_partition.address
+            "Writing {} bytes to partition {} subtype {:#4x} size {:#10x} at offset {:#10x}",
+            size, label, partition.subtype, partition.size, partition.address
         );
-        info!("Writing to partition {:?}", update_partition);
-
-        let mut ota = EspOta::new().expect("EspOta::new should have been successfull");
-
-        let boot_slot = ota.get_boot_slot().unwrap();
-        let run_slot = ota.get_running_slot().unwrap();
-        let update_slot = ota.get_update_slot().unwrap();
-
-        let ota_update = ota.initiate_update()?;
-
-        let mut bytes_read_total = 0;
-        let mut image_header_was_checked = false;
-
-        loop {
-            let data_read = client.read(&mut ota_write_data)?;
-
-            // Check if first segment and process image meta data
-            if !image_header_was_checked
-                && data_read
-                    > mem::size_of::<esp_image_header_t>()
-                        + mem::size_of::<esp_image_segment_header_t>()
-                        + mem::size_of::<esp_app_desc_t>()
-            {
-                let mut esp_fw_loader_info = EspFirmwareInfoLoader::new();
-                let res = match esp_fw_loader_info.load(&ota_write_data) {
-                    Ok(load_result) => load_result,
-                    Err(err) => {
-                        error!("failed to retrive firmware info from download: {err}");
-                        return Err(anyhow!("failed to retrive firmware info from download: {err}"));
-                    }
-                };
-                if res != LoadResult::Loaded {
-                    // error!("incomplete data for retriving FW info for downloaded FW");
-                    return Err(anyhow!("Incomplete data for retriving FW info for downloaded FW"));
-                }
-
-                let fw_info = esp_fw_loader_info.get_info();
-                match fw_info {
-                    Ok(info) => {
-                        format_update_summary(
-                            &mut update_summary,
-                            boot_slot.clone(),
-                            run_slot.clone(),
-                            update_slot.clone(),
-                            info.clone(),
-                        );
-                        info!("\n{update_summary}\n");
-
-                        if found_invalid_fw && invalid_fw_version == info.version.to_string() {
-                            info!("New FW has same version as invalide firmware slot. Stopping update");
-                            return Err(anyhow!("New FW has same version as invalide firmware slot. Stopping update"));
-                        }
-                    }
-                    Err(e) => return Err(e.into())
-                }
-
-                image_header_was_checked = true;
-            }
-
-            bytes_read_total += data_read;
-
-            if data_read > 0 {
-                if let Err(err) = ota_update.write(&ota_write_data) {
-                    error!("ERROR failed to write update with: {err:?}");
-                    return Err(anyhow!("ERROR failed to write update with: {err:?}"));
-                }
-            }
-
-            // Check if we have read an entire buffer. If not,
-            // we assume it was the last segment and we stop
-            if ota_write_data.len() > data_read {
-                break;
-            }
-        }
-
-        if bytes_read_total == content_length {
-            if let Err(err) = ota_update.complete() {
-                error!("OTA update failed. esp_ota_end failed {:?}", err);
-                return Err(anyhow!("OTA update failed. esp_ota_end failed {:?}", err));
-            }
-        } else {
-            ota_update.abort().unwrap();
-            error!("ERROR firmware update failed");
-            return Err(anyhow!("ERROR firmware update failed"));
-        };
-
+        esp!(unsafe { esp_ota_begin(self.partition, size as _, &mut self.handle) })?;
         Ok(())
     }
 
-    fn reboot_to_new_image(&self) {
-        unsafe {
-            esp_idf_sys::esp_restart();
-        }
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        esp!(unsafe { esp_ota_write(self.handle, data.as_ptr() as *const _, data.len() as _) })?;
+        Ok(())
     }
-}
-
-fn format_update_summary(
-    update_summary: &mut String,
-    boot_slot: Slot,
-    run_slot: Slot,
-    update_slot: Slot,
-    ota_image_info: FirmwareInfo,
-    ) {
-    let mut label: String = String::new();
-
-    update_summary.push_str("OTA Update Summary\n");
-    update_summary.push_str("==================\n");
-    update_summary.push_str("Boot   partition: ");
-    label.push_str(&boot_slot.label.to_string());
-    update_summary.push_str(label.as_str());
-    update_summary.push_str(", ");
-    add_firmware_info(update_summary, boot_slot.firmware);
-
-    update_summary.push_str("\nRun    partition: ");
-    label = String::new();
-    label.push_str(&run_slot.label.to_string());
-    update_summary.push_str(label.as_str());
-    update_summary.push_str(", ");
-    add_firmware_info(update_summary, run_slot.firmware);
 
-    update_summary.push_str("\nUpdate partition: ");
-    label = String::new();
-    label.push_str(&update_slot.label.to_string());
-    update_summary.push_str(label.as_str());
-    update_summary.push_str(", ");
-    add_firmware_info(update_summary, update_slot.firmware);
-    update_summary.push_str("\n");
-
-    update_summary.push_str("\nDownloaded FW  : ");
-    add_firmware_info(update_summary, Some(ota_image_info));
-    update_summary.push_str("\n");
-}
-
-fn add_firmware_info(
-    update_summary: &mut String,
-    firmware: Option<FirmwareInfo>,
-    ) {
-    let mut version: String = String::new();
-    let mut released: String = String::new();
-    let mut description: String = String::new();
+    fn complete(&mut self) -> Result<()> {
+        esp!(unsafe { esp_ota_end(self.handle) })?;
+        esp!(unsafe { esp_ota_set_boot_partition(self.partition) })?;
+        Ok(())
+    }
 
-    if let Some(fw) = firmware {
-        version.push_str(&fw.version.to_string());
-        update_summary.push_str(version.as_str());
-        update_summary.push_str(", ");
-        released.push_str(&fw.released.to_string());
-        update_summary.push_str(released.as_str());
-        if let Some(desc) = fw.description {
-            update_summary.push_str(", ");
-            description.push_str(&desc.to_string());
-            update_summary.push_str(description.as_str());
-        }
+    fn abort(&mut self) -> Result<()> {
+        esp!(unsafe { esp_ota_abort(self.handle) })?;
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -7,14 +7,14 @@
  * Copyright (c) 2023 Louis Mayencourt
  */
 
-use anyhow::{Result, Context};
+use anyhow::{anyhow, Result, Context};
 
 use esp_idf_svc::nvs::*;
 use esp_idf_sys::*;
 
 use application::configuration::*;
 
-const NVS_STRING_READ_BUFFER_SIZE: usize = 180;
+const NVS_STRING_READ_BUFFER_SIZE: usize = MAX_STORED_STRING_LENGTH + 1;
 
 pub struct NonVolatileStorage;
 
@@ -23,10 +23,13 @@ impl PersistentStorage for NonVolatileStorage {
         let memory_partition = EspCustomNvsPartition::take("nvs").context("Partition `nvs`doesn't exist")?;
         let nvs = EspCustomNvs::new(memory_partition.clone(), "wifi_config", false).context("Partition `nvs` doesn't have a `wifi_config` namespace")?;
 
-        Ok(get_string_from_nvs(&nvs, key)?)
+        get_string_from_nvs(&nvs, key)?.ok_or_else(|| anyhow!("No value for key {}", key))
     }
 
     fn store_string(&mut self, key: &str, value: &str) -> Result<()> {
+        if value.len() > MAX_STORED_STRING_LENGTH {
+            return Err(anyhow!("Value of key {} too long to be read back", key));
+        }
         let memory_partition = EspCustomNvsPartition::take("nvs").context("Partition `nvs`doesn't exist")?;
         let mut nvs = EspCustomNvs::new(memory_partition.clone(), "wifi_config", true).context("Partition `nvs` doesn't have a `wifi_config` namespace")?;
 
@@ -35,17 +38,20 @@ impl PersistentStorage for NonVolatileStorage {
     }
 }
 
-fn get_string_from_nvs(nvs: &EspCustomNvs, key: &str) -> Result<String, EspError> {
+/// Return `None` if the key is not in the NVS.
+fn get_string_from_nvs(nvs: &EspCustomNvs, key: &str) -> Result<Option<String>, EspError> {
     let mut nvm_str_buffer: [u8; NVS_STRING_READ_BUFFER_SIZE] = [0; NVS_STRING_READ_BUFFER_SIZE];
-    nvs.get_str(key, &mut nvm_str_buffer)?;
+    if nvs.get_str(key, &mut nvm_str_buffer)?.is_none() {
+        return Ok(None);
+    }
 
     // remove any tailing zeros
-    Ok(String::from(
+    Ok(Some(String::from(
         core::str::from_utf8(
             &(nvm_str_buffer[0..nvm_str_buffer.iter().position(|&x| x == 0).unwrap()]),
         )
         .unwrap(),
-    ))
+    )))
 }
 
 use core::ptr;
@@ -0,0 +1,26 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright (c) 2023 Louis Mayencourt
+ */
+
+use esp_idf_hal::gpio::*;
+
+use application::button_input::PushButton;
+
+/// Push-button wired between a GPIO and ground, with a pull-up.
+///
+/// The button is pressed when the GPIO level is low.
+pub struct ActiveLowButton<'d, T: Pin> {
+    pin: PinDriver<'d, T, Input>,
+}
+
+impl<'d, T: Pin> ActiveLowButton<'d, T> {
+    pub fn new(pin: PinDriver<'d, T, Input>) -> Self {
+        Self { pin }
+    }
+}
+
+impl<'d, T: Pin> PushButton for ActiveLowButton<'d, T> {
+    fn is_pressed(&self) -> bool {
+        self.pin.is_low()
+    }
+}
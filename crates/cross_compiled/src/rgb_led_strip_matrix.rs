@@ -11,7 +11,8 @@ use smart_leds::colors::*;
 use crate::led_driver::RgbLedStrip;
 
 use application::color::Color;
-use application::display::Display;
+use application::display::{time_words, Dialect, Display, Icon, Word, MAX_BRIGHTNESS};
+use application::error_recovery::ErrorKind;
 use application::time::Time;
 
 /// LEDs matrix have a given size of 11x10 (+4 dots)
@@ -38,13 +39,6 @@ const BARN_HOURS_LOOKUP_TABLE:[[usize; 3]; 12] =
 		[0, 9, 6], // zwöufi 
     ];
 
-/// Index for minute lookup table
-const MIN_5:usize = 1;
-const _MIN_10:usize = 2;
-const _MIN_15:usize = 3;
-const _MIN_20:usize = 4;
-const MIN_30:usize = 5;
-
 /// Minute to text lookup table for Bärn dialect.
 /// Stored as start_x, start_y, length
 const BARN_MINUTES_LOOKUP_TABLE:[[usize; 3]; 6] = 
@@ -90,24 +84,136 @@ const ERROR_SIGN:[RGB8; 114] =
         BLACK, BLACK, BLACK, RED, RED, RED, RED, RED, BLACK, BLACK, BLACK,
     ];
 
+/// Question mark sign, drawn with the default color.
+/// One string per matrix line, 'X' marks a lit pixel.
+const QUESTION_SIGN:[&str; LEDS_MATRIX_HEIGTH] =
+    [
+        "...XXXXX...",
+        "..XX...XX..",
+        "..X.....X..",
+        "........X..",
+        ".......XX..",
+        ".....XXX...",
+        ".....X.....",
+        ".....X.....",
+        "...........",
+        ".....X.....",
+    ];
+
+/// Digits from 0 to 9, 3 pixels wide and 5 pixels high.
+/// Same encoding as the signs, one string per glyph line.
+const DIGIT_GLYPHS:[[&str; 5]; 10] =
+    [
+        ["XXX", "X.X", "X.X", "X.X", "XXX"],
+        [".X.", "XX.", ".X.", ".X.", "XXX"],
+        ["XXX", "..X", "XXX", "X..", "XXX"],
+        ["XXX", "..X", ".XX", "..X", "XXX"],
+        ["X.X", "X.X", "XXX", "..X", "..X"],
+        ["XXX", "X..", "XXX", "..X", "XXX"],
+        ["XXX", "X..", "XXX", "X.X", "XXX"],
+        ["XXX", "..X", ".X.", ".X.", ".X."],
+        ["XXX", "X.X", "XXX", "X.X", "XXX"],
+        ["XXX", "X.X", "XXX", "..X", "XXX"],
+    ];
+
+/// WiFi sign, for an unreachable network.
+const WIFI_SIGN:[&str; LEDS_MATRIX_HEIGTH] =
+    [
+        "...........",
+        "..XXXXXXX..",
+        ".X.......X.",
+        "X..XXXXX..X",
+        "..X.....X..",
+        "....XXX....",
+        "...X...X...",
+        "...........",
+        ".....X.....",
+        "...........",
+    ];
+
+/// WiFi sign with a cross, for refused credentials.
+const WIFI_AUTH_SIGN:[&str; LEDS_MATRIX_HEIGTH] =
+    [
+        "..XXXXXXX..",
+        ".X.......X.",
+        "X..XXXXX..X",
+        "..X.....X..",
+        "....XXX....",
+        "...........",
+        ".....X.....",
+        "...X...X...",
+        "....X.X....",
+        ".....X.....",
+    ];
+
+/// Clock sign, for a failed time synchronization.
+const CLOCK_SIGN:[&str; LEDS_MATRIX_HEIGTH] =
+    [
+        "...XXXXX...",
+        "..X.....X..",
+        ".X...X...X.",
+        ".X...X...X.",
+        ".X...X...X.",
+        ".X...XXX.X.",
+        ".X.......X.",
+        ".X.......X.",
+        "..X.....X..",
+        "...XXXXX...",
+    ];
+
+/// Storage cylinder sign, for a persistent storage failure.
+const STORAGE_SIGN:[&str; LEDS_MATRIX_HEIGTH] =
+    [
+        "...XXXXX...",
+        "..X.....X..",
+        "..XXXXXXX..",
+        "..X.....X..",
+        "..X.....X..",
+        "..XXXXXXX..",
+        "..X.....X..",
+        "..X.....X..",
+        "..X.....X..",
+        "...XXXXX...",
+    ];
+
+/// Download arrow sign, for a failed firmware update.
+const OTA_SIGN:[&str; LEDS_MATRIX_HEIGTH] =
+    [
+        ".....X.....",
+        ".....X.....",
+        ".....X.....",
+        ".....X.....",
+        "..X..X..X..",
+        "...X.X.X...",
+        "....XXX....",
+        ".....X.....",
+        "...........",
+        ".XXXXXXXXX.",
+    ];
+
 pub struct RgbLedStripMatrix<T: RgbLedStrip> {
     driver: T,
     frame: [RGB8; LEDS_MATRIX_PIXEL_COUNT],
     default_color: Color,
+    /// In percent, applied when writing the frame
+    brightness: u8,
+    dialect: Dialect,
 }
 
 impl<T: RgbLedStrip> RgbLedStripMatrix<T>
 {
     /// Create a RGB LED strip matrix of `LEDS_MATRIX_HEIGTH` by `LEDS_MATRIX_WIDTH`.
     ///
-    /// The display is initialized with a new `frame` cleared.
-    /// 
-    /// # Errors
-    /// The function will return an error if the hardware fails to carry the operation.
-    pub fn new(mut driver: T) -> Result<Self> {
-        driver.clear()?;
-
-        Ok(RgbLedStripMatrix{driver, frame:[BLACK; LEDS_MATRIX_PIXEL_COUNT], default_color: Color::new(0, 0, 255)})
+    /// The display starts with a new `frame` cleared, but the LEDs are left
+    /// untouched: call `clear()` to check the strip responds.
+    pub fn new(driver: T) -> Self {
+        RgbLedStripMatrix{
+            driver,
+            frame:[BLACK; LEDS_MATRIX_PIXEL_COUNT],
+            default_color: Color::new(0, 0, 255),
+            brightness: MAX_BRIGHTNESS,
+            dialect: Dialect::default(),
+        }
     }
 
     fn set_pixel_from_lut(&mut self, lut: &[[usize; 3]], idx: usize, color: RGB8) {
@@ -116,6 +222,22 @@ impl<T: RgbLedStrip> RgbLedStripMatrix<T>
         }
     }
 
+    fn set_pixels_from_word(&mut self, word: Word, color: RGB8) {
+        match word {
+            Word::Es => self.set_pixel_from_lut(&BARN_WORD_LOOKUP_TABLE, 0, color),
+            Word::Isch => self.set_pixel_from_lut(&BARN_WORD_LOOKUP_TABLE, 1, color),
+            Word::Uhr => self.set_pixel_from_lut(&BARN_WORD_LOOKUP_TABLE, 2, color),
+            Word::Fuef => self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, 1, color),
+            Word::Zaeae => self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, 2, color),
+            Word::Viertu => self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, 3, color),
+            Word::Zwaenzg => self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, 4, color),
+            Word::Haubi => self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, 5, color),
+            Word::Ab => self.set_pixel_from_lut(&BARN_PREPOSITION_LOOKUP_TABLE, 0, color),
+            Word::Vor => self.set_pixel_from_lut(&BARN_PREPOSITION_LOOKUP_TABLE, 1, color),
+            Word::Hour(hour) => self.set_pixel_from_lut(&BARN_HOURS_LOOKUP_TABLE, usize::from(hour - 1), color),
+        }
+    }
+
     fn set_pixel(&mut self, x_cor:usize, y_cor:usize, color: RGB8) {
         let mut corrected_x_cor = x_cor;
         let corrected_y_cor = LEDS_MATRIX_HEIGTH-1-y_cor;
@@ -138,6 +260,27 @@ impl<T: RgbLedStrip> RgbLedStripMatrix<T>
         self.frame[pixel_num] = color;
     }
 
+    fn set_pixels_from_sign(&mut self, sign: &[&str; LEDS_MATRIX_HEIGTH], color: RGB8) {
+        for (y_cor, line) in sign.iter().enumerate() {
+            for (x_cor, pixel) in line.chars().enumerate() {
+                if pixel == 'X' {
+                    self.set_pixel(x_cor, y_cor, color);
+                }
+            }
+        }
+    }
+
+    fn set_pixels_from_digit(&mut self, digit: u8, x_start: usize, y_start: usize, color: RGB8) {
+        let glyph = &DIGIT_GLYPHS[usize::from(digit % 10)];
+        for (y_cor, line) in glyph.iter().enumerate() {
+            for (x_cor, pixel) in line.chars().enumerate() {
+                if pixel == 'X' {
+                    self.set_pixel(x_start + x_cor, y_start + y_cor, color);
+                }
+            }
+        }
+    }
+
     fn set_dots(&mut self, start:usize, number:usize, color: RGB8) {
         for n in start..start+number {
             self.frame[n] = color;
@@ -145,7 +288,8 @@ impl<T: RgbLedStrip> RgbLedStripMatrix<T>
     }
 
     fn draw_frame(&mut self) -> Result<()> {
-        self.driver.write(&self.frame)?;
+        let frame = self.frame.map(|pixel| Color { rgb: pixel }.dimmed(self.brightness).rgb);
+        self.driver.write(&frame)?;
         Ok(())
     }
 
@@ -174,76 +318,35 @@ impl<T: RgbLedStrip> Display for RgbLedStripMatrix<T> {
 
         self.new_frame();
 
-        // Compute hour to display for German
-        let mut hour_to_display = time.hour;
-        if time.minute >=25 {
-            hour_to_display = time.hour+1;
-        }
-        // 24 -> 12
-		if hour_to_display > 12 {
-			hour_to_display = hour_to_display - 12 ;
-		}
-		// midnight
-		if hour_to_display == 0 {
-			hour_to_display = 12;
-		}
-        self.set_pixel_from_lut(&BARN_HOURS_LOOKUP_TABLE, (hour_to_display-1) as usize, self.default_color.rgb);
-
-        // Display minutes
-		let minutes_word:usize;
-		if time.minute < 5 {
-			// Es isch
-			self.set_pixel_from_lut(&BARN_WORD_LOOKUP_TABLE, 0, self.default_color.rgb);
-			self.set_pixel_from_lut(&BARN_WORD_LOOKUP_TABLE, 1, self.default_color.rgb);
-			// uhr
-			minutes_word = (time.minute/5) as usize;
-			debug!(" <5 id: {}", minutes_word);
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, minutes_word, self.default_color.rgb);
-		} else if time.minute < 25 {
-			// uhr, füf, zää, zwanzg ab
-			minutes_word = (time.minute/5) as usize;
-			debug!(" <25 id: {}", minutes_word);
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, minutes_word, self.default_color.rgb);
-			self.set_pixel_from_lut(&BARN_PREPOSITION_LOOKUP_TABLE, 0, self.default_color.rgb);
-		} else if time.minute < 30 {
-			// füf vor halbi h+1
-			debug!(" <30");
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, MIN_5, self.default_color.rgb);
-			self.set_pixel_from_lut(&BARN_PREPOSITION_LOOKUP_TABLE, 1, self.default_color.rgb);
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, MIN_30, self.default_color.rgb);
-
-		} else if time.minute < 35 {
-			// halbi h+1
-			debug!(" <35");
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, MIN_30, self.default_color.rgb);
-		} else if time.minute < 40 {
-			// füf ab halbi h+1
-			debug!(" <40");
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, MIN_5, self.default_color.rgb);
-			self.set_pixel_from_lut(&BARN_PREPOSITION_LOOKUP_TABLE, 0, self.default_color.rgb);
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, MIN_30, self.default_color.rgb);
-		} else {
-			// füf, zää, zwanzg vor
-			minutes_word = ((60 - time.minute-1)/5 +1) as usize;
-			debug!(" >40 id: {}", minutes_word);
-			self.set_pixel_from_lut(&BARN_MINUTES_LOOKUP_TABLE, minutes_word, self.default_color.rgb);
-			self.set_pixel_from_lut(&BARN_PREPOSITION_LOOKUP_TABLE, 1, self.default_color.rgb);
-		}
-
-		// display inter'minutes
-        if time.minute%5 != 0 {
-            self.set_dots(0, (time.minute%5) as usize, self.default_color.rgb);
+        let (words, dots) = time_words(time, self.dialect);
+        debug!("{} is told as {:?}", time, words);
+        for word in words {
+            self.set_pixels_from_word(word, self.default_color.rgb);
         }
 
+        // display inter'minutes
+        self.set_dots(0, usize::from(dots), self.default_color.rgb);
+
         self.draw_frame()?;
 
         Ok(())   
     }
 
-    fn draw_error(&mut self) -> Result<()> {
+    fn draw_error(&mut self, kind: ErrorKind) -> Result<()> {
         self.new_frame();
 
-        self.frame = ERROR_SIGN;
+        let sign = match kind {
+            ErrorKind::WifiNotFound => &WIFI_SIGN,
+            ErrorKind::WifiAuth => &WIFI_AUTH_SIGN,
+            ErrorKind::TimeSync => &CLOCK_SIGN,
+            ErrorKind::Storage => &STORAGE_SIGN,
+            ErrorKind::Ota => &OTA_SIGN,
+            ErrorKind::Hardware => {
+                self.frame = ERROR_SIGN;
+                return self.draw_frame();
+            }
+        };
+        self.set_pixels_from_sign(sign, RED);
         self.draw_frame()?;
 
         Ok(())
@@ -261,7 +364,39 @@ impl<T: RgbLedStrip> Display for RgbLedStripMatrix<T> {
         Ok(())
     }
 
+    fn draw_icon(&mut self, icon: Icon) -> Result<()> {
+        match icon {
+            Icon::Dots(count) => self.draw_progress(count),
+            Icon::Question => {
+                self.new_frame();
+                self.set_pixels_from_sign(&QUESTION_SIGN, self.default_color.rgb);
+                self.draw_frame()
+            }
+            Icon::Pin(digits) => {
+                // Two digits per line, centered on the matrix
+                self.new_frame();
+                for (n, digit) in digits.iter().enumerate() {
+                    self.set_pixels_from_digit(*digit, 2 + 4 * (n % 2), 5 * (n / 2), self.default_color.rgb);
+                }
+                self.draw_frame()
+            }
+        }
+    }
+
+    fn fill(&mut self, color: application::color::Color) -> Result<()> {
+        self.frame = [color.rgb; LEDS_MATRIX_PIXEL_COUNT];
+        self.draw_frame()
+    }
+
     fn set_default_color(&mut self, color: application::color::Color) {
         self.default_color = color;
     }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness.min(MAX_BRIGHTNESS);
+    }
+
+    fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
 }
@@ -27,7 +27,8 @@ fn main() -> Result<()> {
     let peripherals = Peripherals::take().unwrap();
 
     let led_driver = WS2812::new(114, peripherals.pins.gpio15, peripherals.rmt.channel0)?;
-    let mut display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver)?;
+    let mut display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver);
+    display.clear()?;
 
     loop {
         display.draw_all()?;
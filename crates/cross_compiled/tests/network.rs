@@ -31,7 +31,8 @@ use application::network::Network;
     let peripherals = Peripherals::take().unwrap();
 
     let mut network = network::WifiNetwork::new(peripherals.modem)?;
-    network.configure(&hard_coded_config.get_ssid().unwrap(), &hard_coded_config.get_password().unwrap())?;
+    let wifi = &hard_coded_config.get_networks()[0];
+    network.configure(&wifi.ssid, &wifi.password)?;
     let wifi_res = network.connect();
 
     match wifi_res {
@@ -50,7 +51,7 @@ use application::network::Network;
     assert!(network.is_connected());
 
     // if let Err(e) = network_time::init() {
-    //     display.draw_error()?;
+    //     display.draw_error(ErrorKind::TimeSync)?;
     //     return Err(e);
     // }
 
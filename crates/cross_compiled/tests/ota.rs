@@ -22,9 +22,10 @@ use cross_compiled::rgb_led_strip_matrix;
 
 use application::configuration::Configuration;
 use application::display::Display;
-use application::firmware_update::FirmwareUpdate;
+use application::error_recovery::ErrorKind;
+use application::firmware_update::{manifest_url, FirmwareUpdate, UpdateChannel, DEFAULT_UPDATE_SERVER, HARDWARE_REVISION};
 use application::network::Network;
-// use application::version::Version;
+use application::version::Version;
  
 #[test]
 fn display() {
@@ -32,7 +33,8 @@ fn display() {
     let mut led = PinDriver::output(peripherals.pins.gpio2).unwrap();
 
     let led_driver = WS2812::new(114, peripherals.pins.gpio15, peripherals.rmt.channel0).unwrap();
-    let mut display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver).unwrap();
+    let mut display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver);
+    display.clear().unwrap();
 
     // Display check
     for n in 0..1 {
@@ -71,12 +73,14 @@ fn display() {
     let mut led = PinDriver::output(peripherals.pins.gpio2)?;
 
     let led_driver = WS2812::new(114, peripherals.pins.gpio15, peripherals.rmt.channel0)?;
-    let mut display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver)?;
+    let mut display = rgb_led_strip_matrix::RgbLedStripMatrix::new(led_driver);
+    display.clear()?;
 
     display.draw_progress(1)?;
 
     let mut network = network::WifiNetwork::new(peripherals.modem)?;
-    network.configure(&hard_coded_config.get_ssid().unwrap(), &hard_coded_config.get_password().unwrap())?;
+    let wifi = &hard_coded_config.get_networks()[0];
+    network.configure(&wifi.ssid, &wifi.password)?;
     let wifi_res = network.connect();
 
     match wifi_res {
@@ -96,14 +100,18 @@ fn display() {
 
     let network_time = Box::new(network_time::NetworkTime::new());
     if let Err(e) = network_time.synchronize() {
-        display.draw_error()?;
+        display.draw_error(ErrorKind::TimeSync)?;
         return Err(anyhow!{"Failed to get network time"});
     }
 
     let firmware_update = OtaUpdate;
-    info!("available version {}", firmware_update.read_update_version()?);
+    let url = manifest_url(DEFAULT_UPDATE_SERVER, UpdateChannel::Stable);
+    let manifest = firmware_update.read_manifest(&url)?;
+    let image = manifest.select(HARDWARE_REVISION, UpdateChannel::Stable, &Version::new(0, 0, 0, None))?;
+    info!("available version {}", image.version);
 
-    firmware_update.download_update()?;
+    let image_url = image.resolve_url(&url);
+    firmware_update.download_update(image, &image_url)?;
     info!("Update ready, restart device");
     esp_idf_hal::delay::FreeRtos::delay_ms(5000);
     firmware_update.reboot_to_new_image();
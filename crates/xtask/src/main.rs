@@ -1,14 +1,36 @@
 use std::{env, io::Write};
-use std::fs::File;
+use std::fs::{self, File};
+use std::io;
 
 use anyhow::anyhow;
 use xshell::{cmd, Shell};
 
+use application::behaviour::state_diagram_plantuml;
+use application::configuration::{
+    Configuration, FORM_DISPLAY_COLOR_KEY, FORM_NIGHT_END_KEY, FORM_NIGHT_START_KEY, FORM_PASSWORD_KEY,
+    FORM_PRIORITY_KEY, FORM_SSID_KEY, FORM_UPDATE_CHANNEL_KEY, FORM_UPDATE_SERVER_KEY,
+};
+use application::configuration_backup::Secrets;
+use application::firmware_update::{UpdateChannel, HARDWARE_REVISION};
+use application::ota_manifest::{sha256, FirmwareImage, ImageCheck, OtaManifest};
+use application::ota_signature::PrivateKey;
+use application::version::Version;
+
+/// State diagram generated from the `Behaviour` transition table
+const SYSTEM_STATE_UML_FILE: &str = "doc/uml/1_problem_description/use_case/system_state.puml";
+
+/// Root of the OTA images, published on the `released` branch
+const OTA_IMAGE_DIRECTORY: &str = "ota-image";
+
+
+/// Default output of the provisioning blob
+const PROVISIONING_FILE: &str = "wordclock-configuration.json";
+
 fn main() -> Result<(), anyhow::Error> {
     let args = env::args().skip(1).collect::<Vec<_>>();
     let args = args.iter().map(|s| &**s).collect::<Vec<_>>();
 
-    if args.len() == 0 {
+    if args.is_empty() {
         usage();
         return Err(anyhow!("No argument provided"));
     }
@@ -20,7 +42,10 @@ fn main() -> Result<(), anyhow::Error> {
         "flash" => flash_target(&args[1..]),
         "doc" => doc_target(),
         "uml" => generate_uml_images(),
+        "state_uml" => generate_state_uml(),
         "generate_ota" => generate_ota_image(&args[1..]),
+        "sign" => sign_ota_images(&args[1..]),
+        "provision" => generate_provisioning(&args[1..]),
         _ => {
             usage();
             Ok(())
@@ -28,17 +53,46 @@ fn main() -> Result<(), anyhow::Error> {
     }
 }
 
+/// Release manifest of `channel`, listing the image of each hardware
+fn ota_manifest_file(channel: UpdateChannel) -> String {
+    format!("{}/{}", OTA_IMAGE_DIRECTORY, channel.manifest_file())
+}
+
+/// Manifest of `channel`, empty before its first release
+fn read_ota_manifest(channel: UpdateChannel) -> Result<OtaManifest, anyhow::Error> {
+    match fs::read(ota_manifest_file(channel)) {
+        Ok(json) => OtaManifest::from_json(&json),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(OtaManifest::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_ota_manifest(channel: UpdateChannel, manifest: &OtaManifest) -> Result<(), anyhow::Error> {
+    let mut manifest_file = File::create(ota_manifest_file(channel))?;
+    manifest_file.write_all(manifest.to_json()?.as_bytes())?;
+    Ok(())
+}
+
 fn usage() {
-    println!("USAGE cargo xtask [build|check|clean|flash|doc|uml|generate_ota]");
+    println!("USAGE cargo xtask [build|check|clean|flash|doc|uml|state_uml|generate_ota|sign|provision]");
+    println!("      cargo xtask provision --ssid <ssid> --password <password> [--priority <0-255>] [--night-start hh:mm]");
+    println!("          [--night-end hh:mm] [--color rrggbb] [--update-server <url>] [--update-channel <stable|beta|nightly>]");
+    println!("          [--output <file>]");
+    println!("          Repeat --ssid, followed by its --password and --priority, for each WiFi network.");
+    println!("      cargo xtask generate_ota [release|debug] [--channel <stable|beta|nightly>] [--min-version <version>] [--notes <text>]");
+    println!("          Release the image on the channel, stable by default.");
+    println!("      cargo xtask sign --key <private key file>");
+    println!("          Sign the images of the manifests, the key file holds 64 hex digits, e.g. from `openssl rand -hex 32`.");
 }
 
 fn build_target(args: &[&str]) -> Result<(), anyhow::Error> {
     let sh = Shell::new()?;
 
     // Generate build version file from git describe
-    // Remove the first char, as the git describe return prefix the version with 'v', e.g.: v1.2.3
+    // Remove the 'v' prefix of the git describe output, e.g.: v1.2.3
+    // The boot validation compares this version with the one of the manifest, keep it complete.
     let git_version = cmd!(sh, "git describe").read()?;
-    let git_version = &git_version[1..&git_version.len()-1];
+    let git_version = git_version.trim_start_matches('v');
     let mut version_file = File::create("crates/application/src/build_version.rs")?;
     writeln!(version_file, "// This file is generated automatically during build with the result of the git describe command.")?;
     writeln!(version_file, "// Do not edit manually.\n")?;
@@ -90,15 +144,23 @@ fn generate_uml_images() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+fn generate_state_uml() -> Result<(), anyhow::Error> {
+    let mut uml_file = File::create(SYSTEM_STATE_UML_FILE)?;
+    uml_file.write_all(state_diagram_plantuml().as_bytes())?;
+    println!("Generated {}", SYSTEM_STATE_UML_FILE);
+
+    Ok(())
+}
+
 fn generate_ota_image(args: &[&str]) -> Result<(), anyhow::Error> {
     let sh = Shell::new()?;
     let git_version = cmd!(sh, "git describe").read()?;
     println!("Releasing firmware: {:?}", git_version);
 
     let build_type: &str;
-    match &args[..] {
-        ["release"] => build_type = "release",
-        ["debug"] => build_type = "debug",
+    match args.first() {
+        Some(&"release") => build_type = "release",
+        Some(&"debug") => build_type = "debug",
         _ => {
             return Err(anyhow!(
                 "Unsupported argument {:?}, must be [release|debug]",
@@ -106,9 +168,167 @@ fn generate_ota_image(args: &[&str]) -> Result<(), anyhow::Error> {
             ))
         }
     }
-    cmd!(sh, "espflash save-image ESP32 --flash-size 2MB crates/cross_compiled/target/xtensa-esp32-espidf/{build_type}/cross_compiled ota-image/hardware-v2/firmware-ota.bin").run()?;
-    let mut version_file = File::create("ota-image/hardware-v2/version.txt")?;
-    version_file.write_all(git_version.as_bytes())?;
+    let mut min_version = None;
+    let mut release_notes = String::new();
+    let mut channel = UpdateChannel::Stable;
+    for option in args[1..].chunks(2) {
+        match option {
+            ["--channel", name] => channel = name.parse()?,
+            ["--min-version", version] => min_version = Some(Version::from_string(version)?),
+            ["--notes", notes] => release_notes = notes.to_string(),
+            _ => return Err(anyhow!("Unsupported argument {:?}", option)),
+        }
+    }
+
+    let version = Version::from_string(&git_version)?;
+    if channel == UpdateChannel::Stable && version.is_pre_release() {
+        return Err(anyhow!("{} is not a release, use --channel beta or nightly", git_version));
+    }
+    let hardware_directory = format!("{}/hardware-{}", OTA_IMAGE_DIRECTORY, HARDWARE_REVISION);
+    // Each image of the manifest has its own file, an older release may be kept
+    let image_path = format!("hardware-{}/firmware-{}.bin", HARDWARE_REVISION, version.to_string().trim_start_matches('v'));
+    let image_file = format!("{}/{}", OTA_IMAGE_DIRECTORY, image_path);
+    cmd!(sh, "espflash save-image ESP32 --flash-size 2MB crates/cross_compiled/target/xtensa-esp32-espidf/{build_type}/cross_compiled {image_file}").run()?;
+    if channel == UpdateChannel::Stable {
+        // Still read by the clocks released before the manifest
+        fs::copy(&image_file, format!("{}/firmware-ota.bin", hardware_directory))?;
+        let mut version_file = File::create(format!("{}/version.txt", hardware_directory))?;
+        version_file.write_all(git_version.as_bytes())?;
+    }
+
+    let image = fs::read(&image_file)?;
+    let mut manifest = read_ota_manifest(channel)?;
+    manifest.release(FirmwareImage {
+        version,
+        hardware: String::from(HARDWARE_REVISION),
+        channel,
+        url: image_path,
+        size: image.len(),
+        sha256: sha256(&image),
+        min_version,
+        release_notes,
+        signature: None,
+    });
+    write_ota_manifest(channel, &manifest)?;
+    println!("Generated {}", ota_manifest_file(channel));
+
+    // An image is kept as long as a channel lists it
+    let mut listed_images = Vec::new();
+    for channel in UpdateChannel::ALL {
+        listed_images.extend(read_ota_manifest(channel)?.images.into_iter().map(|image| image.url));
+    }
+    for entry in fs::read_dir(&hardware_directory)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let is_versioned_image = name.starts_with("firmware-") && name.ends_with(".bin") && name != "firmware-ota.bin";
+        let url = format!("hardware-{}/{}", HARDWARE_REVISION, name);
+        if is_versioned_image && !listed_images.contains(&url) {
+            println!("Remove superseded {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sign the images listed in the manifests, after checking them against their file.
+fn sign_ota_images(args: &[&str]) -> Result<(), anyhow::Error> {
+    let ["--key", key_file] = args else {
+        return Err(anyhow!("Unsupported argument {:?}, must be --key <private key file>", args));
+    };
+    let private_key = PrivateKey::from_hex(&fs::read_to_string(key_file)?)?;
+
+    for channel in UpdateChannel::ALL {
+        let mut manifest = read_ota_manifest(channel)?;
+        if manifest.images.is_empty() {
+            continue;
+        }
+        for image in manifest.images.iter_mut() {
+            let mut check = ImageCheck::new(image);
+            check.update(&fs::read(format!("{}/{}", OTA_IMAGE_DIRECTORY, image.url))?)?;
+            check.finish().map_err(|e| anyhow!("{} doesn't match the manifest: {}", image.url, e))?;
+            if image.channel != channel {
+                return Err(anyhow!(
+                    "{} {} is released on {}, but listed in {}",
+                    image.hardware,
+                    image.version,
+                    image.channel,
+                    ota_manifest_file(channel)
+                ));
+            }
+            private_key.sign(image);
+            println!("Signed {} {} on {}", image.hardware, image.version, channel);
+        }
+        write_ota_manifest(channel, &manifest)?;
+    }
+    println!("Verified by the firmware built with WORDCLOCK_OTA_PUBLIC_KEY={}", private_key.public_key().to_hex());
+
+    Ok(())
+}
+
+/// Write a configuration backup, to be restored on the clock with the "/restore" endpoint.
+fn generate_provisioning(args: &[&str]) -> Result<(), anyhow::Error> {
+    // Password and priority belong to the last network given with --ssid
+    let mut networks: Vec<[String; 3]> = Vec::new();
+    let mut fields = Vec::new();
+    let mut output = PROVISIONING_FILE;
+    for option in args.chunks(2) {
+        let [option, value] = option else {
+            return Err(anyhow!("Missing value for {:?}", option));
+        };
+        let network_field = match *option {
+            "--ssid" => {
+                networks.push([value.to_string(), String::new(), String::new()]);
+                continue;
+            }
+            "--password" => 1,
+            "--priority" => 2,
+            "--night-start" => {
+                fields.push((String::from(FORM_NIGHT_START_KEY), value.to_string()));
+                continue;
+            }
+            "--night-end" => {
+                fields.push((String::from(FORM_NIGHT_END_KEY), value.to_string()));
+                continue;
+            }
+            "--color" => {
+                fields.push((String::from(FORM_DISPLAY_COLOR_KEY), value.to_string()));
+                continue;
+            }
+            "--update-server" => {
+                fields.push((String::from(FORM_UPDATE_SERVER_KEY), value.to_string()));
+                continue;
+            }
+            "--update-channel" => {
+                fields.push((String::from(FORM_UPDATE_CHANNEL_KEY), value.to_string()));
+                continue;
+            }
+            "--output" => {
+                output = value;
+                continue;
+            }
+            _ => return Err(anyhow!("Unsupported argument {:?}", option)),
+        };
+        let Some(network) = networks.last_mut() else {
+            return Err(anyhow!("{} must follow --ssid", option));
+        };
+        network[network_field] = value.to_string();
+    }
+    for network in networks {
+        for (key, value) in [FORM_SSID_KEY, FORM_PASSWORD_KEY, FORM_PRIORITY_KEY].into_iter().zip(network) {
+            fields.push((String::from(key), value));
+        }
+    }
+
+    let configuration = Configuration::from_form_fields(&fields).map_err(|errors| {
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        anyhow!("Invalid configuration: {}", messages.join(", "))
+    })?;
+    let mut provisioning_file = File::create(output)?;
+    provisioning_file.write_all(configuration.to_json(Secrets::Include)?.as_bytes())?;
+    println!("Generated {}", output);
 
     Ok(())
 }